@@ -0,0 +1,80 @@
+use std::fs::File;
+use std::io::{self, BufReader, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use nessu_lib::cartridge::Cartridge;
+use zip::result::ZipError;
+use zip::ZipArchive;
+
+pub fn is_zip(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.eq_ignore_ascii_case("zip"))
+        .unwrap_or(false)
+}
+
+/// Names of the `.nes` files in a zip archive
+pub fn nes_entries(path: &Path) -> io::Result<Vec<String>> {
+    let archive = open(path)?;
+
+    Ok(archive
+        .file_names()
+        .filter(|name| name.to_ascii_lowercase().ends_with(".nes"))
+        .map(String::from)
+        .collect())
+}
+
+/// Path that refers to an entry inside a zip archive, like `game.zip/foo.nes`
+pub fn entry_path(path: &Path, name: &str) -> PathBuf {
+    path.join(name)
+}
+
+/// Split a path made with [`entry_path`] into the archive and the name of the entry.
+pub fn split_entry_path(path: &Path) -> Option<(&Path, String)> {
+    let archive = path
+        .ancestors()
+        .skip(1)
+        .find(|ancestor| is_zip(ancestor) && ancestor.is_file())?;
+
+    let name = path
+        .strip_prefix(archive)
+        .ok()?
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+
+    Some((archive, name))
+}
+
+/// Save file of a ROM: `game.sav` for `game.nes`, and `game.foo.sav` for `foo.nes` inside
+/// `game.zip`, so that the ROMs of an archive don't share a save.
+pub fn save_path(path: &Path) -> PathBuf {
+    match split_entry_path(path) {
+        Some((archive, name)) => {
+            let stem = Path::new(&name).file_stem().unwrap_or_default();
+            archive.with_extension(format!("{}.sav", stem.to_string_lossy()))
+        }
+        None => path.with_extension("sav"),
+    }
+}
+
+pub fn load_entry(path: &Path, name: &str) -> io::Result<Cartridge> {
+    let mut archive = open(path)?;
+    let entry = archive.by_name(name).map_err(zip_error)?;
+    Cartridge::from_reader(entry)
+}
+
+fn open(path: &Path) -> io::Result<ZipArchive<BufReader<File>>> {
+    ZipArchive::new(BufReader::new(File::open(path)?)).map_err(zip_error)
+}
+
+fn zip_error(e: ZipError) -> io::Error {
+    match e {
+        ZipError::Io(e) => e,
+        e => io::Error::new(
+            ErrorKind::InvalidData,
+            format!("corrupt zip archive: {}", e),
+        ),
+    }
+}
@@ -0,0 +1,96 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{Sample, SampleFormat, Stream, StreamConfig};
+
+/// Audio output stream fed with samples produced by the emulator.
+pub struct AudioOutput {
+    buffer: Arc<Mutex<VecDeque<f32>>>,
+    sample_rate: u32,
+    _stream: Stream,
+}
+
+impl AudioOutput {
+    pub fn new() -> Option<Self> {
+        let device = cpal::default_host().default_output_device()?;
+        let supported_config = device
+            .default_output_config()
+            .map_err(|e| eprintln!("Failed to get audio output config: {}", e))
+            .ok()?;
+
+        let sample_format = supported_config.sample_format();
+        let config: StreamConfig = supported_config.into();
+        let sample_rate = config.sample_rate.0;
+
+        let buffer = Arc::new(Mutex::new(VecDeque::new()));
+
+        let stream = match sample_format {
+            SampleFormat::F32 => build_stream::<f32>(&device, &config, buffer.clone()),
+            SampleFormat::I16 => build_stream::<i16>(&device, &config, buffer.clone()),
+            SampleFormat::U16 => build_stream::<u16>(&device, &config, buffer.clone()),
+        }
+        .map_err(|e| eprintln!("Failed to open audio output stream: {}", e))
+        .ok()?;
+
+        stream
+            .play()
+            .map_err(|e| eprintln!("Failed to start audio output stream: {}", e))
+            .ok()?;
+
+        Some(Self {
+            buffer,
+            sample_rate,
+            _stream: stream,
+        })
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn queue_samples(&self, samples: &[f32], volume: f32) {
+        // Keep at most ~100 ms of audio buffered so that latency does not build up
+        // when the emulator runs faster than real time.
+        let max_len = self.sample_rate as usize / 10;
+
+        let mut buffer = self.buffer.lock().unwrap();
+        buffer.extend(samples.iter().map(|s| s * volume));
+
+        if buffer.len() > max_len {
+            let excess = buffer.len() - max_len;
+            buffer.drain(..excess);
+        }
+    }
+
+    pub fn clear(&self) {
+        self.buffer.lock().unwrap().clear();
+    }
+}
+
+fn build_stream<T: Sample>(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    buffer: Arc<Mutex<VecDeque<f32>>>,
+) -> Result<Stream, cpal::BuildStreamError> {
+    let channels = config.channels as usize;
+    let mut last_sample = 0.0;
+
+    device.build_output_stream(
+        config,
+        move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+            let mut buffer = buffer.lock().unwrap();
+
+            for frame in data.chunks_mut(channels) {
+                // Repeat the last sample on underrun to avoid clicks
+                if let Some(sample) = buffer.pop_front() {
+                    last_sample = sample;
+                }
+
+                let value: T = Sample::from::<f32>(&last_sample);
+                frame.iter_mut().for_each(|s| *s = value);
+            }
+        },
+        |e| eprintln!("Audio output error: {}", e),
+    )
+}
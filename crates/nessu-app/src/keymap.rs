@@ -0,0 +1,217 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{create_dir_all, read_to_string, write};
+use std::path::PathBuf;
+
+use eframe::egui::Key;
+use serde::{Deserialize, Serialize};
+
+use nessu_lib::input::Button as NesButton;
+
+pub const PLAYERS: usize = 2;
+
+/// Controller buttons in the order they are listed in the controls window
+pub const BUTTONS: [(NesButton, &str); 8] = [
+    (NesButton::Up, "Up"),
+    (NesButton::Down, "Down"),
+    (NesButton::Left, "Left"),
+    (NesButton::Right, "Right"),
+    (NesButton::Start, "Start"),
+    (NesButton::Select, "Select"),
+    (NesButton::A, "A"),
+    (NesButton::B, "B"),
+];
+
+/// Keys that can be bound to controller buttons. Tab is left out, as it's the fast-forward key.
+const BINDABLE_KEYS: [Key; 48] = [
+    Key::ArrowDown,
+    Key::ArrowLeft,
+    Key::ArrowRight,
+    Key::ArrowUp,
+    Key::Escape,
+    Key::Enter,
+    Key::Insert,
+    Key::Delete,
+    Key::Home,
+    Key::End,
+    Key::PageUp,
+    Key::PageDown,
+    Key::Num0,
+    Key::Num1,
+    Key::Num2,
+    Key::Num3,
+    Key::Num4,
+    Key::Num5,
+    Key::Num6,
+    Key::Num7,
+    Key::Num8,
+    Key::Num9,
+    Key::A,
+    Key::B,
+    Key::C,
+    Key::D,
+    Key::E,
+    Key::F,
+    Key::G,
+    Key::H,
+    Key::I,
+    Key::J,
+    Key::K,
+    Key::L,
+    Key::M,
+    Key::N,
+    Key::O,
+    Key::P,
+    Key::Q,
+    Key::R,
+    Key::S,
+    Key::T,
+    Key::U,
+    Key::V,
+    Key::W,
+    Key::X,
+    Key::Y,
+    Key::Z,
+];
+
+const CONFIG_FILE_NAME: &str = "keymap.json";
+
+pub fn key_name(key: Key) -> String {
+    format!("{:?}", key)
+}
+
+fn key_from_name(name: &str) -> Option<Key> {
+    BINDABLE_KEYS.into_iter().find(|key| key_name(*key) == name)
+}
+
+pub fn is_bindable(key: Key) -> bool {
+    BINDABLE_KEYS.contains(&key)
+}
+
+/// Maps the buttons of each controller to keyboard keys.
+#[derive(Clone)]
+pub struct KeyMap {
+    /// Indexed by player and then by the button's position in [`BUTTONS`]
+    bindings: [[Key; BUTTONS.len()]; PLAYERS],
+}
+
+/// On-disk representation, mapping button names to key names for each player
+#[derive(Serialize, Deserialize)]
+struct KeyMapConfig {
+    players: Vec<BTreeMap<String, String>>,
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        Self {
+            bindings: [
+                [
+                    Key::ArrowUp,
+                    Key::ArrowDown,
+                    Key::ArrowLeft,
+                    Key::ArrowRight,
+                    Key::Enter,
+                    Key::S,
+                    Key::A,
+                    Key::B,
+                ],
+                [
+                    Key::I,
+                    Key::K,
+                    Key::J,
+                    Key::L,
+                    Key::P,
+                    Key::Y,
+                    Key::O,
+                    Key::U,
+                ],
+            ],
+        }
+    }
+}
+
+impl KeyMap {
+    pub fn key(&self, player: usize, button_idx: usize) -> Key {
+        self.bindings[player][button_idx]
+    }
+
+    pub fn bind(&mut self, player: usize, button_idx: usize, key: Key) {
+        self.bindings[player][button_idx] = key;
+    }
+
+    /// Iterate over the buttons of a player together with their bound keys.
+    pub fn player_bindings(&self, player: usize) -> impl Iterator<Item = (NesButton, Key)> + '_ {
+        BUTTONS
+            .iter()
+            .zip(self.bindings[player].iter())
+            .map(|((button, _), key)| (*button, *key))
+    }
+
+    /// Keys that are bound to more than one button.
+    pub fn conflicts(&self) -> Vec<Key> {
+        let mut counts = HashMap::new();
+        for key in self.bindings.iter().flatten() {
+            *counts.entry(*key).or_insert(0) += 1;
+        }
+
+        BINDABLE_KEYS
+            .into_iter()
+            .filter(|key| counts.get(key).copied().unwrap_or(0) > 1)
+            .collect()
+    }
+
+    fn config_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("nessu").join(CONFIG_FILE_NAME))
+    }
+
+    /// Load the key map from the config file, falling back to the defaults for
+    /// anything that is missing or invalid.
+    pub fn load() -> Self {
+        let mut keymap = Self::default();
+
+        let config = Self::config_path()
+            .and_then(|path| read_to_string(path).ok())
+            .and_then(|json| serde_json::from_str::<KeyMapConfig>(&json).ok());
+
+        if let Some(config) = config {
+            for (player, buttons) in config.players.iter().take(PLAYERS).enumerate() {
+                for (button_idx, (_, button_name)) in BUTTONS.iter().enumerate() {
+                    if let Some(key) = buttons.get(*button_name).and_then(|k| key_from_name(k)) {
+                        keymap.bind(player, button_idx, key);
+                    }
+                }
+            }
+        }
+
+        keymap
+    }
+
+    pub fn save(&self) {
+        let path = match Self::config_path() {
+            Some(path) => path,
+            None => return,
+        };
+
+        let config = KeyMapConfig {
+            players: (0..PLAYERS)
+                .map(|player| {
+                    BUTTONS
+                        .iter()
+                        .enumerate()
+                        .map(|(button_idx, (_, name))| {
+                            (name.to_string(), key_name(self.key(player, button_idx)))
+                        })
+                        .collect()
+                })
+                .collect(),
+        };
+
+        let result = path
+            .parent()
+            .map_or(Ok(()), create_dir_all)
+            .and_then(|_| write(&path, serde_json::to_string_pretty(&config).unwrap()));
+
+        if let Err(e) = result {
+            eprintln!("Failed to write key map {}: {}", path.display(), e);
+        }
+    }
+}
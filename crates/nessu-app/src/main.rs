@@ -1,27 +1,63 @@
 #![deny(clippy::all)]
 
-use std::fs::read;
+mod archive;
+mod audio;
+mod keymap;
+mod rewind;
+mod settings;
+
+use std::fs::{create_dir_all, read, read_to_string, write, File};
+use std::io::{self, BufReader, ErrorKind};
 use std::ops::Add;
+use std::path::{Path, PathBuf};
 use std::process::exit;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, UNIX_EPOCH};
 
 use eframe::epaint::TextureHandle;
 use eframe::{self, egui, CreationContext, Frame, NativeOptions, Theme};
 use egui::panel::{Side, TopBottomSide};
-use egui::{vec2, Color32, Context, CursorIcon, Event, Id, Key, Label, Sense, WidgetText};
+use egui::plot::{Line, Plot, PlotPoints};
+use egui::{
+    vec2, Align2, Color32, Context, CursorIcon, Event, Id, Key, Label, Rect, RichText, Sense,
+    Stroke, WidgetText,
+};
 use egui::{Ui, Widget};
 use log::debug;
 
+use nessu_lib::apu::Channel;
+use nessu_lib::breakpoint::{Breakpoint, Expr};
 use nessu_lib::cartridge::Cartridge;
-use nessu_lib::input::Button as NesButton;
+use nessu_lib::movie::{parse_movie, write_movie};
 use nessu_lib::nes::Nes;
-use nessu_lib::op::{to_asm, CpuOpEntry, OpKind};
-
+use nessu_lib::ntsc::NTSC_WIDTH;
+use nessu_lib::op::{label_name, CpuOpEntry};
+use nessu_lib::ppu::load_palette_from_bytes;
+use nessu_lib::ram_search::{Comparison, RamSearch};
+use nessu_lib::region::Region;
+use nessu_lib::symbols::Symbols;
+
+use crate::audio::AudioOutput;
 use crate::egui::{ColorImage, TextureFilter, Vec2};
+use crate::keymap::{is_bindable, key_name, KeyMap, BUTTONS, PLAYERS};
+use crate::rewind::RewindBuffer;
+use crate::settings::{Settings, DISPLAY_SCALES};
 
 const NES_DISPLAY_SIZE: [usize; 2] = [256, 240];
 const APP_NAME: &str = "NESsu";
 
+/// Frames emulated per update while fast-forwarding, to skip most of the rendering overhead
+const FAST_FORWARD_FRAMES_PER_UPDATE: usize = 4;
+
+/// The frame limiter sleeps until this close to the deadline and spins for the rest,
+/// since sleeps may overshoot by about a millisecond
+const FRAME_LIMITER_SPIN_TIME: Duration = Duration::from_millis(1);
+
+/// How often the speed multiplier is recalculated
+const SPEED_MEASUREMENT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Memory the rewind snapshots may take before the oldest ones are dropped
+const REWIND_MAX_BYTES: usize = 64 * 1024 * 1024;
+
 fn main() {
     #[cfg(feature = "logging")]
     pretty_env_logger::formatted_timed_builder()
@@ -34,31 +70,123 @@ fn main() {
         NativeOptions {
             initial_window_size: Some(Vec2::new(1600.0, 800.0)),
             default_theme: Theme::Dark,
+            // Required by FramePacing::VSync
+            vsync: true,
             ..Default::default()
         },
         Box::new(|cc| Box::new(App::new(cc))),
     );
 }
 
+/// Parse a hexadecimal address, optionally prefixed with `$`.
+fn parse_hex_addr(text: &str) -> Option<u16> {
+    u16::from_str_radix(text.trim().trim_start_matches('$'), 16).ok()
+}
+
+/// Block until `deadline` without pinning a CPU core for the whole wait.
+fn wait_until(deadline: Instant) {
+    let now = Instant::now();
+    if deadline > now + FRAME_LIMITER_SPIN_TIME {
+        std::thread::sleep(deadline - now - FRAME_LIMITER_SPIN_TIME);
+    }
+
+    while Instant::now() < deadline {
+        std::hint::spin_loop();
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum FramePacing {
+    /// Emulate one frame per `target_ft` using the frame limiter
+    FixedStep,
+    /// Emulate one frame per display refresh. Smoothest on 60 Hz displays, but the
+    /// emulation speed follows the refresh rate.
+    VSync,
+}
+
+fn frame_time(region: Region) -> Duration {
+    Duration::from_secs_f64(1.0 / region.frame_rate())
+}
+
 struct App {
     nes: Nes,
     running: bool,
 
     show_ppu_window: bool,
     show_cpu_window: bool,
+    show_controls_window: bool,
+    show_palette_window: bool,
+    show_sprites_window: bool,
+    show_rom_info_window: bool,
+    show_audio_channels_window: bool,
+    /// Channels plotted in the audio channels window, indexed by [`Channel`]
+    audio_channels_shown: [bool; 5],
+    show_memory_window: bool,
+    show_call_stack_window: bool,
+    show_ram_search_window: bool,
+    ram_search: Option<RamSearch>,
+    ram_search_value_input: String,
+    show_game_genie_window: bool,
+    game_genie_input: String,
+    data_breakpoint_input: String,
+    breakpoint_condition_input: String,
+
+    memory_jump_input: String,
+    memory_scroll_to: Option<u16>,
+    /// Address and text of the byte being edited in the memory window
+    memory_edit: Option<(u16, String)>,
     stop_execution_on_error: bool,
+    /// Use the mouse over the display as a Zapper on the second port
+    zapper_enabled: bool,
+    pause_on_focus_loss: bool,
+    /// Emulation was paused when the window lost focus, and is resumed when it gets it back
+    paused_by_focus_loss: bool,
 
     update_scroll: bool,
+    disassembly_labels: bool,
+    symbols: Symbols,
 
     last_ft: Duration,
 
     display_texture: TextureHandle,
     nametable_textures: [TextureHandle; 4],
+    sprite_textures: Vec<TextureHandle>,
 
     next_frame_time: Instant,
     target_ft: Option<Duration>,
+    frame_pacing: FramePacing,
+    /// Frame time limit to restore when fast-forward is released
+    fast_forward_saved_ft: Option<Option<Duration>>,
+
+    /// Emulated frames since `speed_measurement_start`
+    speed_measurement_frames: u32,
+    speed_measurement_start: Instant,
+    /// CPU cycle count at `speed_measurement_start`
+    speed_measurement_cycles: u128,
+    /// Emulated CPU cycles per wall-clock second
+    cycles_per_second: f64,
+    /// Emulation speed relative to the region's frame rate
+    speed: f64,
 
     loaded_cart_filename: Option<String>,
+    loaded_cart_path: Option<PathBuf>,
+
+    audio: Option<AudioOutput>,
+    audio_muted: bool,
+    audio_volume: f32,
+
+    rewind: RewindBuffer,
+    rewinding: bool,
+
+    settings: Settings,
+    /// Message shown in the error window until it is dismissed
+    error_message: Option<String>,
+    /// Zip archive with several ROMs to pick from, and the names of the ROMs
+    zip_entries: Option<(PathBuf, Vec<String>)>,
+
+    keymap: KeyMap,
+    /// Player and button index waiting for a key press
+    rebinding: Option<(usize, usize)>,
 }
 
 impl eframe::App for App {
@@ -67,6 +195,11 @@ impl eframe::App for App {
 
         ctx.request_repaint();
     }
+
+    fn on_close_event(&mut self) -> bool {
+        self.write_save_file();
+        true
+    }
 }
 
 impl App {
@@ -76,6 +209,11 @@ impl App {
             Cartridge::from_bytes(include_bytes!("../../../roms/snow.nes")).unwrap(),
         );
 
+        let audio = AudioOutput::new();
+        if let Some(audio) = audio.as_ref() {
+            nes.set_audio_sample_rate(audio.sample_rate());
+        }
+
         let display_texture = cc.egui_ctx.load_texture(
             "display",
             ColorImage::new(NES_DISPLAY_SIZE, Color32::BLACK),
@@ -105,19 +243,71 @@ impl App {
             ),
         ];
 
+        let sprite_textures = (0..64)
+            .map(|i| {
+                cc.egui_ctx.load_texture(
+                    format!("sprite{}", i),
+                    ColorImage::new([8, 16], Color32::TRANSPARENT),
+                    TextureFilter::Nearest,
+                )
+            })
+            .collect();
+
         Self {
             nes,
             running: true,
             show_ppu_window: false,
             show_cpu_window: true,
+            show_controls_window: false,
+            show_palette_window: false,
+            show_sprites_window: false,
+            show_rom_info_window: false,
+            show_audio_channels_window: false,
+            audio_channels_shown: [true; 5],
+            show_memory_window: false,
+            show_call_stack_window: false,
+            show_ram_search_window: false,
+            ram_search: None,
+            ram_search_value_input: String::new(),
+            show_game_genie_window: false,
+            game_genie_input: String::new(),
+            data_breakpoint_input: String::new(),
+            breakpoint_condition_input: String::new(),
+            memory_jump_input: String::new(),
+            memory_scroll_to: None,
+            memory_edit: None,
+            zapper_enabled: false,
+            pause_on_focus_loss: false,
+            paused_by_focus_loss: false,
             stop_execution_on_error: true,
             last_ft: Duration::from_millis(0),
             display_texture,
             nametable_textures,
+            sprite_textures,
             next_frame_time: Instant::now(),
-            target_ft: Some(Duration::from_nanos(16639263)),
+            target_ft: Some(frame_time(Region::Ntsc)),
+            frame_pacing: FramePacing::FixedStep,
+            fast_forward_saved_ft: None,
+            speed_measurement_frames: 0,
+            speed_measurement_start: Instant::now(),
+            speed_measurement_cycles: 0,
+            cycles_per_second: 0.0,
+            speed: 1.0,
             update_scroll: true,
+            disassembly_labels: true,
+            symbols: Symbols::default(),
             loaded_cart_filename: None,
+            loaded_cart_path: None,
+            audio,
+            audio_muted: false,
+            audio_volume: 0.5,
+            rewind: RewindBuffer::new(10, 4, REWIND_MAX_BYTES),
+            rewinding: false,
+            settings: Settings::load(),
+            error_message: None,
+            zip_entries: None,
+            keymap: KeyMap::load(),
+            rebinding: None,
         }
     }
 
@@ -128,34 +318,83 @@ impl App {
 
         self.handle_dropped_file(ctx);
         self.handle_input(ctx);
+        self.handle_focus(ctx);
 
         if self.running {
-            while self.next_frame_time >= Instant::now() {}
+            let target_ft = match self.frame_pacing {
+                FramePacing::FixedStep => self.target_ft,
+                FramePacing::VSync => None,
+            };
+
+            if target_ft.is_some() {
+                wait_until(self.next_frame_time);
+            }
 
             let start_time = Instant::now();
-            self.next_frame_time = self
-                .target_ft
-                .map(|ft| start_time + ft)
-                .unwrap_or(start_time);
 
-            self.step_frame();
+            // Schedule from the previous deadline rather than the wake-up time so that
+            // oversleeping doesn't accumulate, unless we have fallen too far behind
+            self.next_frame_time = match target_ft {
+                Some(ft) if start_time < self.next_frame_time + ft => self.next_frame_time + ft,
+                Some(ft) => start_time + ft,
+                None => start_time,
+            };
+
+            if self.rewinding {
+                // discard audio of the restored frames
+                self.nes.drain_audio_samples();
+            } else {
+                let frames = if self.fast_forward_saved_ft.is_some() {
+                    FAST_FORWARD_FRAMES_PER_UPDATE
+                } else {
+                    1
+                };
+
+                for _ in 0..frames {
+                    self.step_frame();
+                    self.speed_measurement_frames += 1;
+                    if !self.running {
+                        break;
+                    }
+                }
+                self.queue_audio();
+            }
 
             self.last_ft = Instant::now().duration_since(start_time);
+            self.measure_speed();
         } else {
+            // discard audio produced while stepping
+            self.nes.drain_audio_samples();
+
             // finish any ongoing instruction
             while self.nes.cpu().instruction_ongoing() {
                 self.nes.clock().ok();
             }
 
-            let should_step = ctx
-                .input()
-                .events
-                .iter()
-                .any(|e| matches!(e, Event::Text(s) if s.as_str() == "."));
+            let (should_step, should_step_frame, advance_frames) = if ctx.wants_keyboard_input() {
+                // The keys go to a text field instead, e.g. F in a hex address
+                (false, false, false)
+            } else {
+                let input = ctx.input();
+                let typed = |text: &str| {
+                    input
+                        .events
+                        .iter()
+                        .any(|e| matches!(e, Event::Text(s) if s.as_str() == text))
+                };
+                (typed("."), typed("["), input.key_down(Key::F))
+            };
 
             if should_step {
                 self.step_instruction();
             }
+
+            // Holding the key advances one frame per emulated frame time instead of per repaint
+            let now = Instant::now();
+            if should_step_frame || (advance_frames && now >= self.next_frame_time) {
+                self.next_frame_time = now + frame_time(self.nes.region());
+                self.step_frame();
+            }
         }
 
         self.topbar(ctx);
@@ -165,18 +404,318 @@ impl App {
         self.ppu_window(ctx);
         self.cpu_window(ctx);
         self.options_window(ctx);
+        self.controls_window(ctx);
+        self.palette_window(ctx);
+        self.sprites_window(ctx);
+        self.rom_info_window(ctx);
+        self.audio_channels_window(ctx);
+        self.memory_window(ctx);
+        self.call_stack_window(ctx);
+        self.ram_search_window(ctx);
+        self.game_genie_window(ctx);
+        self.zip_entries_window(ctx);
+        self.error_window(ctx);
     }
 
-    fn load_cartridge(&mut self, name: &str, cartridge: Cartridge) {
-        self.loaded_cart_filename = Some(name.to_string());
+    fn load_cartridge(&mut self, path: &Path, mut cartridge: Cartridge) {
+        self.write_save_file();
+
+        let save_path = archive::save_path(path);
+        if let Ok(data) = read(&save_path) {
+            if !cartridge.load_ram(&data) {
+                eprintln!("Ignoring save file {}", save_path.display());
+            }
+        }
+
+        self.loaded_cart_filename = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string());
+        self.loaded_cart_path = Some(path.to_path_buf());
         self.nes.insert_cartridge(cartridge);
+        self.settings.add_recent_rom(path);
+        self.settings.save();
+        self.set_region(self.nes.region());
+        self.rewind.clear();
+        self.symbols = Symbols::default();
+        self.ram_search = None;
         self.update_scroll = true;
+
+        if let Some(audio) = self.audio.as_ref() {
+            audio.clear();
+        }
+    }
+
+    /// Write battery-backed RAM of the loaded cartridge next to the ROM file.
+    fn write_save_file(&self) {
+        if let (Some(path), Some(ram)) = (self.loaded_cart_path.as_ref(), self.nes.save_ram()) {
+            let save_path = archive::save_path(path);
+            if let Err(e) = write(&save_path, ram) {
+                eprintln!("Failed to write save file {}: {}", save_path.display(), e);
+            }
+        }
+    }
+
+    fn screenshot_dir() -> PathBuf {
+        dirs::picture_dir()
+            .map(|dir| dir.join("nessu"))
+            .unwrap_or_else(|| PathBuf::from("screenshots"))
+    }
+
+    fn save_screenshot(&self) {
+        let dir = Self::screenshot_dir();
+        let name = self
+            .loaded_cart_path
+            .as_ref()
+            .and_then(|path| path.file_stem())
+            .map_or_else(|| "nessu".into(), |stem| stem.to_string_lossy());
+        let timestamp = UNIX_EPOCH.elapsed().unwrap_or_default().as_millis();
+        let path = dir.join(format!("{}-{}.png", name, timestamp));
+
+        let result = create_dir_all(&dir).and_then(|_| self.nes.save_screenshot(&path));
+
+        match result {
+            Ok(_) => println!("Saved screenshot {}", path.display()),
+            Err(e) => eprintln!("Failed to save screenshot {}: {}", path.display(), e),
+        }
+    }
+
+    fn save_movie(&mut self) {
+        let frames = self.nes.stop_recording();
+
+        let path = rfd::FileDialog::new()
+            .add_filter("Movie", &["fm2"])
+            .save_file();
+
+        if let Some(path) = path {
+            if let Err(e) = write(&path, write_movie(&frames)) {
+                eprintln!("Failed to write movie {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    fn play_movie(&mut self) {
+        let path = rfd::FileDialog::new()
+            .add_filter("Movie", &["fm2"])
+            .pick_file();
+
+        if let Some(path) = path {
+            match read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|text| parse_movie(&text))
+            {
+                Ok(frames) => {
+                    self.nes.play_movie(frames);
+                    self.rewind.clear();
+                }
+                Err(e) => eprintln!("Failed to load movie {}: {}", path.display(), e),
+            }
+        }
+    }
+
+    fn open_rom(&mut self) {
+        let path = rfd::FileDialog::new()
+            .add_filter("NES ROM", &["nes", "fds", "zip"])
+            .pick_file();
+
+        if let Some(path) = path {
+            self.load_rom_file(&path);
+        }
+    }
+
+    fn load_rom_file(&mut self, path: &Path) {
+        // A ROM inside a zip archive, from the recent ROMs
+        if let Some((archive, name)) = archive::split_entry_path(path) {
+            self.load_zip_entry(archive, &name);
+            return;
+        }
+
+        if archive::is_zip(path) {
+            match archive::nes_entries(path) {
+                Ok(entries) if entries.is_empty() => {
+                    self.error_message = Some(format!("No .nes files found in {}", path.display()));
+                }
+                Ok(entries) if entries.len() == 1 => self.load_zip_entry(path, &entries[0]),
+                Ok(entries) => self.zip_entries = Some((path.to_path_buf(), entries)),
+                Err(e) => self.show_load_error(path, &e),
+            }
+            return;
+        }
+
+        if path.extension().and_then(|ext| ext.to_str()) == Some("fds") {
+            self.load_fds_file(path);
+            return;
+        }
+
+        match File::open(path).and_then(|file| Cartridge::from_reader(BufReader::new(file))) {
+            Ok(cartridge) => self.load_cartridge(path, cartridge),
+            Err(e) => self.show_load_error(path, &e),
+        }
+    }
+
+    /// Load a Famicom Disk System image. The BIOS is looked up from the settings, then as
+    /// `disksys.rom` next to the image, and finally asked from the user.
+    fn load_fds_file(&mut self, path: &Path) {
+        let bios_path = self
+            .settings
+            .fds_bios_path
+            .clone()
+            .filter(|bios_path| bios_path.exists())
+            .or_else(|| Some(path.with_file_name("disksys.rom")).filter(|p| p.exists()))
+            .or_else(|| {
+                rfd::FileDialog::new()
+                    .set_title("Select the FDS BIOS (disksys.rom)")
+                    .add_filter("FDS BIOS", &["rom", "bin"])
+                    .pick_file()
+            });
+
+        let bios_path = match bios_path {
+            Some(bios_path) => bios_path,
+            None => {
+                self.error_message = Some(format!(
+                    "Failed to load {}: the FDS BIOS is required",
+                    path.display()
+                ));
+                return;
+            }
+        };
+
+        let bios = match read(&bios_path) {
+            Ok(bios) => bios,
+            Err(e) => {
+                self.show_load_error(&bios_path, &e);
+                return;
+            }
+        };
+
+        match read(path).and_then(|disk| Cartridge::from_fds(&disk, &bios)) {
+            Ok(cartridge) => {
+                self.settings.fds_bios_path = Some(bios_path);
+                self.load_cartridge(path, cartridge);
+            }
+            Err(e) => self.show_load_error(path, &e),
+        }
+    }
+
+    /// Load a ROM from a zip archive. The ROM is remembered by its [`archive::entry_path`],
+    /// which also gives it a save file of its own.
+    fn load_zip_entry(&mut self, path: &Path, name: &str) {
+        let entry_path = archive::entry_path(path, name);
+        match archive::load_entry(path, name) {
+            Ok(cartridge) => self.load_cartridge(&entry_path, cartridge),
+            Err(e) => self.show_load_error(&entry_path, &e),
+        }
+    }
+
+    fn show_load_error(&mut self, path: &Path, e: &io::Error) {
+        self.error_message = Some(format!(
+            "Failed to load {}: {}",
+            path.display(),
+            describe_load_error(e)
+        ));
+    }
+
+    fn load_palette(&mut self) {
+        let path = rfd::FileDialog::new()
+            .add_filter("Palette", &["pal"])
+            .pick_file();
+
+        if let Some(path) = path {
+            match read(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|bytes| load_palette_from_bytes(&bytes))
+            {
+                Ok(palette) => self.nes.ppu_mut().set_palette(palette),
+                Err(e) => eprintln!("Failed to load palette {}: {}", path.display(), e),
+            }
+        }
+    }
+
+    fn load_symbols(&mut self) {
+        let path = rfd::FileDialog::new()
+            .add_filter("Labels", &["mlb", "nl"])
+            .pick_file();
+
+        if let Some(path) = path {
+            match read_to_string(&path) {
+                Ok(text) if path.extension().and_then(|ext| ext.to_str()) == Some("nl") => {
+                    self.symbols = Symbols::parse_nl(&text)
+                }
+                Ok(text) => {
+                    let prg_rom_size = self.nes.cartridge().info().prg_rom_kb * 1024;
+                    self.symbols = Symbols::parse_mlb(&text, prg_rom_size)
+                }
+                Err(e) => {
+                    self.error_message =
+                        Some(format!("Failed to load labels {}: {}", path.display(), e))
+                }
+            }
+        }
     }
 
     fn file_menu(&mut self, ui: &mut Ui) {
         ui.menu_button("File", |ui| {
+            if ui.button("Open ROM...").clicked() {
+                self.open_rom();
+                ui.close_menu();
+            }
+
+            let recent_roms = self.settings.recent_roms.clone();
+            ui.add_enabled_ui(!recent_roms.is_empty(), |ui| {
+                ui.menu_button("Recent ROMs", |ui| {
+                    for path in recent_roms {
+                        let name = path.file_name().unwrap_or_default().to_string_lossy();
+                        if ui
+                            .button(name)
+                            .on_hover_text(path.display().to_string())
+                            .clicked()
+                        {
+                            self.load_rom_file(&path);
+                            ui.close_menu();
+                        }
+                    }
+                });
+            });
+
+            let side_count = self.nes.cartridge().disk_side_count();
+            if side_count > 0 {
+                ui.menu_button("Disk", |ui| {
+                    let inserted = self.nes.cartridge().inserted_disk_side();
+
+                    if ui
+                        .add_enabled(inserted.is_some(), egui::Button::new("Eject"))
+                        .clicked()
+                    {
+                        self.nes.cartridge_mut().insert_disk_side(None);
+                        ui.close_menu();
+                    }
+
+                    for side in 0..side_count {
+                        let name = format!(
+                            "Disk {} side {}",
+                            side / 2 + 1,
+                            if side % 2 == 0 { 'A' } else { 'B' }
+                        );
+
+                        if ui.radio(inserted == Some(side), name).clicked() {
+                            self.nes.cartridge_mut().insert_disk_side(Some(side));
+                            ui.close_menu();
+                        }
+                    }
+                });
+            }
+
+            ui.separator();
+
             if ui.button("Reset").clicked() {
-                self.nes.reset();
+                self.write_save_file();
+
+                // Reset on a frame boundary so that the movie can replay it
+                if self.nes.is_recording() {
+                    self.nes.reset_at_next_frame();
+                } else {
+                    self.nes.reset();
+                }
+
                 ui.close_menu();
             }
 
@@ -185,7 +724,29 @@ impl App {
                 ui.close_menu();
             }
 
+            if ui.button("Load palette...").clicked() {
+                self.load_palette();
+                ui.close_menu();
+            }
+
+            if self.nes.is_recording() {
+                if ui.button("Stop recording...").clicked() {
+                    self.save_movie();
+                    ui.close_menu();
+                }
+            } else if ui.button("Record movie").clicked() {
+                self.nes.start_recording();
+                self.rewind.clear();
+                ui.close_menu();
+            }
+
+            if ui.button("Play movie...").clicked() {
+                self.play_movie();
+                ui.close_menu();
+            }
+
             if ui.button("Quit").clicked() {
+                self.write_save_file();
                 exit(0);
             }
         });
@@ -202,6 +763,80 @@ impl App {
                 self.show_ppu_window = !self.show_ppu_window;
                 ui.close_menu();
             }
+
+            if egui::Button::new("Controls").wrap(true).ui(ui).clicked() {
+                self.show_controls_window = !self.show_controls_window;
+                ui.close_menu();
+            }
+
+            if egui::Button::new("Palette").wrap(true).ui(ui).clicked() {
+                self.show_palette_window = !self.show_palette_window;
+                ui.close_menu();
+            }
+
+            if egui::Button::new("Sprites").wrap(true).ui(ui).clicked() {
+                self.show_sprites_window = !self.show_sprites_window;
+                ui.close_menu();
+            }
+
+            if egui::Button::new("ROM info").wrap(true).ui(ui).clicked() {
+                self.show_rom_info_window = !self.show_rom_info_window;
+                ui.close_menu();
+            }
+
+            if egui::Button::new("Audio channels")
+                .wrap(true)
+                .ui(ui)
+                .clicked()
+            {
+                self.show_audio_channels_window = !self.show_audio_channels_window;
+                ui.close_menu();
+            }
+
+            if egui::Button::new("Memory").wrap(true).ui(ui).clicked() {
+                self.show_memory_window = !self.show_memory_window;
+                ui.close_menu();
+            }
+
+            if egui::Button::new("Call stack").wrap(true).ui(ui).clicked() {
+                self.show_call_stack_window = !self.show_call_stack_window;
+                ui.close_menu();
+            }
+
+            if egui::Button::new("RAM search").wrap(true).ui(ui).clicked() {
+                self.show_ram_search_window = !self.show_ram_search_window;
+                ui.close_menu();
+            }
+
+            if egui::Button::new("Game Genie").wrap(true).ui(ui).clicked() {
+                self.show_game_genie_window = !self.show_game_genie_window;
+                ui.close_menu();
+            }
+
+            ui.separator();
+
+            let settings = self.settings.clone();
+
+            ui.menu_button("Display scale", |ui| {
+                for scale in DISPLAY_SCALES {
+                    ui.radio_value(
+                        &mut self.settings.display_scale,
+                        scale,
+                        format!("{}x", scale),
+                    );
+                }
+            });
+
+            ui.checkbox(
+                &mut self.settings.aspect_ratio_correction,
+                "Aspect ratio correction (8:7)",
+            );
+            ui.checkbox(&mut self.settings.linear_filter, "Linear filtering");
+            ui.checkbox(&mut self.settings.ntsc_filter, "NTSC filter");
+
+            if self.settings != settings {
+                self.settings.save();
+            }
         });
     }
 
@@ -222,7 +857,24 @@ impl App {
                             ui.label(format!("{}", self.nes.ppu().current_scanline()));
                             ui.end_row();
 
-                            ui.label("Scroll X:");
+                            ui.label("Frame:");
+                            ui.label(format!("{}", self.nes.frame_count()));
+                            ui.end_row();
+
+                            ui.label("CPU/PPU phase:");
+                            ui.label(format!("{}", self.nes.cpu_ppu_phase()));
+                            ui.end_row();
+
+                            let (scroll_x, scroll_y) = self.nes.ppu().scroll_position();
+                            ui.label("Scroll:");
+                            ui.label(format!("{}, {}", scroll_x, scroll_y));
+                            ui.end_row();
+
+                            ui.label("Nametable:");
+                            ui.label(format!("{}", self.nes.ppu().base_nametable()));
+                            ui.end_row();
+
+                            ui.label("Fine X:");
                             ui.label(format!("{}", self.nes.ppu().pixel_x));
                             ui.end_row();
 
@@ -291,21 +943,143 @@ impl App {
                                         self.nes.cpu().prev_op_cycles
                                     ));
                                     ui.end_row();
+
+                                    ui.label("Clock rate:");
+                                    if self.running {
+                                        let target = self.nes.region().cpu_clock_rate();
+                                        ui.label(format!(
+                                            "{:.3} MHz ({:.0}%)",
+                                            self.cycles_per_second / 1_000_000.0,
+                                            self.cycles_per_second / target * 100.0
+                                        ));
+                                    } else {
+                                        ui.label("-");
+                                    }
+                                    ui.end_row();
+
+                                    if self.nes.cpu().is_halted() {
+                                        ui.label("Status:");
+                                        ui.label("CPU jammed");
+                                        ui.end_row();
+                                    }
                                 });
 
                             if ui.button("Step instruction").clicked() {
                                 self.step_instruction();
                             }
 
+                            if ui.button("Step over").clicked() {
+                                self.step_over();
+                            }
+
                             if ui.button("Step frame").clicked() {
                                 self.step_frame();
                             }
+
+                            ui.separator();
+                            self.vectors(ui);
+
+                            ui.separator();
+                            self.data_breakpoints(ui);
                         });
                     });
             });
         self.show_cpu_window = show_cpu_window;
     }
 
+    fn vectors(&mut self, ui: &mut Ui) {
+        let (nmi, reset, irq) = self.nes.vectors();
+
+        egui::Grid::new("vectors")
+            .striped(false)
+            .num_columns(3)
+            .show(ui, |ui| {
+                for (name, addr) in [("NMI:", nmi), ("RESET:", reset), ("IRQ/BRK:", irq)] {
+                    ui.label(name);
+                    ui.label(format!("${:04X}", addr));
+
+                    let mut breakpoint = self.nes.cpu().is_breakpoint(addr);
+                    if ui.checkbox(&mut breakpoint, "Break").changed() {
+                        self.nes.cpu_mut().toggle_breakpoint(addr);
+                    }
+                    ui.end_row();
+                }
+            });
+    }
+
+    /// Text field for setting a breakpoint that only stops when the condition is true
+    fn breakpoint_condition_menu(&mut self, ui: &mut Ui, addr: u16) {
+        ui.label("Break if:");
+        ui.horizontal(|ui| {
+            let response = ui.add(
+                egui::TextEdit::singleline(&mut self.breakpoint_condition_input)
+                    .hint_text("A == $10")
+                    .desired_width(120.0),
+            );
+
+            if ui.button("Set").clicked()
+                || (response.lost_focus() && ui.input().key_pressed(Key::Enter))
+            {
+                match Expr::parse(&self.breakpoint_condition_input) {
+                    Ok(condition) => {
+                        self.nes
+                            .cpu_mut()
+                            .set_conditional_breakpoint(addr, condition);
+                        ui.close_menu();
+                    }
+                    Err(e) => self.error_message = Some(format!("Invalid condition: {}", e)),
+                }
+            }
+        });
+    }
+
+    fn data_breakpoints(&mut self, ui: &mut Ui) {
+        ui.label("Data breakpoints:");
+
+        ui.horizontal(|ui| {
+            ui.add(egui::TextEdit::singleline(&mut self.data_breakpoint_input).desired_width(40.0));
+
+            let addr = parse_hex_addr(&self.data_breakpoint_input);
+
+            if ui.button("R").on_hover_text("Break on read").clicked() {
+                if let Some(addr) = addr {
+                    self.nes.cpu_mut().set_read_breakpoint(addr);
+                }
+            }
+
+            if ui.button("W").on_hover_text("Break on write").clicked() {
+                if let Some(addr) = addr {
+                    self.nes.cpu_mut().set_write_breakpoint(addr);
+                }
+            }
+        });
+
+        let mut read_breakpoints: Vec<u16> = self.nes.cpu().read_breakpoints().collect();
+        let mut write_breakpoints: Vec<u16> = self.nes.cpu().write_breakpoints().collect();
+        read_breakpoints.sort_unstable();
+        write_breakpoints.sort_unstable();
+
+        for addr in read_breakpoints {
+            if ui
+                .button(format!("R ${:04X}", addr))
+                .on_hover_text("Click to remove")
+                .clicked()
+            {
+                self.nes.cpu_mut().clear_read_breakpoint(addr);
+            }
+        }
+
+        for addr in write_breakpoints {
+            if ui
+                .button(format!("W ${:04X}", addr))
+                .on_hover_text("Click to remove")
+                .clicked()
+            {
+                self.nes.cpu_mut().clear_write_breakpoint(addr);
+            }
+        }
+    }
+
     fn options_window(&mut self, ctx: &Context) {
         egui::Window::new("Options").show(ctx, |ui| {
             egui::Grid::new("options_grid")
@@ -324,40 +1098,273 @@ impl App {
                     );
                     ui.end_row();
 
-                    ui.checkbox(&mut self.stop_execution_on_error, "Stop execution on error");
+                    ui.checkbox(
+                        &mut self.nes.ppu_mut().sprite_limit_disabled,
+                        "Remove sprite limit",
+                    );
                     ui.end_row();
-                });
-        });
-    }
 
-    fn step_instruction(&mut self) {
-        if let Err(e) = self.nes.step_instruction() {
-            eprintln!("{}", e);
-            if self.stop_execution_on_error {
-                self.running = false;
-            }
-        }
-        self.update_scroll = true;
-    }
+                    let apu = self.nes.apu_mut();
+                    for (enabled, label) in [
+                        (&mut apu.pulse1_enabled_by_user, "Pulse 1 enabled"),
+                        (&mut apu.pulse2_enabled_by_user, "Pulse 2 enabled"),
+                        (&mut apu.triangle_enabled_by_user, "Triangle enabled"),
+                        (&mut apu.noise_enabled_by_user, "Noise enabled"),
+                        (&mut apu.dmc_enabled_by_user, "DMC enabled"),
+                    ] {
+                        ui.checkbox(enabled, label);
+                        ui.end_row();
+                    }
 
-    fn step_frame(&mut self) {
-        if let Err(e) = self.nes.step_frame() {
-            eprintln!("{}", e);
-            if self.stop_execution_on_error {
-                self.running = false;
-            }
-        }
-        self.update_scroll = true;
-    }
+                    ui.checkbox(&mut self.stop_execution_on_error, "Stop execution on error");
+                    ui.end_row();
 
-    fn disassembly(&mut self, ui: &mut Ui) {
-        let text_style = egui::TextStyle::Body;
-        let row_height = ui.text_style_height(&text_style);
+                    ui.checkbox(
+                        &mut self.pause_on_focus_loss,
+                        "Pause when the window loses focus",
+                    );
+                    ui.end_row();
 
-        let mut scrollarea = egui::ScrollArea::vertical();
-        let disassembly = self.nes.cpu_disassembly();
+                    if ui
+                        .checkbox(&mut self.zapper_enabled, "Zapper on port 2")
+                        .on_hover_text("Aim with the mouse and shoot with the left button")
+                        .changed()
+                        && !self.zapper_enabled
+                    {
+                        self.nes.disconnect_zapper();
+                    }
+                    ui.end_row();
 
-        if self.update_scroll {
+                    let mut deterministic = self.nes.is_deterministic();
+                    if ui
+                        .checkbox(&mut deterministic, "Zero RAM on power-on")
+                        .changed()
+                    {
+                        self.nes.set_deterministic(deterministic);
+                    }
+                    ui.end_row();
+
+                    let mut region = self.nes.region();
+                    egui::ComboBox::from_label("Region")
+                        .selected_text(format!("{:?}", region))
+                        .show_ui(ui, |ui| {
+                            for r in [Region::Ntsc, Region::Pal, Region::Dendy] {
+                                ui.selectable_value(&mut region, r, format!("{:?}", r));
+                            }
+                        });
+                    if region != self.nes.region() {
+                        self.set_region(region);
+                    }
+                    ui.end_row();
+
+                    egui::ComboBox::from_label("Frame pacing")
+                        .selected_text(format!("{:?}", self.frame_pacing))
+                        .show_ui(ui, |ui| {
+                            for pacing in [FramePacing::FixedStep, FramePacing::VSync] {
+                                ui.selectable_value(
+                                    &mut self.frame_pacing,
+                                    pacing,
+                                    format!("{:?}", pacing),
+                                );
+                            }
+                        });
+                    ui.end_row();
+
+                    ui.checkbox(&mut self.audio_muted, "Mute audio");
+                    ui.end_row();
+
+                    ui.add(egui::Slider::new(&mut self.audio_volume, 0.0..=1.0).text("Volume"));
+                    ui.end_row();
+
+                    ui.add(
+                        egui::Slider::new(&mut self.rewind.seconds, 1..=60).text("Rewind seconds"),
+                    );
+                    ui.end_row();
+
+                    ui.add(
+                        egui::Slider::new(&mut self.rewind.interval, 1..=60)
+                            .text("Rewind snapshot interval (frames)"),
+                    );
+                    ui.end_row();
+                });
+        });
+    }
+
+    fn set_region(&mut self, region: Region) {
+        self.nes.set_region(region);
+
+        let target_ft = match self.fast_forward_saved_ft.as_mut() {
+            Some(saved_ft) => saved_ft,
+            None => &mut self.target_ft,
+        };
+
+        if let Some(ft) = target_ft.as_mut() {
+            *ft = frame_time(region);
+        }
+    }
+
+    /// Removes the frame time limit while fast-forwarding and restores it afterwards
+    fn set_fast_forward(&mut self, fast_forward: bool) {
+        match (fast_forward, self.fast_forward_saved_ft) {
+            (true, None) => self.fast_forward_saved_ft = Some(self.target_ft.take()),
+            (false, Some(saved_ft)) => {
+                self.target_ft = saved_ft;
+                self.fast_forward_saved_ft = None;
+                self.next_frame_time = Instant::now();
+            }
+            _ => {}
+        }
+    }
+
+    fn measure_speed(&mut self) {
+        let elapsed = self.speed_measurement_start.elapsed();
+        if elapsed < SPEED_MEASUREMENT_INTERVAL {
+            return;
+        }
+
+        let emulated = frame_time(self.nes.region()) * self.speed_measurement_frames;
+        self.speed = emulated.as_secs_f64() / elapsed.as_secs_f64();
+
+        // The cycle count goes backwards when rewinding or loading a state
+        let cycles = self.nes.cpu().cycles;
+        let emulated_cycles = cycles.saturating_sub(self.speed_measurement_cycles);
+        self.cycles_per_second = emulated_cycles as f64 / elapsed.as_secs_f64();
+
+        self.speed_measurement_frames = 0;
+        self.speed_measurement_start = Instant::now();
+        self.speed_measurement_cycles = cycles;
+    }
+
+    fn controls_window(&mut self, ctx: &Context) {
+        let conflicts = self.keymap.conflicts();
+
+        egui::Window::new("Controls")
+            .open(&mut self.show_controls_window)
+            .show(ctx, |ui| {
+                egui::Grid::new("controls_grid")
+                    .striped(true)
+                    .num_columns(PLAYERS + 1)
+                    .show(ui, |ui| {
+                        ui.label("");
+                        for player in 0..PLAYERS {
+                            ui.label(format!("Player {}", player + 1));
+                        }
+                        ui.end_row();
+
+                        for (button_idx, (_, button_name)) in BUTTONS.iter().enumerate() {
+                            ui.label(*button_name);
+
+                            for player in 0..PLAYERS {
+                                let key = self.keymap.key(player, button_idx);
+
+                                let text = if self.rebinding == Some((player, button_idx)) {
+                                    RichText::new("Press a key...")
+                                } else if conflicts.contains(&key) {
+                                    RichText::new(key_name(key)).color(Color32::RED)
+                                } else {
+                                    RichText::new(key_name(key))
+                                };
+
+                                if ui.button(text).clicked() {
+                                    self.rebinding = Some((player, button_idx));
+                                }
+                            }
+                            ui.end_row();
+                        }
+                    });
+
+                if !conflicts.is_empty() {
+                    let keys: Vec<String> = conflicts.into_iter().map(key_name).collect();
+                    ui.colored_label(
+                        Color32::RED,
+                        format!("Keys bound more than once: {}", keys.join(", ")),
+                    );
+                }
+            });
+
+        if !self.show_controls_window {
+            self.rebinding = None;
+        }
+    }
+
+    fn queue_audio(&mut self) {
+        let samples = self.nes.drain_audio_samples();
+
+        if let Some(audio) = self.audio.as_ref() {
+            let volume = if self.audio_muted {
+                0.0
+            } else {
+                self.audio_volume
+            };
+            audio.queue_samples(&samples, volume);
+        }
+    }
+
+    fn step_instruction(&mut self) {
+        if let Err(e) = self.nes.step_instruction() {
+            eprintln!("{}", e);
+            if self.stop_execution_on_error {
+                self.running = false;
+            }
+        }
+        self.update_scroll = true;
+    }
+
+    /// Steps over JSRs by running until the temporary breakpoint after them is hit, so that
+    /// the UI stays responsive even if the subroutine never returns.
+    fn step_over(&mut self) {
+        if self.nes.set_step_over_breakpoint() {
+            self.running = true;
+        } else {
+            self.step_instruction();
+        }
+    }
+
+    fn run_to(&mut self, addr: u16) {
+        self.nes.cpu_mut().set_temporary_breakpoint(addr);
+        self.running = true;
+    }
+
+    fn step_frame(&mut self) {
+        // Accuracy matters less than speed while fast-forwarding
+        let result = if self.fast_forward_saved_ft.is_some() {
+            self.nes.step_frame_fast()
+        } else {
+            self.nes.step_frame()
+        };
+
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            if self.stop_execution_on_error {
+                self.running = false;
+            }
+        }
+        self.rewind.capture(&self.nes);
+        self.update_scroll = true;
+    }
+
+    fn disassembly(&mut self, ui: &mut Ui) {
+        let text_style = egui::TextStyle::Body;
+        let row_height = ui.text_style_height(&text_style);
+
+        ui.horizontal(|ui| {
+            if ui
+                .checkbox(&mut self.disassembly_labels, "Labels")
+                .changed()
+            {
+                self.update_scroll = true;
+            }
+
+            if ui.button("Load labels...").clicked() {
+                self.load_symbols();
+            }
+        });
+
+        let mut scrollarea = egui::ScrollArea::vertical();
+        let labels = self.disassembly_labels;
+        let disassembly = self.nes.disassemble_range(0x0000, 0xFFFE, labels);
+
+        if self.update_scroll {
             self.update_scroll = false;
 
             let idx = disassembly
@@ -387,78 +1394,88 @@ impl App {
                     ui.expand_to_include_rect(ui.available_rect_before_wrap());
 
                     for op_idx in row_range {
+                        let op = disassembly[op_idx as usize];
                         let CpuOpEntry {
                             addr,
                             opcode,
                             size,
-                            kind,
-                            addr_mode,
                             operands,
-                        } = disassembly[op_idx as usize];
+                            ..
+                        } = op;
 
                         let active = addr == self.nes.cpu().pc;
 
                         ui.horizontal(|ui| {
-                            if self.nes.cpu().is_breakpoint(addr) {
+                            if let Some(breakpoint) = self.nes.cpu().breakpoint(addr) {
+                                // Conditional breakpoints are shown in orange
+                                let color = if breakpoint.condition.is_some() {
+                                    Color32::from_rgb(255, 150, 0)
+                                } else {
+                                    Color32::RED
+                                };
+
                                 ui.painter().circle(
                                     ui.available_rect_before_wrap()
                                         .left_center()
                                         .add(vec2(7.0, 0.0)),
                                     4.0,
-                                    Color32::RED,
+                                    color,
                                     (0.0, Color32::TRANSPARENT),
                                 );
                             }
 
                             ui.allocate_space(vec2(10.0, 0.0));
 
-                            if self
-                                .disassembly_label(ui, active, format!("{:04X}", addr))
+                            let addr_text = match self.symbols.get(addr) {
+                                Some(name) => format!("{:04X} {}:", addr, name),
+                                None if op.labeled => {
+                                    format!("{:04X} {}:", addr, label_name(addr))
+                                }
+                                None => format!("{:04X}", addr),
+                            };
+
+                            let response = self
+                                .disassembly_label(ui, active, addr_text)
                                 .sense(Sense::click())
                                 .ui(ui)
-                                .on_hover_cursor(CursorIcon::PointingHand)
-                                .clicked()
-                            {
+                                .on_hover_cursor(CursorIcon::PointingHand);
+
+                            if response.clicked() {
                                 self.nes.cpu_mut().toggle_breakpoint(addr);
                             }
+
+                            let response = match self.nes.cpu().breakpoint(addr) {
+                                Some(Breakpoint {
+                                    condition: Some(condition),
+                                    ..
+                                }) => response.on_hover_text(format!("Break if {}", condition)),
+                                _ => response,
+                            };
+
+                            response.context_menu(|ui| {
+                                if ui.button("Run to cursor").clicked() {
+                                    self.run_to(addr);
+                                    ui.close_menu();
+                                }
+
+                                ui.separator();
+                                self.breakpoint_condition_menu(ui, addr);
+                            });
                         });
 
-                        if kind == OpKind::Invalid {
-                            self.disassembly_label(ui, active, format!("{:02X}", opcode))
-                                .ui(ui);
-                            self.disassembly_label(ui, active, "???").ui(ui);
-                        } else if size == 2 {
-                            self.disassembly_label(
-                                ui,
-                                active,
-                                format!("{:02X} {:02X}", opcode, operands[0]),
-                            )
-                            .ui(ui);
-                            self.disassembly_label(
-                                ui,
-                                active,
-                                to_asm(kind, addr_mode, operands[0] as u16),
-                            )
-                            .ui(ui);
-                        } else if size == 3 {
-                            self.disassembly_label(
-                                ui,
-                                active,
-                                format!("{:02X} {:02X} {:02X}", opcode, operands[0], operands[1]),
-                            )
-                            .ui(ui);
-                            self.disassembly_label(
-                                ui,
-                                active,
-                                to_asm(kind, addr_mode, u16::from_le_bytes(operands)),
-                            )
-                            .ui(ui);
-                        } else {
-                            self.disassembly_label(ui, active, format!("{:02X}", opcode))
-                                .ui(ui);
-                            self.disassembly_label(ui, active, to_asm(kind, addr_mode, 0))
-                                .ui(ui);
-                        }
+                        let bytes = match size {
+                            2 => format!("{:02X} {:02X}", opcode, operands[0]),
+                            3 => format!("{:02X} {:02X} {:02X}", opcode, operands[0], operands[1]),
+                            _ => format!("{:02X}", opcode),
+                        };
+                        self.disassembly_label(ui, active, bytes).ui(ui);
+
+                        self.disassembly_label(
+                            ui,
+                            active,
+                            op.asm_with_symbols(labels, &self.symbols),
+                        )
+                        .ui(ui);
 
                         ui.end_row();
                     }
@@ -466,6 +1483,277 @@ impl App {
         });
     }
 
+    fn memory_window(&mut self, ctx: &Context) {
+        let mut show_memory_window = self.show_memory_window;
+        egui::Window::new("Memory")
+            .open(&mut show_memory_window)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Go to:");
+                    let response = ui.add(
+                        egui::TextEdit::singleline(&mut self.memory_jump_input).desired_width(40.0),
+                    );
+
+                    if response.lost_focus() && ui.input().key_pressed(Key::Enter) {
+                        self.memory_scroll_to = parse_hex_addr(&self.memory_jump_input);
+                    }
+                });
+
+                ui.separator();
+
+                self.memory_view(ui);
+            });
+        self.show_memory_window = show_memory_window;
+    }
+
+    fn call_stack_window(&mut self, ctx: &Context) {
+        let mut show_call_stack_window = self.show_call_stack_window;
+        egui::Window::new("Call stack")
+            .open(&mut show_call_stack_window)
+            .show(ctx, |ui| {
+                let cpu = self.nes.cpu();
+
+                ui.monospace(format!("${:04X} (PC)", cpu.pc));
+
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    for addr in cpu.call_stack().iter().rev() {
+                        ui.monospace(format!("${:04X}", addr));
+                    }
+                });
+            });
+        self.show_call_stack_window = show_call_stack_window;
+    }
+
+    fn ram_search_window(&mut self, ctx: &Context) {
+        let mut show_ram_search_window = self.show_ram_search_window;
+        egui::Window::new("RAM search")
+            .open(&mut show_ram_search_window)
+            .show(ctx, |ui| {
+                if ui.button("New search").clicked() {
+                    self.ram_search = Some(RamSearch::new(self.nes.ram_snapshot()));
+                }
+
+                if let Some(search) = self.ram_search.as_mut() {
+                    let mut comparison = None;
+
+                    ui.horizontal(|ui| {
+                        for (name, cmp) in [
+                            ("Increased", Comparison::Increased),
+                            ("Decreased", Comparison::Decreased),
+                            ("Changed", Comparison::Changed),
+                            ("Unchanged", Comparison::Unchanged),
+                        ] {
+                            if ui.button(name).clicked() {
+                                comparison = Some(cmp);
+                            }
+                        }
+                    });
+
+                    ui.horizontal(|ui| {
+                        let value = self.ram_search_value_input.trim();
+                        let value = match value.strip_prefix('$') {
+                            Some(hex) => u8::from_str_radix(hex, 16).ok(),
+                            None => value.parse().ok(),
+                        };
+
+                        if ui
+                            .add_enabled(value.is_some(), egui::Button::new("Equal to"))
+                            .clicked()
+                        {
+                            comparison = value.map(Comparison::EqualTo);
+                        }
+
+                        ui.add(
+                            egui::TextEdit::singleline(&mut self.ram_search_value_input)
+                                .hint_text("42 or $2A")
+                                .desired_width(60.0),
+                        );
+                    });
+
+                    if let Some(comparison) = comparison {
+                        search.filter(self.nes.ram_snapshot(), comparison);
+                    }
+
+                    ui.separator();
+                    ui.label(format!("{} candidates", search.candidates().len()));
+
+                    egui::ScrollArea::vertical()
+                        .id_source("ram_search_candidates")
+                        .max_height(200.0)
+                        .show(ui, |ui| {
+                            // Listing all 2048 addresses of a fresh search isn't useful
+                            for &addr in search.candidates().iter().take(100) {
+                                ui.horizontal(|ui| {
+                                    let value = search.value(addr);
+                                    ui.monospace(format!(
+                                        "${:04X}: ${:02X} ({})",
+                                        addr, value, value
+                                    ));
+
+                                    if ui.small_button("Freeze").clicked() {
+                                        self.nes.freeze_ram(addr, value);
+                                    }
+                                });
+                            }
+                        });
+                }
+
+                let frozen: Vec<_> = self.nes.frozen_ram().collect();
+                if !frozen.is_empty() {
+                    ui.separator();
+                    ui.label("Frozen");
+
+                    for (addr, value) in frozen {
+                        ui.horizontal(|ui| {
+                            ui.monospace(format!("${:04X}: ${:02X} ({})", addr, value, value));
+
+                            if ui.small_button("Unfreeze").clicked() {
+                                self.nes.unfreeze_ram(addr);
+                            }
+                        });
+                    }
+                }
+            });
+        self.show_ram_search_window = show_ram_search_window;
+    }
+
+    fn game_genie_window(&mut self, ctx: &Context) {
+        let mut show_game_genie_window = self.show_game_genie_window;
+        egui::Window::new("Game Genie")
+            .open(&mut show_game_genie_window)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    let response = ui.add(
+                        egui::TextEdit::singleline(&mut self.game_genie_input)
+                            .hint_text("SXIOPO")
+                            .desired_width(80.0),
+                    );
+                    let submitted = response.lost_focus() && ui.input().key_pressed(Key::Enter);
+
+                    if ui.button("Add").clicked() || submitted {
+                        match self.nes.add_game_genie_code(&self.game_genie_input) {
+                            Ok(()) => self.game_genie_input.clear(),
+                            Err(e) => self.error_message = Some(e),
+                        }
+                    }
+                });
+
+                let mut removed = None;
+                for (i, code) in self.nes.game_genie_codes().iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.monospace(code.to_string());
+
+                        if ui.small_button("Remove").clicked() {
+                            removed = Some(i);
+                        }
+                    });
+                }
+
+                if let Some(i) = removed {
+                    self.nes.remove_game_genie_code(i);
+                }
+            });
+        self.show_game_genie_window = show_game_genie_window;
+    }
+
+    fn memory_view(&mut self, ui: &mut Ui) {
+        let text_style = egui::TextStyle::Monospace;
+        let row_height = ui.text_style_height(&text_style);
+
+        let mut scrollarea = egui::ScrollArea::vertical().id_source("memory");
+
+        if let Some(addr) = self.memory_scroll_to.take() {
+            scrollarea = scrollarea.vertical_scroll_offset(
+                (row_height + ui.spacing().item_spacing.y) * (addr / 16) as f32,
+            );
+        }
+
+        scrollarea.show_rows(ui, row_height, 0x1000, |ui, row_range| {
+            for row in row_range {
+                ui.horizontal(|ui| {
+                    ui.monospace(format!("{:04X}:", row * 16));
+
+                    for col in 0..16 {
+                        self.memory_cell(ui, (row * 16 + col) as u16);
+                    }
+                });
+            }
+        });
+    }
+
+    fn memory_cell(&mut self, ui: &mut Ui, addr: u16) {
+        if let Some((edit_addr, text)) = self.memory_edit.as_mut() {
+            if *edit_addr == addr {
+                let response = ui.add(
+                    egui::TextEdit::singleline(text)
+                        .desired_width(16.0)
+                        .font(egui::TextStyle::Monospace),
+                );
+
+                if response.lost_focus() {
+                    if ui.input().key_pressed(Key::Enter) {
+                        if let Ok(val) = u8::from_str_radix(text, 16) {
+                            self.nes.cpu_write_mem(addr, val);
+                        }
+                    }
+                    self.memory_edit = None;
+                } else if !response.has_focus() {
+                    response.request_focus();
+                }
+
+                return;
+            }
+        }
+
+        // Reads through the read-only path to avoid side effects on registers
+        let val = self.nes.cpu_read_mem(addr);
+
+        let cpu = self.nes.cpu();
+        let stack_pointer = 0x0100 | cpu.s as u16;
+
+        let mut text = RichText::new(format!("{:02X}", val)).monospace();
+        if addr == cpu.pc {
+            text = text.color(Color32::YELLOW);
+        } else if cpu.is_read_breakpoint(addr) || cpu.is_write_breakpoint(addr) {
+            text = text.color(Color32::RED);
+        } else if addr > stack_pointer && addr <= 0x01FF {
+            text = text.color(Color32::LIGHT_BLUE);
+        }
+
+        let response = Label::new(text)
+            .sense(Sense::click())
+            .ui(ui)
+            .on_hover_text(format!("${:04X}", addr));
+
+        if response.clicked() {
+            self.memory_edit = Some((addr, format!("{:02X}", val)));
+        }
+
+        response.context_menu(|ui| {
+            let cpu = self.nes.cpu_mut();
+
+            if cpu.is_read_breakpoint(addr) {
+                if ui.button("Remove read breakpoint").clicked() {
+                    cpu.clear_read_breakpoint(addr);
+                    ui.close_menu();
+                }
+            } else if ui.button("Break on read").clicked() {
+                cpu.set_read_breakpoint(addr);
+                ui.close_menu();
+            }
+
+            if cpu.is_write_breakpoint(addr) {
+                if ui.button("Remove write breakpoint").clicked() {
+                    cpu.clear_write_breakpoint(addr);
+                    ui.close_menu();
+                }
+            } else if ui.button("Break on write").clicked() {
+                cpu.set_write_breakpoint(addr);
+                ui.close_menu();
+            }
+        });
+    }
+
     fn disassembly_label<T>(&self, ui: &mut Ui, active: bool, text: T) -> Label
     where
         T: Into<WidgetText> + Into<String>,
@@ -480,47 +1768,339 @@ impl App {
     }
 
     fn display_window(&mut self, ctx: &Context) {
-        self.display_texture.set(
-            ColorImage::from_rgba_unmultiplied(NES_DISPLAY_SIZE, self.nes.display_bytes()),
-            TextureFilter::Nearest,
-        );
+        let filter = if self.settings.linear_filter {
+            TextureFilter::Linear
+        } else {
+            TextureFilter::Nearest
+        };
+
+        let image = if self.settings.ntsc_filter {
+            let [_, height] = NES_DISPLAY_SIZE;
+            ColorImage::from_rgba_unmultiplied(
+                [NTSC_WIDTH, height],
+                &self.nes.ntsc_filtered_bytes(),
+            )
+        } else {
+            ColorImage::from_rgba_unmultiplied(NES_DISPLAY_SIZE, self.nes.display_bytes())
+        };
+
+        self.display_texture.set(image, filter);
+
+        let [width, height] = NES_DISPLAY_SIZE;
+        let size = self.settings.display_size(width, height);
+
+        let fast_forward = if self.fast_forward_saved_ft.is_some() {
+            ", fast-forward"
+        } else {
+            ""
+        };
 
         egui::Window::new(format!(
-            "Display ({:.02} ms)",
-            self.last_ft.as_micros() as f32 / 1000.0
+            "Display ({:.02} ms, {:.02}x{})",
+            self.last_ft.as_micros() as f32 / 1000.0,
+            self.speed,
+            fast_forward
         ))
         .id(Id::new("display"))
         .collapsible(false)
         .show(ctx, |ui| {
-            egui::Image::new(self.display_texture.id(), [512.0, 480.0])
+            let response = egui::Image::new(self.display_texture.id(), size)
                 .bg_fill(Color32::BLACK)
                 .ui(ui);
+
+            if self.zapper_enabled {
+                self.update_zapper(&response, width, height);
+            }
         });
     }
 
+    /// Aim the Zapper at the display pixel under the mouse.
+    fn update_zapper(&mut self, display: &egui::Response, width: usize, height: usize) {
+        let (x, y) = match display.hover_pos() {
+            Some(pos) => {
+                let pos = (pos - display.rect.min) / display.rect.size();
+                (
+                    (pos.x * width as f32) as u16,
+                    (pos.y * height as f32) as u16,
+                )
+            }
+            // Aiming off-screen
+            None => (u16::MAX, u16::MAX),
+        };
+
+        let trigger = display.ctx.input().pointer.primary_down();
+        self.nes.set_zapper(x, y, trigger);
+    }
+
     fn nametable_window(&mut self, ctx: &Context) {
         egui::Window::new("Nametables").show(ctx, |ui| {
             for i in 0..4 {
                 self.nametable_textures[i].set(
                     ColorImage::from_rgba_unmultiplied(
                         NES_DISPLAY_SIZE,
-                        &self.nes.nametable_rgb_bytes(i as _),
+                        self.nes.nametable_rgb_bytes(i as _),
                     ),
                     TextureFilter::Nearest,
                 );
             }
 
+            let mut image_rects = Vec::new();
             ui.horizontal(|ui| {
-                self.nametable_image(0).ui(ui);
-                self.nametable_image(1).ui(ui);
+                image_rects.push(self.nametable_image(0).ui(ui).rect);
+                image_rects.push(self.nametable_image(1).ui(ui).rect);
             });
             ui.horizontal(|ui| {
-                self.nametable_image(2).ui(ui);
-                self.nametable_image(3).ui(ui);
+                image_rects.push(self.nametable_image(2).ui(ui).rect);
+                image_rects.push(self.nametable_image(3).ui(ui).rect);
             });
+
+            // Outline the visible area
+            for (nametable, x, y, width, height) in self.nes.ppu().scroll_viewport() {
+                let min = image_rects[nametable as usize].min + vec2(x as f32, y as f32);
+                ui.painter().rect_stroke(
+                    Rect::from_min_size(min, vec2(width as f32, height as f32)),
+                    0.0,
+                    Stroke::new(1.0, Color32::RED),
+                );
+            }
         });
     }
 
+    fn palette_window(&mut self, ctx: &Context) {
+        if !self.show_palette_window {
+            return;
+        }
+
+        let colors = self.nes.palette_rgb_bytes();
+
+        egui::Window::new("Palette")
+            .open(&mut self.show_palette_window)
+            .show(ctx, |ui| {
+                for (label, palettes) in ["Background", "Sprites"].iter().zip(colors.chunks(16)) {
+                    ui.label(*label);
+                    ui.horizontal(|ui| {
+                        for (r, g, b) in palettes {
+                            let (rect, _) =
+                                ui.allocate_exact_size(vec2(20.0, 20.0), Sense::hover());
+                            ui.painter()
+                                .rect_filled(rect, 0.0, Color32::from_rgb(*r, *g, *b));
+                        }
+                    });
+                }
+            });
+    }
+
+    fn zip_entries_window(&mut self, ctx: &Context) {
+        let (path, entries) = match self.zip_entries.as_ref() {
+            Some(zip_entries) => zip_entries.clone(),
+            None => return,
+        };
+
+        let mut selected = None;
+        let mut open = true;
+
+        egui::Window::new("Select ROM")
+            .collapsible(false)
+            .anchor(Align2::CENTER_CENTER, [0.0, 0.0])
+            .open(&mut open)
+            .show(ctx, |ui| {
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    for name in &entries {
+                        if ui.button(name).clicked() {
+                            selected = Some(name.clone());
+                        }
+                    }
+                });
+            });
+
+        if let Some(name) = selected {
+            self.zip_entries = None;
+            self.load_zip_entry(&path, &name);
+        } else if !open {
+            self.zip_entries = None;
+        }
+    }
+
+    fn error_window(&mut self, ctx: &Context) {
+        let message = match self.error_message.as_ref() {
+            Some(message) => message.clone(),
+            None => return,
+        };
+
+        egui::Window::new("Error")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.label(message);
+                if ui.button("OK").clicked() {
+                    self.error_message = None;
+                }
+            });
+    }
+
+    fn rom_info_window(&mut self, ctx: &Context) {
+        let info = self.nes.cartridge().info();
+
+        let mapper = match info.submapper {
+            0 => format!("{:?}", info.mapper),
+            submapper => format!("{:?} (submapper {})", info.mapper, submapper),
+        };
+
+        let chr = match info.chr_rom_kb {
+            0 => "RAM".to_string(),
+            kb => format!("{} KB", kb),
+        };
+
+        egui::Window::new("ROM info")
+            .open(&mut self.show_rom_info_window)
+            .show(ctx, |ui| {
+                egui::Grid::new("rom_info_grid")
+                    .striped(true)
+                    .num_columns(2)
+                    .show(ui, |ui| {
+                        ui.label("File:");
+                        ui.label(self.loaded_cart_filename.as_deref().unwrap_or("-"));
+                        ui.end_row();
+
+                        ui.label("Mapper:");
+                        ui.label(mapper);
+                        ui.end_row();
+
+                        ui.label("PRG-ROM:");
+                        ui.label(format!("{} KB", info.prg_rom_kb));
+                        ui.end_row();
+
+                        ui.label("CHR-ROM:");
+                        ui.label(chr);
+                        ui.end_row();
+
+                        ui.label("PRG-RAM:");
+                        ui.label(format!("{} KB", info.prg_ram_kb));
+                        ui.end_row();
+
+                        ui.label("Mirroring:");
+                        ui.label(format!("{:?}", info.mirroring));
+                        ui.end_row();
+
+                        ui.label("Battery:");
+                        ui.label(if info.battery { "Yes" } else { "No" });
+                        ui.end_row();
+
+                        ui.label("Region:");
+                        ui.label(format!("{:?}", info.region));
+                        ui.end_row();
+                    });
+            });
+    }
+
+    fn audio_channels_window(&mut self, ctx: &Context) {
+        let apu = self.nes.apu();
+        let shown = &mut self.audio_channels_shown;
+
+        egui::Window::new("Audio channels")
+            .open(&mut self.show_audio_channels_window)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    for channel in Channel::ALL {
+                        ui.checkbox(&mut shown[channel as usize], format!("{:?}", channel));
+                    }
+                });
+
+                for channel in Channel::ALL {
+                    if !shown[channel as usize] {
+                        continue;
+                    }
+
+                    ui.label(format!("{:?}", channel));
+                    Plot::new(format!("audio_channel_{:?}", channel))
+                        .height(60.0)
+                        .include_y(0.0)
+                        .include_y(1.0)
+                        .show_axes([false, false])
+                        .allow_drag(false)
+                        .allow_zoom(false)
+                        .allow_scroll(false)
+                        .allow_boxed_zoom(false)
+                        .show(ui, |plot_ui| {
+                            let samples = apu.channel_samples(channel);
+                            plot_ui.line(Line::new(PlotPoints::from_ys_f32(samples)));
+                        });
+                }
+            });
+    }
+
+    fn sprites_window(&mut self, ctx: &Context) {
+        if !self.show_sprites_window {
+            return;
+        }
+
+        let tile_height = if self.nes.ppu().use_large_sprites() {
+            16
+        } else {
+            8
+        };
+
+        for (i, texture) in self.sprite_textures.iter_mut().enumerate() {
+            texture.set(
+                ColorImage::from_rgba_unmultiplied(
+                    [8, tile_height],
+                    &self.nes.sprite_rgb_bytes(i as u8),
+                ),
+                TextureFilter::Nearest,
+            );
+        }
+
+        let entries = self.nes.oam_entries();
+        let active_sprites = self.nes.ppu().active_sprite_indices();
+
+        egui::Window::new("Sprites")
+            .open(&mut self.show_sprites_window)
+            .show(ctx, |ui| {
+                ui.label(format!(
+                    "Highlighted: sprites on scanline {}",
+                    self.nes.ppu().current_scanline()
+                ));
+
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    egui::Grid::new("sprites_grid")
+                        .striped(true)
+                        .num_columns(6)
+                        .show(ui, |ui| {
+                            for header in ["#", "Tile", "Y", "Index", "Attr", "X"] {
+                                ui.label(header);
+                            }
+                            ui.end_row();
+
+                            for (i, (y, tile_idx, attrs, x)) in entries.iter().enumerate() {
+                                let active = active_sprites.contains(&(i as u8));
+                                let text = |text: String| {
+                                    let text = RichText::new(text).monospace();
+                                    if active {
+                                        text.color(Color32::YELLOW)
+                                    } else {
+                                        text
+                                    }
+                                };
+
+                                ui.label(text(format!("{:02}", i)));
+                                egui::Image::new(
+                                    self.sprite_textures[i].id(),
+                                    [16.0, tile_height as f32 * 2.0],
+                                )
+                                .bg_fill(Color32::BLACK)
+                                .ui(ui);
+                                ui.label(text(format!("{:02X}", y)));
+                                ui.label(text(format!("{:02X}", tile_idx)));
+                                ui.label(text(format!("{:02X}", attrs)));
+                                ui.label(text(format!("{:02X}", x)));
+                                ui.end_row();
+                            }
+                        });
+                });
+            });
+    }
+
     fn nametable_image(&mut self, idx: usize) -> egui::Image {
         egui::Image::new(self.nametable_textures[idx].id(), [256.0, 240.0]).bg_fill(Color32::BLACK)
     }
@@ -534,41 +2114,96 @@ impl App {
         });
     }
 
+    fn handle_focus(&mut self, ctx: &Context) {
+        let focused = ctx.input().raw.has_focus;
+
+        if !focused && self.pause_on_focus_loss && self.running {
+            self.running = false;
+            self.paused_by_focus_loss = true;
+        } else if focused && self.paused_by_focus_loss {
+            self.running = true;
+            self.paused_by_focus_loss = false;
+        }
+    }
+
     fn handle_input(&mut self, ctx: &Context) {
+        // Keys typed into text fields, e.g. Tab and Backspace, must not control the emulator
+        let typing = ctx.wants_keyboard_input();
         let input = ctx.input();
 
+        if let Some((player, button_idx)) = self.rebinding {
+            let pressed_key = input.events.iter().find_map(|e| match e {
+                Event::Key {
+                    key, pressed: true, ..
+                } if is_bindable(*key) => Some(*key),
+                _ => None,
+            });
+
+            if let Some(key) = pressed_key {
+                self.keymap.bind(player, button_idx, key);
+                self.keymap.save();
+                self.rebinding = None;
+            }
+
+            return;
+        }
+
         if input.key_pressed(Key::Space) {
             self.running = !self.running;
         }
 
-        self.nes
-            .set_button_state_player1(NesButton::Down, input.key_down(Key::ArrowDown));
-        self.nes
-            .set_button_state_player1(NesButton::Up, input.key_down(Key::ArrowUp));
-        self.nes
-            .set_button_state_player1(NesButton::Left, input.key_down(Key::ArrowLeft));
-        self.nes
-            .set_button_state_player1(NesButton::Right, input.key_down(Key::ArrowRight));
-        self.nes
-            .set_button_state_player1(NesButton::Start, input.key_down(Key::Enter));
-        self.nes
-            .set_button_state_player1(NesButton::Select, input.key_down(Key::S));
-        self.nes
-            .set_button_state_player1(NesButton::A, input.key_down(Key::A));
-        self.nes
-            .set_button_state_player1(NesButton::B, input.key_down(Key::B));
-    }
+        if input.key_pressed(Key::F12) {
+            self.save_screenshot();
+        }
 
-    fn handle_dropped_file(&mut self, ctx: &Context) {
-        for file in ctx.input().raw.dropped_files.iter() {
-            debug!("{:?}", file);
-            if let Some(path) = file.path.as_ref() {
-                let bytes = read(path).unwrap();
+        let fast_forward = self.running && !typing && input.key_down(Key::Tab);
+        self.set_fast_forward(fast_forward);
+
+        self.rewinding = self.running && !typing && input.key_down(Key::Backspace);
+        if self.rewinding {
+            self.rewind.rewind(&mut self.nes);
+            self.update_scroll = true;
+        }
 
-                if let Ok(cartridge) = Cartridge::from_bytes(&bytes) {
-                    self.load_cartridge(path.file_name().unwrap().to_str().unwrap(), cartridge);
+        for player in 0..PLAYERS {
+            for (button, key) in self.keymap.player_bindings(player) {
+                let state = input.key_down(key);
+                match player {
+                    0 => self.nes.set_button_state_player1(button, state),
+                    _ => self.nes.set_button_state_player2(button, state),
                 }
             }
         }
     }
+
+    fn handle_dropped_file(&mut self, ctx: &Context) {
+        let paths: Vec<PathBuf> = ctx
+            .input()
+            .raw
+            .dropped_files
+            .iter()
+            .inspect(|file| debug!("{:?}", file))
+            .filter_map(|file| file.path.clone())
+            .collect();
+
+        for path in paths {
+            self.load_rom_file(&path);
+        }
+    }
+}
+
+/// Describes why a ROM could not be loaded, based on the kind of the error.
+fn describe_load_error(e: &io::Error) -> String {
+    let reason = match e.kind() {
+        ErrorKind::NotFound => "file not found",
+        ErrorKind::PermissionDenied => "permission denied",
+        ErrorKind::InvalidData => "not a valid NES ROM",
+        ErrorKind::Unsupported => "not supported",
+        _ => return e.to_string(),
+    };
+
+    match e.get_ref() {
+        Some(inner) => format!("{} ({})", reason, inner),
+        None => reason.to_string(),
+    }
 }
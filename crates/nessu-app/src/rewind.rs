@@ -0,0 +1,78 @@
+use std::collections::VecDeque;
+
+use nessu_lib::nes::Nes;
+
+const FRAMES_PER_SECOND: u32 = 60;
+
+/// Ring buffer of save states that allows stepping backwards in time.
+pub struct RewindBuffer {
+    states: VecDeque<Vec<u8>>,
+
+    /// How many seconds of history to keep
+    pub seconds: u32,
+    /// Number of frames between snapshots
+    pub interval: u32,
+    /// Upper limit for the total size of the snapshots, in bytes
+    pub max_bytes: usize,
+
+    /// Total size of the snapshots in the buffer
+    bytes: usize,
+    frames_since_capture: u32,
+}
+
+impl RewindBuffer {
+    pub fn new(seconds: u32, interval: u32, max_bytes: usize) -> Self {
+        Self {
+            states: VecDeque::new(),
+            seconds,
+            interval,
+            max_bytes,
+            bytes: 0,
+            frames_since_capture: 0,
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        (self.seconds * FRAMES_PER_SECOND / self.interval.max(1)).max(1) as usize
+    }
+
+    /// Called once per emulated frame. Takes a snapshot every `interval` frames.
+    pub fn capture(&mut self, nes: &Nes) {
+        self.frames_since_capture += 1;
+        if self.frames_since_capture < self.interval {
+            return;
+        }
+        self.frames_since_capture = 0;
+
+        let state = nes.save_state();
+
+        while self.states.len() >= self.capacity()
+            || (!self.states.is_empty() && self.bytes + state.len() > self.max_bytes)
+        {
+            if let Some(oldest) = self.states.pop_front() {
+                self.bytes -= oldest.len();
+            }
+        }
+
+        self.bytes += state.len();
+        self.states.push_back(state);
+    }
+
+    /// Restore the most recent snapshot. Returns false if the buffer is empty.
+    pub fn rewind(&mut self, nes: &mut Nes) -> bool {
+        match self.states.pop_back() {
+            Some(state) => {
+                self.bytes -= state.len();
+                self.frames_since_capture = 0;
+                nes.load_state(&state).is_ok()
+            }
+            None => false,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.states.clear();
+        self.bytes = 0;
+        self.frames_since_capture = 0;
+    }
+}
@@ -0,0 +1,101 @@
+use std::fs::{create_dir_all, read_to_string, write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+const CONFIG_FILE_NAME: &str = "settings.json";
+
+pub const DISPLAY_SCALES: [u32; 4] = [1, 2, 3, 4];
+
+/// Number of ROMs listed in the recent ROMs menu
+const MAX_RECENT_ROMS: usize = 10;
+
+/// Width of an NTSC pixel relative to its height
+pub const NTSC_PIXEL_ASPECT_RATIO: f32 = 8.0 / 7.0;
+
+/// App settings that are kept between sessions.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Integer scale of the NES display, one of [`DISPLAY_SCALES`]
+    pub display_scale: u32,
+    /// Stretch the display to the pixel aspect ratio of NTSC TVs
+    pub aspect_ratio_correction: bool,
+    /// Smooth the display with linear filtering instead of nearest neighbor
+    pub linear_filter: bool,
+    /// Simulate the artifacts of the NTSC composite video signal
+    pub ntsc_filter: bool,
+    /// Recently loaded ROMs, the latest first
+    pub recent_roms: Vec<PathBuf>,
+    /// Famicom Disk System BIOS used to load FDS disk images
+    pub fds_bios_path: Option<PathBuf>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            display_scale: 2,
+            aspect_ratio_correction: false,
+            linear_filter: false,
+            ntsc_filter: false,
+            recent_roms: Vec::new(),
+            fds_bios_path: None,
+        }
+    }
+}
+
+impl Settings {
+    fn config_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("nessu").join(CONFIG_FILE_NAME))
+    }
+
+    /// Load the settings from the config file, falling back to the defaults for
+    /// anything that is missing or invalid.
+    pub fn load() -> Self {
+        let mut settings = Self::config_path()
+            .and_then(|path| read_to_string(path).ok())
+            .and_then(|json| serde_json::from_str::<Settings>(&json).ok())
+            .unwrap_or_default();
+
+        if !DISPLAY_SCALES.contains(&settings.display_scale) {
+            settings.display_scale = Self::default().display_scale;
+        }
+
+        settings
+    }
+
+    pub fn save(&self) {
+        let path = match Self::config_path() {
+            Some(path) => path,
+            None => return,
+        };
+
+        let result = path
+            .parent()
+            .map_or(Ok(()), create_dir_all)
+            .and_then(|_| write(&path, serde_json::to_string_pretty(self).unwrap()));
+
+        if let Err(e) = result {
+            eprintln!("Failed to write settings {}: {}", path.display(), e);
+        }
+    }
+
+    /// Move the ROM to the top of the recent ROMs.
+    pub fn add_recent_rom(&mut self, path: &Path) {
+        self.recent_roms.retain(|recent| recent != path);
+        self.recent_roms.insert(0, path.to_path_buf());
+        self.recent_roms.truncate(MAX_RECENT_ROMS);
+    }
+
+    /// Size of the NES display in points
+    pub fn display_size(&self, width: usize, height: usize) -> [f32; 2] {
+        let scale = self.display_scale as f32;
+        let aspect_ratio = if self.aspect_ratio_correction {
+            NTSC_PIXEL_ASPECT_RATIO
+        } else {
+            1.0
+        };
+
+        [width as f32 * scale * aspect_ratio, height as f32 * scale]
+    }
+}
@@ -0,0 +1,147 @@
+//! Boots every `.nes` ROM in a directory headless and reports which ones fail to load or run.
+//!
+//! ```text
+//! cargo run --release -p nessu-lib --example boot_test -- <directory> [frames]
+//! ```
+
+use std::any::Any;
+use std::fs::{read, read_dir};
+use std::panic::{catch_unwind, set_hook, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+use nessu_lib::cartridge::Cartridge;
+use nessu_lib::nes::Nes;
+
+/// About 10 seconds of NTSC emulation
+const DEFAULT_FRAMES: usize = 600;
+
+enum Outcome {
+    Ok,
+    LoadFailed(String),
+    Error(String),
+    Halted,
+    Panicked(String),
+}
+
+fn boot(path: &Path, frames: usize) -> Outcome {
+    let bytes = match read(path) {
+        Ok(bytes) => bytes,
+        Err(e) => return Outcome::LoadFailed(e.to_string()),
+    };
+
+    // Parsing is inside too, as a malformed ROM may panic instead of failing to load
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        let cartridge = match Cartridge::from_bytes(&bytes) {
+            Ok(cartridge) => cartridge,
+            Err(e) => return Outcome::LoadFailed(e.to_string()),
+        };
+
+        let mut nes = Nes::new();
+        nes.insert_cartridge(cartridge);
+
+        if let Err(e) = nes.run_frames(frames) {
+            return Outcome::Error(e);
+        }
+
+        if nes.cpu().is_halted() {
+            Outcome::Halted
+        } else {
+            Outcome::Ok
+        }
+    }));
+
+    result.unwrap_or_else(|payload| Outcome::Panicked(panic_message(payload)))
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+
+    let dir = match args.next() {
+        Some(dir) => PathBuf::from(dir),
+        None => {
+            eprintln!("Usage: boot_test <directory> [frames]");
+            exit(2);
+        }
+    };
+
+    let frames = match args.next().map(|frames| frames.parse()) {
+        None => DEFAULT_FRAMES,
+        Some(Ok(frames)) => frames,
+        Some(Err(_)) => {
+            eprintln!("Invalid frame count");
+            exit(2);
+        }
+    };
+
+    let mut roms: Vec<PathBuf> = match read_dir(&dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension()
+                    .and_then(|ext| ext.to_str())
+                    .map(|ext| ext.eq_ignore_ascii_case("nes"))
+                    .unwrap_or(false)
+            })
+            .collect(),
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", dir.display(), e);
+            exit(2);
+        }
+    };
+    roms.sort();
+
+    // Panics are reported in the table instead
+    set_hook(Box::new(|_| {}));
+
+    let name_width = roms
+        .iter()
+        .map(|path| path.file_name().unwrap_or_default().len())
+        .max()
+        .unwrap_or(0)
+        .max("ROM".len());
+
+    println!("{:<width$}  Result", "ROM", width = name_width);
+
+    let mut failures = 0;
+    for path in &roms {
+        let outcome = boot(path, frames);
+
+        let result = match &outcome {
+            Outcome::Ok => "ok".to_string(),
+            Outcome::LoadFailed(e) => format!("load failed: {}", e),
+            Outcome::Error(e) => format!("error: {}", e),
+            Outcome::Halted => "CPU halted".to_string(),
+            Outcome::Panicked(e) => format!("panicked: {}", e),
+        };
+
+        if !matches!(outcome, Outcome::Ok) {
+            failures += 1;
+        }
+
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        println!("{:<width$}  {}", name, result, width = name_width);
+    }
+
+    println!();
+    println!(
+        "{} of {} ROMs booted for {} frames",
+        roms.len() - failures,
+        roms.len(),
+        frames
+    );
+
+    if failures > 0 {
+        exit(1);
+    }
+}
@@ -0,0 +1,59 @@
+// Build the bindings first with `wasm-pack build crates/nessu-lib --target web --out-dir examples/web/pkg`
+// and serve this directory over HTTP.
+import init, { Nes, Button } from "./pkg/nessu_lib.js";
+
+const KEYS = {
+    ArrowUp: Button.Up,
+    ArrowDown: Button.Down,
+    ArrowLeft: Button.Left,
+    ArrowRight: Button.Right,
+    Enter: Button.Start,
+    KeyS: Button.Select,
+    KeyA: Button.A,
+    KeyB: Button.B,
+};
+
+await init();
+
+const nes = new Nes();
+const canvas = document.getElementById("display");
+const ctx = canvas.getContext("2d");
+let running = false;
+
+document.getElementById("rom").addEventListener("change", async (e) => {
+    const bytes = new Uint8Array(await e.target.files[0].arrayBuffer());
+    try {
+        nes.insert_cartridge_from_bytes(bytes);
+        running = true;
+    } catch (err) {
+        alert(`Failed to load ROM: ${err}`);
+    }
+});
+
+for (const [type, pressed] of [["keydown", true], ["keyup", false]]) {
+    document.addEventListener(type, (e) => {
+        const button = KEYS[e.code];
+        if (button !== undefined) {
+            nes.set_button_state_player1(button, pressed);
+            e.preventDefault();
+        }
+    });
+}
+
+function frame() {
+    if (running) {
+        try {
+            nes.step_frame();
+        } catch (err) {
+            console.error(err);
+            running = false;
+        }
+
+        const pixels = new Uint8ClampedArray(nes.display_bytes());
+        ctx.putImageData(new ImageData(pixels, 256, 240), 0, 0);
+    }
+
+    requestAnimationFrame(frame);
+}
+
+requestAnimationFrame(frame);
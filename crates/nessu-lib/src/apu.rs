@@ -1,11 +1,275 @@
-pub struct Apu {}
+mod channel_buffer;
+mod dmc;
+mod envelope;
+mod frame_counter;
+mod length_counter;
+mod noise;
+mod pulse;
+mod resampler;
+mod triangle;
+
+use crate::apu::channel_buffer::ChannelBuffer;
+use crate::apu::dmc::Dmc;
+use crate::apu::frame_counter::{FrameCounter, FrameEvent};
+use crate::apu::noise::Noise;
+use crate::apu::pulse::Pulse;
+use crate::apu::resampler::Resampler;
+use crate::apu::triangle::Triangle;
+use crate::bitwise::HasBits;
+use crate::region::Region;
+
+pub use crate::apu::channel_buffer::CHANNEL_BUFFER_LEN;
+pub use crate::apu::resampler::MAX_BUFFERED_SAMPLES;
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+
+/// CPU cycles between the samples recorded for [`Apu::channel_samples`]
+const CHANNEL_SAMPLE_PERIOD: u32 = 40;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Channel {
+    Pulse1,
+    Pulse2,
+    Triangle,
+    Noise,
+    Dmc,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 5] = [
+        Channel::Pulse1,
+        Channel::Pulse2,
+        Channel::Triangle,
+        Channel::Noise,
+        Channel::Dmc,
+    ];
+}
+
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct Apu {
+    pulse1: Pulse,
+    pulse2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+
+    frame_counter: FrameCounter,
+
+    /// Pulse timers are clocked on every other CPU cycle
+    odd_cycle: bool,
+
+    pub pulse1_enabled_by_user: bool,
+    pub pulse2_enabled_by_user: bool,
+    pub triangle_enabled_by_user: bool,
+    pub noise_enabled_by_user: bool,
+    pub dmc_enabled_by_user: bool,
+
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    resampler: Resampler,
+
+    /// Recent output of each channel, indexed by [`Channel`]
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    channel_buffers: [ChannelBuffer; 5],
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    channel_sample_timer: u32,
+}
 
 impl Apu {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            pulse1: Pulse::new(true),
+            pulse2: Pulse::new(false),
+            triangle: Triangle::new(),
+            noise: Noise::new(),
+            dmc: Dmc::new(),
+            frame_counter: FrameCounter::new(),
+            odd_cycle: false,
+            pulse1_enabled_by_user: true,
+            pulse2_enabled_by_user: true,
+            triangle_enabled_by_user: true,
+            noise_enabled_by_user: true,
+            dmc_enabled_by_user: true,
+            resampler: Resampler::new(DEFAULT_SAMPLE_RATE),
+            channel_buffers: Default::default(),
+            channel_sample_timer: 0,
+        }
+    }
+
+    /// Reset as the reset button does: all channels are silenced, the DMC output level keeps
+    /// only its lowest bit and the frame counter restarts as if $4017 was written again.
+    pub fn reset(&mut self) {
+        self.write_status(0);
+        self.frame_counter.clear_irq_flag();
+        self.dmc.reset();
+        self.write_frame_counter(self.frame_counter.last_write());
+    }
+
+    pub fn clock(&mut self) {
+        match self.frame_counter.clock() {
+            FrameEvent::QuarterFrame => self.clock_quarter_frame(),
+            FrameEvent::HalfFrame => {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+            }
+            FrameEvent::None => {}
+        }
+
+        if self.odd_cycle {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+        }
+
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock();
+
+        self.odd_cycle = !self.odd_cycle;
+
+        let sample = self.output_sample();
+        self.resampler.push(sample);
+
+        self.channel_sample_timer += 1;
+        if self.channel_sample_timer == CHANNEL_SAMPLE_PERIOD {
+            self.channel_sample_timer = 0;
+            self.record_channel_samples();
+        }
+    }
+
+    fn record_channel_samples(&mut self) {
+        let outputs = [
+            self.pulse1.output() as f32 / 15.0,
+            self.pulse2.output() as f32 / 15.0,
+            self.triangle.output() as f32 / 15.0,
+            self.noise.output() as f32 / 15.0,
+            self.dmc.output() as f32 / 127.0,
+        ];
+
+        for (buffer, output) in self.channel_buffers.iter_mut().zip(outputs) {
+            buffer.push(output);
+        }
+    }
+
+    /// The last [`CHANNEL_BUFFER_LEN`] samples of a channel from oldest to newest, in the
+    /// range 0.0..=1.0. Muting the channel does not affect these.
+    pub fn channel_samples(&self, channel: Channel) -> &[f32] {
+        self.channel_buffers[channel as usize].samples()
+    }
+
+    /// Mix the current output of all channels into a sample in the range 0.0..=1.0.
+    /// Channels muted by the user are left out of the mix.
+    pub fn output_sample(&self) -> f32 {
+        let unless_muted = |enabled: bool, output: u8| if enabled { output as f32 } else { 0.0 };
+
+        let pulse = unless_muted(self.pulse1_enabled_by_user, self.pulse1.output())
+            + unless_muted(self.pulse2_enabled_by_user, self.pulse2.output());
+        let triangle = unless_muted(self.triangle_enabled_by_user, self.triangle.output());
+        let noise = unless_muted(self.noise_enabled_by_user, self.noise.output());
+        let dmc = unless_muted(self.dmc_enabled_by_user, self.dmc.output());
+
+        let pulse_out = if pulse == 0.0 {
+            0.0
+        } else {
+            95.88 / (8128.0 / pulse + 100.0)
+        };
+
+        let tnd = triangle / 8227.0 + noise / 12241.0 + dmc / 22638.0;
+        let tnd_out = if tnd == 0.0 {
+            0.0
+        } else {
+            159.79 / (1.0 / tnd + 100.0)
+        };
+
+        pulse_out + tnd_out
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.frame_counter.set_region(region);
+        self.noise.set_region(region);
+        self.dmc.set_region(region);
+        self.resampler.set_cpu_clock_rate(region.cpu_clock_rate());
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.resampler.sample_rate()
     }
 
-    pub fn reset(&mut self) {}
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.resampler.set_sample_rate(sample_rate);
+    }
+
+    pub fn drain_samples(&mut self) -> Vec<f32> {
+        self.resampler.drain()
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        self.pulse1.clock_quarter_frame();
+        self.pulse2.clock_quarter_frame();
+        self.triangle.clock_quarter_frame();
+        self.noise.clock_quarter_frame();
+    }
+
+    fn clock_half_frame(&mut self) {
+        self.pulse1.clock_half_frame();
+        self.pulse2.clock_half_frame();
+        self.triangle.clock_half_frame();
+        self.noise.clock_half_frame();
+    }
 
-    pub fn clock(&mut self) {}
+    pub fn write_register(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x4000..=0x4003 => self.pulse1.write_register(addr, val),
+            0x4004..=0x4007 => self.pulse2.write_register(addr, val),
+            0x4008..=0x400B => self.triangle.write_register(addr, val),
+            0x400C..=0x400F => self.noise.write_register(addr, val),
+            0x4010..=0x4013 => self.dmc.write_register(addr, val),
+            0x4015 => self.write_status(val),
+            0x4017 => self.write_frame_counter(val),
+            _ => {}
+        }
+    }
+
+    pub fn read_status(&mut self, read_only: bool) -> u8 {
+        let status = (self.pulse1.length_counter_active() as u8)
+            | (self.pulse2.length_counter_active() as u8) << 1
+            | (self.triangle.length_counter_active() as u8) << 2
+            | (self.noise.length_counter_active() as u8) << 3
+            | (self.dmc.active() as u8) << 4
+            | (self.frame_counter.irq_flag() as u8) << 6
+            | (self.dmc.irq_flag() as u8) << 7;
+
+        if !read_only {
+            self.frame_counter.clear_irq_flag();
+        }
+
+        status
+    }
+
+    fn write_status(&mut self, val: u8) {
+        self.pulse1.set_enabled(val.has_bits(0b0_0001));
+        self.pulse2.set_enabled(val.has_bits(0b0_0010));
+        self.triangle.set_enabled(val.has_bits(0b0_0100));
+        self.noise.set_enabled(val.has_bits(0b0_1000));
+        self.dmc.set_enabled(val.has_bits(0b1_0000));
+    }
+
+    fn write_frame_counter(&mut self, val: u8) {
+        let clock_immediately = self.frame_counter.write(val, self.odd_cycle);
+
+        if clock_immediately {
+            self.clock_quarter_frame();
+            self.clock_half_frame();
+        }
+    }
+
+    pub fn irq_triggered(&self) -> bool {
+        self.frame_counter.irq_flag() || self.dmc.irq_flag()
+    }
+
+    pub fn dmc_dma_request(&self) -> Option<u16> {
+        self.dmc.dma_request()
+    }
+
+    pub fn dmc_dma_complete(&mut self, val: u8) {
+        self.dmc.fill_sample_buffer(val);
+    }
 }
@@ -0,0 +1,37 @@
+/// Number of recent samples kept for each channel
+pub const CHANNEL_BUFFER_LEN: usize = 512;
+
+/// Ring buffer of the most recent output samples of a channel. Every sample is stored twice,
+/// so that the samples can always be borrowed in order as one contiguous slice.
+#[derive(Clone)]
+pub struct ChannelBuffer {
+    samples: Vec<f32>,
+    /// Index of the oldest sample
+    pos: usize,
+}
+
+impl Default for ChannelBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChannelBuffer {
+    pub fn new() -> Self {
+        Self {
+            samples: vec![0.0; CHANNEL_BUFFER_LEN * 2],
+            pos: 0,
+        }
+    }
+
+    pub fn push(&mut self, sample: f32) {
+        self.samples[self.pos] = sample;
+        self.samples[self.pos + CHANNEL_BUFFER_LEN] = sample;
+        self.pos = (self.pos + 1) % CHANNEL_BUFFER_LEN;
+    }
+
+    /// The buffered samples from oldest to newest
+    pub fn samples(&self) -> &[f32] {
+        &self.samples[self.pos..self.pos + CHANNEL_BUFFER_LEN]
+    }
+}
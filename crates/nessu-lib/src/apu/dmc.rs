@@ -0,0 +1,184 @@
+use crate::bitwise::HasBits;
+use crate::region::Region;
+
+/// DMC timer periods in CPU cycles
+const NTSC_RATE_TABLE: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+const PAL_RATE_TABLE: [u16; 16] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
+
+#[derive(Clone)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct Dmc {
+    irq_enabled: bool,
+    irq_flag: bool,
+    loop_flag: bool,
+
+    timer_period: u16,
+    timer: u16,
+
+    /// 7-bit output level
+    output_level: u8,
+
+    sample_addr: u16,
+    sample_length: u16,
+    current_addr: u16,
+    bytes_remaining: u16,
+
+    sample_buffer: Option<u8>,
+
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
+
+    region: Region,
+}
+
+impl Dmc {
+    pub fn new() -> Self {
+        Self {
+            irq_enabled: false,
+            irq_flag: false,
+            loop_flag: false,
+            timer_period: NTSC_RATE_TABLE[0],
+            timer: NTSC_RATE_TABLE[0],
+            output_level: 0,
+            sample_addr: 0xC000,
+            sample_length: 1,
+            current_addr: 0xC000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            shift_register: 0,
+            bits_remaining: 8,
+            silence: true,
+            region: Region::Ntsc,
+        }
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    fn rate_table(&self) -> &'static [u16; 16] {
+        match self.region {
+            Region::Pal => &PAL_RATE_TABLE,
+            Region::Ntsc | Region::Dendy => &NTSC_RATE_TABLE,
+        }
+    }
+
+    pub fn write_register(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x4010 => {
+                self.irq_enabled = val.has_bits(0x80);
+                self.loop_flag = val.has_bits(0x40);
+                self.timer_period = self.rate_table()[(val & 0xF) as usize];
+
+                if !self.irq_enabled {
+                    self.irq_flag = false;
+                }
+            }
+            0x4011 => self.output_level = val & 0x7F,
+            0x4012 => self.sample_addr = 0xC000 | ((val as u16) << 6),
+            0x4013 => self.sample_length = ((val as u16) << 4) | 1,
+            _ => {}
+        }
+    }
+
+    /// Reset keeps only the lowest bit of the output level.
+    pub fn reset(&mut self) {
+        self.output_level &= 1;
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.irq_flag = false;
+
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_addr = self.sample_addr;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    pub fn active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    pub fn output(&self) -> u8 {
+        self.output_level
+    }
+
+    pub fn irq_flag(&self) -> bool {
+        self.irq_flag
+    }
+
+    /// Address of the next sample byte, if the sample buffer needs to be refilled.
+    pub fn dma_request(&self) -> Option<u16> {
+        if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.current_addr)
+        } else {
+            None
+        }
+    }
+
+    /// Fill the sample buffer with a byte fetched by the CPU.
+    pub fn fill_sample_buffer(&mut self, val: u8) {
+        self.sample_buffer = Some(val);
+
+        self.current_addr = if self.current_addr == 0xFFFF {
+            0x8000
+        } else {
+            self.current_addr + 1
+        };
+
+        self.bytes_remaining -= 1;
+
+        if self.bytes_remaining == 0 {
+            if self.loop_flag {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq_flag = true;
+            }
+        }
+    }
+
+    pub fn clock(&mut self) {
+        if self.timer > 1 {
+            self.timer -= 1;
+            return;
+        }
+
+        self.timer = self.timer_period;
+
+        if !self.silence {
+            if self.shift_register.has_bits(1) {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+
+        self.shift_register >>= 1;
+        self.bits_remaining -= 1;
+
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+
+            match self.sample_buffer.take() {
+                Some(val) => {
+                    self.silence = false;
+                    self.shift_register = val;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use crate::bitwise::HasBits;
+
+#[derive(Clone)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct Envelope {
+    start: bool,
+    loop_flag: bool,
+    constant_volume: bool,
+    /// Constant volume, or the divider period when decaying
+    volume: u8,
+    divider: u8,
+    decay_level: u8,
+}
+
+impl Envelope {
+    pub fn new() -> Self {
+        Self {
+            start: false,
+            loop_flag: false,
+            constant_volume: false,
+            volume: 0,
+            divider: 0,
+            decay_level: 0,
+        }
+    }
+
+    pub fn write(&mut self, val: u8) {
+        self.loop_flag = val.has_bits(0x20);
+        self.constant_volume = val.has_bits(0x10);
+        self.volume = val & 0xF;
+    }
+
+    pub fn restart(&mut self) {
+        self.start = true;
+    }
+
+    pub fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay_level = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+
+            if self.decay_level > 0 {
+                self.decay_level -= 1;
+            } else if self.loop_flag {
+                self.decay_level = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.constant_volume {
+            self.volume
+        } else {
+            self.decay_level
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use crate::bitwise::HasBits;
+use crate::region::Region;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FrameEvent {
+    None,
+    /// Clocks envelopes and the triangle's linear counter
+    QuarterFrame,
+    /// Clocks everything clocked by a quarter frame, and length counters and sweep units
+    HalfFrame,
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct FrameCounter {
+    five_step_mode: bool,
+    irq_inhibit: bool,
+    irq_flag: bool,
+
+    /// CPU cycles since the start of the sequence
+    cycle: u32,
+
+    /// CPU cycles until a write to $4017 resets the sequence
+    reset_delay: u8,
+
+    region: Region,
+}
+
+impl FrameCounter {
+    pub fn new() -> Self {
+        Self {
+            five_step_mode: false,
+            irq_inhibit: false,
+            irq_flag: false,
+            cycle: 0,
+            reset_delay: 0,
+            region: Region::Ntsc,
+        }
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    /// CPU cycles of the first quarter frame, first half frame, second quarter frame,
+    /// and the last half frame of the 4-step and 5-step sequences.
+    fn step_cycles(&self) -> [u32; 5] {
+        match self.region {
+            Region::Pal => [8313, 16627, 24939, 33253, 41565],
+            Region::Ntsc | Region::Dendy => [7457, 14913, 22371, 29829, 37281],
+        }
+    }
+
+    /// Write to $4017. Returns true if all units should be clocked immediately.
+    pub fn write(&mut self, val: u8, odd_cycle: bool) -> bool {
+        self.five_step_mode = val.has_bits(0x80);
+        self.irq_inhibit = val.has_bits(0x40);
+
+        if self.irq_inhibit {
+            self.irq_flag = false;
+        }
+
+        // The sequence is reset 3 or 4 CPU cycles after the write,
+        // depending on whether it happened during an APU cycle.
+        self.reset_delay = if odd_cycle { 4 } else { 3 };
+
+        self.five_step_mode
+    }
+
+    /// Value of the last write to $4017, as far as the frame counter remembers it
+    pub fn last_write(&self) -> u8 {
+        ((self.five_step_mode as u8) << 7) | ((self.irq_inhibit as u8) << 6)
+    }
+
+    pub fn irq_flag(&self) -> bool {
+        self.irq_flag
+    }
+
+    pub fn clear_irq_flag(&mut self) {
+        self.irq_flag = false;
+    }
+
+    fn set_irq_flag(&mut self) {
+        if !self.irq_inhibit {
+            self.irq_flag = true;
+        }
+    }
+
+    pub fn clock(&mut self) -> FrameEvent {
+        if self.reset_delay > 0 {
+            self.reset_delay -= 1;
+
+            if self.reset_delay == 0 {
+                self.cycle = 0;
+            }
+        }
+
+        self.cycle += 1;
+
+        let [quarter1, half1, quarter2, four_step_end, five_step_end] = self.step_cycles();
+
+        match (self.cycle, self.five_step_mode) {
+            (c, _) if c == quarter1 => FrameEvent::QuarterFrame,
+            (c, _) if c == half1 => FrameEvent::HalfFrame,
+            (c, _) if c == quarter2 => FrameEvent::QuarterFrame,
+            (c, false) if c == four_step_end - 1 => {
+                self.set_irq_flag();
+                FrameEvent::None
+            }
+            (c, false) if c == four_step_end => {
+                self.set_irq_flag();
+                FrameEvent::HalfFrame
+            }
+            (c, false) if c == four_step_end + 1 => {
+                self.set_irq_flag();
+                self.cycle = 0;
+                FrameEvent::None
+            }
+            (c, true) if c == five_step_end => FrameEvent::HalfFrame,
+            (c, true) if c == five_step_end + 1 => {
+                self.cycle = 0;
+                FrameEvent::None
+            }
+            _ => FrameEvent::None,
+        }
+    }
+}
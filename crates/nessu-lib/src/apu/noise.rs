@@ -0,0 +1,111 @@
+use crate::apu::envelope::Envelope;
+use crate::apu::length_counter::LengthCounter;
+use crate::bitwise::HasBits;
+use crate::region::Region;
+
+/// Noise timer periods in CPU cycles
+const NTSC_PERIOD_TABLE: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+const PAL_PERIOD_TABLE: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+
+#[derive(Clone)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct Noise {
+    mode: bool,
+
+    timer_period: u16,
+    timer: u16,
+
+    /// 15-bit linear feedback shift register
+    shift_register: u16,
+
+    envelope: Envelope,
+    length_counter: LengthCounter,
+
+    region: Region,
+}
+
+impl Noise {
+    pub fn new() -> Self {
+        Self {
+            mode: false,
+            timer_period: NTSC_PERIOD_TABLE[0],
+            timer: NTSC_PERIOD_TABLE[0],
+            shift_register: 1,
+            envelope: Envelope::new(),
+            length_counter: LengthCounter::new(),
+            region: Region::Ntsc,
+        }
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    fn period_table(&self) -> &'static [u16; 16] {
+        match self.region {
+            Region::Pal => &PAL_PERIOD_TABLE,
+            Region::Ntsc | Region::Dendy => &NTSC_PERIOD_TABLE,
+        }
+    }
+
+    pub fn write_register(&mut self, reg: u16, val: u8) {
+        match reg & 0b11 {
+            0 => {
+                self.length_counter.set_halt(val.has_bits(0x20));
+                self.envelope.write(val);
+            }
+            1 => {}
+            2 => {
+                self.mode = val.has_bits(0x80);
+                self.timer_period = self.period_table()[(val & 0xF) as usize];
+            }
+            3 => {
+                self.length_counter.load(val >> 3);
+                self.envelope.restart();
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.length_counter.set_enabled(enabled);
+    }
+
+    pub fn length_counter_active(&self) -> bool {
+        self.length_counter.active()
+    }
+
+    pub fn clock_timer(&mut self) {
+        if self.timer > 1 {
+            self.timer -= 1;
+            return;
+        }
+
+        self.timer = self.timer_period;
+
+        let tap = if self.mode { 6 } else { 1 };
+        let feedback = (self.shift_register ^ (self.shift_register >> tap)) & 1;
+
+        self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.shift_register.has_bits(1) || !self.length_counter.active() {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use crate::apu::envelope::Envelope;
+use crate::apu::length_counter::LengthCounter;
+use crate::bitwise::HasBits;
+
+const DUTY_TABLE: [u8; 4] = [0b0100_0000, 0b0110_0000, 0b0111_1000, 0b1001_1111];
+
+#[derive(Clone)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct Pulse {
+    /// Pulse 1 negates the sweep change with one's complement, pulse 2 with two's complement
+    ones_complement: bool,
+
+    duty: u8,
+    duty_pos: u8,
+
+    timer_period: u16,
+    timer: u16,
+
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_divider: u8,
+    sweep_reload: bool,
+
+    envelope: Envelope,
+    length_counter: LengthCounter,
+}
+
+impl Pulse {
+    pub fn new(ones_complement: bool) -> Self {
+        Self {
+            ones_complement,
+            duty: 0,
+            duty_pos: 0,
+            timer_period: 0,
+            timer: 0,
+            sweep_enabled: false,
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_divider: 0,
+            sweep_reload: false,
+            envelope: Envelope::new(),
+            length_counter: LengthCounter::new(),
+        }
+    }
+
+    pub fn write_register(&mut self, reg: u16, val: u8) {
+        match reg & 0b11 {
+            0 => {
+                self.duty = val >> 6;
+                self.length_counter.set_halt(val.has_bits(0x20));
+                self.envelope.write(val);
+            }
+            1 => {
+                self.sweep_enabled = val.has_bits(0x80);
+                self.sweep_period = (val >> 4) & 0b111;
+                self.sweep_negate = val.has_bits(0x08);
+                self.sweep_shift = val & 0b111;
+                self.sweep_reload = true;
+            }
+            2 => self.timer_period = (self.timer_period & 0x700) | val as u16,
+            3 => {
+                self.timer_period = (self.timer_period & 0xFF) | (((val & 0b111) as u16) << 8);
+                self.length_counter.load(val >> 3);
+                self.duty_pos = 0;
+                self.envelope.restart();
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.length_counter.set_enabled(enabled);
+    }
+
+    pub fn length_counter_active(&self) -> bool {
+        self.length_counter.active()
+    }
+
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.duty_pos = (self.duty_pos + 1) & 0b111;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.muted() {
+            self.timer_period = self.sweep_target_period();
+        }
+
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    fn sweep_target_period(&self) -> u16 {
+        let change = self.timer_period >> self.sweep_shift;
+
+        if self.sweep_negate {
+            self.timer_period
+                .saturating_sub(change + self.ones_complement as u16)
+        } else {
+            self.timer_period + change
+        }
+    }
+
+    fn muted(&self) -> bool {
+        self.timer_period < 8 || self.sweep_target_period() > 0x7FF
+    }
+
+    pub fn output(&self) -> u8 {
+        let duty_high = (DUTY_TABLE[self.duty as usize] << self.duty_pos).has_bits(0x80);
+
+        if !duty_high || self.muted() || !self.length_counter.active() {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+
+use crate::apu::DEFAULT_SAMPLE_RATE;
+use crate::region::Region;
+
+/// Cutoff frequency of the high-pass filter that removes the DC offset of the mixer output
+const HIGH_PASS_CUTOFF: f32 = 90.0;
+
+/// Maximum number of samples kept in the buffer. The oldest samples are dropped
+/// if the buffer is not drained in time.
+pub const MAX_BUFFERED_SAMPLES: usize = 8192;
+
+/// Downsamples the APU output, produced once per CPU cycle, to the host sample rate.
+///
+/// Each output sample is the average of the CPU-rate samples within its period,
+/// which acts as a simple low-pass filter before decimation.
+pub struct Resampler {
+    sample_rate: u32,
+    /// CPU clock rate in Hz
+    cpu_clock_rate: f64,
+
+    /// CPU cycles per output sample
+    cycles_per_sample: f64,
+    cycle_counter: f64,
+
+    sum: f32,
+    count: u32,
+
+    high_pass_coeff: f32,
+    high_pass_prev_in: f32,
+    high_pass_prev_out: f32,
+
+    samples: VecDeque<f32>,
+}
+
+impl Default for Resampler {
+    fn default() -> Self {
+        Self::new(DEFAULT_SAMPLE_RATE)
+    }
+}
+
+impl Resampler {
+    pub fn new(sample_rate: u32) -> Self {
+        let mut resampler = Self {
+            sample_rate,
+            cpu_clock_rate: Region::Ntsc.cpu_clock_rate(),
+            cycles_per_sample: 0.0,
+            cycle_counter: 0.0,
+            sum: 0.0,
+            count: 0,
+            high_pass_coeff: 0.0,
+            high_pass_prev_in: 0.0,
+            high_pass_prev_out: 0.0,
+            samples: VecDeque::with_capacity(MAX_BUFFERED_SAMPLES),
+        };
+
+        resampler.set_sample_rate(sample_rate);
+        resampler
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        let sample_rate = sample_rate.max(1);
+
+        self.sample_rate = sample_rate;
+        self.cycles_per_sample = self.cpu_clock_rate / sample_rate as f64;
+
+        let rc = 1.0 / (2.0 * PI * HIGH_PASS_CUTOFF);
+        let dt = 1.0 / sample_rate as f32;
+        self.high_pass_coeff = rc / (rc + dt);
+    }
+
+    pub fn set_cpu_clock_rate(&mut self, cpu_clock_rate: f64) {
+        self.cpu_clock_rate = cpu_clock_rate;
+        self.set_sample_rate(self.sample_rate);
+    }
+
+    pub fn push(&mut self, sample: f32) {
+        self.sum += sample;
+        self.count += 1;
+        self.cycle_counter += 1.0;
+
+        if self.cycle_counter >= self.cycles_per_sample {
+            self.cycle_counter -= self.cycles_per_sample;
+
+            let average = self.sum / self.count as f32;
+            self.sum = 0.0;
+            self.count = 0;
+
+            let sample = self.high_pass(average);
+
+            if self.samples.len() >= MAX_BUFFERED_SAMPLES {
+                self.samples.pop_front();
+            }
+            self.samples.push_back(sample);
+        }
+    }
+
+    fn high_pass(&mut self, sample: f32) -> f32 {
+        let out =
+            self.high_pass_coeff * (self.high_pass_prev_out + sample - self.high_pass_prev_in);
+
+        self.high_pass_prev_in = sample;
+        self.high_pass_prev_out = out;
+
+        out
+    }
+
+    pub fn drain(&mut self) -> Vec<f32> {
+        self.samples.drain(..).collect()
+    }
+}
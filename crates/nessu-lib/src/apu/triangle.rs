@@ -0,0 +1,98 @@
+use crate::apu::length_counter::LengthCounter;
+use crate::bitwise::HasBits;
+
+const SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12,
+    13, 14, 15,
+];
+
+#[derive(Clone)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct Triangle {
+    /// Doubles as the length counter halt flag
+    control_flag: bool,
+
+    linear_counter: u8,
+    linear_counter_reload: u8,
+    linear_counter_reload_flag: bool,
+
+    timer_period: u16,
+    timer: u16,
+
+    sequence_pos: u8,
+
+    length_counter: LengthCounter,
+}
+
+impl Triangle {
+    pub fn new() -> Self {
+        Self {
+            control_flag: false,
+            linear_counter: 0,
+            linear_counter_reload: 0,
+            linear_counter_reload_flag: false,
+            timer_period: 0,
+            timer: 0,
+            sequence_pos: 0,
+            length_counter: LengthCounter::new(),
+        }
+    }
+
+    pub fn write_register(&mut self, reg: u16, val: u8) {
+        match reg & 0b11 {
+            0 => {
+                self.control_flag = val.has_bits(0x80);
+                self.length_counter.set_halt(self.control_flag);
+                self.linear_counter_reload = val & 0x7F;
+            }
+            1 => {}
+            2 => self.timer_period = (self.timer_period & 0x700) | val as u16,
+            3 => {
+                self.timer_period = (self.timer_period & 0xFF) | (((val & 0b111) as u16) << 8);
+                self.length_counter.load(val >> 3);
+                self.linear_counter_reload_flag = true;
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.length_counter.set_enabled(enabled);
+    }
+
+    pub fn length_counter_active(&self) -> bool {
+        self.length_counter.active()
+    }
+
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+
+            if self.linear_counter > 0 && self.length_counter.active() {
+                self.sequence_pos = (self.sequence_pos + 1) & 0x1F;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        if self.linear_counter_reload_flag {
+            self.linear_counter = self.linear_counter_reload;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+
+        if !self.control_flag {
+            self.linear_counter_reload_flag = false;
+        }
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+    }
+
+    pub fn output(&self) -> u8 {
+        SEQUENCE[self.sequence_pos as usize]
+    }
+}
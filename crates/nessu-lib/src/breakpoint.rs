@@ -0,0 +1,298 @@
+use std::fmt::{Display, Formatter};
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::cpu::Cpu;
+use crate::nes::Nes;
+
+/// Execution breakpoint that stops only when its condition, if any, is true
+#[derive(Clone, Debug)]
+pub struct Breakpoint {
+    pub addr: u16,
+    pub condition: Option<Expr>,
+}
+
+impl Breakpoint {
+    pub(crate) fn condition_met(&self, nes: &mut Nes) -> bool {
+        match &self.condition {
+            Some(condition) => condition.eval(nes) != 0,
+            None => true,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Register {
+    A,
+    X,
+    Y,
+    S,
+    P,
+    Pc,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    LogicalAnd,
+    LogicalOr,
+}
+
+/// Breakpoint condition such as `A == $10 && mem[$00FF] > 5`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Expr {
+    Num(u32),
+    Register(Register),
+    /// Memory byte at the address, read without side effects
+    Mem(Box<Expr>),
+    Binary(Box<Expr>, BinOp, Box<Expr>),
+}
+
+impl Expr {
+    /// Parse an expression of registers (A, X, Y, S, P, PC), numbers (`16`, `$10`, `0x10`),
+    /// memory reads (`mem[...]`), `+ - &`, comparisons, `&&`, `||` and parentheses.
+    pub fn parse(text: &str) -> Result<Expr, String> {
+        let mut parser = Parser {
+            chars: text.chars().peekable(),
+        };
+
+        let expr = parser.logical_or()?;
+        parser.skip_whitespace();
+
+        match parser.chars.next() {
+            Some(c) => Err(format!("Unexpected '{}'", c)),
+            None => Ok(expr),
+        }
+    }
+
+    /// Evaluate the expression. Comparisons and logical operators give 1 when true.
+    pub fn eval(&self, nes: &mut Nes) -> u32 {
+        match self {
+            Expr::Num(val) => *val,
+            Expr::Register(reg) => {
+                let cpu = nes.cpu();
+                match reg {
+                    Register::A => cpu.a as u32,
+                    Register::X => cpu.x as u32,
+                    Register::Y => cpu.y as u32,
+                    Register::S => cpu.s as u32,
+                    Register::P => cpu.p as u32,
+                    Register::Pc => cpu.pc as u32,
+                }
+            }
+            Expr::Mem(addr) => {
+                let addr = addr.eval(nes) as u16;
+                Cpu::read_mem_u8(nes, addr) as u32
+            }
+            Expr::Binary(lhs, op, rhs) => {
+                let lhs = lhs.eval(nes);
+
+                // Short-circuit so that the right side doesn't read memory needlessly
+                match op {
+                    BinOp::LogicalAnd if lhs == 0 => return 0,
+                    BinOp::LogicalOr if lhs != 0 => return 1,
+                    _ => {}
+                }
+
+                let rhs = rhs.eval(nes);
+
+                match op {
+                    BinOp::Add => lhs.wrapping_add(rhs),
+                    BinOp::Sub => lhs.wrapping_sub(rhs),
+                    BinOp::And => lhs & rhs,
+                    BinOp::Eq => (lhs == rhs) as u32,
+                    BinOp::Ne => (lhs != rhs) as u32,
+                    BinOp::Lt => (lhs < rhs) as u32,
+                    BinOp::Le => (lhs <= rhs) as u32,
+                    BinOp::Gt => (lhs > rhs) as u32,
+                    BinOp::Ge => (lhs >= rhs) as u32,
+                    BinOp::LogicalAnd | BinOp::LogicalOr => (rhs != 0) as u32,
+                }
+            }
+        }
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expr::Num(val) => write!(f, "${:X}", val),
+            Expr::Register(reg) => write!(f, "{}", format!("{:?}", reg).to_uppercase()),
+            Expr::Mem(addr) => write!(f, "mem[{}]", addr),
+            Expr::Binary(lhs, op, rhs) => {
+                let op = match op {
+                    BinOp::Add => "+",
+                    BinOp::Sub => "-",
+                    BinOp::And => "&",
+                    BinOp::Eq => "==",
+                    BinOp::Ne => "!=",
+                    BinOp::Lt => "<",
+                    BinOp::Le => "<=",
+                    BinOp::Gt => ">",
+                    BinOp::Ge => ">=",
+                    BinOp::LogicalAnd => "&&",
+                    BinOp::LogicalOr => "||",
+                };
+                write!(f, "({} {} {})", lhs, op, rhs)
+            }
+        }
+    }
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+
+    /// Whether `token` comes next, ignoring leading whitespace
+    fn next_is(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+
+        let mut lookahead = self.chars.clone();
+        token.chars().all(|c| lookahead.next() == Some(c))
+    }
+
+    /// Consume `token` if it comes next, ignoring leading whitespace.
+    fn eat(&mut self, token: &str) -> bool {
+        if self.next_is(token) {
+            self.chars.nth(token.len() - 1);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn logical_or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.logical_and()?;
+        while self.eat("||") {
+            expr = binary(expr, BinOp::LogicalOr, self.logical_and()?);
+        }
+        Ok(expr)
+    }
+
+    fn logical_and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.comparison()?;
+        while self.eat("&&") {
+            expr = binary(expr, BinOp::LogicalAnd, self.comparison()?);
+        }
+        Ok(expr)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        let expr = self.sum()?;
+
+        // Two-character operators first so that `<=` isn't taken as `<`
+        for (token, op) in [
+            ("==", BinOp::Eq),
+            ("!=", BinOp::Ne),
+            ("<=", BinOp::Le),
+            (">=", BinOp::Ge),
+            ("<", BinOp::Lt),
+            (">", BinOp::Gt),
+        ] {
+            if self.eat(token) {
+                return Ok(binary(expr, op, self.sum()?));
+            }
+        }
+
+        Ok(expr)
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        let mut expr = self.operand()?;
+
+        loop {
+            let op = if self.eat("+") {
+                BinOp::Add
+            } else if self.eat("-") {
+                BinOp::Sub
+            } else if !self.next_is("&&") && self.eat("&") {
+                BinOp::And
+            } else {
+                return Ok(expr);
+            };
+
+            expr = binary(expr, op, self.operand()?);
+        }
+    }
+
+    fn operand(&mut self) -> Result<Expr, String> {
+        self.skip_whitespace();
+
+        if self.eat("(") {
+            let expr = self.logical_or()?;
+            return if self.eat(")") {
+                Ok(expr)
+            } else {
+                Err("Expected ')'".to_string())
+            };
+        }
+
+        if self.eat("$") {
+            return self.number(16);
+        }
+
+        if self.eat("0x") || self.eat("0X") {
+            return self.number(16);
+        }
+
+        if matches!(self.chars.peek(), Some(c) if c.is_ascii_digit()) {
+            return self.number(10);
+        }
+
+        let mut word = String::new();
+        while let Some(c) = self.chars.next_if(|c| c.is_ascii_alphabetic()) {
+            word.push(c.to_ascii_uppercase());
+        }
+
+        let register = match word.as_str() {
+            "A" => Register::A,
+            "X" => Register::X,
+            "Y" => Register::Y,
+            "S" | "SP" => Register::S,
+            "P" => Register::P,
+            "PC" => Register::Pc,
+            "MEM" => {
+                if !self.eat("[") {
+                    return Err("Expected '[' after mem".to_string());
+                }
+                let addr = self.logical_or()?;
+                if !self.eat("]") {
+                    return Err("Expected ']'".to_string());
+                }
+                return Ok(Expr::Mem(Box::new(addr)));
+            }
+            "" => return Err("Expected a value".to_string()),
+            _ => return Err(format!("Unknown register {}", word)),
+        };
+
+        Ok(Expr::Register(register))
+    }
+
+    fn number(&mut self, radix: u32) -> Result<Expr, String> {
+        let mut digits = String::new();
+        while let Some(c) = self.chars.next_if(|c| c.is_digit(radix)) {
+            digits.push(c);
+        }
+
+        u32::from_str_radix(&digits, radix)
+            .map(Expr::Num)
+            .map_err(|_| "Expected a number".to_string())
+    }
+}
+
+fn binary(lhs: Expr, op: BinOp, rhs: Expr) -> Expr {
+    Expr::Binary(Box::new(lhs), op, Box::new(rhs))
+}
@@ -1,12 +1,42 @@
-use crate::header::Header;
-use crate::mapper::{build_mapper, Mapper, MapperTrait, Mirroring};
+use std::io::{Error, ErrorKind, Read};
+
+use crate::fds::disk_sides;
+use crate::header::{truncated_error, Header};
+use crate::mapper::{build_mapper, FdsMapper, Mapper, MapperKind, MapperTrait, Mirroring};
+use crate::region::Region;
 use log::debug;
 
+/// Largest ROM image accepted by [`Cartridge::from_reader`]. The largest image an iNES
+/// header can describe is a little over 6MB.
+pub const MAX_ROM_SIZE: usize = 8 * 1024 * 1024;
+
+/// Summary of the header of a loaded ROM.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CartridgeInfo {
+    pub mapper: MapperKind,
+    /// NES 2.0 submapper, 0 for iNES ROMs
+    pub submapper: u8,
+    pub prg_rom_kb: usize,
+    /// 0 if the cartridge has CHR-RAM instead
+    pub chr_rom_kb: usize,
+    pub prg_ram_kb: usize,
+    pub mirroring: Mirroring,
+    /// Has battery-backed PRG-RAM for saves
+    pub battery: bool,
+    pub region: Region,
+}
+
 #[derive(Clone)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
 pub struct Cartridge {
     header: Header,
     mapper: Mapper,
     valid: bool,
+    /// Set when the battery-backed RAM has been restored from a save
+    ram_loaded: bool,
+    /// Identifies the ROM data, which is left out of savestates
+    crc32: u32,
 }
 
 impl Default for Cartridge {
@@ -17,6 +47,8 @@ impl Default for Cartridge {
             header,
             mapper,
             valid: false,
+            ram_loaded: false,
+            crc32: 0,
         }
     }
 }
@@ -33,18 +65,189 @@ impl Cartridge {
             header,
             mapper,
             valid: true,
+            ram_loaded: false,
+            crc32: crc32(&[&bytes[..header.rom_size()]]),
+        })
+    }
+
+    /// Read a ROM image from `reader` with the default size limit of [`MAX_ROM_SIZE`].
+    pub fn from_reader<R: Read>(reader: R) -> std::io::Result<Self> {
+        Self::from_reader_with_limit(reader, MAX_ROM_SIZE)
+    }
+
+    /// Read a ROM image from `reader`. Only the header and the data it declares are read,
+    /// and images larger than `max_size` bytes are rejected before reading them.
+    pub fn from_reader_with_limit<R: Read>(
+        mut reader: R,
+        max_size: usize,
+    ) -> std::io::Result<Self> {
+        let mut bytes = vec![0; 16];
+        reader.read_exact(&mut bytes).map_err(|e| match e.kind() {
+            ErrorKind::UnexpectedEof => Error::new(ErrorKind::InvalidData, "Missing iNES header"),
+            _ => e,
+        })?;
+
+        let size = Header::parse(&bytes)?.rom_size();
+        if size > max_size {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "ROM size of {} bytes exceeds the limit of {} bytes",
+                    size, max_size
+                ),
+            ));
+        }
+
+        bytes.resize(size, 0);
+
+        // read_exact doesn't tell how much was read before the stream ended
+        let mut read = 16;
+        while read < size {
+            match reader.read(&mut bytes[read..]) {
+                Ok(0) => return Err(truncated_error(size, read)),
+                Ok(n) => read += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Self::from_bytes(&bytes)
+    }
+
+    /// Load a Famicom Disk System image, with or without the fwNES header, and the 8KB BIOS
+    /// (`disksys.rom`). The first side of the first disk is inserted.
+    pub fn from_fds(disk: &[u8], bios: &[u8]) -> std::io::Result<Self> {
+        if bios.len() != 0x2000 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("FDS BIOS should be 8192 bytes, got {}", bios.len()),
+            ));
+        }
+
+        let sides = disk_sides(disk)?;
+
+        let header = Header {
+            mapper: MapperKind::FDS,
+            mirroring: Mirroring::Horizontal,
+            prg_ram_size: 0x8000,
+            ..Header::default()
+        };
+
+        Ok(Self {
+            header,
+            mapper: FdsMapper::new(&sides, bios).into(),
+            valid: true,
+            ram_loaded: false,
+            crc32: crc32(&[disk, bios]),
         })
     }
 
+    /// Number of disk sides of an FDS image, 0 for other cartridges
+    pub fn disk_side_count(&self) -> usize {
+        match &self.mapper {
+            Mapper::FdsMapper(fds) => fds.side_count(),
+            _ => 0,
+        }
+    }
+
+    pub fn inserted_disk_side(&self) -> Option<usize> {
+        match &self.mapper {
+            Mapper::FdsMapper(fds) => fds.inserted_side(),
+            _ => None,
+        }
+    }
+
+    /// Insert a side of an FDS image, or eject the disk with `None`.
+    pub fn insert_disk_side(&mut self, side: Option<usize>) {
+        if let Mapper::FdsMapper(fds) = &mut self.mapper {
+            fds.insert_side(side);
+        }
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn info(&self) -> CartridgeInfo {
+        CartridgeInfo {
+            mapper: self.header.mapper,
+            submapper: self.header.submapper,
+            prg_rom_kb: self.header.prg_size as usize * 16,
+            chr_rom_kb: self.header.chr_size as usize * 8,
+            prg_ram_kb: self.header.prg_ram_size / 1024,
+            mirroring: self.header.mirroring,
+            battery: self.header.persistence,
+            region: self.header.region,
+        }
+    }
+
     pub fn is_valid(&self) -> bool {
         self.valid
     }
 
+    /// CRC32 of the ROM image, or of the disk image and BIOS on the FDS
+    pub fn crc32(&self) -> u32 {
+        self.crc32
+    }
+
+    /// Take the ROM data, which is left out of savestates, from a cartridge with the same ROM.
+    #[cfg(feature = "savestate")]
+    pub(crate) fn restore_rom(&mut self, rom: &Cartridge) -> Result<(), String> {
+        if self.header != rom.header || self.crc32 != rom.crc32 {
+            return Err("The state was saved with a different ROM".to_string());
+        }
+
+        self.mapper.restore_rom(&rom.mapper);
+        Ok(())
+    }
+
     pub fn mirroring(&self) -> Mirroring {
+        // Four-screen VRAM on the cartridge overrides any mapper controlled mirroring
+        if self.header.mirroring == Mirroring::FourScreen {
+            return Mirroring::FourScreen;
+        }
+
         self.mapper.mirroring().unwrap_or(self.header.mirroring)
     }
 
-    pub fn cpu_read_u8(&mut self, addr: usize) -> u8 {
+    /// Battery-backed PRG-RAM contents, if the cartridge has any.
+    pub fn save_ram(&self) -> Option<&[u8]> {
+        if !self.header.persistence {
+            return None;
+        }
+
+        Some(self.mapper.battery_ram()).filter(|ram| !ram.is_empty())
+    }
+
+    /// Restore battery-backed PRG-RAM. Data with a mismatching length is ignored.
+    pub fn load_ram(&mut self, data: &[u8]) -> bool {
+        match self.save_ram() {
+            Some(ram) if ram.len() == data.len() => {
+                self.mapper.set_battery_ram(data);
+                self.ram_loaded = true;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub(crate) fn ram_loaded(&self) -> bool {
+        self.ram_loaded
+    }
+
+    /// Zero all PRG-RAM, including battery-backed RAM. A trainer loaded to PRG-RAM is kept.
+    pub(crate) fn clear_ram(&mut self) {
+        let ram = self.mapper.battery_ram();
+        let mut cleared = vec![0; ram.len()];
+
+        if self.header.has_trainer() && ram.len() >= 0x1200 {
+            cleared[0x1000..0x1200].copy_from_slice(&ram[0x1000..0x1200]);
+        }
+
+        self.mapper.set_battery_ram(&cleared);
+    }
+
+    pub fn cpu_read_u8(&mut self, addr: usize) -> Option<u8> {
         self.mapper.cpu_read_u8(addr)
     }
 
@@ -52,10 +255,18 @@ impl Cartridge {
         self.mapper.cpu_write_u8(addr, val, cycle);
     }
 
+    pub fn cpu_write_ppu_register(&mut self, addr: usize, val: u8) {
+        self.mapper.cpu_write_ppu_register(addr, val);
+    }
+
     pub fn ppu_read_u8(&mut self, addr: usize) -> Option<u8> {
         self.mapper.ppu_read_u8(addr)
     }
 
+    pub fn ppu_peek_u8(&mut self, addr: usize) -> Option<u8> {
+        self.mapper.ppu_peek_u8(addr)
+    }
+
     pub fn ppu_write_u8(&mut self, addr: usize, val: u8) -> bool {
         self.mapper.ppu_write_u8(addr, val)
     }
@@ -67,4 +278,21 @@ impl Cartridge {
     pub fn clock_irq(&mut self) {
         self.mapper.clock_irq();
     }
+
+    pub fn clock_cpu(&mut self) {
+        self.mapper.clock_cpu();
+    }
+}
+
+fn crc32(chunks: &[&[u8]]) -> u32 {
+    let mut crc = !0u32;
+
+    for &byte in chunks.iter().flat_map(|chunk| chunk.iter()) {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+
+    !crc
 }
@@ -1,15 +1,25 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Write;
-use std::mem;
 use std::ops::{Deref, DerefMut};
+use std::path::Path;
+use std::{io, mem};
 
 use crate::bitwise::{HasBits, HiLoBytes};
-use crate::input::Button;
+use crate::breakpoint::{Breakpoint, Expr};
+use crate::input::{Button, Zapper};
+use crate::movie::FrameInput;
 use crate::nes::Nes;
-use crate::op::{into_op, op_size, to_asm, AccessMode, AddressingMode, OpKind};
+use crate::op::{expected_cycles, into_op, op_size, to_asm, AccessMode, AddressingMode, OpKind};
 use crate::rand_vec;
+use crate::trace::{Trace, TraceEntry, TraceFormat};
 
 const STACK_START_ADDR: u16 = 0x0100;
+/// Calls deeper than this drop the outermost entries of the call stack
+const MAX_CALL_STACK_DEPTH: usize = 256;
+
+/// CPU cycles after power or reset during which the PPU ignores writes to
+/// $2000, $2001, $2005 and $2006
+const PPU_WARMUP_CYCLES: u128 = 29658;
 
 /// Carry
 const C: u8 = 0b0000_0001;
@@ -28,6 +38,7 @@ const O: u8 = 0b0100_0000;
 /// Negative
 const N: u8 = 0b1000_0000;
 
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
 pub struct Cpu {
     /// Accumulator
     pub a: u8,
@@ -45,13 +56,24 @@ pub struct Cpu {
     pub branch_taken: bool,
     pub page_crossed: bool,
 
-    internal_ram: Vec<u8>,
+    /// Set if the CPU was halted by a DMA during the current op
+    dma_halted_op: bool,
+
+    pub(crate) internal_ram: Vec<u8>,
 
     pending_oamdma: OamDmaStatus,
+    pending_dmcdma: Option<DmcDmaStatus>,
+    /// Cycle on which the last DMC DMA completed
+    dmcdma_completed_at: Option<u128>,
+    /// Emulate the controller bits that are lost when a DMC DMA interrupts a read of
+    /// $4016 or $4017. Games that read the controllers while DMC samples play work around this.
+    pub dmc_controller_conflict: bool,
 
     nmi_pending: Option<u8>,
 
     op_kind: Option<OpKind>,
+    /// Opcode of the current op
+    opcode: u8,
     addressing_mode: AddressingMode,
     access_mode: AccessMode,
 
@@ -70,12 +92,41 @@ pub struct Cpu {
     /// Address of the last opcode
     op_start_addr: u16,
 
-    breakpoints: HashSet<u16>,
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    pub(crate) breakpoints: HashMap<u16, Breakpoint>,
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    pub(crate) read_breakpoints: HashSet<u16>,
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    pub(crate) write_breakpoints: HashSet<u16>,
+
+    /// One-shot breakpoint used by step over and run to cursor, as (address, minimum stack
+    /// pointer). It only triggers while S is at or above the minimum, so that recursive calls
+    /// don't stop a step over early.
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    temporary_breakpoint: Option<(u16, u8)>,
 
     breakpoint_reached: bool,
+    /// Set when a read or write breakpoint was hit during the current cycle
+    data_breakpoint_hit: Option<String>,
+
+    /// Set when a KIL opcode has jammed the CPU
+    halted: bool,
+
+    /// Last value on the CPU data bus, returned by reads from unmapped addresses
+    cpu_open_bus: u8,
+
+    /// Entry addresses of the subroutines and interrupt handlers currently being executed
+    call_stack: Vec<u16>,
+
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    pub(crate) trace: Option<Trace>,
 
     input_p1: u8,
     input_p2: u8,
+    /// Microphone of the Famicom's second controller
+    microphone: bool,
+    /// Zapper connected to the second port in place of a controller
+    zapper: Option<Zapper>,
 
     controller_p1: u8,
     controller_p2: u8,
@@ -92,6 +143,7 @@ impl Cpu {
             p: 0x34,
             branch_taken: false,
             page_crossed: false,
+            dma_halted_op: false,
 
             internal_ram: rand_vec![0x0800],
             pending_oamdma: OamDmaStatus {
@@ -99,12 +151,17 @@ impl Cpu {
                 reading: false,
                 byte: 0,
                 cycle: 0,
-                idx: 0xFF,
+                // No transfer in progress
+                idx: 0x100,
             },
+            pending_dmcdma: None,
+            dmcdma_completed_at: None,
+            dmc_controller_conflict: true,
 
             nmi_pending: None,
 
             op_kind: None,
+            opcode: 0,
             addressing_mode: AddressingMode::Implied,
             access_mode: AccessMode::Read,
             temp_addr: 0,
@@ -113,30 +170,57 @@ impl Cpu {
             prev_op_cycles: 0,
             current_op_cycle: 0,
             op_start_addr: 0,
-            breakpoints: HashSet::new(),
+            breakpoints: HashMap::new(),
+            read_breakpoints: HashSet::new(),
+            write_breakpoints: HashSet::new(),
+            temporary_breakpoint: None,
             breakpoint_reached: false,
+            data_breakpoint_hit: None,
+            halted: false,
+            cpu_open_bus: 0,
+            call_stack: Vec::new(),
+            trace: None,
 
             input_p1: 0,
             input_p2: 0,
+            microphone: false,
+            zapper: None,
 
             controller_p1: 0,
             controller_p2: 0,
         }
     }
 
+    /// Start the reset sequence. A, X, Y, RAM and the connected peripherals are kept, and the
+    /// sequence decrements S by 3, sets I and reads the reset vector.
     pub fn reset(&mut self) {
         *self = Cpu {
             a: self.a,
             x: self.x,
             y: self.y,
-            p: self.p | 0x04,
-            s: self.s.wrapping_sub(3),
+            p: self.p,
+            s: self.s,
             internal_ram: mem::take(&mut self.internal_ram),
             breakpoints: mem::take(&mut self.breakpoints),
+            read_breakpoints: mem::take(&mut self.read_breakpoints),
+            write_breakpoints: mem::take(&mut self.write_breakpoints),
+            trace: self.trace.take(),
+            dmc_controller_conflict: self.dmc_controller_conflict,
+            input_p1: self.input_p1,
+            input_p2: self.input_p2,
+            microphone: self.microphone,
+            zapper: self.zapper,
+            op_kind: Some(OpKind::Reset),
+            addressing_mode: AddressingMode::Implied,
             ..Cpu::new()
         }
     }
 
+    /// Whether the reset sequence started by [`Cpu::reset`] is still running.
+    pub fn is_resetting(&self) -> bool {
+        self.op_kind == Some(OpKind::Reset)
+    }
+
     pub fn set_button_state_player1(&mut self, button: Button, state: bool) {
         if state {
             self.input_p1 |= button as u8;
@@ -145,7 +229,7 @@ impl Cpu {
         }
     }
 
-    pub fn _set_button_state_player2(&mut self, button: Button, state: bool) {
+    pub fn set_button_state_player2(&mut self, button: Button, state: bool) {
         if state {
             self.input_p2 |= button as u8;
         } else {
@@ -153,14 +237,77 @@ impl Cpu {
         }
     }
 
+    /// Set whether sound is picked up by the microphone of the second controller.
+    /// It is reported in bit 2 of $4016.
+    pub fn set_microphone(&mut self, state: bool) {
+        self.microphone = state;
+    }
+
+    /// Connect a Zapper to the second port, or a controller if `None`.
+    pub fn set_zapper(&mut self, zapper: Option<Zapper>) {
+        self.zapper = zapper;
+    }
+
+    pub(crate) fn input(&self) -> FrameInput {
+        FrameInput {
+            player1: self.input_p1,
+            player2: self.input_p2,
+            reset: false,
+        }
+    }
+
+    pub(crate) fn set_input(&mut self, input: FrameInput) {
+        self.input_p1 = input.player1;
+        self.input_p2 = input.player2;
+    }
+
     pub fn instruction_ongoing(&self) -> bool {
         self.op_kind.is_some()
     }
 
+    /// Returns true if the CPU has been jammed by a KIL opcode. Only a reset will recover it.
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Write a line with the register state to `path` before each instruction is executed.
+    pub fn enable_trace<P: AsRef<Path>>(&mut self, path: P, format: TraceFormat) -> io::Result<()> {
+        self.trace = Some(Trace::create(path, format)?);
+        Ok(())
+    }
+
+    pub fn disable_trace(&mut self) {
+        self.trace = None;
+    }
+
+    pub fn is_trace_enabled(&self) -> bool {
+        self.trace.is_some()
+    }
+
+    /// Entry addresses of the subroutines and interrupt handlers that led to the current PC,
+    /// outermost first. Games that manipulate the return addresses on the stack manually can
+    /// make this inaccurate.
+    pub fn call_stack(&self) -> &[u16] {
+        &self.call_stack
+    }
+
+    fn push_call(&mut self, addr: u16) {
+        // Keep the stack bounded for games that never return from their subroutines
+        if self.call_stack.len() >= MAX_CALL_STACK_DEPTH {
+            self.call_stack.remove(0);
+        }
+        self.call_stack.push(addr);
+    }
+
+    fn pop_call(&mut self) {
+        self.call_stack.pop();
+    }
+
     pub fn clock(nes: &mut Nes) -> Result<(), String> {
         let ctx = CpuContext {
             nes,
             read_only: false,
+            external: false,
         };
 
         ctx.clock()
@@ -170,6 +317,7 @@ impl Cpu {
         CpuContext {
             nes,
             read_only: true,
+            external: true,
         }
         .read_mem_u8(addr)
     }
@@ -178,16 +326,41 @@ impl Cpu {
         CpuContext {
             nes,
             read_only: false,
+            external: true,
         }
-        .write_mem_u8(addr, val)
+        .write_mem_u8(addr, val);
+
+        // Writes from outside the emulation loop should not trigger write breakpoints
+        nes.cpu.data_breakpoint_hit = None;
     }
 
     pub fn is_breakpoint(&self, addr: u16) -> bool {
-        self.breakpoints.contains(&addr)
+        self.breakpoints.contains_key(&addr)
+    }
+
+    pub fn breakpoint(&self, addr: u16) -> Option<&Breakpoint> {
+        self.breakpoints.get(&addr)
     }
 
     pub fn set_breakpoint(&mut self, addr: u16) {
-        self.breakpoints.insert(addr);
+        self.breakpoints.insert(
+            addr,
+            Breakpoint {
+                addr,
+                condition: None,
+            },
+        );
+    }
+
+    /// Set a breakpoint that stops only when `condition` evaluates to non-zero.
+    pub fn set_conditional_breakpoint(&mut self, addr: u16, condition: Expr) {
+        self.breakpoints.insert(
+            addr,
+            Breakpoint {
+                addr,
+                condition: Some(condition),
+            },
+        );
     }
 
     pub fn clear_breakpoint(&mut self, addr: u16) {
@@ -201,11 +374,67 @@ impl Cpu {
             self.set_breakpoint(addr);
         }
     }
+
+    /// Set a breakpoint that is removed once it is hit.
+    pub fn set_temporary_breakpoint(&mut self, addr: u16) {
+        self.temporary_breakpoint = Some((addr, 0));
+    }
+
+    pub(crate) fn set_temporary_breakpoint_with_stack(&mut self, addr: u16, min_s: u8) {
+        self.temporary_breakpoint = Some((addr, min_s));
+    }
+
+    pub fn temporary_breakpoint(&self) -> Option<u16> {
+        self.temporary_breakpoint.map(|(addr, _)| addr)
+    }
+
+    pub fn clear_temporary_breakpoint(&mut self) {
+        self.temporary_breakpoint = None;
+    }
+
+    pub fn is_read_breakpoint(&self, addr: u16) -> bool {
+        self.read_breakpoints.contains(&addr)
+    }
+
+    pub fn set_read_breakpoint(&mut self, addr: u16) {
+        self.read_breakpoints.insert(addr);
+    }
+
+    pub fn clear_read_breakpoint(&mut self, addr: u16) {
+        self.read_breakpoints.remove(&addr);
+    }
+
+    pub fn read_breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.read_breakpoints.iter().copied()
+    }
+
+    pub fn is_write_breakpoint(&self, addr: u16) -> bool {
+        self.write_breakpoints.contains(&addr)
+    }
+
+    pub fn set_write_breakpoint(&mut self, addr: u16) {
+        self.write_breakpoints.insert(addr);
+    }
+
+    pub fn clear_write_breakpoint(&mut self, addr: u16) {
+        self.write_breakpoints.remove(&addr);
+    }
+
+    pub fn write_breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.write_breakpoints.iter().copied()
+    }
+
+    /// Take the message of a read or write breakpoint hit since the last call.
+    pub(crate) fn take_data_breakpoint_hit(&mut self) -> Option<String> {
+        self.data_breakpoint_hit.take()
+    }
 }
 
 struct CpuContext<'a> {
     nes: &'a mut Nes,
     read_only: bool,
+    /// Memory is accessed from outside the emulation loop, e.g. by the debugger
+    external: bool,
 }
 
 impl<'a> Deref for CpuContext<'a> {
@@ -223,8 +452,25 @@ impl<'a> DerefMut for CpuContext<'a> {
 }
 
 impl CpuContext<'_> {
+    fn breakpoint_condition_met(&mut self, addr: u16) -> bool {
+        match self.breakpoints.get(&addr).cloned() {
+            Some(breakpoint) => breakpoint.condition_met(self.nes),
+            None => false,
+        }
+    }
+
     fn clock(mut self) -> Result<(), String> {
-        if !self.instruction_ongoing() && self.is_breakpoint(self.pc) {
+        if !self.instruction_ongoing() && !self.breakpoint_reached {
+            if let Some((addr, min_s)) = self.temporary_breakpoint {
+                if addr == self.pc && self.s >= min_s {
+                    self.temporary_breakpoint = None;
+                    self.breakpoint_reached = true;
+                    return Err("Breakpoint reached".to_string());
+                }
+            }
+        }
+
+        if !self.instruction_ongoing() && self.breakpoint_condition_met(self.pc) {
             self.breakpoint_reached = !self.breakpoint_reached;
 
             if self.breakpoint_reached {
@@ -235,8 +481,19 @@ impl CpuContext<'_> {
 
         self.cycles += 1;
 
+        if self.halted {
+            return Ok(());
+        }
+
+        if self.clock_dmcdma() {
+            // CPU is suspended while DMC sample fetch in progress.
+            self.dma_halted_op = self.instruction_ongoing();
+            return Ok(());
+        }
+
         if self.clock_oamdma() {
             // CPU is suspended while OAMDMA writing in progress.
+            self.dma_halted_op = self.instruction_ongoing();
             return Ok(());
         }
 
@@ -292,6 +549,34 @@ impl CpuContext<'_> {
         }
     }
 
+    fn clock_dmcdma(&mut self) -> bool {
+        if self.pending_dmcdma.is_none() {
+            if let Some(addr) = self.nes.apu.dmc_dma_request() {
+                self.pending_dmcdma = Some(DmcDmaStatus { addr, cycle: 0 });
+            }
+        }
+
+        match self.pending_dmcdma.as_mut() {
+            Some(dma) => {
+                dma.cycle += 1;
+
+                // Halt, dummy and alignment cycles before the sample byte is read
+                if dma.cycle >= 4 {
+                    let addr = dma.addr;
+                    self.pending_dmcdma = None;
+
+                    let val = self.read_mem_u8(addr);
+                    self.nes.apu.dmc_dma_complete(val);
+
+                    self.dmcdma_completed_at = Some(self.cycles);
+                }
+
+                true
+            }
+            None => false,
+        }
+    }
+
     fn implied(&mut self) {
         if let Some(op_kind) = self.op_kind {
             match op_kind {
@@ -321,7 +606,9 @@ impl CpuContext<'_> {
                 OpKind::Brk => self.brk(),
                 OpKind::Nmi => self.nmi(),
                 OpKind::Irq => self.irq(),
+                OpKind::Reset => self.reset_sequence(),
                 OpKind::Nop => self.nop(),
+                OpKind::Kil => self.kil(),
 
                 op_kind if self.current_op_cycle > 8 => panic!(
                     "No operation implemented for ({:?}) ({:?}) ({:?}) (op cycle {})",
@@ -371,6 +658,12 @@ impl CpuContext<'_> {
         self.complete_instruction()
     }
 
+    fn kil(&mut self) {
+        self.halted = true;
+        self.pc = self.op_start_addr;
+        self.complete_instruction();
+    }
+
     fn nmi(&mut self) {
         match self.current_op_cycle {
             2 => self.push_stack_u8(self.pc.high_u8()),
@@ -385,6 +678,8 @@ impl CpuContext<'_> {
             6 => self.temp_value |= (self.read_mem_u8(0xFFFB) as u16) << 8,
             7 => {
                 self.pc = self.temp_value;
+                let pc = self.pc;
+                self.push_call(pc);
                 self.complete_instruction();
             }
             _ => {}
@@ -403,6 +698,28 @@ impl CpuContext<'_> {
             }
             5 => self.temp_value = self.read_mem_u8(0xFFFE) as u16,
             6 => self.temp_value |= (self.read_mem_u8(0xFFFF) as u16) << 8,
+            7 => {
+                self.pc = self.temp_value;
+                let pc = self.pc;
+                self.push_call(pc);
+                self.complete_instruction();
+            }
+            _ => {}
+        }
+    }
+
+    /// Like an interrupt, but the stack writes are turned into reads.
+    fn reset_sequence(&mut self) {
+        match self.current_op_cycle {
+            2..=4 => {
+                self.read_mem_u8(0x100 | self.s as u16);
+                self.s = self.s.wrapping_sub(1);
+                if self.current_op_cycle == 4 {
+                    self.set_status_flag(I, true);
+                }
+            }
+            5 => self.temp_value = self.read_mem_u8(0xFFFC) as u16,
+            6 => self.temp_value |= (self.read_mem_u8(0xFFFD) as u16) << 8,
             7 => {
                 self.pc = self.temp_value;
                 self.complete_instruction();
@@ -427,6 +744,8 @@ impl CpuContext<'_> {
             6 => self.pc = self.read_mem_u8(0xFFFE) as u16,
             7 => {
                 self.pc |= (self.read_mem_u8(0xFFFF) as u16) << 8;
+                let pc = self.pc;
+                self.push_call(pc);
                 self.complete_instruction();
             }
             _ => {}
@@ -511,6 +830,7 @@ impl CpuContext<'_> {
             }
             6 => {
                 self.pc |= (self.read_stack_u8() as u16) << 8;
+                self.pop_call();
                 self.complete_instruction();
             }
             _ => {}
@@ -534,6 +854,7 @@ impl CpuContext<'_> {
             }
             6 => {
                 self.increment_pc();
+                self.pop_call();
                 self.complete_instruction();
             }
             _ => {}
@@ -573,11 +894,12 @@ impl CpuContext<'_> {
                 }
             }
             5 => {
-                self.read_from_effective_addr_low();
-
                 if !self.page_crossed && self.access_mode == AccessMode::Read {
+                    self.read_from_effective_addr_low();
                     self.do_read_operation();
                     self.complete_instruction();
+                } else {
+                    self.dummy_read_unfixed_addr();
                 }
             }
             6 => {
@@ -776,7 +1098,7 @@ impl CpuContext<'_> {
                     self.do_read_operation();
                     self.complete_instruction();
                 } else {
-                    self.read_from_effective_addr_low();
+                    self.dummy_read_unfixed_addr();
                 }
             }
             5 => match self.access_mode {
@@ -868,6 +1190,8 @@ impl CpuContext<'_> {
             6 => {
                 self.read_addr_high();
                 self.pc = self.temp_addr;
+                let pc = self.pc;
+                self.push_call(pc);
                 self.complete_instruction()
             }
             _ => {}
@@ -905,23 +1229,60 @@ impl CpuContext<'_> {
             self.log_op_asm(self.op_start_addr, self.current_op_cycle);
         }
 
+        if cfg!(debug_assertions) {
+            self.check_op_cycles();
+        }
+
         self.op_kind = None;
         self.branch_taken = false;
         self.page_crossed = false;
+        self.dma_halted_op = false;
 
         self.prev_op_cycles = self.current_op_cycle;
         self.current_op_cycle = 0;
 
+        if self.halted {
+            // Interrupts are not serviced while jammed
+            return;
+        }
+
         if self.nmi_pending.is_some() {
             self.nmi_pending = None;
             self.op_kind = Some(OpKind::Nmi);
             self.addressing_mode = AddressingMode::Implied;
-        } else if self.nes.cart.irq_triggered() && !self.is_interrupt_disable_flag_set() {
+        } else if (self.nes.cart.irq_triggered() || self.nes.apu.irq_triggered())
+            && !self.is_interrupt_disable_flag_set()
+        {
             self.op_kind = Some(OpKind::Irq);
             self.addressing_mode = AddressingMode::Implied;
         }
     }
 
+    /// Warn if the op took a different number of cycles than the reference table says.
+    fn check_op_cycles(&self) {
+        if matches!(
+            self.op_kind,
+            Some(OpKind::Nmi | OpKind::Irq | OpKind::Reset)
+        ) {
+            return;
+        }
+
+        let expected = match expected_cycles(self.opcode, self.page_crossed, self.branch_taken) {
+            Some(expected) => expected,
+            None => return,
+        };
+
+        if self.current_op_cycle != expected {
+            log::warn!(
+                "Op ${:02X} at ${:04X} took {} cycles, expected {}",
+                self.opcode,
+                self.op_start_addr,
+                self.current_op_cycle,
+                expected
+            );
+        }
+    }
+
     fn effective_cpu_addr(&self, addr: u16) -> u16 {
         match addr {
             0x0800..=0x1FFF => addr & 0x07FF,
@@ -931,30 +1292,56 @@ impl CpuContext<'_> {
     }
 
     fn read_mem_u8(&mut self, addr: u16) -> u8 {
+        if !self.read_only && self.is_read_breakpoint(addr) {
+            self.data_breakpoint_hit = Some(format!("Read breakpoint reached at ${:04X}", addr));
+        }
+
         let addr = self.effective_cpu_addr(addr) as usize;
 
-        match addr {
+        // $4015 is read internally in the CPU, so it does not drive the external data bus
+        if addr == 0x4015 {
+            return self.read_apu_status();
+        }
+
+        let val = match addr {
             0x0000..=0x7FF => self.internal_ram[addr],
             0x2000 => self.read_ppu_open_bus(),
             0x2001 => self.read_ppu_open_bus(),
             0x2002 => self.read_ppu_status(),
             0x2003 => self.read_ppu_open_bus(),
-            0x2004 => self.read_oam_data(),
+            0x2004 => self.nes.ppu.read_oam_data(self.read_only),
             0x2005 => self.read_ppu_open_bus(),
             0x2006 => self.read_ppu_open_bus(),
-            0x4000..=0x4007 => self.read_ppu_open_bus(),
             0x2007 => self.read_ppu_data(),
-            0x4015 => 0,
             0x4016 => self.read_controller_p1(),
             0x4017 => self.read_controller_p2(),
-            _ => self.nes.cart.cpu_read_u8(addr),
+            0x4000..=0x401F => self.cpu_open_bus,
+            0x8000..=0xFFFF => {
+                let val = self.nes.cart.cpu_read_u8(addr).unwrap_or(self.cpu_open_bus);
+                self.nes
+                    .game_genie_codes
+                    .iter()
+                    .fold(val, |val, code| code.apply(addr as u16, val))
+            }
+            _ => self.nes.cart.cpu_read_u8(addr).unwrap_or(self.cpu_open_bus),
+        };
+
+        if !self.read_only {
+            self.cpu_open_bus = val;
         }
+
+        val
     }
 
     fn read_ppu_status(&mut self) -> u8 {
         self.nes.ppu.read_ppu_status(self.read_only)
     }
 
+    fn read_apu_status(&mut self) -> u8 {
+        // Bit 5 is not driven
+        self.nes.apu.read_status(self.read_only) | (self.cpu_open_bus & 0x20)
+    }
+
     fn read_ppu_open_bus(&mut self) -> u8 {
         self.nes.ppu.open_bus
     }
@@ -963,19 +1350,23 @@ impl CpuContext<'_> {
         self.nes.ppu.read_ppu_data(self.read_only)
     }
 
-    fn read_oam_data(&mut self) -> u8 {
-        // Bits 2-4 of sprite attributes should always be clear when read
-
-        // TODO reads during vertical or forced blanking return the value from OAM at that address but do not increment.
-        // TODO Reading OAMDATA while the PPU is rendering will expose internal OAM accesses during sprite evaluation and loading; Micro Machines does this.
-
-        let val = self.nes.ppu.primary_oam[self.nes.ppu.oam_addr as usize] & 0xE3;
-        self.nes.ppu.write_open_bus(val, false);
-        val
+    /// A DMC DMA halts the CPU on a read cycle, and the halted CPU keeps repeating that read
+    /// until the DMA is done. If a controller register is read right after a DMA, the halted
+    /// reads have already clocked the controller once, and a bit is lost.
+    fn controller_read_repeated_by_dmcdma(&self) -> bool {
+        self.dmc_controller_conflict
+            && !self.read_only
+            && self.dmcdma_completed_at == Some(self.cycles - 1)
     }
 
     fn read_controller_p1(&mut self) -> u8 {
-        let val = self.controller_p1 >> 7;
+        if self.controller_read_repeated_by_dmcdma() {
+            self.controller_p1 <<= 1;
+        }
+
+        // Only the lowest bits are driven by the controller port
+        let val =
+            (self.controller_p1 >> 7) | (self.microphone as u8) << 2 | (self.cpu_open_bus & 0xE0);
         if !self.read_only {
             self.controller_p1 <<= 1;
         }
@@ -983,7 +1374,17 @@ impl CpuContext<'_> {
     }
 
     fn read_controller_p2(&mut self) -> u8 {
-        let val = self.controller_p2 >> 7;
+        if let Some(zapper) = self.zapper {
+            // The light bit is 0 when light is sensed
+            let light = self.nes.ppu.zapper_light_sensed(zapper.x, zapper.y);
+            return (!light as u8) << 3 | (zapper.trigger as u8) << 4 | (self.cpu_open_bus & 0xE0);
+        }
+
+        if self.controller_read_repeated_by_dmcdma() {
+            self.controller_p2 <<= 1;
+        }
+
+        let val = (self.controller_p2 >> 7) | (self.cpu_open_bus & 0xE0);
         if !self.read_only {
             self.controller_p2 <<= 1;
         }
@@ -995,11 +1396,24 @@ impl CpuContext<'_> {
     }
 
     fn write_mem_u8(&mut self, addr: u16, val: u8) {
+        if !self.read_only && self.is_write_breakpoint(addr) {
+            self.data_breakpoint_hit = Some(format!("Write breakpoint reached at ${:04X}", addr));
+        }
+
+        if !self.read_only {
+            self.cpu_open_bus = val;
+        }
+
         let addr = self.effective_cpu_addr(addr) as usize;
 
+        if let 0x2000..=0x2007 = addr {
+            self.nes.cart.cpu_write_ppu_register(addr, val);
+        }
+
         match addr {
             0x0000..=0x7FF => self.internal_ram[addr] = val,
 
+            0x2000 | 0x2001 | 0x2005 | 0x2006 if self.ppu_warming_up() => {}
             0x2001 => self.write_ppu_mask(val),
             0x2000 => self.write_ppu_ctrl(val),
             0x2002 => self.nes.ppu.write_open_bus(val, true),
@@ -1009,18 +1423,17 @@ impl CpuContext<'_> {
             0x2006 => self.nes.ppu.write_ppu_addr(val),
             0x2007 => self.nes.ppu.write_vram(val),
 
-            0x4000..=0x4013 => {}
+            0x4000..=0x4013 => self.nes.apu.write_register(addr as u16, val),
 
             0x4014 => self.write_oamdma(val),
 
-            0x4015 => {}
+            0x4015 => self.nes.apu.write_register(addr as u16, val),
 
             0x4016 => {
                 self.controller_p1 = self.input_p1;
-            }
-            0x4017 => {
                 self.controller_p2 = self.input_p2;
             }
+            0x4017 => self.nes.apu.write_register(addr as u16, val),
 
             _ => {
                 let cycles = self.cycles;
@@ -1029,6 +1442,11 @@ impl CpuContext<'_> {
         }
     }
 
+    /// The PPU ignores some register writes until it has warmed up after power or reset.
+    fn ppu_warming_up(&self) -> bool {
+        !self.external && self.cycles < PPU_WARMUP_CYCLES
+    }
+
     fn write_ppu_mask(&mut self, val: u8) {
         self.nes.ppu.write_ppu_mask(val);
     }
@@ -1047,6 +1465,41 @@ impl CpuContext<'_> {
         };
     }
 
+    fn write_trace(&mut self, opcode: u8) {
+        let (op_kind, addressing_mode) = match into_op(opcode) {
+            Some((op_kind, addressing_mode, _)) => (op_kind, addressing_mode),
+            None => (OpKind::Invalid, AddressingMode::Implied),
+        };
+
+        // Operands are read without side effects, as the instruction has not read them yet
+        let bytes = (0..op_size(addressing_mode) as u16)
+            .map(|i| Cpu::read_mem_u8(self.nes, self.op_start_addr.wrapping_add(i)))
+            .collect();
+
+        let entry = TraceEntry {
+            pc: self.op_start_addr,
+            op_kind,
+            addressing_mode,
+            bytes,
+            a: self.a,
+            x: self.x,
+            y: self.y,
+            p: self.p,
+            s: self.s,
+            scanline: self.nes.ppu.current_scanline(),
+            dot: self.nes.ppu.current_cycle(),
+            // The opcode fetch has already been counted
+            cycles: self.cycles - 1,
+        };
+
+        if let Some(trace) = self.trace.as_mut() {
+            if let Err(e) = trace.write(&entry) {
+                log::error!("Failed to write trace: {}", e);
+                self.trace = None;
+            }
+        }
+    }
+
     fn log_op_asm(&mut self, addr: u16, cycles: u8) {
         if self.op_kind == Some(OpKind::Nmi) {
             log::debug!("NMI");
@@ -1126,13 +1579,27 @@ impl CpuContext<'_> {
         self.temp_value = self.read_mem_u8(self.temp_addr) as u16;
     }
 
+    /// Indexed addressing reads from the effective address before the carry from the low
+    /// byte has been added to the high byte. The value is thrown away, but the read can
+    /// still have side effects on registers.
+    fn dummy_read_unfixed_addr(&mut self) {
+        let addr = if self.page_crossed {
+            self.temp_addr.wrapping_sub(0x100)
+        } else {
+            self.temp_addr
+        };
+
+        self.read_mem_u8(addr);
+    }
+
     fn write_to_effective_addr(&mut self) {
         self.write_mem_u8(self.temp_addr, self.temp_value.low_u8());
     }
 
     fn do_read_operation(&mut self) {
         match self.op_kind.unwrap() {
-            OpKind::Dop => self.nop(),
+            // The addressing mode completes the op
+            OpKind::Dop => {}
             OpKind::Ldx => self.ldx(),
             OpKind::Ldy => self.ldy(),
             OpKind::Lda => self.lda(),
@@ -1147,6 +1614,11 @@ impl CpuContext<'_> {
             OpKind::Sbc => self.sbc(),
             OpKind::Aac => self.aac(),
             OpKind::Asr => self.asr(),
+            OpKind::Lax => self.lax(),
+            OpKind::Las => self.las(),
+            OpKind::Arr => self.arr(),
+            OpKind::Xaa => self.xaa(),
+            OpKind::Axs => self.axs(),
 
             op_kind => panic!(
                 "No read operation implemented for {:?} {:?} {:?}",
@@ -1160,6 +1632,11 @@ impl CpuContext<'_> {
             OpKind::Sta => self.sta(),
             OpKind::Stx => self.stx(),
             OpKind::Sty => self.sty(),
+            OpKind::Sax => self.sax(),
+            OpKind::Shy => self.shy(),
+            OpKind::Shx => self.shx(),
+            OpKind::Ahx => self.ahx(),
+            OpKind::Tas => self.tas(),
             op_kind => panic!(
                 "No write operation implemented for {:?} {:?} {:?}",
                 op_kind, self.addressing_mode, self.access_mode
@@ -1175,6 +1652,12 @@ impl CpuContext<'_> {
             OpKind::Asl => self.asl(),
             OpKind::Ror => self.ror(),
             OpKind::Rol => self.rol(),
+            OpKind::Slo => self.slo(),
+            OpKind::Rla => self.rla(),
+            OpKind::Sre => self.sre(),
+            OpKind::Rra => self.rra(),
+            OpKind::Dcp => self.dcp(),
+            OpKind::Isc => self.isc(),
 
             op_kind => panic!(
                 "No modify operation implemented for {:?} {:?} {:?}",
@@ -1193,6 +1676,10 @@ impl CpuContext<'_> {
         let opcode = self.read_next_pc_u8();
         self.increment_pc();
 
+        if self.trace.is_some() {
+            self.write_trace(opcode);
+        }
+
         let (op_kind, addressing_mode, access_mode) = into_op(opcode).ok_or_else(|| {
             format!(
                 "Unknown opcode at ${:04X}: ${:02X}",
@@ -1201,6 +1688,7 @@ impl CpuContext<'_> {
         })?;
 
         self.op_kind = Some(op_kind);
+        self.opcode = opcode;
         self.addressing_mode = addressing_mode;
         self.access_mode = access_mode;
 
@@ -1581,6 +2069,130 @@ impl CpuContext<'_> {
         self.set_status_flag(N, self.a.has_bits(0x80));
     }
 
+    fn lax(&mut self) {
+        self.lda();
+        self.x = self.a;
+    }
+
+    fn sax(&mut self) {
+        self.write_mem_u8(self.temp_addr, self.a & self.x);
+    }
+
+    fn arr(&mut self) {
+        let carry = self.is_carry_flag_set() as u8;
+
+        self.a &= self.temp_value.low_u8();
+        self.a = (self.a >> 1) | (carry << 7);
+
+        // C is taken from bit 6 of the result, V from bit 6 XOR bit 5
+        self.set_status_flag(C, self.a.has_bits(0x40));
+        self.set_status_flag(O, ((self.a >> 6) ^ (self.a >> 5)).has_bits(1));
+        self.set_status_flag(Z, self.a == 0);
+        self.set_status_flag(N, self.a.has_bits(0x80));
+    }
+
+    fn xaa(&mut self) {
+        // The result depends on an unstable "magic" constant, 0xEE being the most common
+        self.a = (self.a | 0xEE) & self.x & self.temp_value.low_u8();
+
+        self.set_status_flag(Z, self.a == 0);
+        self.set_status_flag(N, self.a.has_bits(0x80));
+    }
+
+    fn axs(&mut self) {
+        let val = self.a & self.x;
+        let operand = self.temp_value.low_u8();
+
+        self.x = val.wrapping_sub(operand);
+
+        self.set_status_flag(C, val >= operand);
+        self.set_status_flag(Z, self.x == 0);
+        self.set_status_flag(N, self.x.has_bits(0x80));
+    }
+
+    fn las(&mut self) {
+        let val = self.temp_value.low_u8() & self.s;
+
+        self.a = val;
+        self.x = val;
+        self.s = val;
+
+        self.set_status_flag(Z, val == 0);
+        self.set_status_flag(N, val.has_bits(0x80));
+    }
+
+    fn shy(&mut self) {
+        self.store_and_high_addr_byte(self.y);
+    }
+
+    fn shx(&mut self) {
+        self.store_and_high_addr_byte(self.x);
+    }
+
+    fn ahx(&mut self) {
+        self.store_and_high_addr_byte(self.a & self.x);
+    }
+
+    fn tas(&mut self) {
+        self.s = self.a & self.x;
+        self.store_and_high_addr_byte(self.s);
+    }
+
+    /// Store the value ANDed with the high byte of the unindexed target address + 1.
+    /// If the indexing crossed a page, the stored value also replaces the high byte of the
+    /// target address.
+    fn store_and_high_addr_byte(&mut self, val: u8) {
+        let high = self.temp_addr.high_u8();
+        let base_high = if self.page_crossed {
+            high.wrapping_sub(1)
+        } else {
+            high
+        };
+
+        // The AND is skipped if a DMA halted the CPU during the op
+        let val = if self.dma_halted_op {
+            val
+        } else {
+            val & base_high.wrapping_add(1)
+        };
+
+        if self.page_crossed {
+            self.temp_addr = ((val as u16) << 8) | self.temp_addr.low_u8() as u16;
+        }
+
+        self.write_mem_u8(self.temp_addr, val);
+    }
+
+    fn slo(&mut self) {
+        self.asl();
+        self.ora();
+    }
+
+    fn rla(&mut self) {
+        self.rol();
+        self.and();
+    }
+
+    fn sre(&mut self) {
+        self.lsr();
+        self.eor();
+    }
+
+    fn rra(&mut self) {
+        self.ror();
+        self.adc();
+    }
+
+    fn dcp(&mut self) {
+        self.dec();
+        self.cmp();
+    }
+
+    fn isc(&mut self) {
+        self.inc();
+        self.sbc();
+    }
+
     /// Write a value to stack and decrement the stack pointer.
     fn push_stack_u8(&mut self, val: u8) {
         self.write_stack_u8(val);
@@ -1620,6 +2232,7 @@ impl CpuContext<'_> {
     }
 }
 
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
 struct OamDmaStatus {
     addr: u16,
     reading: bool,
@@ -1627,3 +2240,9 @@ struct OamDmaStatus {
     cycle: u16,
     idx: u16,
 }
+
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+struct DmcDmaStatus {
+    addr: u16,
+    cycle: u8,
+}
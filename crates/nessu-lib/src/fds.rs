@@ -0,0 +1,121 @@
+use std::io::{Error, ErrorKind};
+
+/// Size of one disk side in an `.fds` image
+pub const SIDE_SIZE: usize = 65500;
+
+const HEADER_MAGIC: &[u8] = b"FDS\x1A";
+const DISK_INFO_MAGIC: &[u8] = b"\x01*NINTENDO-HVC*";
+
+/// Whether `bytes` look like an `.fds` disk image, with or without the 16-byte header
+pub fn is_disk_image(bytes: &[u8]) -> bool {
+    bytes.starts_with(HEADER_MAGIC) || bytes.starts_with(DISK_INFO_MAGIC)
+}
+
+/// Split an `.fds` disk image into its sides. The 16-byte fwNES header is optional.
+pub fn disk_sides(bytes: &[u8]) -> std::io::Result<Vec<Vec<u8>>> {
+    let data = if bytes.starts_with(HEADER_MAGIC) {
+        &bytes[16.min(bytes.len())..]
+    } else {
+        bytes
+    };
+
+    if data.is_empty() || data.len() % SIDE_SIZE != 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "FDS image size {} is not a multiple of {} bytes",
+                data.len(),
+                SIDE_SIZE
+            ),
+        ));
+    }
+
+    let sides: Vec<Vec<u8>> = data.chunks(SIDE_SIZE).map(<[u8]>::to_vec).collect();
+
+    if !sides.iter().all(|side| side.starts_with(DISK_INFO_MAGIC)) {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "FDS disk side is missing its disk info block",
+        ));
+    }
+
+    Ok(sides)
+}
+
+/// A file on a disk side, from its file header block
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DiskFile {
+    pub number: u8,
+    pub id: u8,
+    pub name: String,
+    /// CPU or PPU address the file is loaded to
+    pub load_addr: u16,
+    pub size: u16,
+    /// 0 for PRG-RAM, 1 for CHR-RAM and 2 for nametables
+    pub kind: u8,
+}
+
+/// Blocks of a disk side: the disk info, file amount, and the header and data of each file.
+/// Stops at the first block that can't be parsed.
+fn blocks(side: &[u8]) -> Vec<&[u8]> {
+    let mut blocks = vec![];
+    let mut pos = 0;
+    let mut file_size = 0;
+
+    while pos < side.len() {
+        let len = match side[pos] {
+            1 => 56,
+            2 => 2,
+            3 => 16,
+            4 => 1 + file_size,
+            _ => break,
+        };
+
+        let block = match side.get(pos..pos + len) {
+            Some(block) => block,
+            None => break,
+        };
+
+        if block[0] == 3 {
+            file_size = u16::from_le_bytes([block[13], block[14]]) as usize;
+        }
+
+        blocks.push(block);
+        pos += len;
+    }
+
+    blocks
+}
+
+/// The files on a disk side
+pub fn side_files(side: &[u8]) -> Vec<DiskFile> {
+    blocks(side)
+        .into_iter()
+        .filter(|block| block[0] == 3)
+        .map(|block| DiskFile {
+            number: block[1],
+            id: block[2],
+            name: String::from_utf8_lossy(&block[3..11]).into_owned(),
+            load_addr: u16::from_le_bytes([block[11], block[12]]),
+            size: u16::from_le_bytes([block[13], block[14]]),
+            kind: block[15],
+        })
+        .collect()
+}
+
+/// Lay out a disk side as the drive sees it: blocks separated by gaps, each block starting
+/// with a gap end marker and followed by a CRC. The CRC is a placeholder, since a CRC error
+/// is never reported.
+pub(crate) fn with_gaps(side: &[u8]) -> Vec<u8> {
+    // 28300 bits of gap before the first block and 976 bits after each block
+    let mut disk = vec![0; 28300 / 8];
+
+    for block in blocks(side) {
+        disk.push(0x80);
+        disk.extend_from_slice(block);
+        disk.extend([0x4D, 0x62]);
+        disk.extend(vec![0; 976 / 8]);
+    }
+
+    disk
+}
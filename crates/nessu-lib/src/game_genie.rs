@@ -0,0 +1,81 @@
+use std::fmt::{Display, Formatter};
+
+const LETTERS: &str = "APZLGITYEOXUKSVN";
+
+/// Decoded Game Genie code that replaces a byte read from PRG-ROM
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct GameGenieCode {
+    pub addr: u16,
+    pub value: u8,
+    /// Only replace the byte if it currently has this value. Set by 8-letter codes.
+    pub compare: Option<u8>,
+}
+
+impl GameGenieCode {
+    /// Decode a 6- or 8-letter code such as `SXIOPO`. Case and surrounding whitespace
+    /// are ignored.
+    pub fn parse(code: &str) -> Result<Self, String> {
+        let code = code.trim().to_ascii_uppercase();
+
+        let n = code
+            .chars()
+            .map(|c| {
+                LETTERS
+                    .find(c)
+                    .map(|i| i as u16)
+                    .ok_or_else(|| format!("Invalid Game Genie letter '{}'", c))
+            })
+            .collect::<Result<Vec<u16>, String>>()?;
+
+        if n.len() != 6 && n.len() != 8 {
+            return Err(format!(
+                "Game Genie codes have 6 or 8 letters, got {}",
+                n.len()
+            ));
+        }
+
+        let addr = 0x8000
+            | ((n[3] & 7) << 12)
+            | ((n[5] & 7) << 8)
+            | ((n[4] & 8) << 8)
+            | ((n[2] & 7) << 4)
+            | ((n[1] & 8) << 4)
+            | (n[4] & 7)
+            | (n[3] & 8);
+
+        // The bit that 6-letter codes take from the last letter comes from the 8th letter
+        // in 8-letter codes, where the last letters hold the compare value instead
+        let value_low = if n.len() == 8 { n[7] } else { n[5] };
+        let value = ((n[1] & 7) << 4) | ((n[0] & 8) << 4) | (n[0] & 7) | (value_low & 8);
+
+        let compare = if n.len() == 8 {
+            Some(((n[7] & 7) << 4) | ((n[6] & 8) << 4) | (n[6] & 7) | (n[5] & 8))
+        } else {
+            None
+        };
+
+        Ok(Self {
+            addr,
+            value: value as u8,
+            compare: compare.map(|c| c as u8),
+        })
+    }
+
+    /// Value to put on the bus instead of `val` read from `addr`
+    pub fn apply(&self, addr: u16, val: u8) -> u8 {
+        if addr == self.addr && (self.compare.is_none() || self.compare == Some(val)) {
+            self.value
+        } else {
+            val
+        }
+    }
+}
+
+impl Display for GameGenieCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.compare {
+            Some(compare) => write!(f, "${:04X}?{:02X}:{:02X}", self.addr, compare, self.value),
+            None => write!(f, "${:04X}:{:02X}", self.addr, self.value),
+        }
+    }
+}
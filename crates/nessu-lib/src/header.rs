@@ -1,8 +1,10 @@
 use crate::bitwise::HasBits;
 use crate::mapper::{MapperKind, Mirroring};
+use crate::region::Region;
 use std::io::{Error, ErrorKind};
 
 #[derive(Debug, Default, Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Header {
     pub prg_size: u8,
     pub chr_size: u8,
@@ -10,55 +12,105 @@ pub struct Header {
     pub flags7: u8,
     pub mirroring: Mirroring,
     pub mapper: MapperKind,
+    /// NES 2.0 submapper, 0 for iNES headers
+    pub submapper: u8,
     pub prg_start: usize,
     pub prg_end: usize,
     pub chr_start: usize,
     pub chr_end: usize,
+    /// Size of PRG-RAM in bytes. iNES headers give it in 8KB units, with 0 meaning 8KB.
+    pub prg_ram_size: usize,
+    /// Size of CHR-RAM in bytes, 0 with CHR-ROM. iNES headers without CHR-ROM get 8KB.
+    pub chr_ram_size: usize,
     pub persistence: bool,
+    pub region: Region,
 }
 
 impl Header {
+    /// Parse the header at the start of a ROM image, checking that the image is long enough
+    /// for the PRG-ROM and CHR-ROM it declares.
     pub fn read_from_slice(slice: &[u8]) -> std::io::Result<Self> {
-        if slice.len() < 16 {
-            return Err(Error::from(ErrorKind::InvalidData));
+        let header = Self::parse(slice)?;
+
+        if slice.len() < header.rom_size() {
+            return Err(truncated_error(header.rom_size(), slice.len()));
         }
 
-        if slice[0..4] != [0x4E, 0x45, 0x53, 0x1A] {
-            // Header should start with "NES"
-            return Err(Error::from(ErrorKind::InvalidData));
+        Ok(header)
+    }
+
+    /// Parse the 16-byte header without looking at the rest of the image.
+    pub fn parse(slice: &[u8]) -> std::io::Result<Self> {
+        // Header should start with "NES"
+        if slice.len() < 16 || slice[0..4] != [0x4E, 0x45, 0x53, 0x1A] {
+            return Err(Error::new(ErrorKind::InvalidData, "Missing iNES header"));
         }
 
         let prg_size = slice[4];
+        if prg_size == 0 {
+            return Err(Error::new(ErrorKind::InvalidData, "ROM has no PRG-ROM"));
+        }
+
         let chr_size = slice[5];
         let flags6 = slice[6];
         let flags7 = slice[7];
 
-        if (flags7 >> 2) & 0b11 == 2 {
-            eprintln!("NES 2.0 not supported yet");
-            return Err(Error::from(ErrorKind::Unsupported));
-        }
+        // NES 2.0 headers are compatible with iNES for the fields read here
+        let nes2 = (flags7 >> 2) & 0b11 == 2;
+
+        // Bytes 8-15 of iNES headers are only trusted if the unused bytes are zeroed, since
+        // older dumps often contain garbage like "DiskDude!" in them
+        let clean_tail = slice[12..16] == [0; 4];
+
+        let region = if nes2 {
+            match slice[12] & 0b11 {
+                1 => Region::Pal,
+                3 => Region::Dendy,
+                _ => Region::Ntsc,
+            }
+        } else if clean_tail && slice[9].has_bits(1) {
+            Region::Pal
+        } else {
+            Region::Ntsc
+        };
 
-        let mirroring = if flags6 & 1 == 0 {
+        let mirroring = if flags6.has_bits(0b1000) {
+            Mirroring::FourScreen
+        } else if flags6 & 1 == 0 {
             Mirroring::Horizontal
         } else {
             Mirroring::Vertical
         };
 
-        if (flags6 >> 3) & 1 == 1 {
-            eprintln!("TODO: Ignore mirroring control or above mirroring bit; instead provide four-screen FVRAM");
-            return Err(Error::from(ErrorKind::Unsupported));
-        }
-
         let persistence = flags6.has_bits(0b10);
 
-        let mapper = MapperKind::from((flags6 >> 4) | (flags7 & 0xF0));
+        // NES 2.0 gives volatile and battery-backed RAM as shift counts of 64 bytes
+        let ram_size = |byte: u8| {
+            let size = |shift: u8| if shift == 0 { 0 } else { 64 << shift };
+            size(byte & 0x0F) + size(byte >> 4)
+        };
 
-        let prg_start = if ((flags6 >> 0x2) & 0x1) == 0x1 {
-            0x210
+        let prg_ram_size = if nes2 {
+            ram_size(slice[10])
+        } else if clean_tail {
+            slice[8].max(1) as usize * 0x2000
         } else {
-            0x10
+            0x2000
         };
 
+        let chr_ram_size = if chr_size > 0 {
+            0
+        } else if nes2 && ram_size(slice[11]) > 0 {
+            ram_size(slice[11])
+        } else {
+            0x2000
+        };
+
+        let mapper = MapperKind::from((flags6 >> 4) | (flags7 & 0xF0));
+        let submapper = if nes2 { slice[8] >> 4 } else { 0 };
+
+        let prg_start = if flags6.has_bits(0b100) { 0x210 } else { 0x10 };
+
         let prg_end = prg_start + prg_size as usize * 0x4000;
 
         let chr_start = prg_end;
@@ -71,18 +123,35 @@ impl Header {
             flags7,
             mirroring,
             mapper,
+            submapper,
             prg_start,
             prg_end,
             chr_start,
             chr_end,
+            prg_ram_size,
+            chr_ram_size,
             persistence,
+            region,
         })
     }
 
-    pub fn copy_chr(&self, src: &[u8], dst: &mut [u8]) {
-        if self.chr_size > 0 {
-            dst[0..=(self.chr_end - self.chr_start - 1)]
-                .copy_from_slice(&src[self.chr_start as usize..self.chr_end as usize]);
+    /// Size of the ROM image, including the header and trainer, implied by the header
+    pub fn rom_size(&self) -> usize {
+        self.chr_end
+    }
+
+    /// Whether the board has CHR-RAM instead of CHR-ROM
+    pub fn chr_is_ram(&self) -> bool {
+        self.chr_size == 0
+    }
+
+    /// CHR-ROM from the image, or zeroed CHR-RAM. CHR-RAM smaller than 8KB is not mirrored,
+    /// so at least 8KB is allocated.
+    pub fn chr_mem(&self, src: &[u8]) -> Vec<u8> {
+        if self.chr_is_ram() {
+            vec![0; self.chr_ram_size.max(0x2000)]
+        } else {
+            self.chr(src).to_vec()
         }
     }
 
@@ -93,6 +162,31 @@ impl Header {
         }
     }
 
+    pub fn has_trainer(&self) -> bool {
+        self.flags6.has_bits(0b100)
+    }
+
+    /// The 512-byte trainer between the header and PRG-ROM, which is loaded to $7000-$71FF.
+    pub fn trainer<'a>(&self, src: &'a [u8]) -> Option<&'a [u8]> {
+        if self.has_trainer() {
+            Some(&src[0x10..0x210])
+        } else {
+            None
+        }
+    }
+
+    /// Copy the trainer to its place in PRG-RAM mapped at $6000. Skipped if the RAM is too
+    /// small to hold it.
+    pub fn copy_trainer(&self, src: &[u8], prg_ram: &mut [u8]) {
+        if prg_ram.len() < 0x1200 {
+            return;
+        }
+
+        if let Some(trainer) = self.trainer(src) {
+            prg_ram[0x1000..0x1200].copy_from_slice(trainer);
+        }
+    }
+
     pub fn chr<'a>(&self, src: &'a [u8]) -> &'a [u8] {
         &src[self.chr_start as usize..self.chr_end as usize]
     }
@@ -101,3 +195,13 @@ impl Header {
         &src[self.prg_start as usize..self.prg_end as usize]
     }
 }
+
+pub(crate) fn truncated_error(required: usize, len: usize) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!(
+            "ROM is truncated: the header requires {} bytes, but there are {}",
+            required, len
+        ),
+    )
+}
@@ -14,3 +14,13 @@ pub enum Button {
     A = 0b1000_0000,
     B = 0b0100_0000,
 }
+
+/// State of a Zapper light gun
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct Zapper {
+    /// Aimed display position. Positions outside the display never sense light.
+    pub x: u16,
+    pub y: u16,
+    pub trigger: bool,
+}
@@ -5,11 +5,23 @@ mod macros;
 
 pub mod apu;
 mod bitwise;
+pub mod breakpoint;
 pub mod cartridge;
 pub mod cpu;
+pub mod fds;
+pub mod game_genie;
 pub mod header;
 pub mod input;
 pub mod mapper;
+pub mod movie;
+mod nametable_cache;
 pub mod nes;
+pub mod ntsc;
 pub mod op;
 pub mod ppu;
+pub mod ram_search;
+pub mod region;
+pub mod symbols;
+pub mod trace;
+#[cfg(target_arch = "wasm32")]
+pub mod wasm;
@@ -1,25 +1,42 @@
+mod axrom;
+mod cnrom;
+mod colordreams;
+mod fds;
+mod gxrom;
 mod mmc1;
 mod mmc3;
 mod mmc4;
+mod mmc5;
 mod nrom;
 mod uxrom;
+mod vrc2_4;
 
 use enum_dispatch::enum_dispatch;
 
 use crate::header::Header;
+use crate::mapper::axrom::AxRomMapper;
+use crate::mapper::cnrom::CnRomMapper;
+use crate::mapper::colordreams::ColorDreamsMapper;
+pub(crate) use crate::mapper::fds::FdsMapper;
+use crate::mapper::gxrom::GxRomMapper;
 use crate::mapper::mmc1::Mmc1Mapper;
 use crate::mapper::mmc3::Mmc3Mapper;
 use crate::mapper::mmc4::Mmc4Mapper;
+use crate::mapper::mmc5::Mmc5Mapper;
 use crate::mapper::nrom::NromMapper;
 use crate::mapper::uxrom::UxRomMapper;
+use crate::mapper::vrc2_4::Vrc2_4Mapper;
 use std::io::ErrorKind;
+use std::ops::{Deref, DerefMut};
 
 #[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Mirroring {
     OneScreenLowerBank,
     OneScreenUpperBank,
     Horizontal,
     Vertical,
+    FourScreen,
 }
 
 impl Default for Mirroring {
@@ -29,12 +46,28 @@ impl Default for Mirroring {
 }
 
 #[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum MapperKind {
     NROM,
     MMC1,
     UXROM,
+    CNROM,
     MMC3,
     MMC4,
+    MMC5,
+    AXROM,
+    COLORDREAMS,
+    /// Mapper 21, VRC4a and VRC4c
+    VRC4AC,
+    /// Mapper 22
+    VRC2A,
+    /// Mapper 23, VRC2b, VRC4e and VRC4f
+    VRC4EF,
+    /// Mapper 25, VRC2c, VRC4b and VRC4d
+    VRC4BD,
+    GXROM,
+    /// Famicom Disk System, which has no iNES mapper number
+    FDS,
     Unknown(u8),
 }
 
@@ -50,8 +83,17 @@ impl From<u8> for MapperKind {
             0 => MapperKind::NROM,
             1 => MapperKind::MMC1,
             2 => MapperKind::UXROM,
+            3 => MapperKind::CNROM,
             4 => MapperKind::MMC3,
+            5 => MapperKind::MMC5,
+            7 => MapperKind::AXROM,
             10 => MapperKind::MMC4,
+            11 => MapperKind::COLORDREAMS,
+            21 => MapperKind::VRC4AC,
+            22 => MapperKind::VRC2A,
+            23 => MapperKind::VRC4EF,
+            25 => MapperKind::VRC4BD,
+            66 => MapperKind::GXROM,
             val => MapperKind::Unknown(val),
         }
     }
@@ -59,12 +101,20 @@ impl From<u8> for MapperKind {
 
 #[enum_dispatch]
 #[derive(Clone)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
 pub enum Mapper {
     NromMapper,
     Mmc1Mapper,
     UxRomMapper,
+    CnRomMapper,
     Mmc3Mapper,
     Mmc4Mapper,
+    Mmc5Mapper,
+    AxRomMapper,
+    ColorDreamsMapper,
+    Vrc2_4Mapper,
+    GxRomMapper,
+    FdsMapper,
 }
 
 pub fn build_mapper(data: &[u8], header: &Header) -> std::io::Result<Mapper> {
@@ -72,21 +122,115 @@ pub fn build_mapper(data: &[u8], header: &Header) -> std::io::Result<Mapper> {
         MapperKind::NROM => Ok(NromMapper::new(data, header).into()),
         MapperKind::MMC1 => Ok(Mmc1Mapper::new(data, header).into()),
         MapperKind::UXROM => Ok(UxRomMapper::new(data, header).into()),
+        MapperKind::CNROM => Ok(CnRomMapper::new(data, header).into()),
         MapperKind::MMC3 => Ok(Mmc3Mapper::new(data, header).into()),
         MapperKind::MMC4 => Ok(Mmc4Mapper::new(data, header).into()),
-        MapperKind::Unknown(val) => {
-            eprintln!("Unsupported mapper: {}", val);
-            Err(std::io::Error::from(ErrorKind::Unsupported))
+        MapperKind::MMC5 => Ok(Mmc5Mapper::new(data, header).into()),
+        MapperKind::AXROM => Ok(AxRomMapper::new(data, header).into()),
+        MapperKind::COLORDREAMS => Ok(ColorDreamsMapper::new(data, header).into()),
+        MapperKind::VRC4AC | MapperKind::VRC2A | MapperKind::VRC4EF | MapperKind::VRC4BD => {
+            Ok(Vrc2_4Mapper::new(data, header).into())
         }
+        MapperKind::GXROM => Ok(GxRomMapper::new(data, header).into()),
+        MapperKind::FDS => Err(std::io::Error::new(
+            ErrorKind::Unsupported,
+            "FDS disk images are loaded with Cartridge::from_fds",
+        )),
+        MapperKind::Unknown(val) => Err(std::io::Error::new(
+            ErrorKind::Unsupported,
+            format!("Unsupported mapper: {}", val),
+        )),
+    }
+}
+
+/// PRG-RAM at $6000-$7FFF, sized from the header. NES 2.0 headers can leave the size out,
+/// which gets the 8 KB that iNES assumes.
+pub(crate) fn prg_ram_size(header: &Header) -> usize {
+    if header.prg_ram_size == 0 {
+        0x2000
+    } else {
+        header.prg_ram_size
+    }
+}
+
+/// Discrete logic boards without a chip to disable the ROM while writing to the bank
+/// register have bus conflicts: the ROM drives the data bus at the same time as the CPU, and
+/// the register gets the AND of both values. NES 2.0 submapper 1 marks boards without them.
+pub(crate) fn has_bus_conflicts(header: &Header) -> bool {
+    header.submapper != 1
+}
+
+/// CHR-ROM, or CHR-RAM on boards without CHR-ROM. Savestates only include CHR-RAM, and
+/// CHR-ROM is taken back from the inserted cartridge.
+#[derive(Clone)]
+pub struct Chr {
+    data: Vec<u8>,
+    ram: bool,
+}
+
+impl Chr {
+    pub(crate) fn new(bytes: &[u8], header: &Header) -> Self {
+        Self {
+            data: header.chr_mem(bytes),
+            ram: header.chr_is_ram(),
+        }
+    }
+
+    pub fn is_ram(&self) -> bool {
+        self.ram
+    }
+
+    fn restore_rom(&mut self, rom: &Chr) {
+        if !self.ram {
+            self.data = rom.data.clone();
+        }
+    }
+}
+
+impl Deref for Chr {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl DerefMut for Chr {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+}
+
+#[cfg(feature = "savestate")]
+impl serde::Serialize for Chr {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let ram: &[u8] = if self.ram { &self.data } else { &[] };
+        serde::Serialize::serialize(&(self.ram, ram), serializer)
+    }
+}
+
+#[cfg(feature = "savestate")]
+impl<'de> serde::Deserialize<'de> for Chr {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let (ram, data) = serde::Deserialize::deserialize(deserializer)?;
+        Ok(Self { data, ram })
     }
 }
 
 #[enum_dispatch(Mapper)]
 pub trait MapperTrait {
     fn mirroring(&self) -> Option<Mirroring>;
-    fn cpu_read_u8(&mut self, addr: usize) -> u8;
+    /// Returns None for unmapped addresses, which read the CPU open bus
+    fn cpu_read_u8(&mut self, addr: usize) -> Option<u8>;
     fn cpu_write_u8(&mut self, addr: usize, val: u8, _cycle: u128);
+    /// Called for CPU writes to $2000-$2007, which some mappers watch
+    fn cpu_write_ppu_register(&mut self, _addr: usize, _val: u8) {}
+
     fn ppu_read_u8(&mut self, addr: usize) -> Option<u8>;
+    /// Like [`MapperTrait::ppu_read_u8`], but without side effects such as switching CHR banks
+    fn ppu_peek_u8(&mut self, addr: usize) -> Option<u8> {
+        self.ppu_read_u8(addr)
+    }
     fn ppu_write_u8(&mut self, addr: usize, val: u8) -> bool;
 
     fn irq_triggered(&mut self) -> bool {
@@ -94,4 +238,18 @@ pub trait MapperTrait {
     }
 
     fn clock_irq(&mut self) {}
+
+    /// Called once every CPU cycle
+    fn clock_cpu(&mut self) {}
+
+    /// PRG-RAM that is kept alive by a battery on the cartridge
+    fn battery_ram(&self) -> &[u8] {
+        &[]
+    }
+
+    fn set_battery_ram(&mut self, _data: &[u8]) {}
+
+    /// Take the ROM data, which is left out of savestates, from the mapper of the same
+    /// cartridge
+    fn restore_rom(&mut self, rom: &Mapper);
 }
@@ -0,0 +1,80 @@
+use crate::header::Header;
+use crate::mapper::{Chr, Mapper, MapperTrait, Mirroring};
+
+#[derive(Clone)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct ColorDreamsMapper {
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    prg_rom: Vec<u8>,
+    prg_bank: u8,
+    chr: Chr,
+    chr_bank: u8,
+}
+
+impl ColorDreamsMapper {
+    pub fn new(bytes: &[u8], header: &Header) -> Self {
+        let prg_rom = header.prg(bytes).to_vec();
+
+        let chr = Chr::new(bytes, header);
+
+        Self {
+            prg_rom,
+            prg_bank: 0,
+            chr,
+            chr_bank: 0,
+        }
+    }
+
+    fn effective_ppu_addr(&self, addr: usize) -> usize {
+        (addr + self.chr_bank as usize * 0x2000) % self.chr.len()
+    }
+}
+
+impl MapperTrait for ColorDreamsMapper {
+    fn mirroring(&self) -> Option<Mirroring> {
+        None
+    }
+
+    fn cpu_read_u8(&mut self, addr: usize) -> Option<u8> {
+        match addr {
+            0x8000..=0xFFFF => {
+                let bank_offset = (self.prg_bank as usize * 0x8000) % self.prg_rom.len();
+                Some(self.prg_rom[addr - 0x8000 + bank_offset])
+            }
+            _ => None,
+        }
+    }
+
+    fn cpu_write_u8(&mut self, addr: usize, val: u8, _cycle: u128) {
+        if let 0x8000..=0xFFFF = addr {
+            self.prg_bank = val & 0b11;
+            self.chr_bank = val >> 4;
+        }
+    }
+
+    fn ppu_read_u8(&mut self, addr: usize) -> Option<u8> {
+        match addr {
+            0x0000..=0x1FFF => Some(self.chr[self.effective_ppu_addr(addr)]),
+            _ => None,
+        }
+    }
+
+    fn ppu_write_u8(&mut self, addr: usize, val: u8) -> bool {
+        match addr {
+            0x0000..=0x1FFF if self.chr.is_ram() => {
+                let addr = self.effective_ppu_addr(addr);
+                self.chr[addr] = val;
+                true
+            }
+            0x0000..=0x1FFF => true,
+            _ => false,
+        }
+    }
+
+    fn restore_rom(&mut self, rom: &Mapper) {
+        if let Mapper::ColorDreamsMapper(rom) = rom {
+            self.prg_rom = rom.prg_rom.clone();
+            self.chr.restore_rom(&rom.chr);
+        }
+    }
+}
@@ -0,0 +1,317 @@
+use crate::bitwise::HasBits;
+use crate::fds::with_gaps;
+use crate::mapper::{Mapper, MapperTrait, Mirroring};
+use crate::rand_vec;
+
+/// CPU cycles between disk bytes, about 96.4 kbit/s
+const BYTE_CYCLES: u32 = 150;
+/// CPU cycles it takes the head to return to the start of the disk
+const REWIND_CYCLES: u32 = 50000;
+
+/// Famicom Disk System RAM adapter with the BIOS.
+///
+/// Disks can be read but not written: the drive reports inserted disks as write protected.
+/// The expansion audio registers are stored, but no sound is generated.
+#[derive(Clone)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct FdsMapper {
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    bios: Vec<u8>,
+    /// 32 KB mapped to $6000-$DFFF
+    prg_ram: Vec<u8>,
+    chr_ram: Vec<u8>,
+    /// Disk sides with gaps and block markers, as read by the drive
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    sides: Vec<Vec<u8>>,
+    inserted_side: Option<usize>,
+
+    disk_io_enabled: bool,
+    sound_io_enabled: bool,
+    mirroring: Mirroring,
+
+    irq_reload: u16,
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq_repeat: bool,
+    timer_irq: bool,
+
+    motor_on: bool,
+    reset_transfer: bool,
+    read_mode: bool,
+    /// Set by the BIOS once the gap before a block has been reached
+    disk_ready: bool,
+    disk_irq_enabled: bool,
+    disk_irq: bool,
+    transfer_complete: bool,
+    read_data: u8,
+    end_of_head: bool,
+    scanning: bool,
+    gap_ended: bool,
+    position: usize,
+    delay: u32,
+
+    wave_ram: Vec<u8>,
+    sound_regs: [u8; 0x0B],
+}
+
+impl FdsMapper {
+    pub fn new(sides: &[Vec<u8>], bios: &[u8]) -> Self {
+        let mut bios = bios.to_vec();
+        bios.resize(0x2000, 0);
+
+        Self {
+            bios,
+            prg_ram: rand_vec![0x8000],
+            chr_ram: vec![0; 0x2000],
+            sides: sides.iter().map(|side| with_gaps(side)).collect(),
+            inserted_side: Some(0),
+            disk_io_enabled: false,
+            sound_io_enabled: false,
+            mirroring: Mirroring::Horizontal,
+            irq_reload: 0,
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_repeat: false,
+            timer_irq: false,
+            motor_on: false,
+            reset_transfer: false,
+            read_mode: true,
+            disk_ready: false,
+            disk_irq_enabled: false,
+            disk_irq: false,
+            transfer_complete: false,
+            read_data: 0,
+            end_of_head: true,
+            scanning: false,
+            gap_ended: false,
+            position: 0,
+            delay: 0,
+            wave_ram: vec![0; 0x40],
+            sound_regs: [0; 0x0B],
+        }
+    }
+
+    pub fn side_count(&self) -> usize {
+        self.sides.len()
+    }
+
+    pub fn inserted_side(&self) -> Option<usize> {
+        self.inserted_side
+    }
+
+    /// Insert a disk side, or eject the disk with `None`. Games expect the disk to stay
+    /// ejected for a while before another side is inserted.
+    pub fn insert_side(&mut self, side: Option<usize>) {
+        self.inserted_side = side.filter(|&side| side < self.sides.len());
+    }
+
+    fn read_status(&mut self) -> u8 {
+        let mut val = 0;
+        val |= self.timer_irq as u8;
+        val |= (self.transfer_complete as u8) << 1;
+        val |= (self.end_of_head as u8) << 6;
+
+        self.transfer_complete = false;
+        self.timer_irq = false;
+        self.disk_irq = false;
+
+        val
+    }
+
+    fn read_drive_status(&self) -> u8 {
+        let inserted = self.inserted_side.is_some();
+
+        let mut val = 0x40;
+        val |= !inserted as u8;
+        val |= ((!inserted || !self.scanning) as u8) << 1;
+        // Writing isn't supported, so disks are write protected
+        val |= 1 << 2;
+        val
+    }
+
+    fn write_control(&mut self, val: u8) {
+        self.motor_on = val.has_bits(0x01);
+        self.reset_transfer = val.has_bits(0x02);
+        self.read_mode = val.has_bits(0x04);
+        self.mirroring = if val.has_bits(0x08) {
+            Mirroring::Horizontal
+        } else {
+            Mirroring::Vertical
+        };
+        self.disk_ready = val.has_bits(0x40);
+        self.disk_irq_enabled = val.has_bits(0x80);
+        self.disk_irq = false;
+    }
+
+    fn clock_timer(&mut self) {
+        if !self.irq_enabled {
+            return;
+        }
+
+        if self.irq_counter == 0 {
+            self.timer_irq = true;
+            self.irq_counter = self.irq_reload;
+            self.irq_enabled = self.irq_repeat;
+        } else {
+            self.irq_counter -= 1;
+        }
+    }
+
+    fn clock_disk(&mut self) {
+        let side = match self.inserted_side {
+            Some(side) if self.motor_on => side,
+            _ => {
+                self.end_of_head = true;
+                self.scanning = false;
+                return;
+            }
+        };
+
+        if self.reset_transfer && !self.scanning {
+            return;
+        }
+
+        if self.end_of_head {
+            self.delay = REWIND_CYCLES;
+            self.end_of_head = false;
+            self.position = 0;
+            self.gap_ended = false;
+            return;
+        }
+
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+
+        self.scanning = true;
+
+        let disk = &self.sides[side];
+        let data = disk.get(self.position).copied().unwrap_or(0);
+
+        if self.read_mode {
+            let mut irq = self.disk_irq_enabled;
+
+            if !self.disk_ready {
+                self.gap_ended = false;
+            } else if data != 0 && !self.gap_ended {
+                // The gap end marker is latched without an IRQ, so the BIOS skips it
+                self.gap_ended = true;
+                irq = false;
+            }
+
+            if self.gap_ended {
+                self.transfer_complete = true;
+                self.read_data = data;
+                self.disk_irq |= irq;
+            }
+        }
+
+        self.position += 1;
+
+        if self.position >= disk.len() {
+            self.motor_on = false;
+        } else {
+            self.delay = BYTE_CYCLES;
+        }
+    }
+}
+
+impl MapperTrait for FdsMapper {
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(self.mirroring)
+    }
+
+    fn cpu_read_u8(&mut self, addr: usize) -> Option<u8> {
+        match addr {
+            0x4030 if self.disk_io_enabled => Some(self.read_status()),
+            0x4031 if self.disk_io_enabled => {
+                self.transfer_complete = false;
+                self.disk_irq = false;
+                Some(self.read_data)
+            }
+            0x4032 if self.disk_io_enabled => Some(self.read_drive_status()),
+            // Battery good
+            0x4033 if self.disk_io_enabled => Some(0x80),
+            0x4040..=0x407F if self.sound_io_enabled => Some(self.wave_ram[addr - 0x4040] | 0x40),
+            0x4090 | 0x4092 if self.sound_io_enabled => Some(0x40),
+            0x6000..=0xDFFF => Some(self.prg_ram[addr - 0x6000]),
+            0xE000..=0xFFFF => Some(self.bios[addr - 0xE000]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write_u8(&mut self, addr: usize, val: u8, _cycle: u128) {
+        match addr {
+            0x4023 => {
+                self.disk_io_enabled = val.has_bits(0x01);
+                self.sound_io_enabled = val.has_bits(0x02);
+
+                if !self.disk_io_enabled {
+                    self.irq_enabled = false;
+                    self.timer_irq = false;
+                    self.disk_irq = false;
+                }
+            }
+            0x4020 => self.irq_reload = (self.irq_reload & 0xFF00) | val as u16,
+            0x4021 => self.irq_reload = (self.irq_reload & 0x00FF) | (val as u16) << 8,
+            0x4022 => {
+                self.irq_repeat = val.has_bits(0x01);
+                self.irq_enabled = val.has_bits(0x02) && self.disk_io_enabled;
+
+                if self.irq_enabled {
+                    self.irq_counter = self.irq_reload;
+                } else {
+                    self.timer_irq = false;
+                }
+            }
+            // Disk writes aren't supported
+            0x4024 => {}
+            0x4025 if self.disk_io_enabled => self.write_control(val),
+            0x4040..=0x407F if self.sound_io_enabled => self.wave_ram[addr - 0x4040] = val & 0x3F,
+            0x4080..=0x408A if self.sound_io_enabled => self.sound_regs[addr - 0x4080] = val,
+            0x6000..=0xDFFF => self.prg_ram[addr - 0x6000] = val,
+            _ => {}
+        }
+    }
+
+    fn ppu_read_u8(&mut self, addr: usize) -> Option<u8> {
+        match addr {
+            0x0000..=0x1FFF => Some(self.chr_ram[addr]),
+            _ => None,
+        }
+    }
+
+    fn ppu_write_u8(&mut self, addr: usize, val: u8) -> bool {
+        match addr {
+            0x0000..=0x1FFF => self.chr_ram[addr] = val,
+            _ => return false,
+        }
+
+        true
+    }
+
+    fn irq_triggered(&mut self) -> bool {
+        self.timer_irq || self.disk_irq
+    }
+
+    fn clock_cpu(&mut self) {
+        self.clock_timer();
+        self.clock_disk();
+    }
+
+    fn battery_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn set_battery_ram(&mut self, data: &[u8]) {
+        self.prg_ram.copy_from_slice(data);
+    }
+
+    fn restore_rom(&mut self, rom: &Mapper) {
+        if let Mapper::FdsMapper(rom) = rom {
+            self.bios = rom.bios.clone();
+            self.sides = rom.sides.clone();
+        }
+    }
+}
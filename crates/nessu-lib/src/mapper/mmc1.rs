@@ -1,13 +1,15 @@
 use crate::bitwise::HasBits;
 use crate::header::Header;
-use crate::mapper::{MapperTrait, Mirroring};
+use crate::mapper::{Chr, Mapper, MapperTrait, Mirroring};
 use crate::rand_vec;
 
 #[derive(Clone)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
 pub struct Mmc1Mapper {
     prg_ram: Vec<u8>,
+    #[cfg_attr(feature = "savestate", serde(skip))]
     prg_rom: Vec<u8>,
-    chr: Vec<u8>,
+    chr: Chr,
     chr_bank0: u8,
     chr_bank1: u8,
 
@@ -25,15 +27,16 @@ pub struct Mmc1Mapper {
 impl Mmc1Mapper {
     pub fn new(bytes: &[u8], header: &Header) -> Self {
         let prg_rom = header.prg(bytes).to_vec();
-        let mut chr = vec![0; 0x20000];
+        let chr = Chr::new(bytes, header);
 
-        if header.chr_size > 0 {
-            let chr_in = header.chr(bytes);
-            chr[..chr_in.len()].copy_from_slice(chr_in);
-        }
+        // SOROM has 16KB of PRG-RAM, which is also used when the header asks for less
+        let prg_ram_size = header.prg_ram_size.max(0x4000);
+
+        let mut prg_ram = rand_vec![prg_ram_size];
+        header.copy_trainer(bytes, &mut prg_ram);
 
         Self {
-            prg_ram: rand_vec![2 << 13],
+            prg_ram,
             prg_rom,
             chr,
             chr_bank0: 0,
@@ -119,17 +122,63 @@ impl Mmc1Mapper {
         }
     }
 
+    /// Boards with 8KB of CHR-RAM use the upper bits of the CHR bank registers to select
+    /// PRG-ROM and PRG-RAM banks instead.
+    fn chr_bank(&self, bank: u8) -> usize {
+        if self.chr.is_ram() {
+            bank as usize & 1
+        } else {
+            bank as usize
+        }
+    }
+
+    /// SUROM selects the 256KB half of its 512KB PRG-ROM with bit 4 of the CHR bank.
+    fn prg_rom_outer_bank(&self) -> usize {
+        if self.prg_rom.len() > 0x40000 {
+            ((self.chr_bank0 as usize >> 4) & 1) * 0x40000
+        } else {
+            0
+        }
+    }
+
+    fn effective_cpu_addr(&self, addr: usize) -> usize {
+        let offset = match (addr, self.prg_bank_mode) {
+            (0x8000..=0xFFFF, 0 | 1) => addr - 0x8000 + (self.prg_bank & !1) as usize * 0x4000,
+            (0x8000..=0xBFFF, 2) => addr - 0x8000,
+            (0x8000..=0xBFFF, _) => addr - 0x8000 + self.prg_bank as usize * 0x4000,
+            (_, 2) => addr - 0xC000 + self.prg_bank as usize * 0x4000,
+            (_, _) => addr - 0xC000 + self.prg_rom.len().min(0x40000) - 0x4000,
+        };
+
+        (self.prg_rom_outer_bank() + offset) % self.prg_rom.len()
+    }
+
+    /// SOROM selects the 8KB PRG-RAM bank with bit 3 of the CHR bank, and SXROM with bits 2-3.
+    fn prg_ram_addr(&self, addr: usize) -> usize {
+        let bank = match self.prg_ram.len() {
+            _ if !self.chr.is_ram() => 0,
+            0x4000 => (self.chr_bank0 as usize >> 3) & 1,
+            _ => (self.chr_bank0 as usize >> 2) & 0b11,
+        };
+
+        (bank * 0x2000 + addr - 0x6000) % self.prg_ram.len()
+    }
+
     fn effective_ppu_addr(&self, addr: usize) -> usize {
-        match addr {
+        let addr = match addr {
             0x0000..=0x1FFF if self.chr_bank_mode == 0 => {
-                addr + ((self.chr_bank0 & !1) as usize * 0x1000)
+                addr + ((self.chr_bank(self.chr_bank0) & !1) * 0x1000)
+            }
+            0x0000..=0x0FFF if self.chr_bank_mode == 1 => {
+                addr + (self.chr_bank(self.chr_bank0) * 0x1000)
             }
-            0x0000..=0x0FFF if self.chr_bank_mode == 1 => addr + (self.chr_bank0 as usize * 0x1000),
             0x1000..=0x1FFF if self.chr_bank_mode == 1 => {
-                addr - 0x1000 + (self.chr_bank1 as usize * 0x1000)
+                addr - 0x1000 + (self.chr_bank(self.chr_bank1) * 0x1000)
             }
             _ => addr,
-        }
+        };
+
+        addr % self.chr.len()
     }
 }
 
@@ -144,37 +193,11 @@ impl MapperTrait for Mmc1Mapper {
         })
     }
 
-    fn cpu_read_u8(&mut self, addr: usize) -> u8 {
+    fn cpu_read_u8(&mut self, addr: usize) -> Option<u8> {
         match addr {
-            0x6000..=0x7FFF => self.prg_ram[addr - 0x6000],
-            0x8000..=0xFFFF if self.prg_bank_mode == 0 || self.prg_bank_mode == 1 => {
-                self.prg_rom[addr - 0x8000 + ((self.prg_bank & !1) as usize * 0x4000)]
-            }
-            0x8000..=0xBFFF => {
-                if self.prg_bank_mode == 2 {
-                    self.prg_rom[addr - 0x8000]
-                } else if self.prg_bank_mode == 3 {
-                    self.prg_rom[addr - 0x8000 + (self.prg_bank as usize * 0x4000)]
-                } else {
-                    panic!(
-                        "Not implemented: tried to read from 0x{:04X} with bank mode {}",
-                        addr, self.prg_bank_mode
-                    );
-                }
-            }
-            0xC000..=0xFFFF => {
-                if self.prg_bank_mode == 2 {
-                    self.prg_rom[addr - 0xC000 + (self.prg_bank as usize * 0x4000)]
-                } else if self.prg_bank_mode == 3 {
-                    self.prg_rom[addr - 0xC000 + self.prg_rom.len() - 0x4000]
-                } else {
-                    panic!(
-                        "Not implemented: tried to read from 0x{:04X} with bank mode {}",
-                        addr, self.prg_bank_mode
-                    );
-                }
-            }
-            _ => 0,
+            0x6000..=0x7FFF => Some(self.prg_ram[self.prg_ram_addr(addr)]),
+            0x8000..=0xFFFF => Some(self.prg_rom[self.effective_cpu_addr(addr)]),
+            _ => None,
         }
     }
 
@@ -187,7 +210,10 @@ impl MapperTrait for Mmc1Mapper {
         }
 
         match addr {
-            0x6000..=0x7FFF => self.prg_ram[addr - 0x6000] = val,
+            0x6000..=0x7FFF => {
+                let addr = self.prg_ram_addr(addr);
+                self.prg_ram[addr] = val;
+            }
             0x8000..=0xFFFF => self.write_load_register(addr, val),
             _ => {}
         }
@@ -205,12 +231,28 @@ impl MapperTrait for Mmc1Mapper {
 
     fn ppu_write_u8(&mut self, addr: usize, val: u8) -> bool {
         match addr {
-            0x0000..=0x1FFF => {
+            0x0000..=0x1FFF if self.chr.is_ram() => {
                 let addr = self.effective_ppu_addr(addr);
                 self.chr[addr] = val;
                 true
             }
+            0x0000..=0x1FFF => true,
             _ => false,
         }
     }
+
+    fn battery_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn set_battery_ram(&mut self, data: &[u8]) {
+        self.prg_ram.copy_from_slice(data);
+    }
+
+    fn restore_rom(&mut self, rom: &Mapper) {
+        if let Mapper::Mmc1Mapper(rom) = rom {
+            self.prg_rom = rom.prg_rom.clone();
+            self.chr.restore_rom(&rom.chr);
+        }
+    }
 }
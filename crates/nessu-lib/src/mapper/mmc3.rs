@@ -1,13 +1,15 @@
 use crate::bitwise::{HasBits, IsEven};
 use crate::header::Header;
-use crate::mapper::{MapperTrait, Mirroring};
+use crate::mapper::{prg_ram_size, Chr, Mapper, MapperTrait, Mirroring};
 use crate::rand_vec;
 
 #[derive(Clone)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
 pub struct Mmc3Mapper {
     prg_ram: Vec<u8>,
+    #[cfg_attr(feature = "savestate", serde(skip))]
     prg_rom: Vec<u8>,
-    chr: Vec<u8>,
+    chr: Chr,
 
     r: [u8; 8],
 
@@ -29,24 +31,30 @@ pub struct Mmc3Mapper {
     chr_a12_inversion: u8,
     irq_reload: u8,
     irq_counter: u8,
+    irq_reload_requested: bool,
     irq_enabled: bool,
     irq_triggered: bool,
+    /// MMC6 and MMC3 revision A only fire the IRQ when the counter reaches zero
+    /// by decrementing, or by a reload requested through $C001.
+    irq_alt_behavior: bool,
 }
 
 impl Mmc3Mapper {
     pub fn new(bytes: &[u8], header: &Header) -> Self {
         let prg_rom = header.prg(bytes).to_vec();
-        let mut chr = vec![0; 0x40000];
-        header.copy_chr(bytes, &mut chr);
+        let chr = Chr::new(bytes, header);
 
         let prg_bank_8000 = 0x0000;
         let prg_bank_a000 = 0x2000;
         let prg_bank_c000 = prg_rom.len() - 0x4000;
         let prg_bank_e000 = prg_rom.len() - 0x2000;
 
+        let mut prg_ram = rand_vec![prg_ram_size(header)];
+        header.copy_trainer(bytes, &mut prg_ram);
+
         Self {
             r: [0; 8],
-            prg_ram: rand_vec![0x2000],
+            prg_ram,
             prg_rom,
             chr,
             prg_bank_8000,
@@ -67,11 +75,18 @@ impl Mmc3Mapper {
             chr_a12_inversion: 0,
             irq_reload: 0,
             irq_counter: 0,
+            irq_reload_requested: false,
             irq_enabled: false,
             irq_triggered: false,
+            // Submapper 1 is MMC6 and submapper 4 is MMC3A
+            irq_alt_behavior: matches!(header.submapper, 1 | 4),
         }
     }
 
+    pub fn set_irq_alt_behavior(&mut self, enabled: bool) {
+        self.irq_alt_behavior = enabled;
+    }
+
     fn bank_select(&mut self, val: u8) {
         self.next_bank_update = val & 0b111;
         self.prg_rom_mode = (val >> 6) & 1;
@@ -118,6 +133,7 @@ impl Mmc3Mapper {
 
     fn reset_irq_counter(&mut self) {
         self.irq_counter = 0;
+        self.irq_reload_requested = true;
     }
 
     fn enable_irq(&mut self) {
@@ -155,23 +171,23 @@ impl MapperTrait for Mmc3Mapper {
     }
 
     #[rustfmt::skip]
-    fn cpu_read_u8(&mut self, addr: usize) -> u8 {
+    fn cpu_read_u8(&mut self, addr: usize) -> Option<u8> {
         match addr {
-            0x6000..=0x7FFF => {
-                // TODO return open bus if disabled
-                self.prg_ram[addr & 0x1FFF]
-            }
-            0x8000..=0x9FFF => self.prg_rom[(addr & 0x1FFF) + self.prg_bank_8000],
-            0xA000..=0xBFFF => self.prg_rom[(addr & 0x1FFF) + self.prg_bank_a000],
-            0xC000..=0xDFFF => self.prg_rom[(addr & 0x1FFF) + self.prg_bank_c000],
-            0xE000..=0xFFFF => self.prg_rom[(addr & 0x1FFF) + self.prg_bank_e000],
-            _ => 0,
+            0x6000..=0x7FFF if self.prg_ram_enabled => Some(self.prg_ram[(addr - 0x6000) % self.prg_ram.len()]),
+            0x8000..=0x9FFF => Some(self.prg_rom[(addr & 0x1FFF) + self.prg_bank_8000]),
+            0xA000..=0xBFFF => Some(self.prg_rom[(addr & 0x1FFF) + self.prg_bank_a000]),
+            0xC000..=0xDFFF => Some(self.prg_rom[(addr & 0x1FFF) + self.prg_bank_c000]),
+            0xE000..=0xFFFF => Some(self.prg_rom[(addr & 0x1FFF) + self.prg_bank_e000]),
+            _ => None,
         }
     }
 
     fn cpu_write_u8(&mut self, addr: usize, val: u8, _cycle: u128) {
         match addr {
-            0x6000..=0x7FFF if !self.prg_ram_read_only => self.prg_ram[addr - 0x6000] = val,
+            0x6000..=0x7FFF if self.prg_ram_enabled && !self.prg_ram_read_only => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr - 0x6000) % len] = val
+            }
             0x8000..=0x9FFE if addr.is_even() => self.bank_select(val),
             0x8001..=0x9FFF if addr.is_odd() => self.set_bank_data(val),
             0xA000..=0xBFFE if addr.is_even() => self.set_mirroring(val),
@@ -185,11 +201,20 @@ impl MapperTrait for Mmc3Mapper {
     }
 
     fn ppu_read_u8(&mut self, addr: usize) -> Option<u8> {
-        self.effective_ppu_addr(addr).map(|addr| self.chr[addr])
+        self.effective_ppu_addr(addr)
+            .map(|addr| self.chr[addr % self.chr.len()])
     }
 
-    fn ppu_write_u8(&mut self, _addr: usize, _val: u8) -> bool {
-        false
+    fn ppu_write_u8(&mut self, addr: usize, val: u8) -> bool {
+        match self.effective_ppu_addr(addr) {
+            Some(addr) if self.chr.is_ram() => {
+                let len = self.chr.len();
+                self.chr[addr % len] = val;
+                true
+            }
+            Some(_) => true,
+            None => false,
+        }
     }
 
     fn irq_triggered(&mut self) -> bool {
@@ -197,14 +222,39 @@ impl MapperTrait for Mmc3Mapper {
     }
 
     fn clock_irq(&mut self) {
+        let prev_counter = self.irq_counter;
+
         if self.irq_counter == 0 {
             self.irq_counter = self.irq_reload;
         } else {
             self.irq_counter -= 1;
         }
 
-        if self.irq_counter == 0 && self.irq_enabled {
+        let fire = if self.irq_alt_behavior {
+            (prev_counter > 0 || self.irq_reload_requested) && self.irq_counter == 0
+        } else {
+            self.irq_counter == 0
+        };
+
+        if fire && self.irq_enabled {
             self.irq_triggered = true;
         }
+
+        self.irq_reload_requested = false;
+    }
+
+    fn battery_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn set_battery_ram(&mut self, data: &[u8]) {
+        self.prg_ram.copy_from_slice(data);
+    }
+
+    fn restore_rom(&mut self, rom: &Mapper) {
+        if let Mapper::Mmc3Mapper(rom) = rom {
+            self.prg_rom = rom.prg_rom.clone();
+            self.chr.restore_rom(&rom.chr);
+        }
     }
 }
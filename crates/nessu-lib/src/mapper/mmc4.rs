@@ -1,12 +1,14 @@
 use crate::header::Header;
-use crate::mapper::{MapperTrait, Mirroring};
+use crate::mapper::{prg_ram_size, Chr, Mapper, MapperTrait, Mirroring};
 use crate::rand_vec;
 
 #[derive(Clone)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
 pub struct Mmc4Mapper {
     prg_ram: Vec<u8>,
+    #[cfg_attr(feature = "savestate", serde(skip))]
     prg_rom: Vec<u8>,
-    chr: Vec<u8>,
+    chr: Chr,
     chr_bank0_fd: u8,
     chr_bank0_fe: u8,
     chr_bank1_fd: u8,
@@ -20,10 +22,13 @@ pub struct Mmc4Mapper {
 impl Mmc4Mapper {
     pub fn new(bytes: &[u8], header: &Header) -> Self {
         let prg_rom = header.prg(bytes).to_vec();
-        let chr = header.chr(bytes).to_vec();
+        let chr = Chr::new(bytes, header);
+
+        let mut prg_ram = rand_vec![prg_ram_size(header)];
+        header.copy_trainer(bytes, &mut prg_ram);
 
         Self {
-            prg_ram: rand_vec![0x2000],
+            prg_ram,
             prg_rom,
             chr,
             chr_bank0_fd: 0,
@@ -71,18 +76,21 @@ impl MapperTrait for Mmc4Mapper {
         })
     }
 
-    fn cpu_read_u8(&mut self, addr: usize) -> u8 {
+    fn cpu_read_u8(&mut self, addr: usize) -> Option<u8> {
         match addr {
-            0x6000..=0x7FFF => self.prg_ram[addr - 0x6000],
-            0x8000..=0xBFFF => self.prg_rom[addr - 0x8000 + self.prg_bank as usize * 0x4000],
-            0xC000..=0xFFFF => self.prg_rom[addr - 0xC000 + self.prg_rom.len() - 0x4000],
-            _ => 0,
+            0x6000..=0x7FFF => Some(self.prg_ram[(addr - 0x6000) % self.prg_ram.len()]),
+            0x8000..=0xBFFF => Some(self.prg_rom[addr - 0x8000 + self.prg_bank as usize * 0x4000]),
+            0xC000..=0xFFFF => Some(self.prg_rom[addr - 0xC000 + self.prg_rom.len() - 0x4000]),
+            _ => None,
         }
     }
 
     fn cpu_write_u8(&mut self, addr: usize, val: u8, _cycle: u128) {
         match addr {
-            0x6000..=0x7FFF => self.prg_ram[addr - 0x6000] = val,
+            0x6000..=0x7FFF => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr - 0x6000) % len] = val
+            }
             0xA000..=0xAFFF => self.set_prg_bank(val),
             0xB000..=0xBFFF => self.set_chr_bank0_fd(val),
             0xC000..=0xCFFF => self.set_chr_bank0_fe(val),
@@ -102,6 +110,10 @@ impl MapperTrait for Mmc4Mapper {
             _ => {}
         }
 
+        self.ppu_peek_u8(addr)
+    }
+
+    fn ppu_peek_u8(&mut self, addr: usize) -> Option<u8> {
         #[rustfmt::skip]
         let addr = match addr {
             0x0000..=0x0FFF if self.latch_0 == 0xFD => addr + self.chr_bank0_fd as usize * 0x1000,
@@ -111,15 +123,31 @@ impl MapperTrait for Mmc4Mapper {
             _ => return None,
         };
 
-        Some(self.chr[addr])
+        Some(self.chr[addr % self.chr.len()])
     }
 
     fn ppu_write_u8(&mut self, addr: usize, val: u8) -> bool {
         match addr {
-            0x0000..=0x1FFF => self.chr[addr] = val,
+            0x0000..=0x1FFF if self.chr.is_ram() => self.chr[addr] = val,
+            0x0000..=0x1FFF => {}
             _ => return false,
         }
 
         true
     }
+
+    fn battery_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn set_battery_ram(&mut self, data: &[u8]) {
+        self.prg_ram.copy_from_slice(data);
+    }
+
+    fn restore_rom(&mut self, rom: &Mapper) {
+        if let Mapper::Mmc4Mapper(rom) = rom {
+            self.prg_rom = rom.prg_rom.clone();
+            self.chr.restore_rom(&rom.chr);
+        }
+    }
 }
@@ -0,0 +1,377 @@
+use crate::bitwise::HasBits;
+use crate::header::Header;
+use crate::mapper::{Chr, Mapper, MapperTrait, Mirroring};
+use crate::rand_vec;
+
+/// Nametable reads and attribute reads of the 32 background tiles of a scanline
+const BG_NAMETABLE_READS: u8 = 64;
+/// Nametable and attribute reads of the background and the 8 sprites of a scanline
+const SPRITE_NAMETABLE_READS: u8 = BG_NAMETABLE_READS + 16;
+/// CPU cycles without PPU reads after which the PPU is considered to be out of the frame
+const PPU_IDLE_CYCLES: u8 = 3;
+
+/// Nintendo MMC5 (mapper 5), partially.
+///
+/// Supported are the PRG and CHR banking modes, PRG-RAM, the nametable mapping including
+/// ExRAM and fill mode, the scanline IRQ and the multiplier. The extended attribute mode,
+/// vertical split and expansion audio are not: their registers are accepted but do nothing.
+#[derive(Clone)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct Mmc5Mapper {
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    /// The 2KB of CIRAM, which MMC5 maps to the nametables itself
+    ciram: Vec<u8>,
+    exram: Vec<u8>,
+
+    prg_mode: u8,
+    chr_mode: u8,
+    prg_ram_protect: [u8; 2],
+    exram_mode: u8,
+    /// Source of each nametable: CIRAM page 0 or 1, ExRAM or fill mode
+    nametable_mapping: u8,
+    fill_tile: u8,
+    fill_attr: u8,
+    /// $5113-$5117
+    prg_banks: [u8; 5],
+    /// $5120-$5127 for sprites, $5128-$512B for the background, with the upper bits of $5130
+    chr_banks: [u16; 12],
+    chr_upper_bits: u16,
+    /// Whether $5128-$512B were written after $5120-$5127
+    last_chr_write_bg: bool,
+
+    large_sprites: bool,
+    rendering_enabled: bool,
+
+    multiplicand: u8,
+    multiplier: u8,
+
+    irq_target: u8,
+    irq_enabled: bool,
+    irq_pending: bool,
+    in_frame: bool,
+    scanline: u8,
+    last_ppu_read: usize,
+    same_ppu_reads: u8,
+    /// Nametable and attribute reads since the scanline started, used to tell sprite
+    /// pattern fetches from background ones
+    nametable_reads: u8,
+    ppu_idle_cycles: u8,
+}
+
+impl Mmc5Mapper {
+    pub fn new(bytes: &[u8], header: &Header) -> Self {
+        let prg_rom = header.prg(bytes).to_vec();
+
+        let chr = Chr::new(bytes, header);
+
+        let mut prg_ram = rand_vec![header.prg_ram_size.max(0x2000)];
+        header.copy_trainer(bytes, &mut prg_ram);
+
+        Self {
+            prg_rom,
+            prg_ram,
+            chr,
+            ciram: vec![0; 0x800],
+            exram: vec![0; 0x400],
+            prg_mode: 3,
+            chr_mode: 0,
+            prg_ram_protect: [0; 2],
+            exram_mode: 0,
+            nametable_mapping: 0,
+            fill_tile: 0,
+            fill_attr: 0,
+            prg_banks: [0, 0, 0, 0, 0xFF],
+            chr_banks: [0; 12],
+            chr_upper_bits: 0,
+            last_chr_write_bg: false,
+            large_sprites: false,
+            rendering_enabled: false,
+            // Both operands are $FF at power on
+            multiplicand: 0xFF,
+            multiplier: 0xFF,
+            irq_target: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            in_frame: false,
+            scanline: 0,
+            last_ppu_read: 0,
+            same_ppu_reads: 0,
+            nametable_reads: 0,
+            ppu_idle_cycles: 0,
+        }
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_protect == [0b10, 0b01]
+    }
+
+    /// Offset in PRG-RAM of $6000-$7FFF, or in PRG-ROM or PRG-RAM of $8000-$FFFF
+    fn prg_addr(&self, addr: usize) -> PrgAddr {
+        if addr < 0x8000 {
+            return PrgAddr::Ram(self.prg_ram_offset(self.prg_banks[0], addr));
+        }
+
+        // Register and size of the bank in 8KB units
+        let (reg, size) = match (self.prg_mode, addr) {
+            (0, _) => (4, 4),
+            (1, 0x8000..=0xBFFF) => (2, 2),
+            (1, _) => (4, 2),
+            (2, 0x8000..=0xBFFF) => (2, 2),
+            (2, 0xC000..=0xDFFF) => (3, 1),
+            (2, _) => (4, 1),
+            _ => (1 + (addr - 0x8000) / 0x2000, 1),
+        };
+
+        let val = self.prg_banks[reg];
+        let page = ((val & 0x7F) as usize & !(size - 1)) + (addr - 0x8000) / 0x2000 % size;
+        let offset = page * 0x2000 + (addr & 0x1FFF);
+
+        // $5117 always maps ROM
+        if reg == 4 || val.has_bits(0x80) {
+            PrgAddr::Rom(offset % self.prg_rom.len())
+        } else {
+            PrgAddr::Ram(self.prg_ram_offset(page as u8, addr))
+        }
+    }
+
+    fn prg_ram_offset(&self, bank: u8, addr: usize) -> usize {
+        ((bank & 0x07) as usize * 0x2000 + (addr & 0x1FFF)) % self.prg_ram.len()
+    }
+
+    fn write_chr_bank(&mut self, reg: usize, val: u8) {
+        self.chr_banks[reg] = self.chr_upper_bits << 8 | val as u16;
+        self.last_chr_write_bg = reg >= 8;
+    }
+
+    /// Whether a pattern read uses the background banks $5128-$512B
+    fn bg_chr_banks(&self) -> bool {
+        if self.large_sprites && self.in_frame {
+            let sprite_fetch =
+                (BG_NAMETABLE_READS + 1..=SPRITE_NAMETABLE_READS).contains(&self.nametable_reads);
+            !sprite_fetch
+        } else {
+            self.last_chr_write_bg
+        }
+    }
+
+    fn chr_addr(&self, addr: usize) -> usize {
+        // 1KB pages per bank
+        let size = 8 >> self.chr_mode;
+        let page = addr / 0x0400;
+
+        let bank = if self.bg_chr_banks() {
+            // The background banks cover 4KB and are repeated in both pattern tables
+            let page = if size == 8 { page } else { page % 4 };
+            self.chr_banks[8 + ((page / size) * size + size - 1) % 4] as usize * size + page % size
+        } else {
+            self.chr_banks[(page / size) * size + size - 1] as usize * size + page % size
+        };
+
+        (bank * 0x0400 + (addr & 0x03FF)) % self.chr.len()
+    }
+
+    fn read_nametable(&self, addr: usize) -> u8 {
+        let offset = addr & 0x03FF;
+
+        match self.nametable_source(addr) {
+            0 => self.ciram[offset],
+            1 => self.ciram[0x400 + offset],
+            2 if self.exram_mode <= 1 => self.exram[offset],
+            2 => 0,
+            _ if offset >= 0x3C0 => self.fill_attr * 0x55,
+            _ => self.fill_tile,
+        }
+    }
+
+    fn write_nametable(&mut self, addr: usize, val: u8) {
+        let offset = addr & 0x03FF;
+
+        match self.nametable_source(addr) {
+            0 => self.ciram[offset] = val,
+            1 => self.ciram[0x400 + offset] = val,
+            2 if self.exram_mode <= 1 => self.exram[offset] = val,
+            _ => {}
+        }
+    }
+
+    fn nametable_source(&self, addr: usize) -> u8 {
+        let nametable = (addr >> 10) & 0b11;
+        (self.nametable_mapping >> (nametable * 2)) & 0b11
+    }
+
+    /// Detect the start of a scanline from the three reads of the same nametable address
+    /// that the PPU makes at the end of each rendered scanline.
+    fn detect_scanline(&mut self, addr: usize) {
+        self.ppu_idle_cycles = 0;
+
+        if addr == self.last_ppu_read {
+            self.same_ppu_reads = self.same_ppu_reads.saturating_add(1);
+        } else {
+            self.same_ppu_reads = 0;
+        }
+        self.last_ppu_read = addr;
+
+        if !(0x2000..=0x2FFF).contains(&addr) {
+            return;
+        }
+
+        self.nametable_reads = self.nametable_reads.saturating_add(1);
+
+        if self.same_ppu_reads == 2 && self.rendering_enabled {
+            self.nametable_reads = 0;
+
+            if !self.in_frame {
+                self.in_frame = true;
+                self.scanline = 0;
+            } else {
+                self.scanline = self.scanline.wrapping_add(1);
+                if self.scanline == self.irq_target {
+                    self.irq_pending = true;
+                }
+            }
+        }
+    }
+
+    /// Unsigned 16-bit product of $5205 and $5206
+    fn product(&self) -> u16 {
+        self.multiplicand as u16 * self.multiplier as u16
+    }
+
+    fn read_irq_status(&mut self) -> u8 {
+        let val = (self.irq_pending as u8) << 7 | (self.in_frame as u8) << 6;
+        self.irq_pending = false;
+        val
+    }
+}
+
+enum PrgAddr {
+    Rom(usize),
+    Ram(usize),
+}
+
+impl MapperTrait for Mmc5Mapper {
+    /// Nametables are mapped by the mapper itself
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(Mirroring::FourScreen)
+    }
+
+    fn cpu_read_u8(&mut self, addr: usize) -> Option<u8> {
+        match addr {
+            0x5204 => Some(self.read_irq_status()),
+            0x5205 => Some(self.product().to_le_bytes()[0]),
+            0x5206 => Some(self.product().to_le_bytes()[1]),
+            0x5C00..=0x5FFF if self.exram_mode >= 2 => Some(self.exram[addr - 0x5C00]),
+            0x6000..=0xFFFF => {
+                // The CPU reads the NMI vector when vblank starts
+                if addr == 0xFFFA || addr == 0xFFFB {
+                    self.in_frame = false;
+                }
+
+                match self.prg_addr(addr) {
+                    PrgAddr::Rom(offset) => Some(self.prg_rom[offset]),
+                    PrgAddr::Ram(offset) => Some(self.prg_ram[offset]),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn cpu_write_u8(&mut self, addr: usize, val: u8, _cycle: u128) {
+        match addr {
+            0x5100 => self.prg_mode = val & 0b11,
+            0x5101 => self.chr_mode = val & 0b11,
+            0x5102 => self.prg_ram_protect[0] = val & 0b11,
+            0x5103 => self.prg_ram_protect[1] = val & 0b11,
+            0x5104 => self.exram_mode = val & 0b11,
+            0x5105 => self.nametable_mapping = val,
+            0x5106 => self.fill_tile = val,
+            0x5107 => self.fill_attr = val & 0b11,
+            0x5113..=0x5117 => self.prg_banks[addr - 0x5113] = val,
+            0x5120..=0x512B => self.write_chr_bank(addr - 0x5120, val),
+            0x5130 => self.chr_upper_bits = val as u16 & 0b11,
+            0x5203 => self.irq_target = val,
+            0x5204 => self.irq_enabled = val.has_bits(0x80),
+            0x5205 => self.multiplicand = val,
+            0x5206 => self.multiplier = val,
+            // Writes in the nametable modes would only work during rendering
+            0x5C00..=0x5FFF if self.exram_mode != 3 => self.exram[addr - 0x5C00] = val,
+            0x6000..=0xFFFF if self.prg_ram_writable() => {
+                if let PrgAddr::Ram(offset) = self.prg_addr(addr) {
+                    self.prg_ram[offset] = val;
+                }
+            }
+            // Expansion audio and split screen
+            _ => {}
+        }
+    }
+
+    fn cpu_write_ppu_register(&mut self, addr: usize, val: u8) {
+        match addr {
+            0x2000 => self.large_sprites = val.has_bits(0x20),
+            0x2001 => {
+                self.rendering_enabled = val & 0x18 != 0;
+                if !self.rendering_enabled {
+                    self.in_frame = false;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read_u8(&mut self, addr: usize) -> Option<u8> {
+        self.detect_scanline(addr);
+        self.ppu_peek_u8(addr)
+    }
+
+    fn ppu_peek_u8(&mut self, addr: usize) -> Option<u8> {
+        match addr {
+            0x0000..=0x1FFF => Some(self.chr[self.chr_addr(addr)]),
+            0x2000..=0x2FFF => Some(self.read_nametable(addr)),
+            _ => None,
+        }
+    }
+
+    fn ppu_write_u8(&mut self, addr: usize, val: u8) -> bool {
+        match addr {
+            0x0000..=0x1FFF if self.chr.is_ram() => {
+                let addr = self.chr_addr(addr);
+                self.chr[addr] = val;
+            }
+            0x0000..=0x1FFF => {}
+            0x2000..=0x2FFF => self.write_nametable(addr, val),
+            _ => return false,
+        }
+
+        true
+    }
+
+    fn irq_triggered(&mut self) -> bool {
+        self.irq_pending && self.irq_enabled
+    }
+
+    fn clock_cpu(&mut self) {
+        if self.ppu_idle_cycles < PPU_IDLE_CYCLES {
+            self.ppu_idle_cycles += 1;
+        } else {
+            self.in_frame = false;
+        }
+    }
+
+    fn battery_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn set_battery_ram(&mut self, data: &[u8]) {
+        self.prg_ram.copy_from_slice(data);
+    }
+
+    fn restore_rom(&mut self, rom: &Mapper) {
+        if let Mapper::Mmc5Mapper(rom) = rom {
+            self.prg_rom = rom.prg_rom.clone();
+            self.chr.restore_rom(&rom.chr);
+        }
+    }
+}
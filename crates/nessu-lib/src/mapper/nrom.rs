@@ -1,10 +1,14 @@
 use crate::header::Header;
-use crate::mapper::{MapperTrait, Mirroring};
+use crate::mapper::{prg_ram_size, Chr, Mapper, MapperTrait, Mirroring};
+use crate::rand_vec;
 
 #[derive(Clone)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
 pub struct NromMapper {
+    #[cfg_attr(feature = "savestate", serde(skip))]
     prg_rom: Vec<u8>,
-    chr: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
     prg_mirrored: bool,
 }
 
@@ -16,13 +20,16 @@ impl NromMapper {
             vec![0; 0x4000]
         };
 
-        let mut chr = vec![0; 0x2000];
-        header.copy_chr(bytes, &mut chr);
+        let chr = Chr::new(bytes, header);
 
         let prg_mirrored = prg_rom.len() <= 0x4000;
 
+        let mut prg_ram = rand_vec![prg_ram_size(header)];
+        header.copy_trainer(bytes, &mut prg_ram);
+
         Self {
             prg_rom,
+            prg_ram,
             chr,
             prg_mirrored,
         }
@@ -41,17 +48,24 @@ impl MapperTrait for NromMapper {
         None
     }
 
-    fn cpu_read_u8(&mut self, addr: usize) -> u8 {
+    fn cpu_read_u8(&mut self, addr: usize) -> Option<u8> {
         let addr = self.effective_cpu_addr(addr) as usize;
         match addr {
-            0x8000..=0xFFFF if addr - 0x8000 < self.prg_rom.len() => self.prg_rom[addr - 0x8000],
-            _ => 0,
+            0x6000..=0x7FFF => Some(self.prg_ram[(addr - 0x6000) % self.prg_ram.len()]),
+            0x8000..=0xFFFF if addr - 0x8000 < self.prg_rom.len() => {
+                Some(self.prg_rom[addr - 0x8000])
+            }
+            _ => None,
         }
     }
 
     fn cpu_write_u8(&mut self, addr: usize, val: u8, _cycle: u128) {
         let addr = self.effective_cpu_addr(addr) as usize;
         match addr {
+            0x6000..=0x7FFF => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr - 0x6000) % len] = val
+            }
             0x8000..=0xFFFF if addr - 0x8000 < self.prg_rom.len() => {
                 self.prg_rom[addr - 0x8000] = val
             }
@@ -68,10 +82,26 @@ impl MapperTrait for NromMapper {
 
     fn ppu_write_u8(&mut self, addr: usize, val: u8) -> bool {
         match addr {
-            0x0000..=0x1FFF => self.chr[addr] = val,
+            0x0000..=0x1FFF if self.chr.is_ram() => self.chr[addr] = val,
+            0x0000..=0x1FFF => {}
             _ => return false,
         }
 
         true
     }
+
+    fn battery_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn set_battery_ram(&mut self, data: &[u8]) {
+        self.prg_ram.copy_from_slice(data);
+    }
+
+    fn restore_rom(&mut self, rom: &Mapper) {
+        if let Mapper::NromMapper(rom) = rom {
+            self.prg_rom = rom.prg_rom.clone();
+            self.chr.restore_rom(&rom.chr);
+        }
+    }
 }
@@ -1,24 +1,34 @@
 use crate::header::Header;
-use crate::mapper::{MapperTrait, Mirroring};
+use crate::mapper::{has_bus_conflicts, Chr, Mapper, MapperTrait, Mirroring};
 
 #[derive(Clone)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
 pub struct UxRomMapper {
+    #[cfg_attr(feature = "savestate", serde(skip))]
     prg_rom: Vec<u8>,
     prg_bank0: u8,
-    chr: Vec<u8>,
+    chr: Chr,
+    bus_conflicts: bool,
 }
 
 impl UxRomMapper {
     pub fn new(bytes: &[u8], header: &Header) -> Self {
         let prg_rom = bytes[header.prg_start as usize..header.prg_end as usize].to_vec();
 
-        let mut chr = vec![0; 0x2000];
-        header.copy_chr(bytes, &mut chr);
+        let chr = Chr::new(bytes, header);
 
         Self {
             prg_rom,
             prg_bank0: 0,
             chr,
+            bus_conflicts: has_bus_conflicts(header),
+        }
+    }
+
+    fn effective_cpu_addr(&self, addr: usize) -> usize {
+        match addr {
+            0x8000..=0xBFFF => addr - 0x8000 + ((self.prg_bank0 as usize) << 14),
+            _ => self.prg_rom.len() - 0x4000 + (addr - 0xC000),
         }
     }
 }
@@ -28,17 +38,23 @@ impl MapperTrait for UxRomMapper {
         None
     }
 
-    fn cpu_read_u8(&mut self, addr: usize) -> u8 {
+    fn cpu_read_u8(&mut self, addr: usize) -> Option<u8> {
         match addr {
-            0x8000..=0xBFFF => self.prg_rom[addr - 0x8000 + ((self.prg_bank0 as usize) << 14)],
-            0xC000..=0xFFFF => self.prg_rom[addr + 0x10000],
-            _ => 0,
+            0x8000..=0xFFFF => Some(self.prg_rom[self.effective_cpu_addr(addr)]),
+            _ => None,
         }
     }
 
     fn cpu_write_u8(&mut self, addr: usize, val: u8, _cycle: u128) {
         if let 0x8000..=0xFFFF = addr {
-            self.prg_bank0 = (val & 0b111) as u8;
+            let val = if self.bus_conflicts {
+                val & self.prg_rom[self.effective_cpu_addr(addr)]
+            } else {
+                val
+            };
+
+            let bank_count = self.prg_rom.len() / 0x4000;
+            self.prg_bank0 = (val as usize % bank_count) as u8;
         }
     }
 
@@ -51,10 +67,18 @@ impl MapperTrait for UxRomMapper {
 
     fn ppu_write_u8(&mut self, addr: usize, val: u8) -> bool {
         match addr {
-            0x0000..=0x1FFF => self.chr[addr] = val,
+            0x0000..=0x1FFF if self.chr.is_ram() => self.chr[addr] = val,
+            0x0000..=0x1FFF => {}
             _ => return false,
         }
 
         true
     }
+
+    fn restore_rom(&mut self, rom: &Mapper) {
+        if let Mapper::UxRomMapper(rom) = rom {
+            self.prg_rom = rom.prg_rom.clone();
+            self.chr.restore_rom(&rom.chr);
+        }
+    }
 }
@@ -0,0 +1,262 @@
+use crate::bitwise::HasBits;
+use crate::header::Header;
+use crate::mapper::{prg_ram_size, Chr, Mapper, MapperKind, MapperTrait, Mirroring};
+use crate::rand_vec;
+
+/// PPU dots per scanline. In scanline mode the IRQ prescaler counts down by 3 every CPU cycle.
+const IRQ_PRESCALER_PERIOD: i16 = 341;
+
+/// Konami VRC2 and VRC4 (mappers 21, 22, 23 and 25).
+///
+/// The variants differ mostly in which CPU address lines select the register within each
+/// $1000 block. Without a submapper the two possible lines of each mapper number are combined,
+/// which works for all known games.
+#[derive(Clone)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct Vrc2_4Mapper {
+    kind: MapperKind,
+
+    prg_ram: Vec<u8>,
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    prg_rom: Vec<u8>,
+    chr: Chr,
+
+    prg_bank_0: u8,
+    prg_bank_1: u8,
+    /// Swaps the switchable $8000 bank with the fixed second-to-last bank at $C000
+    prg_swap_mode: bool,
+    /// 1 KB CHR banks, 9 bits each on VRC4
+    chr_banks: [u16; 8],
+    mirroring: Mirroring,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_prescaler: i16,
+    irq_enabled: bool,
+    irq_enabled_after_ack: bool,
+    /// Clock the counter every CPU cycle instead of every scanline
+    irq_cycle_mode: bool,
+    irq_pending: bool,
+}
+
+impl Vrc2_4Mapper {
+    pub fn new(bytes: &[u8], header: &Header) -> Self {
+        let prg_rom = header.prg(bytes).to_vec();
+
+        let chr = Chr::new(bytes, header);
+
+        let mut prg_ram = rand_vec![prg_ram_size(header)];
+        header.copy_trainer(bytes, &mut prg_ram);
+
+        Self {
+            kind: header.mapper,
+            prg_ram,
+            prg_rom,
+            chr,
+            prg_bank_0: 0,
+            prg_bank_1: 0,
+            prg_swap_mode: false,
+            chr_banks: [0; 8],
+            mirroring: Mirroring::Vertical,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_prescaler: IRQ_PRESCALER_PERIOD,
+            irq_enabled: false,
+            irq_enabled_after_ack: false,
+            irq_cycle_mode: false,
+            irq_pending: false,
+        }
+    }
+
+    fn is_vrc2(&self) -> bool {
+        self.kind == MapperKind::VRC2A
+    }
+
+    /// Register index 0-3 within a $1000 block, from the address lines used by the variant
+    fn register(&self, addr: usize) -> usize {
+        let line = |n: usize| (addr >> n) & 1;
+
+        let (low, high) = match self.kind {
+            MapperKind::VRC4AC => (line(1) | line(6), line(2) | line(7)),
+            MapperKind::VRC2A => (line(1), line(0)),
+            MapperKind::VRC4EF => (line(0) | line(2), line(1) | line(3)),
+            MapperKind::VRC4BD => (line(1) | line(3), line(0) | line(2)),
+            _ => unreachable!(),
+        };
+
+        low | high << 1
+    }
+
+    fn prg_bank_offset(&self, bank: usize) -> usize {
+        (bank * 0x2000) % self.prg_rom.len()
+    }
+
+    fn set_chr_bank(&mut self, bank_idx: usize, high: bool, val: u8) {
+        let bank = &mut self.chr_banks[bank_idx];
+
+        *bank = if high {
+            (*bank & 0x0F) | ((val as u16 & 0x1F) << 4)
+        } else {
+            (*bank & 0x1F0) | (val as u16 & 0x0F)
+        };
+    }
+
+    fn effective_ppu_addr(&self, addr: usize) -> usize {
+        let mut bank = self.chr_banks[addr / 0x0400] as usize;
+
+        // VRC2a ignores the lowest bit of the bank number
+        if self.is_vrc2() {
+            bank >>= 1;
+        }
+
+        (bank * 0x0400 + (addr & 0x03FF)) % self.chr.len()
+    }
+
+    fn write_irq_control(&mut self, val: u8) {
+        self.irq_enabled_after_ack = val.has_bits(0b001);
+        self.irq_enabled = val.has_bits(0b010);
+        self.irq_cycle_mode = val.has_bits(0b100);
+        self.irq_pending = false;
+
+        if self.irq_enabled {
+            self.irq_counter = self.irq_latch;
+            self.irq_prescaler = IRQ_PRESCALER_PERIOD;
+        }
+    }
+
+    fn acknowledge_irq(&mut self) {
+        self.irq_pending = false;
+        self.irq_enabled = self.irq_enabled_after_ack;
+    }
+
+    fn clock_irq_counter(&mut self) {
+        if self.irq_counter == 0xFF {
+            self.irq_counter = self.irq_latch;
+            self.irq_pending = true;
+        } else {
+            self.irq_counter += 1;
+        }
+    }
+}
+
+impl MapperTrait for Vrc2_4Mapper {
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(self.mirroring)
+    }
+
+    fn cpu_read_u8(&mut self, addr: usize) -> Option<u8> {
+        let last_bank = (self.prg_rom.len() / 0x2000).saturating_sub(1);
+
+        let bank = match addr {
+            0x6000..=0x7FFF => return Some(self.prg_ram[(addr - 0x6000) % self.prg_ram.len()]),
+            0x8000..=0x9FFF if self.prg_swap_mode => last_bank.saturating_sub(1),
+            0x8000..=0x9FFF => self.prg_bank_0 as usize,
+            0xA000..=0xBFFF => self.prg_bank_1 as usize,
+            0xC000..=0xDFFF if self.prg_swap_mode => self.prg_bank_0 as usize,
+            0xC000..=0xDFFF => last_bank.saturating_sub(1),
+            0xE000..=0xFFFF => last_bank,
+            _ => return None,
+        };
+
+        Some(self.prg_rom[self.prg_bank_offset(bank) + (addr & 0x1FFF)])
+    }
+
+    fn cpu_write_u8(&mut self, addr: usize, val: u8, _cycle: u128) {
+        if let 0x6000..=0x7FFF = addr {
+            let len = self.prg_ram.len();
+            self.prg_ram[(addr - 0x6000) % len] = val;
+            return;
+        }
+
+        let reg = self.register(addr);
+
+        match (addr & 0xF000, reg) {
+            (0x8000, _) => self.prg_bank_0 = val & 0x1F,
+            (0x9000, _) if self.is_vrc2() => {
+                self.mirroring = match val & 1 {
+                    0 => Mirroring::Vertical,
+                    _ => Mirroring::Horizontal,
+                }
+            }
+            (0x9000, 0) => {
+                self.mirroring = match val & 0b11 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::OneScreenLowerBank,
+                    _ => Mirroring::OneScreenUpperBank,
+                }
+            }
+            (0x9000, _) => self.prg_swap_mode = val.has_bits(0b10),
+            (0xA000, _) => self.prg_bank_1 = val & 0x1F,
+            (0xB000..=0xE000, reg) => {
+                // Each 1 KB bank is set with a low and a high register
+                let bank_idx = ((addr & 0xF000) - 0xB000) / 0x0800 + reg / 2;
+                self.set_chr_bank(bank_idx, reg & 1 == 1, val);
+            }
+            (0xF000, 0) if !self.is_vrc2() => {
+                self.irq_latch = (self.irq_latch & 0xF0) | (val & 0x0F)
+            }
+            (0xF000, 1) if !self.is_vrc2() => self.irq_latch = (self.irq_latch & 0x0F) | (val << 4),
+            (0xF000, 2) if !self.is_vrc2() => self.write_irq_control(val),
+            (0xF000, 3) if !self.is_vrc2() => self.acknowledge_irq(),
+            _ => {}
+        }
+    }
+
+    fn ppu_read_u8(&mut self, addr: usize) -> Option<u8> {
+        match addr {
+            0x0000..=0x1FFF => Some(self.chr[self.effective_ppu_addr(addr)]),
+            _ => None,
+        }
+    }
+
+    fn ppu_write_u8(&mut self, addr: usize, val: u8) -> bool {
+        match addr {
+            0x0000..=0x1FFF if self.chr.is_ram() => {
+                let addr = self.effective_ppu_addr(addr);
+                self.chr[addr] = val;
+                true
+            }
+            0x0000..=0x1FFF => true,
+            _ => false,
+        }
+    }
+
+    fn irq_triggered(&mut self) -> bool {
+        self.irq_pending
+    }
+
+    /// The VRC4 IRQ counter is driven by CPU cycles. In scanline mode a prescaler divides them
+    /// to approximate scanlines.
+    fn clock_cpu(&mut self) {
+        if !self.irq_enabled {
+            return;
+        }
+
+        if self.irq_cycle_mode {
+            self.clock_irq_counter();
+        } else {
+            self.irq_prescaler -= 3;
+
+            if self.irq_prescaler <= 0 {
+                self.irq_prescaler += IRQ_PRESCALER_PERIOD;
+                self.clock_irq_counter();
+            }
+        }
+    }
+
+    fn battery_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn set_battery_ram(&mut self, data: &[u8]) {
+        self.prg_ram.copy_from_slice(data);
+    }
+
+    fn restore_rom(&mut self, rom: &Mapper) {
+        if let Mapper::Vrc2_4Mapper(rom) = rom {
+            self.prg_rom = rom.prg_rom.clone();
+            self.chr.restore_rom(&rom.chr);
+        }
+    }
+}
@@ -0,0 +1,100 @@
+/// Buttons of the FM2 input log in the order they are written, from bit 0 to bit 7 of the
+/// controller state.
+const FM2_BUTTONS: &[u8; 8] = b"RLDUTSBA";
+
+/// Bit of the FM2 commands field for a soft reset
+const SOFT_RESET: u8 = 1;
+
+/// Controller state of both players during one frame, as bitfields of [`Button`](crate::input::Button).
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct FrameInput {
+    pub player1: u8,
+    pub player2: u8,
+    /// Soft reset at the start of the frame
+    pub reset: bool,
+}
+
+/// Write the inputs in the format of an FM2 input log, one `|commands|port0|port1|` line per frame.
+/// A reset is written as the soft reset command.
+pub fn write_movie(frames: &[FrameInput]) -> String {
+    frames
+        .iter()
+        .map(|frame| {
+            format!(
+                "|{}|{}|{}||\n",
+                frame.reset as u8,
+                write_gamepad(frame.player1),
+                write_gamepad(frame.player2)
+            )
+        })
+        .collect()
+}
+
+/// Parse inputs written by [`write_movie`]. Lines that don't start with `|`, like the header
+/// of an FM2 file, are ignored. Of the FM2 commands, only the soft reset is supported.
+pub fn parse_movie(text: &str) -> Result<Vec<FrameInput>, String> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| line.starts_with('|'))
+        .map(|(line_idx, line)| {
+            let fields: Vec<&str> = line.split('|').collect();
+
+            let frame = match fields.as_slice() {
+                [_, commands, player1, player2, ..] => parse_commands(commands)
+                    .zip(parse_gamepad(player1))
+                    .zip(parse_gamepad(player2)),
+                _ => None,
+            };
+
+            frame
+                .map(|((commands, player1), player2)| FrameInput {
+                    player1,
+                    player2,
+                    reset: commands & SOFT_RESET != 0,
+                })
+                .ok_or_else(|| format!("Invalid input on line {}", line_idx + 1))
+        })
+        .collect()
+}
+
+fn write_gamepad(state: u8) -> String {
+    FM2_BUTTONS
+        .iter()
+        .enumerate()
+        .map(|(bit, &button)| {
+            if state & (1 << bit) != 0 {
+                button as char
+            } else {
+                '.'
+            }
+        })
+        .collect()
+}
+
+fn parse_commands(field: &str) -> Option<u8> {
+    if field.is_empty() {
+        return Some(0);
+    }
+
+    field.parse().ok()
+}
+
+/// An empty field means that nothing is connected to the port.
+fn parse_gamepad(field: &str) -> Option<u8> {
+    if field.is_empty() {
+        return Some(0);
+    }
+
+    if field.len() != FM2_BUTTONS.len() {
+        return None;
+    }
+
+    Some(
+        field
+            .bytes()
+            .enumerate()
+            .filter(|(_, c)| *c != b'.' && *c != b' ')
+            .fold(0, |state, (bit, _)| state | 1 << bit),
+    )
+}
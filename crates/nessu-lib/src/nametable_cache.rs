@@ -0,0 +1,100 @@
+use crate::ppu::{Palette, Ppu};
+
+const NAMETABLE_BYTES: usize = 256 * 240 * 4;
+const TILES_PER_NAMETABLE: usize = 32 * 30;
+
+/// Everything a nametable tile is rendered from
+#[derive(Copy, Clone, PartialEq, Eq)]
+struct TileKey {
+    pattern: [u8; 16],
+    /// Palette RAM values of the tile's palette
+    colors: [u8; 4],
+}
+
+/// Rendered images of the four nametables. Only the tiles whose pattern or palette has
+/// changed since the previous render are rendered again.
+pub struct NametableCache {
+    images: Vec<Vec<u8>>,
+    /// The inputs each tile was last rendered with, or None if it has to be rendered
+    tiles: Vec<Vec<Option<TileKey>>>,
+    palette: Option<Palette>,
+}
+
+impl Default for NametableCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NametableCache {
+    pub fn new() -> Self {
+        Self {
+            images: vec![vec![0; NAMETABLE_BYTES]; 4],
+            tiles: vec![vec![None; TILES_PER_NAMETABLE]; 4],
+            palette: None,
+        }
+    }
+
+    /// Render all tiles again on the next call to [`NametableCache::render`].
+    pub fn invalidate(&mut self) {
+        for tiles in &mut self.tiles {
+            tiles.fill(None);
+        }
+    }
+
+    /// The nametable as RGBA bytes, 256×240 pixels in row-major order.
+    pub fn render(&mut self, ppu: &mut Ppu, nametable_idx: u8) -> &[u8] {
+        if self.palette.as_ref() != Some(ppu.palette()) {
+            self.palette = Some(*ppu.palette());
+            self.invalidate();
+        }
+
+        let nametable_addr = 0x2000 + 0x400 * nametable_idx as u16;
+        let base_pattern_addr = ppu.background_pattern_table_address();
+        let image = &mut self.images[nametable_idx as usize];
+        let tiles = &mut self.tiles[nametable_idx as usize];
+
+        for (i, tile) in tiles.iter_mut().enumerate() {
+            let (x0, y0) = (i % 32, i / 32);
+
+            let nt_byte = ppu.peek_mem_u8(nametable_addr + i as u16);
+            let tile_base_addr = base_pattern_addr + ((nt_byte as u16) << 4);
+
+            let attr_addr = nametable_addr | 0x3C0 | ((y0 as u16 >> 2) << 3) | (x0 as u16 >> 2);
+            let mut attr_tile = ppu.peek_mem_u8(attr_addr);
+            attr_tile >>= (((x0 & 0b10) >> 1) | (y0 & 0b10)) << 1;
+            let palette_addr = 0x3F00 | ((attr_tile as u16 & 0b11) << 2);
+
+            let mut pattern = [0; 16];
+            for (offset, byte) in pattern.iter_mut().enumerate() {
+                *byte = ppu.peek_mem_u8(tile_base_addr + offset as u16);
+            }
+
+            let key = TileKey {
+                pattern,
+                colors: [0, 1, 2, 3].map(|i| ppu.peek_mem_u8(palette_addr | i)),
+            };
+
+            if *tile == Some(key) {
+                continue;
+            }
+            *tile = Some(key);
+
+            for y in 0..8 {
+                let tile_lo = key.pattern[y];
+                let tile_hi = key.pattern[y + 8];
+
+                for x in 0..8 {
+                    let pixel_idx = ((tile_lo >> (7 - x)) & 1) | (((tile_hi >> (7 - x)) & 1) << 1);
+                    let color_idx = key.colors[pixel_idx as usize];
+                    let color = ppu.palette()[color_idx as usize & 0x3F];
+
+                    let display_idx = ((y0 * 8 + y) * 256 + x0 * 8 + x) * 4;
+                    image[display_idx..][..4].copy_from_slice(&[color.0, color.1, color.2, 255]);
+                }
+            }
+        }
+
+        image
+    }
+}
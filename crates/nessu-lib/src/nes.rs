@@ -1,19 +1,77 @@
+use std::collections::{BTreeMap, HashSet};
+#[cfg(feature = "screenshot")]
+use std::fs::File;
+#[cfg(feature = "screenshot")]
+use std::io::{self, BufWriter};
+#[cfg(feature = "savestate")]
+use std::mem;
 use std::ops::DerefMut;
+#[cfg(feature = "screenshot")]
+use std::path::Path;
 
 use crate::apu::Apu;
 use crate::cartridge::Cartridge;
 use crate::cpu::Cpu;
-use crate::input::Button;
+use crate::game_genie::GameGenieCode;
+use crate::input::{Button, Zapper};
+use crate::movie::FrameInput;
+use crate::nametable_cache::NametableCache;
+use crate::ntsc;
 use crate::op::{into_op, op_size, AddressingMode, CpuOpEntry, OpKind};
-use crate::ppu::{Ppu, DEFAULT_PALETTE};
+use crate::ppu::Ppu;
+use crate::region::Region;
 
+/// Written to $6001-$6003 by test ROMs that report their status at $6000
+const TEST_ROM_SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+
+/// Callback invoked from the emulation loop, see [`Nes::on_frame`] and [`Nes::on_scanline`].
+pub type PpuCallback = Box<dyn FnMut(&Ppu)>;
+
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
 pub struct Nes {
     pub(crate) cpu: Cpu,
     pub(crate) ppu: Ppu,
     pub(crate) apu: Apu,
     pub(crate) cart: Box<Cartridge>,
 
+    region: Region,
+
     counter: u128,
+    /// Frames completed since power-on
+    frame_count: u64,
+
+    /// Zero the memory that would otherwise be randomized on power-on
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    deterministic: bool,
+
+    /// Inputs recorded since [`Nes::start_recording`]
+    recording: Option<Vec<FrameInput>>,
+    /// Movie being played back and the index of its next frame
+    playback: Option<(Vec<FrameInput>, usize)>,
+    /// Soft reset at the start of the next frame
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    reset_requested: bool,
+    /// Error from the reset sequence, reported by the next clock since resetting cannot fail
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    reset_error: Option<String>,
+    /// Battery-backed RAM from before the movie, which runs with zeroed RAM instead
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    movie_save_ram: Option<Vec<u8>>,
+
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    frame_callback: Option<PpuCallback>,
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    scanline_callback: Option<PpuCallback>,
+
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    nametable_cache: NametableCache,
+
+    /// CPU RAM addresses that are set to a value at the end of every frame
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    frozen_ram: BTreeMap<u16, u8>,
+
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    pub(crate) game_genie_codes: Vec<GameGenieCode>,
 }
 
 impl Nes {
@@ -28,14 +86,50 @@ impl Nes {
             ppu,
             apu,
             cart,
+            region: Region::Ntsc,
             counter: 1,
+            frame_count: 0,
+            deterministic: false,
+            recording: None,
+            playback: None,
+            reset_requested: false,
+            reset_error: None,
+            movie_save_ram: None,
+            frame_callback: None,
+            scanline_callback: None,
+            nametable_cache: NametableCache::new(),
+            frozen_ram: BTreeMap::new(),
+            game_genie_codes: Vec::new(),
         }
     }
 
+    /// Like [`Nes::new`], but RAM is zero-filled on power-on instead of randomized, so that
+    /// the same inputs always produce the same output.
+    pub fn new_deterministic() -> Self {
+        let mut nes = Self::new();
+        nes.set_deterministic(true);
+        nes.cpu.internal_ram.fill(0);
+        nes
+    }
+
+    /// Enable or disable the zero-filled power-on RAM of [`Nes::new_deterministic`]. Takes
+    /// effect on the next power cycle or cartridge insertion.
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        self.deterministic = deterministic;
+    }
+
+    pub fn is_deterministic(&self) -> bool {
+        self.deterministic
+    }
+
     pub fn cartridge(&self) -> &Cartridge {
         &self.cart
     }
 
+    pub fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cart
+    }
+
     pub fn cpu(&self) -> &Cpu {
         &self.cpu
     }
@@ -48,6 +142,10 @@ impl Nes {
         &self.apu
     }
 
+    pub fn apu_mut(&mut self) -> &mut Apu {
+        &mut self.apu
+    }
+
     pub fn ppu(&self) -> &Ppu {
         &self.ppu
     }
@@ -60,81 +158,406 @@ impl Nes {
         &self.ppu.display
     }
 
-    pub fn nametable_rgb_bytes(&mut self, nametable_idx: u8) -> Vec<u8> {
-        let base_pattern_addr = self.ppu.background_pattern_table_address();
+    /// The current frame as RGBA bytes, 256×240 pixels in row-major order (256 * 240 * 4 bytes).
+    pub fn framebuffer(&self) -> &[u8] {
+        &self.ppu.display
+    }
 
-        let mut colors = vec![0; 0x3C000];
-        let mut i = 0;
+    /// The current frame run through the [NTSC filter](crate::ntsc) as RGBA bytes,
+    /// [`NTSC_WIDTH`](crate::ntsc::NTSC_WIDTH) × 240 pixels. The filter is only computed when
+    /// this is called, and is expensive enough to be worth skipping when not needed.
+    pub fn ntsc_filtered_bytes(&self) -> Vec<u8> {
+        // The phase of the color subcarrier cycles through three values from frame to frame
+        let frame_phase = (self.frame_count % 3) as usize * 4;
+        ntsc::filter(&self.ppu.pixels, frame_phase)
+    }
 
-        for y0 in 0..30 {
-            for x0 in 0..32 {
-                let nt_byte = self
-                    .ppu
-                    .read_mem_u8(0x2000 + (0x400 * nametable_idx as u16) + i);
-                i += 1;
+    /// Write the current frame to a PNG file.
+    #[cfg(feature = "screenshot")]
+    pub fn save_screenshot<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let file = BufWriter::new(File::create(path)?);
 
-                let tile_base_addr = base_pattern_addr + ((nt_byte as u16) << 4);
+        let mut encoder = png::Encoder::new(file, 256, 240);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
 
-                let attr_addr = 0x23C0
-                    | (0x400 * nametable_idx as usize)
-                    | (((y0 as usize) >> 2) << 3)
-                    | ((x0 as usize) >> 2);
-                let mut attr_tile = self.ppu.read_mem_u8(attr_addr as u16);
-                attr_tile >>= (((x0 & 0b10) >> 1) | (y0 & 0b10)) << 1;
-                attr_tile &= 0b11;
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(self.framebuffer())?;
+        writer.finish()?;
 
-                let attr_lo = (attr_tile & 0b01) * 0xFF;
-                let attr_hi = ((attr_tile & 0b10) >> 1) * 0xFF;
+        Ok(())
+    }
 
-                for y in 0..8 {
-                    let tile_lo = self.ppu.read_mem_u8(tile_base_addr + y);
-                    let tile_hi = self.ppu.read_mem_u8(tile_base_addr + y + 8);
+    /// Render pattern table 0 or 1 as RGBA bytes, 128×128 pixels (128 * 128 * 4 bytes).
+    /// The tiles are laid out in a 16×16 grid and colored with palette 0-7 from $3F00.
+    pub fn pattern_table_rgb_bytes(&mut self, table: u8, palette: u8) -> Vec<u8> {
+        let base_addr = (table as u16 & 1) * 0x1000;
+        let palette_addr = 0x3F00 | ((palette as u16 & 0b111) << 2);
 
-                    for x in 0..8 {
-                        let pixel_idx =
-                            ((tile_lo >> (7 - x)) & 1) | (((tile_hi >> (7 - x)) & 1) << 1);
+        let mut colors = vec![0; 128 * 128 * 4];
 
-                        let palette_idx =
-                            ((attr_lo >> (7 - x)) & 1) | (((attr_hi >> (7 - x)) & 1) << 1);
+        for tile in 0..256u16 {
+            let tile_x = (tile % 16) as usize * 8;
+            let tile_y = (tile / 16) as usize * 8;
+            let tile_addr = base_addr + (tile << 4);
 
-                        let color_idx = self
-                            .ppu
-                            .read_mem_u8(0x3F00 | ((palette_idx as u16) << 2) | pixel_idx as u16);
+            for y in 0..8 {
+                let tile_lo = self.ppu.peek_mem_u8(tile_addr + y);
+                let tile_hi = self.ppu.peek_mem_u8(tile_addr + y + 8);
 
-                        let display_idx =
-                            (((y0 * 8 + y) as usize * 256) + (x0 * 8 + x) as usize) as usize * 4;
+                for x in 0..8 {
+                    let pixel_idx = ((tile_lo >> (7 - x)) & 1) | (((tile_hi >> (7 - x)) & 1) << 1);
+                    let color_idx = self.ppu.peek_mem_u8(palette_addr | pixel_idx as u16);
+                    let color = self.ppu.palette()[color_idx as usize & 0x3F];
+
+                    let idx = ((tile_y + y as usize) * 128 + tile_x + x) * 4;
+                    colors[idx..idx + 4].copy_from_slice(&[color.0, color.1, color.2, 255]);
+                }
+            }
+        }
 
-                        let color = DEFAULT_PALETTE[color_idx as usize & 0x3F];
+        colors
+    }
+
+    /// The 64 sprites of primary OAM as (Y, tile index, attributes, X).
+    pub fn oam_entries(&self) -> [(u8, u8, u8, u8); 64] {
+        let mut entries = [(0, 0, 0, 0); 64];
+
+        for (entry, bytes) in entries.iter_mut().zip(self.ppu.primary_oam.chunks_exact(4)) {
+            *entry = (bytes[0], bytes[1], bytes[2], bytes[3]);
+        }
+
+        entries
+    }
+
+    /// RGBA bytes of the tile of a sprite in primary OAM, flipped and colored the way it
+    /// is drawn. The tile is 8x16 pixels in 8x16 sprite mode and 8x8 otherwise.
+    /// Transparent pixels have zero alpha.
+    pub fn sprite_rgb_bytes(&mut self, sprite_idx: u8) -> Vec<u8> {
+        let (_, tile_idx, attrs, _) = self.oam_entries()[sprite_idx as usize & 0x3F];
+        let palette_addr = 0x3F10 | ((attrs as u16 & 0b11) << 2);
+        let flip_horizontal = attrs & 0x40 != 0;
+        let flip_vertical = attrs & 0x80 != 0;
+
+        let (tile_addr, height) = if self.ppu.use_large_sprites() {
+            let table = (tile_idx as u16 & 1) << 12;
+            (table + ((tile_idx as u16 & !1) << 4), 16)
+        } else {
+            let table = self.ppu.sprite_pattern_table_address();
+            (table + ((tile_idx as u16) << 4), 8)
+        };
 
-                        colors[display_idx] = color.0;
-                        colors[display_idx + 1] = color.1;
-                        colors[display_idx + 2] = color.2;
-                        colors[display_idx + 3] = 255;
-                    }
+        let mut colors = vec![0; 8 * height * 4];
+
+        for y in 0..height {
+            let row = if flip_vertical { height - 1 - y } else { y };
+            // The bottom half of an 8x16 sprite is the next tile
+            let row_addr = tile_addr + ((row as u16 & 8) << 1) + (row as u16 & 7);
+            let tile_lo = self.ppu.peek_mem_u8(row_addr);
+            let tile_hi = self.ppu.peek_mem_u8(row_addr + 8);
+
+            for x in 0..8 {
+                let bit = if flip_horizontal { x } else { 7 - x };
+                let pixel_idx = ((tile_lo >> bit) & 1) | (((tile_hi >> bit) & 1) << 1);
+                if pixel_idx == 0 {
+                    continue;
                 }
+
+                let color_idx = self.ppu.peek_mem_u8(palette_addr | pixel_idx as u16);
+                let color = self.ppu.palette()[color_idx as usize & 0x3F];
+
+                let idx = (y * 8 + x) * 4;
+                colors[idx..idx + 4].copy_from_slice(&[color.0, color.1, color.2, 255]);
             }
         }
 
         colors
     }
 
+    /// The 32 palette entries at $3F00-$3F1F resolved to RGB colors. Background palettes
+    /// come first, followed by sprite palettes.
+    pub fn palette_rgb_bytes(&mut self) -> [(u8, u8, u8); 32] {
+        let mut colors = [(0, 0, 0); 32];
+
+        for (i, color) in colors.iter_mut().enumerate() {
+            let color_idx = self.ppu.peek_mem_u8(0x3F00 + i as u16);
+            *color = self.ppu.palette()[color_idx as usize & 0x3F];
+        }
+
+        colors
+    }
+
+    /// The nametable as RGBA bytes, 256×240 pixels in row-major order. Only the tiles that
+    /// have changed since the previous call are rendered.
+    pub fn nametable_rgb_bytes(&mut self, nametable_idx: u8) -> &[u8] {
+        self.nametable_cache.render(&mut self.ppu, nametable_idx)
+    }
+
+    /// Render the nametables from scratch on the next call to [`Nes::nametable_rgb_bytes`],
+    /// instead of only the tiles that have changed.
+    pub fn refresh_nametables(&mut self) {
+        self.nametable_cache.invalidate();
+    }
+
+    /// Serialize the complete machine state. The ROM data of the cartridge is left out, so
+    /// the state can only be loaded with the same ROM inserted.
+    #[cfg(feature = "savestate")]
+    pub fn save_state(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("failed to serialize state")
+    }
+
+    /// Restore a state created with [`Nes::save_state`]. Fails if the state was saved with
+    /// a different ROM than the one inserted.
+    #[cfg(feature = "savestate")]
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut state: Nes = bincode::deserialize(data).map_err(|e| e.to_string())?;
+        state.cart.restore_rom(&self.cart)?;
+
+        // The PPU refers to the cartridge by pointer, which has to be relinked
+        state.ppu.set_cartridge(state.cart.deref_mut());
+
+        // Keep settings that are not part of the emulated machine
+        state.apu.set_sample_rate(self.apu.sample_rate());
+        state.set_region(state.region);
+        state.ppu.set_palette(*self.ppu.palette());
+        state.cpu.breakpoints = mem::take(&mut self.cpu.breakpoints);
+        state.cpu.read_breakpoints = mem::take(&mut self.cpu.read_breakpoints);
+        state.cpu.write_breakpoints = mem::take(&mut self.cpu.write_breakpoints);
+        state.cpu.trace = self.cpu.trace.take();
+        if state.recording.is_some() || state.playback.is_some() {
+            state.movie_save_ram = self.movie_save_ram.take().or_else(|| self.save_ram_copy());
+        }
+        state.deterministic = self.deterministic;
+        state.frame_callback = self.frame_callback.take();
+        state.scanline_callback = self.scanline_callback.take();
+        state.frozen_ram = mem::take(&mut self.frozen_ram);
+        state.game_genie_codes = mem::take(&mut self.game_genie_codes);
+
+        *self = state;
+
+        Ok(())
+    }
+
     pub fn insert_cartridge(&mut self, cart: Cartridge) {
+        let region = cart.header().region;
         self.cart = Box::new(cart);
+        self.recording = None;
+        self.playback = None;
+        self.movie_save_ram = None;
+        self.frozen_ram.clear();
+        self.game_genie_codes.clear();
+
+        // RAM restored from a save file is already deterministic
+        if self.deterministic && !self.cart.ram_loaded() {
+            self.cart.clear_ram();
+        }
+
+        self.set_region(region);
         self.reset();
     }
 
+    /// Register a callback that is invoked whenever a frame has been completed, replacing any
+    /// previous one. It runs inside the emulation loop, so it should be cheap.
+    pub fn on_frame(&mut self, f: PpuCallback) {
+        self.frame_callback = Some(f);
+    }
+
+    /// Register a callback that is invoked at the start of every scanline, replacing any
+    /// previous one. The PPU is at dot 0 of the new scanline, so the register values are the
+    /// ones the previous scanline ended with. It runs inside the emulation loop 262 times per
+    /// frame, so it should be cheap.
+    pub fn on_scanline(&mut self, f: PpuCallback) {
+        self.scanline_callback = Some(f);
+    }
+
+    pub fn clear_callbacks(&mut self) {
+        self.frame_callback = None;
+        self.scanline_callback = None;
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// Set the timing of the console. This is detected from the header when a cartridge is inserted.
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.ppu.set_region(region);
+        self.apu.set_region(region);
+    }
+
+    /// Hard reset, as if the console was switched off and on. The CPU, PPU and APU start from
+    /// their power-on state and RAM is randomized, or zeroed on a deterministic console.
     pub fn power(&mut self) {
+        let sample_rate = self.apu.sample_rate();
+
+        let palette = *self.ppu.palette();
+
         self.cpu = Cpu::new();
         self.ppu = Ppu::new(self.cart.deref_mut());
+        self.ppu.set_palette(palette);
         self.apu = Apu::new();
+        self.apu.set_sample_rate(sample_rate);
+        self.set_region(self.region);
+        self.counter = 1;
+        self.frame_count = 0;
+
+        if self.deterministic {
+            self.cpu.internal_ram.fill(0);
+        }
+
         self.reset();
     }
 
+    /// Power cycle with all RAM zeroed, so that the same inputs always produce the same output.
+    /// The battery-backed RAM is set aside until the movie ends.
+    fn power_for_movie(&mut self) {
+        if self.movie_save_ram.is_none() {
+            self.movie_save_ram = self.save_ram_copy();
+        }
+
+        self.power();
+        self.cpu.internal_ram.fill(0);
+        self.cart.clear_ram();
+    }
+
+    fn save_ram_copy(&self) -> Option<Vec<u8>> {
+        self.cart.save_ram().map(<[u8]>::to_vec)
+    }
+
+    /// Put back the battery-backed RAM that was set aside when the movie started
+    fn end_movie(&mut self) {
+        self.recording = None;
+        self.playback = None;
+
+        if let Some(ram) = self.movie_save_ram.take() {
+            self.cart.load_ram(&ram);
+        }
+    }
+
+    /// Battery-backed RAM to write to a save file. While a movie is recorded or played, this
+    /// is the RAM from before the movie, as the movie runs from zeroed RAM.
+    pub fn save_ram(&self) -> Option<&[u8]> {
+        match &self.movie_save_ram {
+            Some(ram) => Some(ram),
+            None => self.cart.save_ram(),
+        }
+    }
+
+    /// Power cycle and start recording the controller state of each frame.
+    pub fn start_recording(&mut self) {
+        self.playback = None;
+        self.power_for_movie();
+        self.recording = Some(Vec::new());
+    }
+
+    /// Stop recording and return the inputs of each frame since [`Nes::start_recording`].
+    pub fn stop_recording(&mut self) -> Vec<FrameInput> {
+        let frames = self.recording.take().unwrap_or_default();
+        self.end_movie();
+        frames
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Power cycle and replay the inputs of a movie, one entry per frame. The inputs set with
+    /// [`Nes::set_button_state_player1`] and [`Nes::set_button_state_player2`] are overridden
+    /// until the movie ends.
+    pub fn play_movie(&mut self, frames: Vec<FrameInput>) {
+        self.recording = None;
+        self.power_for_movie();
+        self.playback = Some((frames, 0));
+    }
+
+    pub fn is_playing_movie(&self) -> bool {
+        self.playback.is_some()
+    }
+
+    pub fn stop_movie(&mut self) {
+        self.end_movie();
+    }
+
+    /// Soft reset at the start of the next frame, before its input is read. Unlike
+    /// [`Nes::reset`], the reset always lands on a frame boundary, so it is recorded in a
+    /// movie and happens at the same point when the movie is played back.
+    pub fn reset_at_next_frame(&mut self) {
+        self.reset_requested = true;
+    }
+
+    /// Apply the movie input or record the input of the frame that is about to start, and
+    /// reset if requested for this frame
+    fn update_movie(&mut self) {
+        let mut reset = std::mem::take(&mut self.reset_requested);
+
+        if let Some((frames, next_frame)) = self.playback.as_mut() {
+            match frames.get(*next_frame) {
+                Some(input) => {
+                    // The movie overrides resets along with the controllers
+                    reset = input.reset;
+                    self.cpu.set_input(*input);
+                    *next_frame += 1;
+                }
+                None => self.playback = None,
+            }
+
+            if self.playback.is_none() {
+                self.end_movie();
+            }
+        }
+
+        if let Some(recording) = self.recording.as_mut() {
+            recording.push(FrameInput {
+                reset,
+                ..self.cpu.input()
+            });
+        }
+
+        if reset {
+            self.reset();
+        }
+    }
+
+    /// Soft reset, as if the reset button was pressed. RAM, PRG-RAM and VRAM are kept, the
+    /// CPU, PPU and APU are partially reset and the CPU runs its 7 cycle reset sequence, so
+    /// that it is ready to fetch the first instruction from the reset vector.
     pub fn reset(&mut self) {
         self.ppu.reset(self.cart.deref_mut());
         self.cpu.reset();
-        self.cpu.pc = self.cpu_read_mem(0xFFFC) as u16 | (self.cpu_read_mem(0xFFFD) as u16) << 8;
         self.apu.reset();
+
+        if !self.cart.is_valid() {
+            return;
+        }
+
+        while self.cpu.is_resetting() {
+            match self.clock() {
+                // Data breakpoints on the stack or the reset vector are not reported
+                Err(msg)
+                    if msg.starts_with("Read breakpoint")
+                        || msg.starts_with("Write breakpoint") => {}
+                Err(msg) => {
+                    self.reset_error = Some(msg);
+                    break;
+                }
+                Ok(()) => {}
+            }
+        }
+    }
+
+    /// Number of frames completed since power-on.
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /// Which PPU tick of the current CPU cycle comes next. The CPU is clocked along with
+    /// the PPU when this is 0. Ranges from 0 to 2 on NTSC, and 0 to 3 on PAL where a CPU
+    /// cycle is 3.2 PPU ticks long.
+    pub fn cpu_ppu_phase(&self) -> u8 {
+        let (cpu_cycles, ppu_cycles) = self.region.cpu_ppu_ratio();
+        let (cpu_cycles, ppu_cycles) = (cpu_cycles as u128, ppu_cycles as u128);
+        ((self.counter * cpu_cycles % ppu_cycles) / cpu_cycles) as u8
     }
 
     pub fn clock(&mut self) -> Result<(), String> {
@@ -142,19 +565,107 @@ impl Nes {
             return Ok(());
         }
 
-        self.ppu.clock();
+        if let Some(msg) = self.reset_error.take() {
+            return Err(msg);
+        }
+
+        self.clock_ppu();
 
-        if self.counter % 3 == 0 {
-            Cpu::clock(self)?;
-            self.apu.clock();
+        if self.is_cpu_cycle() {
+            self.clock_cpu()?;
         }
 
         self.counter += 1;
 
+        // Data breakpoints are reported after the access has completed
+        match self.cpu.take_data_breakpoint_hit() {
+            Some(msg) => Err(msg),
+            None => Ok(()),
+        }
+    }
+
+    fn clock_ppu(&mut self) {
+        self.ppu.clock();
+
+        if self.ppu.current_cycle() == 0 {
+            if let Some(callback) = self.scanline_callback.as_mut() {
+                callback(&self.ppu);
+            }
+        }
+
+        if self.ppu.frame_completed() {
+            self.frame_count += 1;
+
+            for (&addr, &val) in self.frozen_ram.iter() {
+                self.cpu.internal_ram[addr as usize & 0x07FF] = val;
+            }
+
+            if let Some(callback) = self.frame_callback.as_mut() {
+                callback(&self.ppu);
+            }
+        }
+    }
+
+    fn clock_cpu(&mut self) -> Result<(), String> {
+        Cpu::clock(self)?;
+        self.apu.clock();
+        self.cart.clock_cpu();
+        Ok(())
+    }
+
+    /// The CPU is clocked once every 3 PPU cycles on NTSC, and every 3.2 on PAL
+    fn is_cpu_cycle(&self) -> bool {
+        let (cpu_cycles, ppu_cycles) = self.region.cpu_ppu_ratio();
+        let (cpu_cycles, ppu_cycles) = (cpu_cycles as u128, ppu_cycles as u128);
+        (self.counter * cpu_cycles) % ppu_cycles < cpu_cycles
+    }
+
+    /// A faster but less accurate [`Nes::step_frame`]. The CPU runs a whole instruction at
+    /// a time, after which the PPU catches up. Games that time their PPU register accesses
+    /// to the cycle can glitch, but most run fine.
+    pub fn step_frame_fast(&mut self) -> Result<(), String> {
+        if !self.cart.is_valid() {
+            return Ok(());
+        }
+
+        self.update_movie();
+
+        let mut frame_completed = false;
+
+        while !frame_completed {
+            let mut cpu_cycles = 0;
+
+            loop {
+                self.clock_cpu()?;
+                cpu_cycles += 1;
+
+                if !self.cpu.instruction_ongoing() {
+                    break;
+                }
+            }
+
+            while cpu_cycles > 0 {
+                self.clock_ppu();
+                frame_completed |= self.ppu.frame_completed();
+
+                if self.is_cpu_cycle() {
+                    cpu_cycles -= 1;
+                }
+
+                self.counter += 1;
+            }
+
+            if let Some(msg) = self.cpu.take_data_breakpoint_hit() {
+                return Err(msg);
+            }
+        }
+
         Ok(())
     }
 
     pub fn step_frame(&mut self) -> Result<(), String> {
+        self.update_movie();
+
         loop {
             self.clock()?;
 
@@ -164,7 +675,25 @@ impl Nes {
         }
     }
 
+    /// Step exactly one frame and return the resulting [framebuffer](Nes::framebuffer).
+    pub fn run_frame(&mut self) -> Result<&[u8], String> {
+        self.step_frame()?;
+        Ok(self.framebuffer())
+    }
+
+    /// Step `n` frames and return the framebuffer of the last one.
+    pub fn run_frames(&mut self, n: usize) -> Result<&[u8], String> {
+        for _ in 0..n {
+            self.step_frame()?;
+        }
+        Ok(self.framebuffer())
+    }
+
     pub fn step_instruction(&mut self) -> Result<(), String> {
+        if self.cpu.is_halted() {
+            return Ok(());
+        }
+
         // clock until cpu instruction is started
         while !self.cpu.instruction_ongoing() {
             self.clock()?;
@@ -178,18 +707,207 @@ impl Nes {
         Ok(())
     }
 
+    /// Step over the current instruction. If it is a JSR, run until the subroutine returns,
+    /// otherwise behave like [`Nes::step_instruction`]. Gives up with an error if the
+    /// subroutine hasn't returned within `max_cycles` CPU cycles.
+    pub fn step_over(&mut self, max_cycles: u64) -> Result<(), String> {
+        if self.set_step_over_breakpoint() {
+            self.run_until_temporary_breakpoint(max_cycles)
+        } else {
+            self.step_instruction()
+        }
+    }
+
+    /// Run until PC reaches `addr`, for at most `max_cycles` CPU cycles. If another breakpoint
+    /// stops execution first or the cycles run out, the temporary breakpoint is kept so that
+    /// resuming continues towards `addr`.
+    pub fn run_to(&mut self, addr: u16, max_cycles: u64) -> Result<(), String> {
+        self.cpu.set_temporary_breakpoint(addr);
+        self.run_until_temporary_breakpoint(max_cycles)
+    }
+
+    /// Run until `pred` returns true or `max_cycles` CPU cycles have passed, and return whether
+    /// it returned true. The predicate is checked before starting and after every CPU cycle.
+    /// Breakpoints and a halted CPU stop the run with an error.
+    pub fn run_until<F: FnMut(&Nes) -> bool>(
+        &mut self,
+        max_cycles: u64,
+        mut pred: F,
+    ) -> Result<bool, String> {
+        if !self.cart.is_valid() {
+            return Ok(pred(self));
+        }
+
+        for _ in 0..max_cycles {
+            if pred(self) {
+                return Ok(true);
+            }
+
+            if self.cpu.is_halted() {
+                return Err("CPU halted".to_string());
+            }
+
+            loop {
+                let cpu_cycle = self.is_cpu_cycle();
+                self.clock()?;
+                if cpu_cycle {
+                    break;
+                }
+            }
+        }
+
+        Ok(pred(self))
+    }
+
+    /// If the next instruction is a JSR, set a temporary breakpoint on the instruction following
+    /// it and return true.
+    pub fn set_step_over_breakpoint(&mut self) -> bool {
+        if self.cpu.instruction_ongoing() {
+            return false;
+        }
+
+        let pc = self.cpu.pc;
+        match into_op(self.cpu_read_mem(pc)) {
+            Some((OpKind::Jsr, _, _)) => {
+                let s = self.cpu.s;
+                self.cpu
+                    .set_temporary_breakpoint_with_stack(pc.wrapping_add(3), s);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn run_until_temporary_breakpoint(&mut self, max_cycles: u64) -> Result<(), String> {
+        match self.run_until(max_cycles, |nes| nes.cpu.temporary_breakpoint().is_none()) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(format!(
+                "Temporary breakpoint not reached in {} cycles",
+                max_cycles
+            )),
+            // The temporary breakpoint itself stops the run with an error too
+            Err(_) if self.cpu.temporary_breakpoint().is_none() => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Set the sample rate of the samples returned by [`Nes::drain_audio_samples`].
+    /// Defaults to 44100 Hz.
+    pub fn set_audio_sample_rate(&mut self, rate: u32) {
+        self.apu.set_sample_rate(rate);
+    }
+
+    pub fn audio_sample_rate(&self) -> u32 {
+        self.apu.sample_rate()
+    }
+
+    /// Take the audio samples produced since the last call.
+    ///
+    /// Each [`Nes::step_frame`] produces roughly `rate / 60` mono samples,
+    /// e.g. 735 samples at 44100 Hz, so an audio stream buffering a few frames'
+    /// worth of samples is enough. At most [`crate::apu::MAX_BUFFERED_SAMPLES`]
+    /// samples are kept between calls.
+    pub fn drain_audio_samples(&mut self) -> Vec<f32> {
+        self.apu.drain_samples()
+    }
+
     pub fn set_button_state_player1(&mut self, button: Button, state: bool) {
         self.cpu.set_button_state_player1(button, state);
     }
 
-    pub fn _set_button_state_player2(&mut self, button: Button, state: bool) {
-        self.cpu._set_button_state_player2(button, state);
+    pub fn set_button_state_player2(&mut self, button: Button, state: bool) {
+        self.cpu.set_button_state_player2(button, state);
+    }
+
+    /// Connect a Zapper to the second port, aimed at the given display position.
+    pub fn set_zapper(&mut self, x: u16, y: u16, trigger: bool) {
+        self.cpu.set_zapper(Some(Zapper { x, y, trigger }));
+    }
+
+    /// Connect a controller to the second port in place of the Zapper.
+    pub fn disconnect_zapper(&mut self) {
+        self.cpu.set_zapper(None);
+    }
+
+    /// Set whether sound is picked up by the microphone of the Famicom's second controller.
+    pub fn set_microphone(&mut self, state: bool) {
+        self.cpu.set_microphone(state);
+    }
+
+    /// Status and message of a test ROM that reports its results at $6000, such as blargg's
+    /// test ROMs. The status is $80 while the test is running, $81 when the test needs the
+    /// reset button to be pressed, and otherwise the result code, $00 meaning success.
+    /// Returns None if the ROM has not written the signature that marks the status valid.
+    pub fn test_rom_status(&mut self) -> Option<(u8, String)> {
+        if (0..3).any(|i| self.cpu_read_mem(0x6001 + i) != TEST_ROM_SIGNATURE[i as usize]) {
+            return None;
+        }
+
+        let status = self.cpu_read_mem(0x6000);
+
+        let message: Vec<u8> = (0x6004..=0x7FFF)
+            .map(|addr| self.cpu_read_mem(addr))
+            .take_while(|&c| c != 0)
+            .collect();
+
+        Some((status, String::from_utf8_lossy(&message).into_owned()))
+    }
+
+    /// Copy of the CPU RAM at $0000-$07FF
+    pub fn ram_snapshot(&self) -> Vec<u8> {
+        self.cpu.internal_ram.clone()
+    }
+
+    /// Keep a CPU RAM address at `val` by writing it at the end of every frame.
+    pub fn freeze_ram(&mut self, addr: u16, val: u8) {
+        self.frozen_ram.insert(addr & 0x07FF, val);
+        self.cpu.internal_ram[addr as usize & 0x07FF] = val;
+    }
+
+    pub fn unfreeze_ram(&mut self, addr: u16) {
+        self.frozen_ram.remove(&(addr & 0x07FF));
+    }
+
+    pub fn frozen_ram(&self) -> impl Iterator<Item = (u16, u8)> + '_ {
+        self.frozen_ram.iter().map(|(&addr, &val)| (addr, val))
+    }
+
+    /// Decode a Game Genie code and patch PRG-ROM reads with it until it's removed.
+    pub fn add_game_genie_code(&mut self, code: &str) -> Result<(), String> {
+        let code = GameGenieCode::parse(code)?;
+        if !self.game_genie_codes.contains(&code) {
+            self.game_genie_codes.push(code);
+        }
+        Ok(())
+    }
+
+    /// Remove the code at `index` of [`Nes::game_genie_codes`] and return it, or `None` if
+    /// there is no such code.
+    pub fn remove_game_genie_code(&mut self, index: usize) -> Option<GameGenieCode> {
+        (index < self.game_genie_codes.len()).then(|| self.game_genie_codes.remove(index))
+    }
+
+    pub fn game_genie_codes(&self) -> &[GameGenieCode] {
+        &self.game_genie_codes
     }
 
     pub fn cpu_read_mem(&mut self, addr: u16) -> u8 {
         Cpu::read_mem_u8(self, addr)
     }
 
+    /// The NMI, RESET and IRQ/BRK vectors at $FFFA-$FFFF. They are read without side
+    /// effects, like [`Nes::cpu_read_mem`].
+    pub fn vectors(&mut self) -> (u16, u16, u16) {
+        let mut read_vector =
+            |addr| u16::from_le_bytes([self.cpu_read_mem(addr), self.cpu_read_mem(addr + 1)]);
+
+        (
+            read_vector(0xFFFA),
+            read_vector(0xFFFC),
+            read_vector(0xFFFE),
+        )
+    }
+
     pub fn cpu_write_mem(&mut self, addr: u16, val: u8) {
         Cpu::write_mem_u8(self, addr, val)
     }
@@ -203,18 +921,30 @@ impl Nes {
     }
 
     pub fn cpu_disassembly(&mut self) -> Vec<CpuOpEntry> {
-        let mut ops = Vec::new();
-
-        let mut pc = 0x0000;
+        self.disassemble_range(0x0000, 0xFFFE, false)
+    }
 
-        loop {
-            let op = self.cpu_op_at(pc);
-            pc += op.size as u16;
+    /// Disassemble the ops starting between `start` and `end`, inclusive. With `with_labels`
+    /// the ops that other ops in the range branch or jump to are marked as labeled.
+    pub fn disassemble_range(
+        &mut self,
+        start: u16,
+        end: u16,
+        with_labels: bool,
+    ) -> Vec<CpuOpEntry> {
+        let mut ops = Vec::new();
 
+        let mut pc = start as u32;
+        while pc <= end as u32 {
+            let op = self.cpu_op_at(pc as u16);
+            pc += op.size as u32;
             ops.push(op);
+        }
 
-            if pc == 0xFFFF {
-                break;
+        if with_labels {
+            let targets: HashSet<u16> = ops.iter().filter_map(|op| op.target).collect();
+            for op in ops.iter_mut() {
+                op.labeled = targets.contains(&op.addr);
             }
         }
 
@@ -233,6 +963,8 @@ impl Nes {
                     kind: OpKind::Invalid,
                     addr_mode: AddressingMode::Implied,
                     operands: [0, 0],
+                    target: None,
+                    labeled: false,
                 }
             }
         };
@@ -247,6 +979,8 @@ impl Nes {
                 kind: OpKind::Invalid,
                 addr_mode: AddressingMode::Implied,
                 operands: [0, 0],
+                target: None,
+                labeled: false,
             };
         }
 
@@ -258,6 +992,16 @@ impl Nes {
             operands[1] = self.cpu_read_mem(addr + 2);
         }
 
+        let target = match (kind, addr_mode) {
+            (_, AddressingMode::Relative) => {
+                Some(addr.wrapping_add(2).wrapping_add(operands[0] as i8 as u16))
+            }
+            (OpKind::Jmp | OpKind::Jsr, AddressingMode::Absolute) => {
+                Some(u16::from_le_bytes(operands))
+            }
+            _ => None,
+        };
+
         CpuOpEntry {
             addr,
             opcode,
@@ -265,6 +1009,8 @@ impl Nes {
             kind,
             addr_mode,
             operands,
+            target,
+            labeled: false,
         }
     }
 }
@@ -0,0 +1,132 @@
+//! A simplified NTSC composite video filter, based on the signal description at
+//! https://www.nesdev.org/wiki/NTSC_video
+//!
+//! Every PPU pixel is turned into 8 samples of the composite signal, which are then decoded
+//! back to RGB with a 12 sample wide window. The decoding blurs the chroma over neighboring
+//! pixels, which produces the color bleeding and artifacts of a real TV.
+
+use std::f32::consts::PI;
+
+/// Width of the filtered image. The composite signal of a scanline has 2048 samples, which
+/// are resampled to roughly the horizontal resolution of a TV.
+pub const NTSC_WIDTH: usize = 602;
+
+const WIDTH: usize = 256;
+const HEIGHT: usize = 240;
+
+const SAMPLES_PER_PIXEL: usize = 8;
+const SAMPLES_PER_LINE: usize = WIDTH * SAMPLES_PER_PIXEL;
+
+/// Length of one color subcarrier cycle in samples
+const SUBCARRIER_PERIOD: usize = 12;
+
+/// Shift of the subcarrier phase from one scanline to the next. A scanline is 341 * 8 samples.
+const LINE_PHASE_SHIFT: usize = 341 * SAMPLES_PER_PIXEL % SUBCARRIER_PERIOD;
+
+/// Voltage levels of the signal for each luma level, relative to sync
+const LOW_LEVELS: [f32; 4] = [0.350, 0.518, 0.962, 1.550];
+const HIGH_LEVELS: [f32; 4] = [1.094, 1.506, 1.962, 1.962];
+const BLACK: f32 = 0.518;
+const WHITE: f32 = 1.962;
+
+/// Factor applied to the parts of the signal that are dimmed by the emphasis bits
+const EMPHASIS_ATTENUATION: f32 = 0.746;
+
+/// Rotates the decoded hue to match the usual palettes
+const HUE_OFFSET: f32 = 3.9;
+
+/// Composite signal level of a pixel at a subcarrier phase. The pixel is a 6-bit palette
+/// value with the emphasis bits of PPUMASK in bits 6-8.
+fn signal_level(pixel: u16, phase: usize) -> f32 {
+    let color = (pixel & 0x0F) as usize;
+    let mut level = ((pixel >> 4) & 3) as usize;
+    let emphasis = pixel >> 6;
+
+    // Colors $xE and $xF are always black
+    if color > 13 {
+        level = 1;
+    }
+
+    let in_color_phase = |color: usize| (color + phase) % SUBCARRIER_PERIOD < 6;
+
+    let low = LOW_LEVELS[level];
+    let high = HIGH_LEVELS[level];
+
+    // Color 0 is a flat high level, and colors $D-$F a flat low level
+    let signal = match color {
+        0 => high,
+        13..=15 => low,
+        _ if in_color_phase(color) => high,
+        _ => low,
+    };
+
+    let attenuated = (emphasis & 1 != 0 && in_color_phase(0))
+        || (emphasis & 2 != 0 && in_color_phase(4))
+        || (emphasis & 4 != 0 && in_color_phase(8));
+
+    let signal = if attenuated {
+        signal * EMPHASIS_ATTENUATION
+    } else {
+        signal
+    };
+
+    (signal - BLACK) / (WHITE - BLACK)
+}
+
+fn gamma_corrected(val: f32) -> u8 {
+    (val.max(0.0).powf(2.2 / 1.8) * 255.0).min(255.0) as u8
+}
+
+/// Encode a frame of palette values to a composite signal and decode it back to RGBA bytes,
+/// [`NTSC_WIDTH`] × 240 pixels. `frame_phase` is the subcarrier phase at the start of the
+/// frame, which shifts from frame to frame and causes the dot crawl.
+pub(crate) fn filter(pixels: &[u16], frame_phase: usize) -> Vec<u8> {
+    let (cos, sin): (Vec<f32>, Vec<f32>) = (0..SUBCARRIER_PERIOD)
+        .map(|phase| {
+            let angle = PI * (phase as f32 + HUE_OFFSET) / 6.0;
+            (angle.cos(), angle.sin())
+        })
+        .unzip();
+
+    let mut output = vec![0; NTSC_WIDTH * HEIGHT * 4];
+    let mut signal = vec![0.0; SAMPLES_PER_LINE];
+
+    for (y, line) in pixels.chunks_exact(WIDTH).take(HEIGHT).enumerate() {
+        let line_phase = (frame_phase + y * LINE_PHASE_SHIFT) % SUBCARRIER_PERIOD;
+
+        for (sample_idx, sample) in signal.iter_mut().enumerate() {
+            let pixel = line[sample_idx / SAMPLES_PER_PIXEL];
+            *sample = signal_level(pixel, (line_phase + sample_idx) % SUBCARRIER_PERIOD);
+        }
+
+        for x in 0..NTSC_WIDTH {
+            let center = x * SAMPLES_PER_LINE / NTSC_WIDTH;
+            let start = center.saturating_sub(SUBCARRIER_PERIOD / 2);
+            let end = (start + SUBCARRIER_PERIOD).min(SAMPLES_PER_LINE);
+
+            let (mut luma, mut i, mut q) = (0.0, 0.0, 0.0);
+            for (sample_idx, sample) in signal.iter().enumerate().take(end).skip(start) {
+                let phase = (line_phase + sample_idx) % SUBCARRIER_PERIOD;
+                let level = sample / SUBCARRIER_PERIOD as f32;
+
+                luma += level;
+                i += level * cos[phase];
+                q += level * sin[phase];
+            }
+
+            let r = luma + 0.946882 * i + 0.623557 * q;
+            let g = luma - 0.274788 * i - 0.635691 * q;
+            let b = luma - 1.108545 * i + 1.709007 * q;
+
+            let idx = (y * NTSC_WIDTH + x) * 4;
+            output[idx..idx + 4].copy_from_slice(&[
+                gamma_corrected(r),
+                gamma_corrected(g),
+                gamma_corrected(b),
+                255,
+            ]);
+        }
+    }
+
+    output
+}
@@ -1,4 +1,7 @@
+use crate::symbols::Symbols;
+
 #[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum AddressingMode {
     Implied,
     Accumulator,
@@ -16,6 +19,7 @@ pub enum AddressingMode {
 }
 
 #[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum AccessMode {
     Read,
     Write,
@@ -23,6 +27,7 @@ pub enum AccessMode {
 }
 
 #[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum OpKind {
     /// Add with Carry
     Adc,
@@ -140,16 +145,95 @@ pub enum OpKind {
     Nmi,
     /// Interrupt request
     Irq,
+    /// Reset sequence
+    Reset,
     /// Double operation (2x NOP) <Unofficial>
     Dop,
     /// AND byte with accumulator <Unofficial>
     Aac,
     /// AND byte with accumulator, then shift accumulator right one bit <Unofficial>
     Asr,
+    /// Shift left one bit in memory, then OR accumulator with memory <Unofficial>
+    Slo,
+    /// Rotate one bit left in memory, then AND accumulator with memory <Unofficial>
+    Rla,
+    /// Shift right one bit in memory, then EOR accumulator with memory <Unofficial>
+    Sre,
+    /// Rotate one bit right in memory, then add memory to accumulator <Unofficial>
+    Rra,
+    /// Subtract 1 from memory, then compare with accumulator <Unofficial>
+    Dcp,
+    /// Increase memory by one, then subtract memory from accumulator <Unofficial>
+    Isc,
+    /// Load accumulator and X register with memory <Unofficial>
+    Lax,
+    /// AND X register with accumulator and store result in memory <Unofficial>
+    Sax,
+    /// AND Y register with high byte of the address + 1 and store in memory <Unofficial>
+    Shy,
+    /// AND X register with high byte of the address + 1 and store in memory <Unofficial>
+    Shx,
+    /// AND accumulator and X register with high byte of the address + 1 and store in memory <Unofficial>
+    Ahx,
+    /// AND accumulator with X register into stack pointer, then store it like Shy <Unofficial>
+    Tas,
+    /// AND memory with stack pointer and transfer result to A, X and S <Unofficial>
+    Las,
+    /// AND byte with accumulator, then rotate one bit right in accumulator <Unofficial>
+    Arr,
+    /// Transfer X register to accumulator, then AND accumulator with byte <Unofficial>
+    Xaa,
+    /// AND X register with accumulator, then subtract byte from it without borrow <Unofficial>
+    Axs,
+    /// Halt the CPU <Unofficial>
+    Kil,
     /// No such operation
     Invalid,
 }
 
+/// Cycles taken by each opcode, not counting the extra cycles of page crossings and taken
+/// branches. Opcodes that jam the CPU are 0.
+#[rustfmt::skip]
+pub const CYCLE_TABLE: [u8; 256] = [
+    7, 6, 0, 8, 3, 3, 5, 5, 3, 2, 2, 2, 4, 4, 6, 6,
+    2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+    6, 6, 0, 8, 3, 3, 5, 5, 4, 2, 2, 2, 4, 4, 6, 6,
+    2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+    6, 6, 0, 8, 3, 3, 5, 5, 3, 2, 2, 2, 3, 4, 6, 6,
+    2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+    6, 6, 0, 8, 3, 3, 5, 5, 4, 2, 2, 2, 5, 4, 6, 6,
+    2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+    2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4,
+    2, 6, 0, 6, 4, 4, 4, 4, 2, 5, 2, 5, 5, 5, 5, 5,
+    2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4,
+    2, 5, 0, 5, 4, 4, 4, 4, 2, 4, 2, 4, 4, 4, 4, 4,
+    2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6,
+    2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+    2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6,
+    2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+];
+
+/// Total cycles of an instruction according to [`CYCLE_TABLE`]. Indexed reads take an extra
+/// cycle when the page is crossed, and taken branches take one more cycle, or two if the
+/// target is on another page. Returns `None` for unknown and jamming opcodes.
+pub fn expected_cycles(opcode: u8, page_crossed: bool, branch_taken: bool) -> Option<u8> {
+    let (_, addressing_mode, access_mode) = into_op(opcode)?;
+    let base = CYCLE_TABLE[opcode as usize];
+    if base == 0 {
+        return None;
+    }
+
+    let extra = match addressing_mode {
+        AddressingMode::Relative => branch_taken as u8 + (branch_taken && page_crossed) as u8,
+        AddressingMode::AbsoluteX | AddressingMode::AbsoluteY | AddressingMode::IndirectY => {
+            (page_crossed && access_mode == AccessMode::Read) as u8
+        }
+        _ => 0,
+    };
+
+    Some(base + extra)
+}
+
 #[rustfmt::skip]
 pub fn into_op(code: u8) -> Option<(OpKind, AddressingMode, AccessMode)> {
     Some(match code {
@@ -312,32 +396,108 @@ pub fn into_op(code: u8) -> Option<(OpKind, AddressingMode, AccessMode)> {
         
         0x0B | 0x2B => (OpKind::Aac, AddressingMode::Immediate, AccessMode::Read),
         0x4B => (OpKind::Asr, AddressingMode::Immediate, AccessMode::Read),
+
+        0x07 => (OpKind::Slo, AddressingMode::ZeroPage, AccessMode::ReadModifyWrite),
+        0x17 => (OpKind::Slo, AddressingMode::ZeroPageX, AccessMode::ReadModifyWrite),
+        0x0F => (OpKind::Slo, AddressingMode::Absolute, AccessMode::ReadModifyWrite),
+        0x1F => (OpKind::Slo, AddressingMode::AbsoluteX, AccessMode::ReadModifyWrite),
+        0x1B => (OpKind::Slo, AddressingMode::AbsoluteY, AccessMode::ReadModifyWrite),
+        0x03 => (OpKind::Slo, AddressingMode::IndirectX, AccessMode::ReadModifyWrite),
+        0x13 => (OpKind::Slo, AddressingMode::IndirectY, AccessMode::ReadModifyWrite),
+        0x27 => (OpKind::Rla, AddressingMode::ZeroPage, AccessMode::ReadModifyWrite),
+        0x37 => (OpKind::Rla, AddressingMode::ZeroPageX, AccessMode::ReadModifyWrite),
+        0x2F => (OpKind::Rla, AddressingMode::Absolute, AccessMode::ReadModifyWrite),
+        0x3F => (OpKind::Rla, AddressingMode::AbsoluteX, AccessMode::ReadModifyWrite),
+        0x3B => (OpKind::Rla, AddressingMode::AbsoluteY, AccessMode::ReadModifyWrite),
+        0x23 => (OpKind::Rla, AddressingMode::IndirectX, AccessMode::ReadModifyWrite),
+        0x33 => (OpKind::Rla, AddressingMode::IndirectY, AccessMode::ReadModifyWrite),
+        0x47 => (OpKind::Sre, AddressingMode::ZeroPage, AccessMode::ReadModifyWrite),
+        0x57 => (OpKind::Sre, AddressingMode::ZeroPageX, AccessMode::ReadModifyWrite),
+        0x4F => (OpKind::Sre, AddressingMode::Absolute, AccessMode::ReadModifyWrite),
+        0x5F => (OpKind::Sre, AddressingMode::AbsoluteX, AccessMode::ReadModifyWrite),
+        0x5B => (OpKind::Sre, AddressingMode::AbsoluteY, AccessMode::ReadModifyWrite),
+        0x43 => (OpKind::Sre, AddressingMode::IndirectX, AccessMode::ReadModifyWrite),
+        0x53 => (OpKind::Sre, AddressingMode::IndirectY, AccessMode::ReadModifyWrite),
+        0x67 => (OpKind::Rra, AddressingMode::ZeroPage, AccessMode::ReadModifyWrite),
+        0x77 => (OpKind::Rra, AddressingMode::ZeroPageX, AccessMode::ReadModifyWrite),
+        0x6F => (OpKind::Rra, AddressingMode::Absolute, AccessMode::ReadModifyWrite),
+        0x7F => (OpKind::Rra, AddressingMode::AbsoluteX, AccessMode::ReadModifyWrite),
+        0x7B => (OpKind::Rra, AddressingMode::AbsoluteY, AccessMode::ReadModifyWrite),
+        0x63 => (OpKind::Rra, AddressingMode::IndirectX, AccessMode::ReadModifyWrite),
+        0x73 => (OpKind::Rra, AddressingMode::IndirectY, AccessMode::ReadModifyWrite),
+        0xC7 => (OpKind::Dcp, AddressingMode::ZeroPage, AccessMode::ReadModifyWrite),
+        0xD7 => (OpKind::Dcp, AddressingMode::ZeroPageX, AccessMode::ReadModifyWrite),
+        0xCF => (OpKind::Dcp, AddressingMode::Absolute, AccessMode::ReadModifyWrite),
+        0xDF => (OpKind::Dcp, AddressingMode::AbsoluteX, AccessMode::ReadModifyWrite),
+        0xDB => (OpKind::Dcp, AddressingMode::AbsoluteY, AccessMode::ReadModifyWrite),
+        0xC3 => (OpKind::Dcp, AddressingMode::IndirectX, AccessMode::ReadModifyWrite),
+        0xD3 => (OpKind::Dcp, AddressingMode::IndirectY, AccessMode::ReadModifyWrite),
+        0xE7 => (OpKind::Isc, AddressingMode::ZeroPage, AccessMode::ReadModifyWrite),
+        0xF7 => (OpKind::Isc, AddressingMode::ZeroPageX, AccessMode::ReadModifyWrite),
+        0xEF => (OpKind::Isc, AddressingMode::Absolute, AccessMode::ReadModifyWrite),
+        0xFF => (OpKind::Isc, AddressingMode::AbsoluteX, AccessMode::ReadModifyWrite),
+        0xFB => (OpKind::Isc, AddressingMode::AbsoluteY, AccessMode::ReadModifyWrite),
+        0xE3 => (OpKind::Isc, AddressingMode::IndirectX, AccessMode::ReadModifyWrite),
+        0xF3 => (OpKind::Isc, AddressingMode::IndirectY, AccessMode::ReadModifyWrite),
+
+        0xA7 => (OpKind::Lax, AddressingMode::ZeroPage, AccessMode::Read),
+        0xB7 => (OpKind::Lax, AddressingMode::ZeroPageY, AccessMode::Read),
+        0xAF => (OpKind::Lax, AddressingMode::Absolute, AccessMode::Read),
+        0xBF => (OpKind::Lax, AddressingMode::AbsoluteY, AccessMode::Read),
+        0xA3 => (OpKind::Lax, AddressingMode::IndirectX, AccessMode::Read),
+        0xB3 => (OpKind::Lax, AddressingMode::IndirectY, AccessMode::Read),
+        0x87 => (OpKind::Sax, AddressingMode::ZeroPage, AccessMode::Write),
+        0x97 => (OpKind::Sax, AddressingMode::ZeroPageY, AccessMode::Write),
+        0x8F => (OpKind::Sax, AddressingMode::Absolute, AccessMode::Write),
+        0x83 => (OpKind::Sax, AddressingMode::IndirectX, AccessMode::Write),
+
+        0x9C => (OpKind::Shy, AddressingMode::AbsoluteX, AccessMode::Write),
+        0x9E => (OpKind::Shx, AddressingMode::AbsoluteY, AccessMode::Write),
+        0x9F => (OpKind::Ahx, AddressingMode::AbsoluteY, AccessMode::Write),
+        0x93 => (OpKind::Ahx, AddressingMode::IndirectY, AccessMode::Write),
+        0x9B => (OpKind::Tas, AddressingMode::AbsoluteY, AccessMode::Write),
+        0xBB => (OpKind::Las, AddressingMode::AbsoluteY, AccessMode::Read),
+
+        0x6B => (OpKind::Arr, AddressingMode::Immediate, AccessMode::Read),
+        0x8B => (OpKind::Xaa, AddressingMode::Immediate, AccessMode::Read),
+        0xCB => (OpKind::Axs, AddressingMode::Immediate, AccessMode::Read),
+
+        0x02 | 0x12 | 0x22 | 0x32 | 0x42 | 0x52 | 0x62 | 0x72 | 0x92 | 0xB2 | 0xD2 | 0xF2
+            => (OpKind::Kil, AddressingMode::Implied, AccessMode::Read),
         _ => return None,
     })
 }
 
 pub fn to_asm(op_kind: OpKind, addressing_mode: AddressingMode, val: u16) -> String {
+    let operand = match op_size(addressing_mode) {
+        2 if addressing_mode != AddressingMode::Relative => format!("${:02X}", val),
+        _ => format!("${:04X}", val),
+    };
+
+    asm_with_operand(op_kind, addressing_mode, &operand)
+}
+
+/// Assembly of an op with the operand written as the given text
+fn asm_with_operand(op_kind: OpKind, addressing_mode: AddressingMode, operand: &str) -> String {
     if op_kind == OpKind::Invalid {
         return "???".to_string();
     }
 
-    // TODO improve (labels, effective addr, etc.)
+    let op = format!("{:?}", op_kind).to_uppercase();
+
     match addressing_mode {
-        AddressingMode::Implied => format!("{:?}", op_kind),
-        AddressingMode::Accumulator => format!("{:?} A", op_kind),
-        AddressingMode::Immediate => format!("{:?} #${:02X}", op_kind, val),
-        AddressingMode::Relative => format!("{:?} ${:04X}", op_kind, val),
-        AddressingMode::Absolute => format!("{:?} ${:04X}", op_kind, val),
-        AddressingMode::AbsoluteX => format!("{:?} ${:04X},X", op_kind, val),
-        AddressingMode::AbsoluteY => format!("{:?} ${:04X},Y", op_kind, val),
-        AddressingMode::ZeroPage => format!("{:?} ${:02X}", op_kind, val),
-        AddressingMode::ZeroPageX => format!("{:?} ${:02X},X", op_kind, val),
-        AddressingMode::ZeroPageY => format!("{:?} ${:02X},Y", op_kind, val),
-        AddressingMode::Indirect => format!("{:?} (${:04X})", op_kind, val),
-        AddressingMode::IndirectX => format!("{:?} (${:02X},X)", op_kind, val),
-        AddressingMode::IndirectY => format!("{:?} (${:02X}),Y", op_kind, val),
+        AddressingMode::Implied => op,
+        AddressingMode::Accumulator => format!("{} A", op),
+        AddressingMode::Immediate => format!("{} #{}", op, operand),
+        AddressingMode::Relative | AddressingMode::Absolute | AddressingMode::ZeroPage => {
+            format!("{} {}", op, operand)
+        }
+        AddressingMode::AbsoluteX | AddressingMode::ZeroPageX => format!("{} {},X", op, operand),
+        AddressingMode::AbsoluteY | AddressingMode::ZeroPageY => format!("{} {},Y", op, operand),
+        AddressingMode::Indirect => format!("{} ({})", op, operand),
+        AddressingMode::IndirectX => format!("{} ({},X)", op, operand),
+        AddressingMode::IndirectY => format!("{} ({}),Y", op, operand),
     }
-    .to_uppercase()
 }
 
 pub fn op_size(addressing_mode: AddressingMode) -> u8 {
@@ -366,4 +526,47 @@ pub struct CpuOpEntry {
     pub kind: OpKind,
     pub addr_mode: AddressingMode,
     pub operands: [u8; 2],
+    /// Absolute address a branch, JMP or JSR goes to
+    pub target: Option<u16>,
+    /// Whether another entry branches or jumps here
+    pub labeled: bool,
+}
+
+impl CpuOpEntry {
+    pub fn operand(&self) -> u16 {
+        match self.size {
+            2 => self.operands[0] as u16,
+            3 => u16::from_le_bytes(self.operands),
+            _ => 0,
+        }
+    }
+
+    /// Assembly of the op, with the target shown as an `L_XXXX` label if `labels` is set.
+    pub fn asm(&self, labels: bool) -> String {
+        self.asm_with_symbols(labels, &Symbols::default())
+    }
+
+    /// Like `asm`, but with the addresses that have a symbol shown by name.
+    pub fn asm_with_symbols(&self, labels: bool, symbols: &Symbols) -> String {
+        let addr = match self.addr_mode {
+            AddressingMode::Implied | AddressingMode::Accumulator | AddressingMode::Immediate => {
+                None
+            }
+            _ => Some(self.target.unwrap_or_else(|| self.operand())),
+        };
+
+        match (addr.and_then(|addr| symbols.get(addr)), self.target) {
+            (Some(name), _) => asm_with_operand(self.kind, self.addr_mode, name),
+            (None, Some(target)) if labels => {
+                asm_with_operand(self.kind, self.addr_mode, &label_name(target))
+            }
+            (None, Some(target)) => to_asm(self.kind, self.addr_mode, target),
+            (None, None) => to_asm(self.kind, self.addr_mode, self.operand()),
+        }
+    }
+}
+
+/// Name of the label generated for a branch or jump target
+pub fn label_name(addr: u16) -> String {
+    format!("L_{:04X}", addr)
 }
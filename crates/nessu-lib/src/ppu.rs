@@ -1,12 +1,69 @@
-use std::mem::transmute;
+use std::mem;
 
 use crate::bitwise::HasBits;
 use crate::cartridge::Cartridge;
 use crate::mapper::Mirroring;
+use crate::region::Region;
 
 const DISPLAY_BYTES: usize = 245760;
-pub const DEFAULT_PALETTE: &[(u8, u8, u8); 64] =
-    unsafe { transmute(include_bytes!("../../../default.pal") as &[u8; 192]) };
+const DISPLAY_PIXELS: usize = DISPLAY_BYTES / 4;
+
+/// RGB colors of the 64 palette values
+pub type Palette = [(u8, u8, u8); 64];
+
+pub const DEFAULT_PALETTE: Palette = palette_from_bytes(include_bytes!("../../../default.pal"));
+
+const fn palette_from_bytes(bytes: &[u8; 192]) -> Palette {
+    let mut palette = [(0, 0, 0); 64];
+
+    let mut i = 0;
+    while i < 64 {
+        palette[i] = (bytes[i * 3], bytes[i * 3 + 1], bytes[i * 3 + 2]);
+        i += 1;
+    }
+
+    palette
+}
+
+/// Parse the contents of a .pal file, which must contain exactly 64 RGB triplets.
+pub fn load_palette_from_bytes(bytes: &[u8]) -> Result<Palette, String> {
+    let bytes: &[u8; 192] = bytes
+        .try_into()
+        .map_err(|_| format!("Palette must be 192 bytes, got {}", bytes.len()))?;
+
+    Ok(palette_from_bytes(bytes))
+}
+
+/// Time it takes for a bit of the PPU open bus to decay
+const OPEN_BUS_DECAY_SECONDS: f64 = 0.6;
+
+/// Scanlines during which the Zapper keeps sensing a bright pixel after it has been drawn
+const ZAPPER_LIGHT_SCANLINES: i32 = 20;
+/// Luminance from 0 to 255 at which a pixel is bright enough for the Zapper
+const ZAPPER_LIGHT_THRESHOLD: u32 = 160;
+/// Distance in pixels around the aimed position that the Zapper sees
+const ZAPPER_RADIUS: i32 = 2;
+/// PPU cycles A12 has to stay low before the MMC3 counts a rising edge
+const A12_FILTER_CYCLES: u64 = 10;
+
+/// Splits a nametable-sized span starting at `start` into parts that are each within one of
+/// the two nametables along the axis, wrapping around after the second one. Each part is
+/// (nametable, start, length).
+fn nametable_spans(start: u16, size: u16) -> Vec<(u16, u16, u16)> {
+    let mut spans = Vec::new();
+    let mut pos = start;
+    let mut remaining = size;
+
+    while remaining > 0 {
+        let offset = pos % size;
+        let len = (size - offset).min(remaining);
+        spans.push((pos / size % 2, offset, len));
+        pos = (pos + len) % (size * 2);
+        remaining -= len;
+    }
+
+    spans
+}
 
 const NAMETABLE_X_BITS: u16 = 0b000_0100_0000_0000;
 const NAMETABLE_Y_BITS: u16 = 0b000_1000_0000_0000;
@@ -14,9 +71,20 @@ const NAMETABLE_BITS: u16 = NAMETABLE_X_BITS | NAMETABLE_Y_BITS;
 const TILE_X_BITS: u16 = 0b000_0000_0001_1111;
 const TILE_Y_BITS: u16 = 0b000_0011_1110_0000;
 const PIXEL_Y_BITS: u16 = 0b111_0000_0000_0000;
-const VBL_PPU_CYCLE: u128 = 82182;
+const EMPHASIS_ATTENUATION: f32 = 0.75;
+
+#[cfg(feature = "savestate")]
+fn default_palette() -> Palette {
+    DEFAULT_PALETTE
+}
+
+#[cfg(feature = "savestate")]
+fn default_pixels() -> Vec<u16> {
+    vec![0; DISPLAY_PIXELS]
+}
 
 #[derive(Copy, Clone)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
 pub struct Sprite {
     idx: u8,
     active: bool,
@@ -43,7 +111,9 @@ impl Default for Sprite {
     }
 }
 
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
 pub struct Ppu {
+    #[cfg_attr(feature = "savestate", serde(skip, default = "std::ptr::null_mut"))]
     cart: *mut Cartridge,
 
     vram: Vec<u8>,
@@ -58,14 +128,19 @@ pub struct Ppu {
     pub ppu_data_buf: u8,
 
     pub oam_addr: u8,
+    #[cfg_attr(feature = "savestate", serde(with = "serde_big_array::BigArray"))]
     pub primary_oam: [u8; 256],
     pub secondary_oam: [Sprite; 8],
     pub active_sprites: [Sprite; 8],
+    /// Sprites on the current scanline beyond the first 8, when the sprite limit is disabled
+    pub active_extra_sprites: Vec<Sprite>,
 
     pub pixel_x: u8,
 
     w_toggle: bool,
 
+    /// PPU cycles since the start of the frame, including the skipped idle cycle of odd frames.
+    /// Equals [`Ppu::vbl_ppu_cycle`] on the cycle that sets the vblank flag.
     vbl_cycle_counter: u128,
 
     nmi_triggered: bool,
@@ -82,6 +157,8 @@ pub struct Ppu {
     next_bg_tile_hi: u8,
 
     sprite_evaluation_idx: usize,
+    /// Byte within the sprite that is read during overflow evaluation
+    sprite_evaluation_byte: usize,
     found_sprites: usize,
 
     cycle: u16,
@@ -89,15 +166,32 @@ pub struct Ppu {
 
     odd_frame: bool,
 
+    region: Region,
+
+    #[cfg_attr(feature = "savestate", serde(skip, default = "default_palette"))]
+    palette: Palette,
+
     pub display: Vec<u8>,
+    /// Palette value of each pixel with the emphasis bits in bits 6-8, used by the NTSC filter
+    #[cfg_attr(feature = "savestate", serde(skip, default = "default_pixels"))]
+    pub(crate) pixels: Vec<u16>,
 
     pub open_bus: u8,
-    pub open_bus_decay_timer: u32,
+    /// PPU cycles until each bit of the open bus decays to 0
+    pub open_bus_decay_timers: [u32; 8],
 
-    pub a12_timer: u8,
+    /// PPU cycles clocked since power-on
+    total_cycles: u64,
+    /// Whether the last address put on the PPU bus had A12 set
+    a12_high: bool,
+    /// Value of `total_cycles` when A12 last went low
+    a12_low_since: u64,
 
     pub sprite_rendering_enabled_by_user: bool,
     pub bg_rendering_enabled_by_user: bool,
+    /// Render all sprites on a scanline instead of only the first 8 to reduce flicker.
+    /// Sprite overflow and sprite zero hits still behave as if the limit was in place.
+    pub sprite_limit_disabled: bool,
 }
 
 impl Ppu {
@@ -117,6 +211,7 @@ impl Ppu {
             primary_oam: [0; 256],
             secondary_oam: [Default::default(); 8],
             active_sprites: [Default::default(); 8],
+            active_extra_sprites: Vec::new(),
             pixel_x: 0,
             w_toggle: false,
             vbl_cycle_counter: 0,
@@ -131,31 +226,78 @@ impl Ppu {
             next_bg_tile_lo: 0,
             next_bg_tile_hi: 0,
             sprite_evaluation_idx: 0,
+            sprite_evaluation_byte: 0,
             found_sprites: 0,
             cycle: 0,
             odd_frame: false,
             scanline: 0,
+            region: Region::Ntsc,
+            palette: DEFAULT_PALETTE,
             display: vec![0; DISPLAY_BYTES],
+            pixels: vec![0; DISPLAY_PIXELS],
             open_bus: 0,
-            open_bus_decay_timer: 0,
-            a12_timer: 0,
+            open_bus_decay_timers: [0; 8],
+            total_cycles: 0,
+            a12_high: false,
+            a12_low_since: 0,
 
             sprite_rendering_enabled_by_user: true,
             bg_rendering_enabled_by_user: true,
+            sprite_limit_disabled: false,
         }
     }
 
+    /// Reset as the reset button does: PPUCTRL, PPUMASK, the scroll, the write toggle and the
+    /// read buffer are cleared, while VRAM, OAM, the VRAM address and the vblank flag are kept.
     pub fn reset(&mut self, cart: *mut Cartridge) {
         *self = Ppu {
+            vram: mem::take(&mut self.vram),
+            vram_addr: self.vram_addr,
             oam_addr: self.oam_addr,
-            ppu_addr: self.ppu_addr,
+            primary_oam: self.primary_oam,
             ppu_status: self.ppu_status & 0x80,
             open_bus: self.open_bus,
-            open_bus_decay_timer: self.open_bus_decay_timer,
+            open_bus_decay_timers: self.open_bus_decay_timers,
+            region: self.region,
+            palette: self.palette,
+            display: mem::take(&mut self.display),
+            pixels: mem::take(&mut self.pixels),
+            sprite_rendering_enabled_by_user: self.sprite_rendering_enabled_by_user,
+            bg_rendering_enabled_by_user: self.bg_rendering_enabled_by_user,
+            sprite_limit_disabled: self.sprite_limit_disabled,
             ..Ppu::new(cart)
         }
     }
 
+    #[cfg(feature = "savestate")]
+    pub(crate) fn set_cartridge(&mut self, cart: *mut Cartridge) {
+        self.cart = cart;
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    /// PPU cycles from the start of the frame until the vblank flag is set, on cycle 1 of the
+    /// first vblank scanline. 241 * 341 + 1 = 82182 on NTSC and PAL.
+    #[inline]
+    fn vbl_ppu_cycle(&self) -> u128 {
+        self.region.vblank_scanline() as u128 * 341 + 1
+    }
+
+    #[inline]
+    fn pre_render_scanline(&self) -> u16 {
+        self.region.scanlines_per_frame() - 1
+    }
+
     fn cart_mut(&mut self) -> &mut Cartridge {
         unsafe { &mut *self.cart }
     }
@@ -168,6 +310,69 @@ impl Ppu {
         self.scanline
     }
 
+    /// The scroll position set by the game, in pixels from the top left corner of the first
+    /// nametable, so that X ranges from 0 to 511 and Y from 0 to 479. Decoded from the
+    /// temporary VRAM address, which is copied to the VRAM address at the start of each
+    /// frame and the horizontal part at the end of each scanline.
+    pub fn scroll_position(&self) -> (u16, u16) {
+        let nametable = self.base_nametable() as u16;
+        let coarse_x = self.ppu_addr & TILE_X_BITS;
+        let coarse_y = (self.ppu_addr & TILE_Y_BITS) >> 5;
+        let fine_y = (self.ppu_addr & PIXEL_Y_BITS) >> 12;
+
+        let x = (nametable & 1) * 256 + coarse_x * 8 + self.pixel_x as u16;
+        let y = (nametable >> 1) * 240 + coarse_y * 8 + fine_y;
+        (x, y)
+    }
+
+    /// Index of the nametable that the scroll position is relative to, from 0 to 3
+    pub fn base_nametable(&self) -> u8 {
+        ((self.ppu_addr & NAMETABLE_BITS) >> 10) as u8
+    }
+
+    /// The visible 256x240 area at the current scroll position, split along the nametable
+    /// boundaries into up to four rectangles. Each is (nametable, x, y, width, height)
+    /// in pixels within the nametable.
+    pub fn scroll_viewport(&self) -> Vec<(u8, u16, u16, u16, u16)> {
+        let (x, y) = self.scroll_position();
+
+        let mut rects = Vec::new();
+        for (row, top, height) in nametable_spans(y, 240) {
+            for (col, left, width) in nametable_spans(x, 256) {
+                rects.push(((row * 2 + col) as u8, left, top, width, height));
+            }
+        }
+        rects
+    }
+
+    /// Whether a Zapper aimed at the given display position senses light, i.e. a bright
+    /// pixel near it has been drawn within the last few scanlines.
+    pub fn zapper_light_sensed(&self, x: u16, y: u16) -> bool {
+        let now = self.scanline as i32 * 341 + self.cycle as i32;
+
+        for py in (y as i32 - ZAPPER_RADIUS)..=(y as i32 + ZAPPER_RADIUS) {
+            for px in (x as i32 - ZAPPER_RADIUS)..=(x as i32 + ZAPPER_RADIUS) {
+                if !(0..256).contains(&px) || !(0..240).contains(&py) {
+                    continue;
+                }
+
+                // Pixels are output one cycle after the start of the scanline
+                let since_drawn = now - (py * 341 + px + 1);
+                if !(0..ZAPPER_LIGHT_SCANLINES * 341).contains(&since_drawn) {
+                    continue;
+                }
+
+                let idx = (py * 256 + px) as usize * 4;
+                let [r, g, b] = [0, 1, 2].map(|i| self.display[idx + i] as u32);
+                if (r * 299 + g * 587 + b * 114) / 1000 >= ZAPPER_LIGHT_THRESHOLD {
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+
     #[inline]
     pub fn frame_completed(&self) -> bool {
         self.scanline == 0 && self.cycle == 0
@@ -181,7 +386,7 @@ impl Ppu {
         }
     }
 
-    fn sprite_pattern_table_address(&self) -> u16 {
+    pub fn sprite_pattern_table_address(&self) -> u16 {
         match (self.ppu_ctrl >> 3) & 1 {
             0 => 0x0000,
             1 => 0x1000,
@@ -189,10 +394,20 @@ impl Ppu {
         }
     }
 
-    fn use_large_sprites(&self) -> bool {
+    pub fn use_large_sprites(&self) -> bool {
         self.ppu_ctrl.has_bits(0b10_0000)
     }
 
+    /// Indices of the primary OAM sprites that are drawn on the current scanline.
+    pub fn active_sprite_indices(&self) -> Vec<u8> {
+        self.active_sprites
+            .iter()
+            .chain(&self.active_extra_sprites)
+            .filter(|sprite| sprite.active)
+            .map(|sprite| sprite.idx)
+            .collect()
+    }
+
     pub fn read_ppu_status(&mut self, read_only: bool) -> u8 {
         let mut status = self.ppu_status;
 
@@ -200,12 +415,14 @@ impl Ppu {
             self.w_toggle = false;
             self.ppu_status &= 0x7F;
 
-            if self.vbl_cycle_counter == VBL_PPU_CYCLE - 1 {
+            let vbl_ppu_cycle = self.vbl_ppu_cycle();
+
+            if self.vbl_cycle_counter == vbl_ppu_cycle - 1 {
                 status &= 0x7F;
                 // suppress next nmi
                 self.suppress_next_nmi = true;
-            } else if self.vbl_cycle_counter == VBL_PPU_CYCLE
-                || self.vbl_cycle_counter == VBL_PPU_CYCLE + 1
+            } else if self.vbl_cycle_counter == vbl_ppu_cycle
+                || self.vbl_cycle_counter == vbl_ppu_cycle + 1
             {
                 // suppress current nmi
                 self.nmi_triggered = false;
@@ -227,8 +444,9 @@ impl Ppu {
             self.nmi_triggered = true;
         }
 
+        let vbl_ppu_cycle = self.vbl_ppu_cycle();
         if !self.ppu_ctrl.has_bits(0x80)
-            && (VBL_PPU_CYCLE - 1..=VBL_PPU_CYCLE + 1).contains(&self.vbl_cycle_counter)
+            && (vbl_ppu_cycle - 1..=vbl_ppu_cycle + 1).contains(&self.vbl_cycle_counter)
         {
             // NMI should not occur if disabled too close to VBL start
             self.nmi_triggered = false;
@@ -285,17 +503,24 @@ impl Ppu {
     }
 
     fn update_vram_addr(&mut self, new_addr: u16) {
-        if self.a12_timer >= 8 && new_addr.has_bits(0x1000) {
-            self.cart_mut().clock_irq();
+        // While rendering the bus carries the fetch addresses instead
+        if !self.is_rendering() {
+            self.update_a12(new_addr);
         }
+        self.vram_addr = new_addr;
+    }
 
-        if !self.vram_addr.has_bits(0x1000) {
-            self.a12_timer = self.a12_timer.saturating_add(1);
-        } else {
-            self.a12_timer = 0;
+    /// Clock the cartridge IRQ on A12 rising edges that follow a long enough low period.
+    fn update_a12(&mut self, addr: u16) {
+        let high = addr.has_bits(0x1000);
+
+        if high && !self.a12_high && self.total_cycles - self.a12_low_since >= A12_FILTER_CYCLES {
+            self.cart_mut().clock_irq();
+        } else if !high && self.a12_high {
+            self.a12_low_since = self.total_cycles;
         }
 
-        self.vram_addr = new_addr;
+        self.a12_high = high;
     }
 
     #[inline]
@@ -308,18 +533,89 @@ impl Ppu {
         self.ppu_mask.has_bits(0b1_0000)
     }
 
+    #[inline]
+    fn background_visible_at(&self, x: u16) -> bool {
+        x >= 8 || self.ppu_mask.has_bits(0b0_0010)
+    }
+
+    #[inline]
+    fn sprites_visible_at(&self, x: u16) -> bool {
+        x >= 8 || self.ppu_mask.has_bits(0b0_0100)
+    }
+
     #[inline]
     fn rendering_enabled(&self) -> bool {
         self.background_rendering_enabled() || self.sprite_rendering_enabled()
     }
 
+    /// Whether the PPU is fetching data for the current or the next scanline
+    #[inline]
+    fn is_rendering(&self) -> bool {
+        self.rendering_enabled()
+            && (self.scanline <= 239 || self.scanline == self.pre_render_scanline())
+    }
+
+    /// https://www.nesdev.org/wiki/PPU_registers#OAMDATA
+    pub fn read_oam_data(&mut self, read_only: bool) -> u8 {
+        let val = if self.is_rendering() {
+            self.internal_oam_value()
+        } else {
+            let val = self.primary_oam[self.oam_addr as usize];
+
+            // Bits 2-4 of sprite attributes don't exist and always read back as zero
+            if self.oam_addr & 3 == 2 {
+                val & 0xE3
+            } else {
+                val
+            }
+        };
+
+        if !read_only {
+            self.write_open_bus(val, false);
+        }
+        val
+    }
+
+    /// The value the PPU is currently reading from primary or secondary OAM while rendering
+    fn internal_oam_value(&self) -> u8 {
+        match (self.scanline, self.cycle) {
+            // Secondary OAM clear reads $FF on every cycle
+            (0..=239, 1..=64) => 0xFF,
+            (0..=239, 65..=256) => {
+                let primary_oam_idx = (self.sprite_evaluation_idx & 0x3F) << 2;
+                self.primary_oam[primary_oam_idx + self.sprite_evaluation_byte]
+            }
+            // Sprite fetches read Y, tile, attributes and then X for the rest of the 8 cycles
+            (_, 257..=320) => self.secondary_oam_byte((self.cycle - 257) as usize),
+            _ => self.secondary_oam_byte(0),
+        }
+    }
+
+    fn secondary_oam_byte(&self, fetch_cycle: usize) -> u8 {
+        let sprite = &self.secondary_oam[fetch_cycle / 8];
+
+        if !sprite.active {
+            return 0xFF;
+        }
+
+        match fetch_cycle % 8 {
+            0 => sprite.y.wrapping_sub(1),
+            1 => sprite.tile_idx,
+            2 => sprite.attrs,
+            _ => sprite.x,
+        }
+    }
+
     pub fn write_oam_data(&mut self, val: u8) {
-        // TODO: Writes during rendering do not modify values in OAM, but do perform
-        // a glitchy increment of OAMADDR, bumping only the high 6 bits
-        // https://www.nesdev.org/wiki/PPU_registers#OAMDATA
+        if self.is_rendering() {
+            // Writes during rendering don't modify OAM but perform a glitchy increment
+            // of OAMADDR, bumping only the high 6 bits
+            self.oam_addr = self.oam_addr.wrapping_add(4);
+        } else {
+            self.primary_oam[self.oam_addr as usize] = val;
+            self.oam_addr = self.oam_addr.wrapping_add(1);
+        }
 
-        self.primary_oam[self.oam_addr as usize] = val;
-        self.oam_addr = self.oam_addr.wrapping_add(1);
         self.write_open_bus(val, true);
     }
 
@@ -328,19 +624,34 @@ impl Ppu {
         self.write_open_bus(val, true);
     }
 
+    /// Set the value of the open bus. If `refresh` is set, the decay of the bits that are 1 is
+    /// restarted.
     pub fn write_open_bus(&mut self, val: u8, refresh: bool) {
-        if refresh && val != 0 {
-            self.open_bus_decay_timer = 5360520;
+        if refresh {
+            let decay_cycles = self.open_bus_decay_cycles();
+            for (bit, timer) in self.open_bus_decay_timers.iter_mut().enumerate() {
+                if val.has_bits(1 << bit) {
+                    *timer = decay_cycles;
+                }
+            }
         }
 
         self.open_bus = val;
     }
 
+    /// PPU cycles it takes for a bit of the open bus to decay, about 600 ms
+    fn open_bus_decay_cycles(&self) -> u32 {
+        let (cpu_cycles, ppu_cycles) = self.region.cpu_ppu_ratio();
+        let ppu_clock_rate = self.region.cpu_clock_rate() * ppu_cycles as f64 / cpu_cycles as f64;
+        (ppu_clock_rate * OPEN_BUS_DECAY_SECONDS) as u32
+    }
+
     fn secondary_oam_clear(&mut self) {
         self.secondary_oam = Default::default();
     }
 
     pub fn clock(&mut self) {
+        self.total_cycles += 1;
         self.update_open_bus();
 
         if self.cycle == 0 && self.scanline == 0 {
@@ -349,9 +660,11 @@ impl Ppu {
             self.vbl_cycle_counter += 1;
         }
 
+        // The idle cycle of odd frames is skipped only on NTSC
         if self.cycle == 0
             && self.scanline == 0
             && self.odd_frame
+            && self.region == Region::Ntsc
             && self.background_rendering_enabled()
         {
             self.cycle += 1;
@@ -362,7 +675,6 @@ impl Ppu {
         if let 0..=239 = self.scanline {
             if let 1..=64 = self.cycle {
                 self.secondary_oam_clear();
-                self.clear_sprite_overflow();
             }
 
             if let 65..=256 = self.cycle {
@@ -370,8 +682,10 @@ impl Ppu {
             }
         }
 
+        let pre_render_scanline = self.pre_render_scanline();
+
         // Visible and pre-render scanlines
-        if let 0..=239 | 261 = self.scanline {
+        if self.scanline <= 239 || self.scanline == pre_render_scanline {
             if self.cycle == 257 {
                 // Garbage nt byte
                 self.load_nametable_byte();
@@ -423,6 +737,7 @@ impl Ppu {
             if self.cycle == 257 {
                 self.reload_horizontal_scroll_bits();
                 self.active_sprites = self.secondary_oam;
+                self.load_extra_sprites();
             }
 
             if self.cycle == 337 || self.cycle == 339 {
@@ -456,15 +771,17 @@ impl Ppu {
         }
 
         // V-Blank
-        if self.scanline == 241 && self.cycle == 1 {
+        if self.scanline == self.region.vblank_scanline() && self.cycle == 1 {
+            debug_assert_eq!(self.vbl_cycle_counter, self.vbl_ppu_cycle());
             self.set_vblank_status();
         }
 
-        if self.scanline == 261 {
+        if self.scanline == pre_render_scanline {
             match self.cycle {
                 1 => {
                     self.clear_vblank_status();
                     self.clear_sprite_zero_hit();
+                    self.clear_sprite_overflow();
                 }
                 280..=304 => {
                     self.reload_vertical_scroll_bits();
@@ -477,22 +794,30 @@ impl Ppu {
 
         if self.cycle >= 341 {
             self.sprite_evaluation_idx = 0;
+            self.sprite_evaluation_byte = 0;
             self.found_sprites = 0;
             self.cycle = 0;
             self.scanline += 1;
 
-            if self.scanline >= 262 {
+            if self.scanline >= self.region.scanlines_per_frame() {
                 self.scanline = 0;
                 self.odd_frame = !self.odd_frame;
             }
         }
     }
 
+    /// Each bit of the open bus decays to 0 separately, depending on when it was last refreshed.
     fn update_open_bus(&mut self) {
-        if self.open_bus_decay_timer == 0 {
-            self.open_bus = 0;
-        } else {
-            self.open_bus_decay_timer -= 1;
+        if self.open_bus == 0 {
+            return;
+        }
+
+        for (bit, timer) in self.open_bus_decay_timers.iter_mut().enumerate() {
+            if *timer == 0 {
+                self.open_bus &= !(1 << bit);
+            } else {
+                *timer -= 1;
+            }
         }
     }
 
@@ -505,34 +830,95 @@ impl Ppu {
         }
 
         let primary_oam_idx = (self.sprite_evaluation_idx & 0x3F) << 2;
+
+        if self.found_sprites >= 8 {
+            self.sprite_overflow_evaluation(primary_oam_idx);
+            return;
+        }
+
         let sprite_y = self.primary_oam[primary_oam_idx].saturating_add(1);
+        let in_range = self.sprite_y_in_range(sprite_y);
 
-        let next_y = self.scanline + 1;
-        let sprite_height = if self.use_large_sprites() { 16 } else { 8 };
+        let sprite = &mut self.secondary_oam[self.found_sprites];
+        sprite.y = sprite_y;
+        sprite.idx = self.sprite_evaluation_idx as u8;
 
-        let in_range = next_y > 0
-            && next_y < 0xF0
-            && next_y >= sprite_y as u16
-            && next_y < sprite_y as u16 + sprite_height;
+        if in_range {
+            sprite.active = true;
+            sprite.x = self.primary_oam[primary_oam_idx + 3];
+            sprite.attrs = self.primary_oam[primary_oam_idx + 2];
+            sprite.tile_idx = self.primary_oam[primary_oam_idx + 1];
 
-        if self.found_sprites < 8 {
-            let sprite = &mut self.secondary_oam[self.found_sprites];
-            sprite.y = sprite_y;
-            sprite.idx = self.sprite_evaluation_idx as u8;
+            self.found_sprites += 1;
+        }
 
-            if in_range {
-                sprite.active = true;
-                sprite.x = self.primary_oam[primary_oam_idx + 3];
-                sprite.attrs = self.primary_oam[primary_oam_idx + 2];
-                sprite.tile_idx = self.primary_oam[primary_oam_idx + 1];
+        self.sprite_evaluation_idx += 1;
+    }
 
-                self.found_sprites += 1;
-            }
-        } else if in_range {
+    /// Once eight sprites have been found, the hardware keeps looking for a ninth one
+    /// but buggily increments the byte index along with the sprite index, treating
+    /// tile, attribute and X bytes as Y coordinates.
+    fn sprite_overflow_evaluation(&mut self, primary_oam_idx: usize) {
+        let sprite_y = self.primary_oam[primary_oam_idx + self.sprite_evaluation_byte];
+
+        if self.sprite_y_in_range(sprite_y.saturating_add(1)) {
             self.set_sprite_overflow();
+
+            // Evaluation effectively stops for this scanline
+            self.sprite_evaluation_idx = 64;
+            return;
         }
 
         self.sprite_evaluation_idx += 1;
+        self.sprite_evaluation_byte = (self.sprite_evaluation_byte + 1) & 3;
+    }
+
+    /// Find and fetch the sprites of the next scanline that didn't fit in secondary OAM.
+    /// The hardware never fetches these, so they are fetched all at once.
+    fn load_extra_sprites(&mut self) {
+        self.active_extra_sprites.clear();
+
+        let last_sprite = &self.secondary_oam[7];
+        if !self.sprite_limit_disabled || self.scanline > 239 || !last_sprite.active {
+            return;
+        }
+
+        for idx in last_sprite.idx as usize + 1..64 {
+            let primary_oam_idx = idx << 2;
+            let sprite_y = self.primary_oam[primary_oam_idx].saturating_add(1);
+
+            if !self.sprite_y_in_range(sprite_y) {
+                continue;
+            }
+
+            let mut sprite = Sprite {
+                idx: idx as u8,
+                active: true,
+                x: self.primary_oam[primary_oam_idx + 3],
+                y: sprite_y,
+                tile_idx: self.primary_oam[primary_oam_idx + 1],
+                attrs: self.primary_oam[primary_oam_idx + 2],
+                tile_lo: 0,
+                tile_hi: 0,
+            };
+
+            let addr = self.sprite_tile_addr(&sprite);
+            // These fetches must not switch CHR banks on MMC2/MMC4 or confuse MMC5
+            sprite.tile_lo = self.peek_mem_u8(addr);
+            sprite.tile_hi = self.peek_mem_u8(addr + 8);
+
+            self.active_extra_sprites.push(sprite);
+        }
+    }
+
+    fn sprite_y_in_range(&self, sprite_y: u8) -> bool {
+        let next_y = self.scanline + 1;
+        let sprite_height = if self.use_large_sprites() { 16 } else { 8 };
+
+        next_y > 0
+            && next_y < 0xF0
+            && next_y >= sprite_y as u16
+            && next_y < sprite_y as u16 + sprite_height
     }
 
     pub fn read_ppu_data(&mut self, read_only: bool) -> u8 {
@@ -563,7 +949,9 @@ impl Ppu {
             prev_val
         };
 
-        self.write_open_bus(val, false);
+        if !read_only {
+            self.write_open_bus(val, false);
+        }
         val
     }
 
@@ -581,6 +969,16 @@ impl Ppu {
             .unwrap_or_else(|| self.vram[addr as usize])
     }
 
+    /// Read without triggering mapper side effects, for fetches the hardware never makes and
+    /// for the debug views.
+    pub(crate) fn peek_mem_u8(&mut self, addr: u16) -> u8 {
+        let addr = self.effective_addr(addr) as usize;
+
+        self.cart_mut()
+            .ppu_peek_u8(addr)
+            .unwrap_or_else(|| self.vram[addr])
+    }
+
     pub fn write_mem_u8(&mut self, addr: u16, val: u8) {
         let addr = self.effective_addr(addr) as usize;
 
@@ -607,6 +1005,7 @@ impl Ppu {
                             }
                         }
                         Mirroring::Vertical => addr & 0x07FF,
+                        Mirroring::FourScreen => addr & 0x0FFF,
                     }
             }
 
@@ -723,7 +1122,11 @@ impl Ppu {
 
         let mut palette_addr = None;
 
-        if self.background_rendering_enabled() {
+        if self.background_rendering_enabled() && !self.background_visible_at(x) {
+            if self.bg_rendering_enabled_by_user {
+                palette_addr = Some(0x3F00);
+            }
+        } else if self.background_rendering_enabled() {
             let bit_pos = 0x8000 >> self.pixel_x;
             let pix0 = (self.shift_bg_tile_lo & bit_pos > 0) as u16;
             let pix1 = (self.shift_bg_tile_hi & bit_pos > 0) as u16;
@@ -746,10 +1149,13 @@ impl Ppu {
             }
         }
 
-        if self.sprite_rendering_enabled() {
+        if self.sprite_rendering_enabled() && self.sprites_visible_at(x) {
+            let extra_sprites = mem::take(&mut self.active_extra_sprites);
+
             for (_, sprite) in self
                 .active_sprites
                 .into_iter()
+                .chain(extra_sprites.iter().copied())
                 .filter(|sprite| sprite.active && x >= sprite.x as u16 && x < sprite.x as u16 + 8)
                 .enumerate()
             {
@@ -771,7 +1177,12 @@ impl Ppu {
 
                     let behind_background = sprite.attrs.has_bits(0b0010_0000);
 
-                    if bg_opaque && sprite.idx == 0 {
+                    // Sprite zero hit requires both layers to be rendered and never occurs at x=255
+                    if bg_opaque
+                        && sprite.idx == 0
+                        && x != 255
+                        && self.background_rendering_enabled()
+                    {
                         self.set_sprite_zero_hit();
                     }
 
@@ -783,6 +1194,8 @@ impl Ppu {
                     }
                 }
             }
+
+            self.active_extra_sprites = extra_sprites;
         }
 
         let display_idx = (y * 256 + x) as usize * 4;
@@ -792,11 +1205,48 @@ impl Ppu {
         }
 
         if let Some(addr) = palette_addr {
-            let color = DEFAULT_PALETTE[self.read_mem_u8(addr) as usize & 0x3F];
+            let palette_val = self.read_mem_u8(addr);
+            let color = self.output_color(palette_val);
             self.display[display_idx..][..=3].copy_from_slice(&[color.0, color.1, color.2, 255]);
+
+            let emphasis = (self.ppu_mask >> 5) as u16;
+            self.pixels[display_idx / 4] = self.grayscale(palette_val) as u16 | emphasis << 6;
         }
     }
 
+    /// The palette value that is output, with only the gray column used in grayscale mode
+    fn grayscale(&self, palette_val: u8) -> u8 {
+        if self.ppu_mask.has_bits(0b0000_0001) {
+            palette_val & 0x30
+        } else {
+            palette_val & 0x3F
+        }
+    }
+
+    /// Look up the RGB color for a palette value, taking grayscale and color emphasis into account.
+    fn output_color(&self, palette_val: u8) -> (u8, u8, u8) {
+        let (r, g, b) = self.palette[self.grayscale(palette_val) as usize];
+
+        let emphasis = self.ppu_mask >> 5;
+        if emphasis == 0 {
+            return (r, g, b);
+        }
+
+        let attenuate = |val: u8, emphasized: bool| {
+            if emphasized {
+                val
+            } else {
+                (val as f32 * EMPHASIS_ATTENUATION) as u8
+            }
+        };
+
+        (
+            attenuate(r, emphasis.has_bits(0b001)),
+            attenuate(g, emphasis.has_bits(0b010)),
+            attenuate(b, emphasis.has_bits(0b100)),
+        )
+    }
+
     fn load_nametable_byte(&mut self) {
         self.next_nt_tile = self.read_mem_u8(0x2000 | (self.vram_addr & 0x0FFF));
     }
@@ -818,6 +1268,9 @@ impl Ppu {
             + ((self.next_nt_tile as u16) << 4)
             + ((self.vram_addr & PIXEL_Y_BITS) >> 12);
 
+        if self.rendering_enabled() {
+            self.update_a12(bg_tile_addr);
+        }
         self.next_bg_tile_lo = self.read_mem_u8(bg_tile_addr);
     }
 
@@ -840,6 +1293,19 @@ impl Ppu {
 
     fn load_low_sprite_tile_byte(&mut self) {
         let sprite_idx = (self.cycle as usize - 261) >> 3;
+
+        if self.rendering_enabled() {
+            // Empty slots fetch tile $FF
+            let addr = if self.active_sprites[sprite_idx].active {
+                self.sprite_addr(sprite_idx)
+            } else if self.use_large_sprites() {
+                0x1FF0
+            } else {
+                self.sprite_pattern_table_address() | 0x0FF0
+            };
+            self.update_a12(addr);
+        }
+
         if self.active_sprites[sprite_idx].active {
             self.active_sprites[sprite_idx].tile_lo =
                 self.read_mem_u8(self.sprite_addr(sprite_idx));
@@ -855,8 +1321,10 @@ impl Ppu {
     }
 
     fn sprite_addr(&self, i: usize) -> u16 {
-        let sprite = self.active_sprites[i];
+        self.sprite_tile_addr(&self.active_sprites[i])
+    }
 
+    fn sprite_tile_addr(&self, sprite: &Sprite) -> u16 {
         let next_y = self.scanline + 1;
 
         let flip_vertical = sprite.attrs.has_bits(0x80);
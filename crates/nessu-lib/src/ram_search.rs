@@ -0,0 +1,59 @@
+/// How a value has to compare to its previous value to stay a candidate
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Comparison {
+    Increased,
+    Decreased,
+    Changed,
+    Unchanged,
+    EqualTo(u8),
+}
+
+impl Comparison {
+    pub fn matches(self, prev: u8, curr: u8) -> bool {
+        match self {
+            Comparison::Increased => curr > prev,
+            Comparison::Decreased => curr < prev,
+            Comparison::Changed => curr != prev,
+            Comparison::Unchanged => curr == prev,
+            Comparison::EqualTo(val) => curr == val,
+        }
+    }
+}
+
+/// Narrows down the CPU RAM addresses that hold a value by comparing snapshots
+/// taken with [`Nes::ram_snapshot`](crate::nes::Nes::ram_snapshot).
+#[derive(Clone, Debug)]
+pub struct RamSearch {
+    snapshot: Vec<u8>,
+    candidates: Vec<u16>,
+}
+
+impl RamSearch {
+    /// Start a search with every address as a candidate.
+    pub fn new(snapshot: Vec<u8>) -> Self {
+        Self {
+            candidates: (0..snapshot.len() as u16).collect(),
+            snapshot,
+        }
+    }
+
+    /// Keep the candidates whose value compares to the previous snapshot as given,
+    /// and make `snapshot` the one the next filter compares to.
+    pub fn filter(&mut self, snapshot: Vec<u8>, comparison: Comparison) {
+        let prev = &self.snapshot;
+        self.candidates.retain(|&addr| {
+            let addr = addr as usize;
+            comparison.matches(prev[addr], snapshot[addr])
+        });
+        self.snapshot = snapshot;
+    }
+
+    pub fn candidates(&self) -> &[u16] {
+        &self.candidates
+    }
+
+    /// Value of the address in the latest snapshot
+    pub fn value(&self, addr: u16) -> u8 {
+        self.snapshot[addr as usize]
+    }
+}
@@ -0,0 +1,54 @@
+/// TV system the console is built for, which determines its timing.
+#[derive(Debug, Default, Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+    /// Famiclone with PAL frame rate and NTSC-like CPU speed
+    Dendy,
+}
+
+impl Region {
+    /// Scanlines per frame, including the pre-render scanline
+    pub fn scanlines_per_frame(self) -> u16 {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    /// Scanline on which the vblank flag is set
+    pub fn vblank_scanline(self) -> u16 {
+        match self {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291,
+        }
+    }
+
+    /// CPU cycles per PPU cycles, as (cpu, ppu). NTSC runs the CPU every 3 PPU cycles,
+    /// PAL every 3.2.
+    pub fn cpu_ppu_ratio(self) -> (u8, u8) {
+        match self {
+            Region::Ntsc | Region::Dendy => (1, 3),
+            Region::Pal => (5, 16),
+        }
+    }
+
+    /// CPU clock rate in Hz
+    pub fn cpu_clock_rate(self) -> f64 {
+        match self {
+            Region::Ntsc => 1_789_773.0,
+            Region::Pal => 1_662_607.0,
+            Region::Dendy => 1_773_448.0,
+        }
+    }
+
+    /// Frames per second
+    pub fn frame_rate(self) -> f64 {
+        match self {
+            Region::Ntsc => 60.0988,
+            Region::Pal | Region::Dendy => 50.0070,
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+
+/// Names of CPU addresses, loaded from debugger label files
+#[derive(Clone, Default, Debug)]
+pub struct Symbols {
+    labels: HashMap<u16, String>,
+}
+
+impl Symbols {
+    pub fn get(&self, addr: u16) -> Option<&str> {
+        self.labels.get(&addr).map(String::as_str)
+    }
+
+    pub fn insert(&mut self, addr: u16, name: &str) {
+        self.labels.insert(addr, name.to_string());
+    }
+
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    /// Parse a Mesen `.mlb` label file. Lines that can't be parsed are ignored.
+    ///
+    /// PRG-ROM offsets are mapped to CPU addresses assuming that ROMs of up to 32KB are mapped
+    /// to $8000-$FFFF and that the last 16KB bank of larger ROMs is fixed at $C000. Labels in
+    /// the other banks of larger ROMs are skipped, since they can be mapped anywhere.
+    pub fn parse_mlb(text: &str, prg_rom_size: usize) -> Self {
+        let mut symbols = Symbols::default();
+
+        for (kind, offset, name) in text.lines().filter_map(parse_mlb_line) {
+            let addrs = match kind {
+                "P" | "NesPrgRom" => prg_rom_addrs(offset, prg_rom_size),
+                "R" | "NesInternalRam" if offset < 0x0800 => vec![offset],
+                "S" | "W" | "NesSaveRam" | "NesWorkRam" if offset < 0x2000 => vec![0x6000 + offset],
+                "G" | "NesMemory" if offset < 0x10000 => vec![offset],
+                _ => vec![],
+            };
+
+            for addr in addrs {
+                symbols.insert(addr as u16, name);
+            }
+        }
+
+        symbols
+    }
+
+    /// Parse an FCEUX `.nl` label file, where addresses are CPU addresses.
+    /// Lines that can't be parsed are ignored.
+    pub fn parse_nl(text: &str) -> Self {
+        let mut symbols = Symbols::default();
+
+        for (addr, name) in text.lines().filter_map(parse_nl_line) {
+            symbols.insert(addr, name);
+        }
+
+        symbols
+    }
+}
+
+/// Memory type, offset and name of a line like `P:1A2B:Name:Comment`
+fn parse_mlb_line(line: &str) -> Option<(&str, usize, &str)> {
+    let mut parts = line.trim().splitn(4, ':');
+    let kind = parts.next()?;
+    // Ranges are labeled by their first address
+    let offset = parts.next()?.split('-').next()?;
+    let offset = usize::from_str_radix(offset, 16).ok()?;
+    let name = parts.next().filter(|name| !name.is_empty())?;
+
+    Some((kind, offset, name))
+}
+
+/// Address and name of a line like `$C000#Name#Comment`
+fn parse_nl_line(line: &str) -> Option<(u16, &str)> {
+    let mut parts = line.trim().split('#');
+    // Arrays have their size after a slash
+    let addr = parts.next()?.strip_prefix('$')?.split('/').next()?;
+    let addr = u16::from_str_radix(addr, 16).ok()?;
+    let name = parts.next().filter(|name| !name.is_empty())?;
+
+    Some((addr, name))
+}
+
+/// CPU addresses that a PRG-ROM offset is mapped to
+fn prg_rom_addrs(offset: usize, prg_rom_size: usize) -> Vec<usize> {
+    if offset >= prg_rom_size {
+        vec![]
+    } else if prg_rom_size <= 0x8000 {
+        (0x8000..0x10000)
+            .step_by(prg_rom_size)
+            .map(|bank| bank + offset)
+            .collect()
+    } else if offset >= prg_rom_size - 0x4000 {
+        vec![0xC000 + offset - (prg_rom_size - 0x4000)]
+    } else {
+        vec![]
+    }
+}
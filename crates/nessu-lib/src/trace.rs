@@ -0,0 +1,96 @@
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::op::{to_asm, AddressingMode, OpKind};
+
+/// Layout of the lines written by [`Cpu::enable_trace`](crate::cpu::Cpu::enable_trace).
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub enum TraceFormat {
+    /// Same columns as the nestest golden log, e.g.
+    /// `C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7`
+    #[default]
+    Nestest,
+    /// Nestest columns without the disassembly, which annotates operands differently than the
+    /// golden log does
+    NoDisassembly,
+}
+
+/// CPU state at the start of an instruction
+pub(crate) struct TraceEntry {
+    pub pc: u16,
+    pub op_kind: OpKind,
+    pub addressing_mode: AddressingMode,
+    /// Opcode followed by its operands
+    pub bytes: Vec<u8>,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub s: u8,
+    /// PPU position, including the dots of the opcode fetch cycle
+    pub scanline: u16,
+    pub dot: u16,
+    pub cycles: u128,
+}
+
+impl TraceEntry {
+    pub fn format(&self, format: TraceFormat) -> String {
+        let mut line = String::new();
+
+        let bytes = self
+            .bytes
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect::<Vec<_>>()
+            .join(" ");
+        write!(line, "{:04X}  {:<8}  ", self.pc, bytes).unwrap();
+
+        if format == TraceFormat::Nestest {
+            write!(line, "{:<32}", self.disassembly()).unwrap();
+        }
+
+        write!(
+            line,
+            "A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+            self.a, self.x, self.y, self.p, self.s, self.scanline, self.dot, self.cycles
+        )
+        .unwrap();
+
+        line
+    }
+
+    fn disassembly(&self) -> String {
+        let operand = match self.bytes.len() {
+            2 if self.addressing_mode == AddressingMode::Relative => self
+                .pc
+                .wrapping_add(2)
+                .wrapping_add(self.bytes[1] as i8 as u16),
+            2 => self.bytes[1] as u16,
+            3 => u16::from_le_bytes([self.bytes[1], self.bytes[2]]),
+            _ => 0,
+        };
+
+        to_asm(self.op_kind, self.addressing_mode, operand)
+    }
+}
+
+/// Instruction trace written to a file
+pub(crate) struct Trace {
+    writer: BufWriter<File>,
+    format: TraceFormat,
+}
+
+impl Trace {
+    pub fn create<P: AsRef<Path>>(path: P, format: TraceFormat) -> io::Result<Self> {
+        Ok(Self {
+            writer: BufWriter::new(File::create(path)?),
+            format,
+        })
+    }
+
+    pub fn write(&mut self, entry: &TraceEntry) -> io::Result<()> {
+        writeln!(self.writer, "{}", entry.format(self.format))
+    }
+}
@@ -0,0 +1,59 @@
+//! JavaScript bindings for running the emulator in a browser. Build with
+//! `wasm-pack build crates/nessu-lib --target web`, see `examples/web` for usage.
+
+use wasm_bindgen::prelude::*;
+
+use crate::cartridge::Cartridge;
+use crate::input::Button;
+use crate::nes::Nes;
+
+#[wasm_bindgen(js_name = Nes)]
+pub struct WasmNes {
+    nes: Nes,
+}
+
+#[wasm_bindgen(js_class = Nes)]
+impl WasmNes {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self { nes: Nes::new() }
+    }
+
+    /// Load an iNES ROM. Throws if the ROM is invalid or its mapper is not supported.
+    pub fn insert_cartridge_from_bytes(&mut self, bytes: &[u8]) -> Result<(), JsValue> {
+        let cart = Cartridge::from_bytes(bytes).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        self.nes.insert_cartridge(cart);
+        Ok(())
+    }
+
+    pub fn step_frame(&mut self) -> Result<(), JsValue> {
+        self.nes.step_frame().map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// The current frame as RGBA bytes, 256×240 pixels. Returned as a copy in a `Uint8Array`,
+    /// which can be wrapped in an `ImageData` directly.
+    pub fn display_bytes(&self) -> Vec<u8> {
+        self.nes.display_bytes().to_vec()
+    }
+
+    pub fn set_button_state_player1(&mut self, button: Button, state: bool) {
+        self.nes.set_button_state_player1(button, state);
+    }
+
+    pub fn set_button_state_player2(&mut self, button: Button, state: bool) {
+        self.nes.set_button_state_player2(button, state);
+    }
+
+    pub fn set_audio_sample_rate(&mut self, rate: u32) {
+        self.nes.set_audio_sample_rate(rate);
+    }
+
+    /// Audio samples produced since the last call, as a `Float32Array`.
+    pub fn drain_audio_samples(&mut self) -> Vec<f32> {
+        self.nes.drain_audio_samples()
+    }
+
+    pub fn reset(&mut self) {
+        self.nes.reset();
+    }
+}
@@ -0,0 +1,121 @@
+use nessu_lib::apu::{Apu, Channel, CHANNEL_BUFFER_LEN};
+
+#[test]
+fn muted_channels_are_left_out_of_the_mix() {
+    let mut apu = Apu::new();
+    // The triangle holds its last output level even when silenced
+    apu.triangle_enabled_by_user = false;
+    apu.noise_enabled_by_user = false;
+    apu.dmc_enabled_by_user = false;
+
+    // Pulse 1: 50% duty, constant volume 15, length counter enabled
+    apu.write_register(0x4015, 0x01);
+    apu.write_register(0x4000, 0b1001_1111);
+    apu.write_register(0x4002, 0xFF);
+    apu.write_register(0x4003, 0x08);
+
+    let mut heard = false;
+    for _ in 0..10000 {
+        apu.clock();
+        heard |= apu.output_sample() > 0.0;
+    }
+    assert!(heard);
+
+    apu.pulse1_enabled_by_user = false;
+    for _ in 0..10000 {
+        apu.clock();
+        assert_eq!(apu.output_sample(), 0.0);
+    }
+
+    // The channel keeps running while muted
+    assert_eq!(apu.read_status(true) & 0x01, 0x01);
+}
+
+#[test]
+fn channel_samples() {
+    let mut apu = Apu::new();
+    apu.write_register(0x4015, 0x01);
+    apu.write_register(0x4000, 0b1001_1111);
+    apu.write_register(0x4002, 0xFF);
+    apu.write_register(0x4003, 0x08);
+    apu.pulse1_enabled_by_user = false;
+
+    for _ in 0..CHANNEL_BUFFER_LEN * 40 {
+        apu.clock();
+    }
+
+    let pulse1 = apu.channel_samples(Channel::Pulse1);
+    assert_eq!(pulse1.len(), CHANNEL_BUFFER_LEN);
+    assert!(pulse1.contains(&1.0));
+    assert!(pulse1.contains(&0.0));
+
+    assert!(apu
+        .channel_samples(Channel::Pulse2)
+        .iter()
+        .all(|&sample| sample == 0.0));
+}
+
+#[test]
+fn reset_silences_channels_and_keeps_frame_counter_mode() {
+    let mut apu = Apu::new();
+    // 5-step mode never raises the frame IRQ
+    apu.write_register(0x4017, 0x80);
+    apu.write_register(0x4015, 0x01);
+    apu.write_register(0x4003, 0x08);
+    apu.write_register(0x4011, 0x45);
+    assert_eq!(apu.read_status(true) & 0x01, 0x01);
+
+    apu.reset();
+    assert_eq!(apu.read_status(true) & 0x01, 0);
+
+    for _ in 0..40000 {
+        apu.clock();
+    }
+    assert!(!apu.irq_triggered());
+
+    // Only the lowest bit of the DMC output level is kept
+    let dmc = apu.channel_samples(Channel::Dmc);
+    assert_eq!(dmc[dmc.len() - 1], 1.0 / 127.0);
+}
+
+#[test]
+fn frame_irq_timing() {
+    // The 4-step sequence raises the IRQ on its last 3 cycles, 29828 CPU cycles in
+    let mut apu = Apu::new();
+    for _ in 0..29827 {
+        apu.clock();
+    }
+    assert!(!apu.irq_triggered());
+
+    apu.clock();
+    assert!(apu.irq_triggered());
+    assert_eq!(apu.read_status(false) & 0x40, 0x40);
+
+    // Reading $4015 acknowledges the IRQ
+    assert!(!apu.irq_triggered());
+
+    // The 5-step sequence never raises it
+    let mut apu = Apu::new();
+    apu.write_register(0x4017, 0x80);
+    for _ in 0..37282 * 2 {
+        apu.clock();
+        assert!(!apu.irq_triggered());
+    }
+}
+
+#[test]
+fn disabling_a_channel_clears_its_length_counter() {
+    let mut apu = Apu::new();
+    apu.write_register(0x4015, 0x0F);
+    for addr in [0x4003, 0x4007, 0x400B, 0x400F] {
+        apu.write_register(addr, 0x08);
+    }
+    assert_eq!(apu.read_status(true) & 0x0F, 0x0F);
+
+    apu.write_register(0x4015, 0x0D);
+    assert_eq!(apu.read_status(true) & 0x0F, 0x0D);
+
+    // Enabling the channel again doesn't restore the counter
+    apu.write_register(0x4015, 0x0F);
+    assert_eq!(apu.read_status(true) & 0x0F, 0x0D);
+}
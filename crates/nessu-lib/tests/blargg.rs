@@ -0,0 +1,101 @@
+//! Runs test ROMs that report their results at $6000, such as blargg's accuracy tests.
+//!
+//! The ROMs are expected in `tests/roms` at the paths listed in [`TEST_ROMS`]. They are not
+//! bundled, so the test is ignored by default. Run it with
+//! `cargo test --test blargg -- --ignored`. Missing ROMs are skipped, but at least one has to
+//! be present. Older test ROMs like sprite_hit_tests_2005.10.05 only report their results on
+//! screen, so they can't be run here.
+
+use std::fs::read;
+use std::path::PathBuf;
+
+use nessu_lib::cartridge::Cartridge;
+use nessu_lib::nes::Nes;
+
+const TEST_ROMS: &[&str] = &[
+    "instr_test-v5/official_only.nes",
+    "instr_test-v5/all_instrs.nes",
+    "instr_misc/instr_misc.nes",
+    "cpu_interrupts_v2/cpu_interrupts.nes",
+    "ppu_vbl_nmi/ppu_vbl_nmi.nes",
+    "ppu_open_bus/ppu_open_bus.nes",
+    "oam_read/oam_read.nes",
+    "apu_test/apu_test.nes",
+];
+
+/// Status while the test is running
+const STATUS_RUNNING: u8 = 0x80;
+/// Status when the test needs the reset button to be pressed
+const STATUS_RESET_NEEDED: u8 = 0x81;
+const STATUS_PASSED: u8 = 0x00;
+
+/// Frames to wait before pressing reset. The protocol requires at least 100 ms.
+const RESET_DELAY_FRAMES: usize = 10;
+
+/// Give up on tests that run longer than this
+const MAX_FRAMES: usize = 60 * 120;
+
+fn rom_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("roms")
+        .join(name)
+}
+
+/// Run a test ROM until it reports a result, returning its message on success.
+fn run_test_rom(rom: &[u8]) -> Result<String, String> {
+    let mut nes = Nes::new();
+    nes.insert_cartridge(Cartridge::from_bytes(rom).map_err(|e| e.to_string())?);
+
+    let mut reset_frame = None;
+
+    for frame in 0..MAX_FRAMES {
+        nes.step_frame()?;
+
+        match nes.test_rom_status() {
+            // The status is not valid until the signature has been written
+            None => {}
+            Some((STATUS_RUNNING, _)) => {}
+            Some((STATUS_RESET_NEEDED, _)) => match reset_frame {
+                None => reset_frame = Some(frame + RESET_DELAY_FRAMES),
+                Some(reset_at) if frame >= reset_at => {
+                    nes.reset();
+                    reset_frame = None;
+                }
+                Some(_) => {}
+            },
+            Some((STATUS_PASSED, message)) => return Ok(message),
+            Some((status, message)) => {
+                return Err(format!("failed with status ${:02X}: {}", status, message))
+            }
+        }
+    }
+
+    Err(format!("no result after {} frames", MAX_FRAMES))
+}
+
+#[test]
+#[ignore = "needs the test ROMs in tests/roms"]
+fn blargg_test_roms() {
+    let mut failures = Vec::new();
+    let mut ran = 0;
+
+    for name in TEST_ROMS {
+        let rom = match read(rom_path(name)) {
+            Ok(rom) => rom,
+            Err(_) => {
+                eprintln!("{} not found in tests/roms, skipping", name);
+                continue;
+            }
+        };
+
+        ran += 1;
+        match run_test_rom(&rom) {
+            Ok(_) => eprintln!("{}: passed", name),
+            Err(e) => failures.push(format!("{}: {}", name, e.trim())),
+        }
+    }
+
+    assert!(ran > 0, "none of the test ROMs were found in tests/roms");
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
@@ -0,0 +1,91 @@
+use std::io::ErrorKind;
+
+use nessu_lib::cartridge::Cartridge;
+use nessu_lib::nes::Nes;
+
+/// An NROM image with 32KB PRG-ROM and 8KB CHR-ROM, with an optional trainer.
+fn nrom(trainer: bool) -> Vec<u8> {
+    let mut bytes = b"NES\x1A".to_vec();
+    bytes.extend([2, 1, (trainer as u8) << 2]);
+    bytes.resize(16, 0);
+
+    if trainer {
+        bytes.extend(vec![0; 0x200]);
+    }
+
+    bytes.extend(vec![0; 0x8000 + 0x2000]);
+    bytes
+}
+
+#[test]
+fn truncated_roms_are_rejected() {
+    for trainer in [false, true] {
+        let rom = nrom(trainer);
+        assert!(Cartridge::from_bytes(&rom).is_ok());
+
+        for len in [rom.len() - 1, rom.len() - 0x2000, 16] {
+            let err = Cartridge::from_bytes(&rom[..len]).err().unwrap();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+    }
+
+    let err = Cartridge::from_bytes(b"NES\x1A").err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+    let mut rom = nrom(false);
+    rom[4] = 0;
+    let err = Cartridge::from_bytes(&rom).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn trainer_is_loaded_to_prg_ram() {
+    let mut rom = nrom(true);
+    for (i, byte) in rom[0x10..0x210].iter_mut().enumerate() {
+        *byte = i as u8 ^ 0x5A;
+    }
+
+    let cart = Cartridge::from_bytes(&rom).unwrap();
+    assert_eq!(cart.header().trainer(&rom), Some(&rom[0x10..0x210]));
+
+    for mut nes in [Nes::new(), Nes::new_deterministic()] {
+        nes.insert_cartridge(Cartridge::from_bytes(&rom).unwrap());
+
+        for i in 0..0x200 {
+            assert_eq!(nes.cpu_read_mem(0x7000 + i), i as u8 ^ 0x5A);
+        }
+    }
+}
+
+#[test]
+fn unsupported_mappers_are_rejected() {
+    let mut rom = nrom(false);
+    rom[6] |= 0xF0;
+    rom[7] |= 0xF0;
+
+    let err = Cartridge::from_bytes(&rom).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+    assert_eq!(err.to_string(), "Unsupported mapper: 255");
+}
+
+#[test]
+fn roms_are_read_from_a_stream() {
+    let mut rom = nrom(true);
+    assert!(Cartridge::from_reader(rom.as_slice()).is_ok());
+
+    // Data after the declared size is left in the stream
+    rom.extend([1, 2, 3]);
+    let mut reader = rom.as_slice();
+    assert!(Cartridge::from_reader(&mut reader).is_ok());
+    assert_eq!(reader, &[1, 2, 3]);
+
+    for len in [rom.len() - 4, 16, 4, 0] {
+        let err = Cartridge::from_reader(&rom[..len]).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    let err = Cartridge::from_reader_with_limit(rom.as_slice(), 0x8000)
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+}
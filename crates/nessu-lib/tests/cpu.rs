@@ -0,0 +1,607 @@
+use nessu_lib::breakpoint::Expr;
+use nessu_lib::cartridge::Cartridge;
+use nessu_lib::input::Button;
+use nessu_lib::nes::Nes;
+use nessu_lib::op::expected_cycles;
+use nessu_lib::ram_search::{Comparison, RamSearch};
+
+/// Build an NROM image that starts executing `code` at $8000.
+fn program_rom(code: &[u8]) -> Vec<u8> {
+    let mut bytes = b"NES\x1A".to_vec();
+    bytes.extend([2, 1]);
+    bytes.resize(16, 0);
+
+    let mut prg = vec![0xEA; 0x8000];
+    prg[..code.len()].copy_from_slice(code);
+    // Reset vector
+    prg[0x7FFC] = 0x00;
+    prg[0x7FFD] = 0x80;
+
+    bytes.extend(prg);
+    bytes.extend(vec![0; 0x2000]);
+    bytes
+}
+
+/// Run the first `instructions` instructions of `code`.
+fn run(code: &[u8], instructions: usize) -> Nes {
+    let mut nes = Nes::new();
+    nes.insert_cartridge(Cartridge::from_bytes(&program_rom(code)).unwrap());
+
+    for _ in 0..instructions {
+        nes.step_instruction().unwrap();
+    }
+
+    nes
+}
+
+#[test]
+fn unmapped_reads_return_open_bus() {
+    // The last value on the bus is the high byte of the operand
+    let nes = run(&[0xAD, 0x00, 0x50], 1); // LDA $5000
+    assert_eq!(nes.cpu().a, 0x50);
+
+    let nes = run(&[0xAE, 0x18, 0x40], 1); // LDX $4018
+    assert_eq!(nes.cpu().x, 0x40);
+
+    // Indexed reads put the base address high byte on the bus
+    let nes = run(&[0xA2, 0x10, 0xBD, 0x00, 0x5F], 2); // LDX #$10; LDA $5F00,X
+    assert_eq!(nes.cpu().a, 0x5F);
+}
+
+#[test]
+fn controller_reads_keep_open_bus_in_upper_bits() {
+    let nes = run(&[0xAD, 0x16, 0x40], 1); // LDA $4016
+    assert_eq!(nes.cpu().a & 0xE0, 0x40);
+}
+
+#[test]
+fn player2_is_read_from_4017() {
+    #[rustfmt::skip]
+    let mut code = vec![
+        0xA9, 0x01, 0x8D, 0x16, 0x40, // LDA #1; STA $4016
+        0xA9, 0x00, 0x8D, 0x16, 0x40, // LDA #0; STA $4016
+    ];
+    for _ in 0..8 {
+        code.extend([0xAD, 0x17, 0x40]); // LDA $4017
+    }
+
+    let mut nes = run(&code, 0);
+    nes.set_button_state_player2(Button::A, true);
+    nes.set_button_state_player2(Button::Start, true);
+    nes.set_button_state_player2(Button::Right, true);
+
+    for _ in 0..4 {
+        nes.step_instruction().unwrap();
+    }
+
+    // A, B, Select, Start, Up, Down, Left, Right
+    let mut bits = Vec::new();
+    for _ in 0..8 {
+        nes.step_instruction().unwrap();
+        bits.push(nes.cpu().a & 1);
+    }
+    assert_eq!(bits, [1, 0, 0, 1, 0, 0, 0, 1]);
+
+    // Player 1 is not affected
+    assert_eq!(nes.cpu_read_mem(0x4016) & 1, 0);
+}
+
+#[test]
+fn microphone_is_reported_in_bit_2_of_4016() {
+    let mut nes = run(&[0xAD, 0x16, 0x40, 0xAD, 0x16, 0x40], 0); // LDA $4016; LDA $4016
+    nes.step_instruction().unwrap();
+    assert_eq!(nes.cpu().a & 0x04, 0);
+
+    nes.set_microphone(true);
+    nes.step_instruction().unwrap();
+    assert_eq!(nes.cpu().a & 0x1F, 0x04);
+    assert_eq!(nes.cpu_read_mem(0x4017) & 0x04, 0);
+}
+
+#[test]
+fn jmp_indirect_wraps_within_page() {
+    let mut nes = run(&[0x6C, 0xFF, 0x02], 0); // JMP ($02FF)
+    nes.cpu_write_mem(0x02FF, 0x34);
+    nes.cpu_write_mem(0x0200, 0x12);
+    nes.cpu_write_mem(0x0300, 0x56);
+
+    nes.step_instruction().unwrap();
+    assert_eq!(nes.cpu().pc, 0x1234);
+}
+
+#[test]
+fn dop_reports_its_cycles() {
+    let nes = run(&[0x80, 0x00], 1); // NOP #$00
+    assert_eq!(nes.cpu().prev_op_cycles, 2);
+
+    let nes = run(&[0x04, 0x00], 1); // NOP $00
+    assert_eq!(nes.cpu().prev_op_cycles, 3);
+
+    let nes = run(&[0x14, 0x00], 1); // NOP $00,X
+    assert_eq!(nes.cpu().prev_op_cycles, 4);
+}
+
+/// Run `code` with a pointer to $0400 at $FF and $00, the wrong high byte at $0100, and
+/// different values at $0400 and $0500.
+fn run_with_zero_page_pointer(code: &[u8], instructions: usize) -> Nes {
+    let mut nes = run(code, 0);
+    nes.cpu_write_mem(0x00FF, 0x00);
+    nes.cpu_write_mem(0x0000, 0x04);
+    nes.cpu_write_mem(0x0100, 0x05);
+    nes.cpu_write_mem(0x0400, 0x42);
+    nes.cpu_write_mem(0x0500, 0x99);
+
+    for _ in 0..instructions {
+        nes.step_instruction().unwrap();
+    }
+
+    nes
+}
+
+#[test]
+fn indexed_indirect_wraps_in_zero_page() {
+    // The pointer address wraps from $FF to $00
+    let nes = run_with_zero_page_pointer(&[0xA2, 0x01, 0xA1, 0xFE], 2); // LDX #1; LDA ($FE,X)
+    assert_eq!(nes.cpu().a, 0x42);
+
+    // Adding X to the operand wraps too
+    let nes = run_with_zero_page_pointer(&[0xA2, 0x80, 0xA1, 0x7F], 2); // LDX #$80; LDA ($7F,X)
+    assert_eq!(nes.cpu().a, 0x42);
+}
+
+#[test]
+fn indirect_indexed_wraps_in_zero_page() {
+    let nes = run_with_zero_page_pointer(&[0xA0, 0x00, 0xB1, 0xFF], 2); // LDY #0; LDA ($FF),Y
+    assert_eq!(nes.cpu().a, 0x42);
+}
+
+#[test]
+fn instruction_cycles_match_reference() {
+    let nes = run(&[0x04, 0x00], 1); // NOP $00
+    assert_eq!(nes.cpu().prev_op_cycles, 3);
+    assert_eq!(expected_cycles(0x04, false, false), Some(3));
+
+    let nes = run(&[0xA2, 0x01, 0xBD, 0xFF, 0x02], 2); // LDX #1; LDA $02FF,X
+    assert_eq!(nes.cpu().prev_op_cycles, 5);
+    assert_eq!(expected_cycles(0xBD, true, false), Some(5));
+
+    let nes = run(&[0xA2, 0x01, 0x9D, 0xFF, 0x02], 2); // LDX #1; STA $02FF,X
+    assert_eq!(nes.cpu().prev_op_cycles, 5);
+    assert_eq!(expected_cycles(0x9D, true, false), Some(5));
+
+    let nes = run(&[0x18, 0x90, 0x00], 2); // CLC; BCC +0
+    assert_eq!(nes.cpu().prev_op_cycles, 3);
+    assert_eq!(expected_cycles(0x90, false, true), Some(3));
+}
+
+/// Run `LDX #$10` and then `op $3FF2,X` with the vblank flag set. The dummy read of the
+/// indexed op goes to $3F02, a mirror of PPUSTATUS, before the effective address $4002.
+fn vblank_flag_after_indexed_op(op: u8) -> bool {
+    let mut nes = run(&[0xA2, 0x10, op, 0xF2, 0x3F], 1);
+    nes.ppu_mut().ppu_status |= 0x80;
+    nes.step_instruction().unwrap();
+
+    nes.ppu().ppu_status & 0x80 != 0
+}
+
+#[test]
+fn indexed_dummy_read_before_page_fix() {
+    assert!(!vblank_flag_after_indexed_op(0x9D)); // STA abs,X
+    assert!(!vblank_flag_after_indexed_op(0xBD)); // LDA abs,X
+    assert!(!vblank_flag_after_indexed_op(0x1E)); // ASL abs,X
+}
+
+const C: u8 = 0x01;
+const Z: u8 = 0x02;
+const V: u8 = 0x40;
+const N: u8 = 0x80;
+
+/// Run `setup` and then `op $10` with `val` at $10. Returns A, the N, V, Z and C flags, and
+/// the value left at $10.
+fn run_zero_page_op(setup: &[u8], op: u8, val: u8) -> (u8, u8, u8) {
+    let mut code = setup.to_vec();
+    code.extend([op, 0x10]);
+
+    let mut nes = run(&code, 0);
+    nes.cpu_write_mem(0x0010, val);
+    while nes.cpu().pc < 0x8000 + code.len() as u16 {
+        nes.step_instruction().unwrap();
+    }
+
+    let cpu = nes.cpu();
+    (cpu.a, cpu.p & (N | V | Z | C), nes.cpu_read_mem(0x0010))
+}
+
+#[test]
+fn unofficial_read_modify_write_opcodes() {
+    // SLO: ASL, then ORA
+    let result = run_zero_page_op(&[0xA9, 0x01], 0x07, 0x81); // LDA #1; SLO $10
+    assert_eq!(result, (0x03, C, 0x02));
+
+    // RLA: ROL, then AND
+    let result = run_zero_page_op(&[0x38, 0xA9, 0xFF], 0x27, 0x80); // SEC; LDA #$FF; RLA $10
+    assert_eq!(result, (0x01, C, 0x01));
+
+    // SRE: LSR, then EOR
+    let result = run_zero_page_op(&[0xA9, 0xFF], 0x47, 0x03); // LDA #$FF; SRE $10
+    assert_eq!(result, (0xFE, N | C, 0x01));
+
+    // RRA: ROR, then ADC with the carry shifted out
+    let result = run_zero_page_op(&[0x18, 0xA9, 0x10], 0x67, 0x03); // CLC; LDA #$10; RRA $10
+    assert_eq!(result, (0x12, 0, 0x01));
+
+    // DCP: DEC, then CMP
+    let result = run_zero_page_op(&[0xA9, 0x04], 0xC7, 0x05); // LDA #4; DCP $10
+    assert_eq!(result, (0x04, Z | C, 0x04));
+
+    // ISC: INC, then SBC
+    let result = run_zero_page_op(&[0x38, 0xA9, 0x10], 0xE7, 0x05); // SEC; LDA #$10; ISC $10
+    assert_eq!(result, (0x0A, C, 0x06));
+}
+
+#[test]
+fn unstable_stores_and_with_high_address_byte() {
+    // LDY #$FF; LDX #0; SHY $0200,X stores Y & ($02 + 1)
+    let mut nes = run(&[0xA0, 0xFF, 0xA2, 0x00, 0x9C, 0x00, 0x02], 2);
+    nes.step_instruction().unwrap();
+    assert_eq!(nes.cpu_read_mem(0x0200), 0x03);
+
+    // LDY #5; LDX #$10; SHY $02F8,X crosses a page. The stored value Y & $03 also replaces
+    // the high byte of the target address.
+    let mut nes = run(&[0xA0, 0x05, 0xA2, 0x10, 0x9C, 0xF8, 0x02], 2);
+    nes.cpu_write_mem(0x0108, 0x00);
+    nes.cpu_write_mem(0x0308, 0xAA);
+    nes.step_instruction().unwrap();
+    assert_eq!(nes.cpu_read_mem(0x0108), 0x01);
+    assert_eq!(nes.cpu_read_mem(0x0308), 0xAA);
+}
+
+/// Run `LDA #$FF`, `CLC` or `SEC`, and `ARR #imm`. Returns A and the N, V, Z and C flags.
+fn arr(carry: bool, imm: u8) -> (u8, u8) {
+    let carry_op = if carry { 0x38 } else { 0x18 };
+    let nes = run(&[0xA9, 0xFF, carry_op, 0x6B, imm], 3);
+    (nes.cpu().a, nes.cpu().p & (N | V | Z | C))
+}
+
+#[test]
+fn arr_flags() {
+    // C is bit 6 of the result and V is bit 6 XOR bit 5
+    assert_eq!(arr(false, 0xFF), (0x7F, C));
+    assert_eq!(arr(true, 0x80), (0xC0, N | V | C));
+    assert_eq!(arr(false, 0x40), (0x20, V));
+    assert_eq!(arr(false, 0x01), (0x00, Z));
+}
+
+#[test]
+fn interrupt_vectors() {
+    let mut nes = run(&[0xAD, 0x00, 0x50], 1); // LDA $5000
+    assert_eq!(nes.vectors(), (0xEAEA, 0x8000, 0xEAEA));
+
+    // Reading the vectors doesn't change the open bus value
+    assert_eq!(nes.cpu_read_mem(0x5000), 0x50);
+}
+
+#[test]
+fn reset_takes_seven_cycles() {
+    let mut nes = run(&[0xEA], 0); // NOP
+    assert_eq!(nes.cpu().cycles, 7);
+    assert_eq!(nes.cpu().pc, 0x8000);
+    assert_eq!(nes.cpu().s, 0xFD);
+    assert_eq!(nes.ppu().current_cycle(), 21);
+
+    // The first opcode is fetched on the 8th cycle
+    nes.step_instruction().unwrap();
+    assert_eq!(nes.cpu().cycles, 9);
+    assert_eq!(nes.cpu().pc, 0x8001);
+
+    nes.reset();
+    assert_eq!(nes.cpu().cycles, 7);
+    assert_eq!(nes.cpu().pc, 0x8000);
+    assert_eq!(nes.cpu().s, 0xFA);
+}
+
+#[test]
+fn ppu_ignores_register_writes_while_warming_up() {
+    // LDA #$04; STA $2000; JMP $8000
+    let mut nes = run(&[0xA9, 0x04, 0x8D, 0x00, 0x20, 0x4C, 0x00, 0x80], 0);
+
+    while nes.cpu().cycles < 29650 {
+        nes.step_instruction().unwrap();
+        assert_eq!(nes.ppu().ppu_ctrl, 0);
+    }
+
+    for _ in 0..10 {
+        nes.step_instruction().unwrap();
+    }
+    assert_eq!(nes.ppu().ppu_ctrl, 0x04);
+
+    // Writes from the debugger are not ignored
+    nes.reset();
+    nes.cpu_write_mem(0x2000, 0x08);
+    assert_eq!(nes.ppu().ppu_ctrl, 0x08);
+}
+
+#[test]
+fn deterministic_power_on_zeroes_ram() {
+    let mut nes = Nes::new_deterministic();
+    nes.insert_cartridge(Cartridge::from_bytes(&program_rom(&[])).unwrap());
+
+    for addr in (0x0000..0x0800).chain(0x6000..0x8000) {
+        assert_eq!(nes.cpu_read_mem(addr), 0, "${:04X}", addr);
+    }
+
+    nes.cpu_write_mem(0x0000, 0x42);
+    nes.power();
+    assert_eq!(nes.cpu_read_mem(0x0000), 0);
+}
+
+#[test]
+fn frame_count_and_cpu_ppu_phase() {
+    let mut nes = run(&[0x4C, 0x00, 0x80], 0); // JMP $8000
+    assert_eq!(nes.frame_count(), 0);
+
+    let mut phases = Vec::new();
+    for _ in 0..6 {
+        phases.push(nes.cpu_ppu_phase());
+        nes.clock().unwrap();
+    }
+    assert_eq!(phases, [1, 2, 0, 1, 2, 0]);
+
+    nes.step_frame().unwrap();
+    nes.step_frame().unwrap();
+    assert_eq!(nes.frame_count(), 2);
+}
+
+/// Plays a DMC sample at the fastest rate while reading the controller in a loop. Every time
+/// one of the 8 reads returns 0 although all buttons are held, $00 is incremented.
+fn count_corrupted_controller_reads(dmc_controller_conflict: bool) -> u8 {
+    #[rustfmt::skip]
+    let code = [
+        0xA9, 0x00, 0x85, 0x00, // LDA #0; STA $00
+        0xA9, 0x0F, 0x8D, 0x10, 0x40, // LDA #$0F; STA $4010
+        0xA9, 0xFF, 0x8D, 0x13, 0x40, // LDA #$FF; STA $4013
+        0xA9, 0x10, 0x8D, 0x15, 0x40, // LDA #$10; STA $4015
+        // loop:
+        0xA9, 0x01, 0x8D, 0x16, 0x40, // LDA #1; STA $4016
+        0xA9, 0x00, 0x8D, 0x16, 0x40, // LDA #0; STA $4016
+        // The loop would be 128 cycles long without this. DMC DMAs come every 432 cycles, so
+        // they would only hit 8 cycles of the loop, and whether one of them is a controller
+        // read would depend on the CPU/DMC phase left by the 7 cycle reset sequence. With 131
+        // cycles, the DMAs drift over every cycle of the loop.
+        0x24, 0x00, // BIT $00
+        0xA2, 0x08, // LDX #8
+        // read:
+        0xAD, 0x16, 0x40, // LDA $4016
+        0x4A, // LSR A
+        0xB0, 0x02, // BCS +2
+        0xE6, 0x00, // INC $00
+        0xCA, // DEX
+        0xD0, 0xF5, // BNE read
+        0x4C, 0x13, 0x80, // JMP loop
+    ];
+
+    let mut nes = Nes::new();
+    nes.insert_cartridge(Cartridge::from_bytes(&program_rom(&code)).unwrap());
+    nes.cpu_mut().dmc_controller_conflict = dmc_controller_conflict;
+
+    for button in [
+        Button::A,
+        Button::B,
+        Button::Select,
+        Button::Start,
+        Button::Up,
+        Button::Down,
+        Button::Left,
+        Button::Right,
+    ] {
+        nes.set_button_state_player1(button, true);
+    }
+
+    for _ in 0..20000 {
+        nes.step_instruction().unwrap();
+    }
+
+    nes.cpu_read_mem(0x0000)
+}
+
+#[test]
+fn dmc_dma_during_controller_read_drops_a_bit() {
+    assert!(count_corrupted_controller_reads(true) > 0);
+    assert_eq!(count_corrupted_controller_reads(false), 0);
+}
+
+#[test]
+fn disassembly_resolves_branch_targets() {
+    let code = [
+        0x20, 0x09, 0x80, // JSR $8009
+        0xCA, // DEX
+        0xD0, 0xFD, // BNE $8003
+        0x4C, 0x03, 0x80, // JMP $8003
+        0x60, // RTS
+    ];
+    let mut nes = run(&code, 0);
+
+    let ops = nes.disassemble_range(0x8000, 0x8009, false);
+    let asm: Vec<String> = ops.iter().map(|op| op.asm(false)).collect();
+    assert_eq!(asm, ["JSR $8009", "DEX", "BNE $8003", "JMP $8003", "RTS"]);
+    assert_eq!(ops[2].target, Some(0x8003));
+    assert!(ops.iter().all(|op| !op.labeled));
+
+    let ops = nes.disassemble_range(0x8000, 0x8009, true);
+    let asm: Vec<String> = ops.iter().map(|op| op.asm(true)).collect();
+    assert_eq!(
+        asm,
+        ["JSR L_8009", "DEX", "BNE L_8003", "JMP L_8003", "RTS"]
+    );
+    let labeled: Vec<u16> = ops
+        .iter()
+        .filter(|op| op.labeled)
+        .map(|op| op.addr)
+        .collect();
+    assert_eq!(labeled, [0x8003, 0x8009]);
+}
+
+#[test]
+fn conditional_breakpoints() {
+    let code = [
+        0xE8, // INX
+        0x86, 0x10, // STX $10
+        0x4C, 0x00, 0x80, // JMP $8000
+    ];
+    let mut nes = run(&code, 0);
+
+    let condition = Expr::parse("x == 3 && mem[$0010] >= 2").unwrap();
+    nes.cpu_mut().set_conditional_breakpoint(0x8000, condition);
+
+    assert!(nes.run_frame().is_err());
+    assert_eq!(nes.cpu().pc, 0x8000);
+    assert_eq!(nes.cpu().x, 3);
+
+    // Resuming continues past the breakpoint until the condition is met again
+    assert!(nes.run_frame().is_err());
+    assert_eq!(nes.cpu().x, 3);
+    assert_eq!(nes.cpu_read_mem(0x10), 3);
+}
+
+#[test]
+fn breakpoint_expressions() {
+    let mut nes = run(&[0xA9, 0x10, 0xA2, 0x05], 2); // LDA #$10, LDX #$05
+    nes.cpu_write_mem(0x00FF, 6);
+
+    let eval = |nes: &mut Nes, text: &str| Expr::parse(text).unwrap().eval(nes);
+    assert_eq!(eval(&mut nes, "A == 0x10"), 1);
+    assert_eq!(eval(&mut nes, "mem[$00FF] > 5"), 1);
+    assert_eq!(eval(&mut nes, "mem[$FA + x] <= 5"), 0);
+    assert_eq!(eval(&mut nes, "a & $F0 != 16 || (X - 1) == 4"), 1);
+    assert_eq!(eval(&mut nes, "PC"), 0x8004);
+
+    assert!(Expr::parse("A ==").is_err());
+    assert!(Expr::parse("Q == 1").is_err());
+    assert!(Expr::parse("(A == 1").is_err());
+    assert!(Expr::parse("A = 1").is_err());
+}
+
+#[test]
+fn reset_keeps_ram_and_power_clears_it() {
+    let code = [
+        0xA9, 0x11, // LDA #$11
+        0xA2, 0x22, // LDX #$22
+        0xA0, 0x33, // LDY #$33
+        0x8D, 0x00, 0x03, // STA $0300
+        0x8D, 0x00, 0x60, // STA $6000
+        0x4C, 0x0C, 0x80, // JMP $800C
+    ];
+    let mut nes = Nes::new_deterministic();
+    nes.insert_cartridge(Cartridge::from_bytes(&program_rom(&code)).unwrap());
+    for _ in 0..6 {
+        nes.step_instruction().unwrap();
+    }
+
+    nes.reset();
+    let cpu = nes.cpu();
+    assert_eq!((cpu.a, cpu.x, cpu.y), (0x11, 0x22, 0x33));
+    assert_eq!(cpu.s, 0xFA);
+    assert_eq!(cpu.p & 0x04, 0x04);
+    assert_eq!(cpu.pc, 0x8000);
+    assert_eq!(nes.cpu_read_mem(0x0300), 0x11);
+    assert_eq!(nes.cpu_read_mem(0x6000), 0x11);
+
+    nes.power();
+    let cpu = nes.cpu();
+    assert_eq!((cpu.a, cpu.x, cpu.y), (0, 0, 0));
+    assert_eq!(cpu.s, 0xFD);
+    assert_eq!(nes.cpu_read_mem(0x0300), 0);
+}
+
+#[test]
+fn ram_search() {
+    let mut nes = run(&[0x4C, 0x00, 0x80], 0); // JMP $8000
+    for addr in 0x0000..0x0800 {
+        nes.cpu_write_mem(addr, 0);
+    }
+
+    let mut search = RamSearch::new(nes.ram_snapshot());
+    assert_eq!(search.candidates().len(), 0x800);
+
+    nes.cpu_write_mem(0x0010, 3);
+    nes.cpu_write_mem(0x0020, 5);
+    search.filter(nes.ram_snapshot(), Comparison::Increased);
+    assert_eq!(search.candidates(), [0x0010, 0x0020]);
+
+    nes.cpu_write_mem(0x0010, 2);
+    search.filter(nes.ram_snapshot(), Comparison::Decreased);
+    assert_eq!(search.candidates(), [0x0010]);
+
+    search.filter(nes.ram_snapshot(), Comparison::EqualTo(2));
+    assert_eq!(search.candidates(), [0x0010]);
+    assert_eq!(search.value(0x0010), 2);
+
+    search.filter(nes.ram_snapshot(), Comparison::Changed);
+    assert!(search.candidates().is_empty());
+}
+
+#[test]
+fn frozen_ram_is_restored_every_frame() {
+    // INC $20; JMP $8000
+    let mut nes = run(&[0xE6, 0x20, 0x4C, 0x00, 0x80], 0);
+
+    nes.freeze_ram(0x0020, 0x42);
+    assert_eq!(nes.cpu_read_mem(0x0020), 0x42);
+    nes.run_frame().unwrap();
+    assert_eq!(nes.cpu_read_mem(0x0020), 0x42);
+
+    nes.unfreeze_ram(0x0020);
+    assert_eq!(nes.frozen_ram().count(), 0);
+    nes.run_frame().unwrap();
+    assert_ne!(nes.cpu_read_mem(0x0020), 0x42);
+}
+
+#[test]
+fn run_until_predicate() {
+    // LDX #$00; INX; STX $20; JMP $8002
+    let mut nes = run(&[0xA2, 0x00, 0xE8, 0x86, 0x20, 0x4C, 0x02, 0x80], 0);
+
+    assert_eq!(nes.run_until(100_000, |nes| nes.cpu().x == 10), Ok(true));
+    assert_eq!(nes.cpu().x, 10);
+
+    assert_eq!(
+        nes.run_until(100_000, |nes| nes.cpu().pc == 0x8005),
+        Ok(true)
+    );
+
+    // Already true before running
+    let cycles = nes.cpu().cycles;
+    assert_eq!(nes.run_until(100, |_| true), Ok(true));
+    assert_eq!(nes.cpu().cycles, cycles);
+
+    // The loop never reaches $9000
+    assert_eq!(nes.run_until(1000, |nes| nes.cpu().pc == 0x9000), Ok(false));
+    assert_eq!(nes.cpu().cycles, cycles + 1000);
+
+    nes.cpu_mut().set_breakpoint(0x8002);
+    assert!(nes.run_until(100, |_| false).is_err());
+}
+
+#[test]
+fn step_over_gives_up_after_max_cycles() {
+    // JSR $8006; JMP $8003; RTS
+    let mut nes = run(&[0x20, 0x06, 0x80, 0x4C, 0x03, 0x80, 0x60], 0);
+    assert_eq!(nes.step_over(1000), Ok(()));
+    assert_eq!(nes.cpu().pc, 0x8003);
+
+    // JSR $8003; JMP $8003
+    let mut nes = run(&[0x20, 0x03, 0x80, 0x4C, 0x03, 0x80], 0);
+    let cycles = nes.cpu().cycles;
+    assert!(nes.step_over(1000).is_err());
+    assert_eq!(nes.cpu().cycles, cycles + 1000);
+    assert_eq!(nes.cpu().temporary_breakpoint(), Some(0x8003));
+
+    assert!(nes.run_to(0x9000, 1000).is_err());
+}
+
+#[test]
+fn run_until_reports_a_halted_cpu() {
+    // KIL
+    let mut nes = run(&[0x02], 0);
+    assert!(nes.run_until(100_000, |_| false).is_err());
+}
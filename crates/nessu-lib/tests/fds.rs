@@ -0,0 +1,104 @@
+use std::io::ErrorKind;
+
+use nessu_lib::cartridge::Cartridge;
+use nessu_lib::fds::{disk_sides, is_disk_image, side_files, DiskFile, SIDE_SIZE};
+use nessu_lib::nes::Nes;
+
+/// A disk side with one file of `data` loaded to $6000
+fn disk_side(data: &[u8]) -> Vec<u8> {
+    let mut side = b"\x01*NINTENDO-HVC*".to_vec();
+    side.resize(56, 0);
+    side.extend([2, 1]);
+
+    side.extend([3, 0, 0x10]);
+    side.extend(b"MAIN    ");
+    side.extend(0x6000u16.to_le_bytes());
+    side.extend((data.len() as u16).to_le_bytes());
+    side.push(0);
+
+    side.push(4);
+    side.extend(data);
+
+    side.resize(SIDE_SIZE, 0);
+    side
+}
+
+/// A BIOS that loops forever
+fn bios() -> Vec<u8> {
+    let mut bios = vec![0xEA; 0x2000];
+    // JMP $E000
+    bios[..3].copy_from_slice(&[0x4C, 0x00, 0xE0]);
+    bios[0x1FFC] = 0x00;
+    bios[0x1FFD] = 0xE0;
+    bios
+}
+
+#[test]
+fn parse_disk_image() {
+    let side = disk_side(&[1, 2, 3]);
+
+    let mut image = b"FDS\x1A\x02".to_vec();
+    image.resize(16, 0);
+    image.extend(&side);
+    image.extend(&side);
+
+    assert!(is_disk_image(&image));
+    assert!(is_disk_image(&side));
+    assert!(!is_disk_image(b"NES\x1A"));
+
+    assert_eq!(disk_sides(&image).unwrap().len(), 2);
+    assert_eq!(disk_sides(&side).unwrap().len(), 1);
+
+    let err = disk_sides(&image[..image.len() - 1]).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    let err = disk_sides(&vec![0; SIDE_SIZE]).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+    assert_eq!(
+        side_files(&side),
+        vec![DiskFile {
+            number: 0,
+            id: 0x10,
+            name: "MAIN    ".to_string(),
+            load_addr: 0x6000,
+            size: 3,
+            kind: 0,
+        }]
+    );
+}
+
+#[test]
+fn disk_is_read_byte_by_byte() {
+    let side = disk_side(&[1, 2, 3]);
+    let mut nes = Nes::new();
+    nes.insert_cartridge(Cartridge::from_fds(&side, &bios()).unwrap());
+
+    assert_eq!(nes.cartridge().disk_side_count(), 1);
+    assert_eq!(nes.cartridge().inserted_disk_side(), Some(0));
+
+    nes.cpu_write_mem(0x4023, 0x01);
+    assert_eq!(nes.cpu_read_mem(0x4032) & 0x01, 0);
+
+    // Motor on, read mode and waiting for the first block
+    nes.cpu_write_mem(0x4025, 0x45);
+
+    // The gap end marker comes first
+    let mut bytes = vec![];
+    while bytes.len() < 16 {
+        nes.step_instruction().unwrap();
+        if nes.cpu_read_mem(0x4030) & 0x02 != 0 {
+            bytes.push(nes.cpu_read_mem(0x4031));
+        }
+    }
+    assert_eq!(&bytes, b"\x80\x01*NINTENDO-HVC*");
+
+    nes.cartridge_mut().insert_disk_side(None);
+    assert_eq!(nes.cpu_read_mem(0x4032) & 0x01, 0x01);
+}
+
+#[test]
+fn fds_bios_must_be_8kb() {
+    let side = disk_side(&[]);
+    let err = Cartridge::from_fds(&side, &[0; 0x1000]).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+}
@@ -0,0 +1,70 @@
+use nessu_lib::cartridge::Cartridge;
+use nessu_lib::game_genie::GameGenieCode;
+use nessu_lib::nes::Nes;
+
+fn nes_with_prg(prg: Vec<u8>) -> Nes {
+    let mut rom = b"NES\x1A".to_vec();
+    rom.extend([2, 1]);
+    rom.resize(16, 0);
+    rom.extend(prg);
+    rom.extend(vec![0; 0x2000]);
+
+    let mut nes = Nes::new();
+    nes.insert_cartridge(Cartridge::from_bytes(&rom).unwrap());
+    nes
+}
+
+#[test]
+fn decode_codes() {
+    // Infinite lives in Super Mario Bros.
+    assert_eq!(
+        GameGenieCode::parse("SXIOPO"),
+        Ok(GameGenieCode {
+            addr: 0x91D9,
+            value: 0xAD,
+            compare: None,
+        })
+    );
+
+    assert_eq!(
+        GameGenieCode::parse(" sxiopo "),
+        GameGenieCode::parse("SXIOPO")
+    );
+
+    let code = GameGenieCode::parse("YEUZUGAA").unwrap();
+    assert_eq!(code.addr, 0xACB3);
+    assert_eq!(code.value, 0x07);
+    assert_eq!(code.compare, Some(0x00));
+    assert_eq!(code.to_string(), "$ACB3?00:07");
+    assert_eq!(code.apply(0xACB3, 0x00), 0x07);
+    assert_eq!(code.apply(0xACB3, 0x01), 0x01);
+    assert_eq!(code.apply(0xACB4, 0x00), 0x00);
+
+    assert!(GameGenieCode::parse("SXIOP").is_err());
+    assert!(GameGenieCode::parse("SXIOPB").is_err());
+    assert!(GameGenieCode::parse("").is_err());
+}
+
+#[test]
+fn codes_patch_prg_reads() {
+    let mut prg = vec![0xEA; 0x8000];
+    prg[0x11D9] = 0xCE;
+    prg[0x2CB3] = 0x01;
+    let mut nes = nes_with_prg(prg);
+
+    assert!(nes.add_game_genie_code("SXIOPB").is_err());
+    assert!(nes.game_genie_codes().is_empty());
+
+    nes.add_game_genie_code("SXIOPO").unwrap();
+    assert_eq!(nes.cpu_read_mem(0x91D9), 0xAD);
+    assert_eq!(nes.cpu_read_mem(0x91DA), 0xEA);
+
+    // The compare value doesn't match, so the byte is left alone
+    nes.add_game_genie_code("YEUZUGAA").unwrap();
+    assert_eq!(nes.cpu_read_mem(0xACB3), 0x01);
+
+    assert!(nes.remove_game_genie_code(2).is_none());
+    assert!(nes.remove_game_genie_code(0).is_some());
+    assert_eq!(nes.cpu_read_mem(0x91D9), 0xCE);
+    assert_eq!(nes.game_genie_codes().len(), 1);
+}
@@ -0,0 +1,618 @@
+use nessu_lib::cartridge::{Cartridge, CartridgeInfo};
+use nessu_lib::mapper::{MapperKind, Mirroring};
+use nessu_lib::nes::Nes;
+use nessu_lib::region::Region;
+
+/// Build an iNES image where the first byte of every PRG and CHR bank holds its bank number.
+fn rom(
+    mapper: u8,
+    prg_banks_16k: u8,
+    chr_banks_8k: u8,
+    prg_bank_size: usize,
+    chr_bank_size: usize,
+) -> Vec<u8> {
+    let mut bytes = b"NES\x1A".to_vec();
+    bytes.extend([
+        prg_banks_16k,
+        chr_banks_8k,
+        (mapper & 0x0F) << 4,
+        mapper & 0xF0,
+    ]);
+    bytes.resize(16, 0);
+
+    let mut prg = vec![0xEA; prg_banks_16k as usize * 0x4000];
+    for (bank, chunk) in prg.chunks_mut(prg_bank_size).enumerate() {
+        chunk[0] = bank as u8;
+    }
+
+    let mut chr = vec![0; chr_banks_8k as usize * 0x2000];
+    for (bank, chunk) in chr.chunks_mut(chr_bank_size).enumerate() {
+        chunk[0] = bank as u8;
+    }
+
+    bytes.extend(prg);
+    bytes.extend(chr);
+    bytes
+}
+
+fn nes(rom: &[u8]) -> Nes {
+    let mut nes = Nes::new();
+    nes.insert_cartridge(Cartridge::from_bytes(rom).unwrap());
+    nes
+}
+
+#[test]
+fn cartridge_info() {
+    let mut rom = rom(4, 8, 16, 0x2000, 0x0400);
+    // Battery and vertical mirroring
+    rom[6] |= 0b11;
+
+    let cart = Cartridge::from_bytes(&rom).unwrap();
+    assert_eq!(
+        cart.info(),
+        CartridgeInfo {
+            mapper: MapperKind::MMC3,
+            submapper: 0,
+            prg_rom_kb: 128,
+            chr_rom_kb: 128,
+            prg_ram_kb: 8,
+            mirroring: Mirroring::Vertical,
+            battery: true,
+            region: Region::Ntsc,
+        }
+    );
+}
+
+#[test]
+fn gxrom_switches_prg_and_chr_banks() {
+    let mut nes = nes(&rom(66, 8, 4, 0x8000, 0x2000));
+
+    assert_eq!(nes.cpu_read_mem(0x8000), 0);
+    assert_eq!(nes.ppu_read_mem(0x0000), 0);
+
+    nes.cpu_write_mem(0x8000, 0x21);
+    assert_eq!(nes.cpu_read_mem(0x8000), 2);
+    assert_eq!(nes.ppu_read_mem(0x0000), 1);
+
+    nes.cpu_write_mem(0xFFFF, 0x13);
+    assert_eq!(nes.cpu_read_mem(0x8000), 1);
+    assert_eq!(nes.ppu_read_mem(0x0000), 3);
+}
+
+#[test]
+fn color_dreams_switches_prg_and_chr_banks() {
+    let mut nes = nes(&rom(11, 8, 16, 0x8000, 0x2000));
+
+    assert_eq!(nes.cpu_read_mem(0x8000), 0);
+    assert_eq!(nes.ppu_read_mem(0x0000), 0);
+
+    // PRG bank in bits 0-1 and CHR bank in bits 4-7
+    nes.cpu_write_mem(0x8000, 0x52);
+    assert_eq!(nes.cpu_read_mem(0x8000), 2);
+    assert_eq!(nes.ppu_read_mem(0x0000), 5);
+
+    nes.cpu_write_mem(0xFFFF, 0xF3);
+    assert_eq!(nes.cpu_read_mem(0x8000), 3);
+    assert_eq!(nes.ppu_read_mem(0x0000), 15);
+}
+
+#[test]
+fn mmc3_prg_ram_protect() {
+    let mut nes = nes(&rom(4, 4, 1, 0x2000, 0x0400));
+
+    // Enabled and writable
+    nes.cpu_write_mem(0xA001, 0x80);
+    nes.cpu_write_mem(0x6000, 0x42);
+    assert_eq!(nes.cpu_read_mem(0x6000), 0x42);
+
+    // Read-only
+    nes.cpu_write_mem(0xA001, 0xC0);
+    nes.cpu_write_mem(0x6000, 0x43);
+    assert_eq!(nes.cpu_read_mem(0x6000), 0x42);
+
+    // Disabled, reads return the last value on the bus
+    nes.cpu_write_mem(0xA001, 0x00);
+    assert_eq!(nes.cpu_read_mem(0x6000), 0x00);
+    nes.cpu_write_mem(0x6000, 0x44);
+
+    nes.cpu_write_mem(0xA001, 0x80);
+    assert_eq!(nes.cpu_read_mem(0x6000), 0x42);
+}
+
+/// Write a value to an MMC1 register through the serial port, one bit per write.
+fn mmc1_write(nes: &mut Nes, addr: u16, val: u8) {
+    for bit in 0..5 {
+        nes.cpu_write_mem(addr, val >> bit);
+    }
+}
+
+#[test]
+fn mmc1_surom_selects_prg_half_with_chr_bank() {
+    // 512KB PRG-ROM and CHR-RAM
+    let mut nes = nes(&rom(1, 32, 0, 0x4000, 0x2000));
+
+    assert_eq!(nes.cpu_read_mem(0x8000), 0);
+    assert_eq!(nes.cpu_read_mem(0xC000), 15);
+
+    mmc1_write(&mut nes, 0xA000, 0x10);
+    assert_eq!(nes.cpu_read_mem(0x8000), 16);
+    assert_eq!(nes.cpu_read_mem(0xC000), 31);
+
+    mmc1_write(&mut nes, 0xE000, 5);
+    assert_eq!(nes.cpu_read_mem(0x8000), 21);
+
+    mmc1_write(&mut nes, 0xA000, 0x00);
+    assert_eq!(nes.cpu_read_mem(0x8000), 5);
+    assert_eq!(nes.cpu_read_mem(0xC000), 15);
+}
+
+#[test]
+fn mmc1_sxrom_prg_ram_banks() {
+    // 32KB of PRG-RAM
+    let mut rom = rom(1, 32, 0, 0x4000, 0x2000);
+    rom[8] = 4;
+    let mut nes = nes(&rom);
+
+    for bank in 0..4 {
+        mmc1_write(&mut nes, 0xA000, bank << 2);
+        nes.cpu_write_mem(0x6000, bank + 1);
+    }
+
+    for bank in 0..4 {
+        mmc1_write(&mut nes, 0xA000, bank << 2);
+        assert_eq!(nes.cpu_read_mem(0x6000), bank + 1);
+    }
+}
+
+/// Make an NES 2.0 header with battery-backed PRG-RAM of `64 << shift` bytes.
+fn with_nes2_battery_ram(rom: &mut [u8], shift: u8) {
+    rom[6] |= 0b10;
+    rom[7] |= 0x08;
+    rom[10] = shift << 4;
+}
+
+#[test]
+fn prg_ram_size_from_nes2_header() {
+    // 32KB of PRG-RAM on MMC3, which doesn't bank it
+    let mut mmc3 = rom(4, 4, 1, 0x2000, 0x0400);
+    with_nes2_battery_ram(&mut mmc3, 9);
+
+    let cart = Cartridge::from_bytes(&mmc3).unwrap();
+    assert_eq!(cart.info().prg_ram_kb, 32);
+    assert_eq!(cart.save_ram().unwrap().len(), 0x8000);
+
+    let mut nes = nes(&mmc3);
+    nes.cpu_write_mem(0xA001, 0x80);
+    nes.cpu_write_mem(0x6000, 0x42);
+    nes.cpu_write_mem(0x7FFF, 0x43);
+    assert_eq!(nes.cpu_read_mem(0x6000), 0x42);
+    assert_eq!(nes.cpu_read_mem(0x7FFF), 0x43);
+
+    // 2KB is mirrored across $6000-$7FFF
+    let mut nrom = rom(0, 2, 1, 0x4000, 0x2000);
+    with_nes2_battery_ram(&mut nrom, 5);
+    nes.insert_cartridge(Cartridge::from_bytes(&nrom).unwrap());
+
+    nes.cpu_write_mem(0x6000, 0x44);
+    assert_eq!(nes.cpu_read_mem(0x6800), 0x44);
+    assert_eq!(nes.cpu_read_mem(0x7800), 0x44);
+}
+
+#[test]
+fn prg_ram_size_from_ines_header() {
+    let mut rom = rom(4, 4, 1, 0x2000, 0x0400);
+    rom[8] = 2;
+    assert_eq!(Cartridge::from_bytes(&rom).unwrap().info().prg_ram_kb, 16);
+
+    // Byte 8 is ignored when the unused bytes hold garbage
+    rom[7..16].copy_from_slice(b"DiskDude!");
+    rom[7] = 0;
+    assert_eq!(Cartridge::from_bytes(&rom).unwrap().info().prg_ram_kb, 8);
+}
+
+/// Mappers with both CHR-RAM and CHR-ROM boards: NROM, MMC1, UxROM, CNROM, MMC3, MMC5, AxROM,
+/// MMC4, Color Dreams, VRC4 and GxROM
+const CHR_MAPPERS: [u8; 11] = [0, 1, 2, 3, 4, 5, 7, 10, 11, 21, 66];
+
+#[test]
+fn chr_ram_is_writable() {
+    for mapper in CHR_MAPPERS {
+        let mut nes = nes(&rom(mapper, 8, 0, 0x2000, 0x0400));
+
+        nes.ppu_write_mem(0x0001, 0x42);
+        nes.ppu_write_mem(0x1FFF, 0x43);
+        assert_eq!(nes.ppu_read_mem(0x0001), 0x42, "mapper {}", mapper);
+        assert_eq!(nes.ppu_read_mem(0x1FFF), 0x43, "mapper {}", mapper);
+    }
+}
+
+#[test]
+fn chr_rom_ignores_writes() {
+    for mapper in CHR_MAPPERS {
+        let mut nes = nes(&rom(mapper, 8, 2, 0x2000, 0x0400));
+
+        nes.ppu_write_mem(0x0000, 0x42);
+        nes.ppu_write_mem(0x0001, 0x43);
+        assert_eq!(nes.ppu_read_mem(0x0000), 0, "mapper {}", mapper);
+        assert_eq!(nes.ppu_read_mem(0x0001), 0, "mapper {}", mapper);
+    }
+}
+
+#[test]
+fn chr_ram_size_from_nes2_header() {
+    // 32KB of CHR-RAM on MMC3
+    let mut rom = rom(4, 8, 0, 0x2000, 0x0400);
+    rom[7] |= 0x08;
+    rom[11] = 9;
+
+    let cart = Cartridge::from_bytes(&rom).unwrap();
+    assert_eq!(cart.header().chr_ram_size, 0x8000);
+
+    let mut nes = nes(&rom);
+
+    // 1KB banks 0 and 31 at $1000 and $1400
+    nes.cpu_write_mem(0x8000, 2);
+    nes.cpu_write_mem(0x8001, 0);
+    nes.cpu_write_mem(0x8000, 3);
+    nes.cpu_write_mem(0x8001, 31);
+
+    nes.ppu_write_mem(0x1000, 0x42);
+    nes.ppu_write_mem(0x1400, 0x43);
+    assert_eq!(nes.ppu_read_mem(0x1000), 0x42);
+    assert_eq!(nes.ppu_read_mem(0x1400), 0x43);
+
+    // Bank 32 wraps around to bank 0
+    nes.cpu_write_mem(0x8001, 32);
+    assert_eq!(nes.ppu_read_mem(0x1400), 0x42);
+}
+
+/// Offset of the byte in a UxROM image that is mapped to `addr` in the fixed last bank.
+fn uxrom_fixed_bank_offset(rom: &[u8], addr: u16) -> usize {
+    rom.len() - 0x4000 + (addr as usize - 0xC000)
+}
+
+#[test]
+fn uxrom_switches_all_banks_of_256k_prg() {
+    let mut rom = rom(2, 16, 0, 0x4000, 0x2000);
+
+    // Bank numbers in ROM at $D000 to avoid bus conflicts
+    for bank in 0..32 {
+        let offset = uxrom_fixed_bank_offset(&rom, 0xD000 + bank);
+        rom[offset] = bank as u8;
+    }
+
+    let mut nes = nes(&rom);
+
+    for bank in 0..16 {
+        nes.cpu_write_mem(0xD000 + bank as u16, bank);
+        assert_eq!(nes.cpu_read_mem(0x8000), bank);
+        assert_eq!(nes.cpu_read_mem(0xC000), 15);
+    }
+
+    // Bank numbers past the end wrap around
+    nes.cpu_write_mem(0xD000 + 17, 17);
+    assert_eq!(nes.cpu_read_mem(0x8000), 1);
+}
+
+#[test]
+fn uxrom_bus_conflicts() {
+    let mut rom = rom(2, 8, 0, 0x4000, 0x2000);
+    let offset = uxrom_fixed_bank_offset(&rom, 0xD000);
+    rom[offset] = 0x05;
+
+    let mut nes = nes(&rom);
+
+    // The value is ANDed with the ROM byte at the written address
+    nes.cpu_write_mem(0xD000, 0x07);
+    assert_eq!(nes.cpu_read_mem(0x8000), 5);
+
+    // NES 2.0 submapper 1 has no bus conflicts
+    rom[7] |= 0x08;
+    rom[8] = 0x10;
+    nes = self::nes(&rom);
+
+    nes.cpu_write_mem(0xD000, 0x07);
+    assert_eq!(nes.cpu_read_mem(0x8000), 7);
+}
+
+#[test]
+fn cnrom_switches_chr_banks_with_bus_conflicts() {
+    let mut rom = rom(3, 2, 4, 0x4000, 0x2000);
+    // $FFF0-$FFF3 holds the bank numbers
+    for bank in 0..4 {
+        let offset = 16 + 0x8000 - 0x10 + bank;
+        rom[offset] = bank as u8;
+    }
+
+    let mut nes = nes(&rom);
+    assert_eq!(nes.ppu_read_mem(0x0000), 0);
+
+    nes.cpu_write_mem(0xFFF2, 0x02);
+    assert_eq!(nes.ppu_read_mem(0x0000), 2);
+
+    nes.cpu_write_mem(0xFFF1, 0x03);
+    assert_eq!(nes.ppu_read_mem(0x0000), 1);
+}
+
+/// An MMC3 NES 2.0 image with the given submapper.
+fn mmc3_rom(submapper: u8) -> Vec<u8> {
+    let mut rom = rom(4, 4, 1, 0x2000, 0x0400);
+    rom[7] |= 0x08;
+    rom[8] = submapper << 4;
+    rom
+}
+
+/// Point the PPU address at `addr` and let `dots` PPU cycles pass with rendering disabled.
+fn set_ppu_addr(nes: &mut Nes, addr: u16, dots: usize) {
+    let ppu = nes.ppu_mut();
+    ppu.write_ppu_addr((addr >> 8) as u8);
+    ppu.write_ppu_addr(addr as u8);
+
+    for _ in 0..dots {
+        ppu.clock();
+    }
+}
+
+/// Raise PPU A12 after holding it low long enough for the MMC3 to see the rising edge.
+fn clock_a12(nes: &mut Nes) {
+    set_ppu_addr(nes, 0x0000, 10);
+    set_ppu_addr(nes, 0x1000, 0);
+}
+
+/// Whether an IRQ fires on each of `clocks` A12 rising edges after reloading the counter.
+fn mmc3_irqs(submapper: u8, latch: u8, clocks: usize) -> Vec<bool> {
+    let mut nes = nes(&mmc3_rom(submapper));
+    nes.cpu_write_mem(0xC000, latch);
+    nes.cpu_write_mem(0xC001, 0);
+    nes.cpu_write_mem(0xE001, 0);
+
+    (0..clocks)
+        .map(|_| {
+            clock_a12(&mut nes);
+            nes.cartridge_mut().irq_triggered()
+        })
+        .collect()
+}
+
+#[test]
+fn mmc3_irq_counter() {
+    for submapper in [0, 1, 4] {
+        assert_eq!(
+            mmc3_irqs(submapper, 2, 6),
+            [false, false, true, false, false, true]
+        );
+    }
+
+    // With a latch of 0 the new behavior fires on every clock
+    assert_eq!(mmc3_irqs(0, 0, 3), [true, true, true]);
+
+    // MMC6 and MMC3A only fire on the clock that follows the $C001 write
+    for submapper in [1, 4] {
+        assert_eq!(mmc3_irqs(submapper, 0, 3), [true, false, false]);
+    }
+}
+
+#[test]
+fn mmc3_a12_filter() {
+    let mut nes = nes(&rom(4, 4, 1, 0x2000, 0x0400));
+    nes.cpu_write_mem(0xC000, 0);
+    nes.cpu_write_mem(0xE001, 0);
+
+    // A single access that leaves A12 low for a long time still lets the next rise through
+    set_ppu_addr(&mut nes, 0x0000, 100);
+    set_ppu_addr(&mut nes, 0x1000, 0);
+    assert!(nes.cartridge_mut().irq_triggered());
+
+    // Rises after short low periods are filtered out no matter how many accesses there are
+    for _ in 0..16 {
+        set_ppu_addr(&mut nes, 0x0000, 0);
+        set_ppu_addr(&mut nes, 0x0400, 2);
+        set_ppu_addr(&mut nes, 0x1000, 0);
+    }
+    assert!(!nes.cartridge_mut().irq_triggered());
+
+    set_ppu_addr(&mut nes, 0x0000, 9);
+    set_ppu_addr(&mut nes, 0x1000, 0);
+    assert!(!nes.cartridge_mut().irq_triggered());
+
+    set_ppu_addr(&mut nes, 0x0000, 10);
+    set_ppu_addr(&mut nes, 0x1000, 0);
+    assert!(nes.cartridge_mut().irq_triggered());
+}
+
+/// Scanlines on which the MMC3 IRQ fires during one rendered frame, with the counter
+/// reloaded from `latch` on the pre-render scanline.
+fn mmc3_irq_scanlines(ppu_ctrl: u8, latch: u8) -> Vec<u16> {
+    let mut nes = nes(&rom(4, 4, 1, 0x2000, 0x0400));
+    nes.ppu_mut().write_ppu_ctrl(ppu_ctrl);
+    nes.ppu_mut().write_ppu_mask(0x18);
+
+    while nes.ppu().current_scanline() != 261 {
+        nes.ppu_mut().clock();
+    }
+
+    nes.cpu_write_mem(0xC000, latch);
+    nes.cpu_write_mem(0xC001, 0);
+    nes.cpu_write_mem(0xE001, 0);
+
+    let mut scanlines = vec![];
+    while nes.ppu().current_scanline() != 240 {
+        nes.ppu_mut().clock();
+        if nes.cartridge_mut().irq_triggered() {
+            scanlines.push(nes.ppu().current_scanline());
+        }
+    }
+    scanlines
+}
+
+#[test]
+fn mmc3_irq_is_clocked_by_rendering() {
+    // Background from $0000 and sprites from $1000, the counter is reloaded after each IRQ
+    assert_eq!(
+        mmc3_irq_scanlines(0x08, 10),
+        (9..240).step_by(11).collect::<Vec<_>>()
+    );
+    assert_eq!(mmc3_irq_scanlines(0x08, 100), [99, 200]);
+
+    // A12 never rises when both use the same pattern table
+    assert_eq!(mmc3_irq_scanlines(0x00, 10), []);
+}
+
+#[test]
+fn mmc4_latches_ignore_sprites_beyond_the_limit() {
+    let mut nes = nes(&rom(10, 8, 4, 0x4000, 0x1000));
+    nes.cpu_write_mem(0xB000, 1);
+    nes.cpu_write_mem(0xC000, 2);
+
+    // Reading tile $FE switches to the $FE bank
+    nes.ppu_read_mem(0x0FE8);
+    assert_eq!(nes.ppu_read_mem(0x0000), 2);
+
+    // Only the ninth sprite on the scanlines uses tile $FD, and the hardware never fetches it
+    nes.ppu_mut().sprite_limit_disabled = true;
+    let oam = &mut nes.ppu_mut().primary_oam;
+    oam.fill(0xFF);
+    for i in 0..9 {
+        oam[i * 4..][..4].copy_from_slice(&[49, 0, 0, i as u8 * 8]);
+    }
+    oam[8 * 4 + 1] = 0xFD;
+
+    nes.ppu_mut().write_ppu_mask(0x18);
+    for scanline in [261, 240] {
+        while nes.ppu().current_scanline() != scanline {
+            nes.ppu_mut().clock();
+        }
+    }
+
+    assert_eq!(nes.ppu_read_mem(0x0000), 2);
+}
+
+#[test]
+fn mmc4_latches_ignore_debug_views() {
+    let mut nes = nes(&rom(10, 8, 4, 0x4000, 0x1000));
+    nes.cpu_write_mem(0xB000, 1);
+    nes.cpu_write_mem(0xC000, 2);
+    assert_eq!(nes.ppu_read_mem(0x0000), 1);
+
+    // Both views read tile $FE of pattern table 0, which would switch to the $FE bank
+    nes.ppu_write_mem(0x2000, 0xFE);
+    nes.pattern_table_rgb_bytes(0, 0);
+    nes.nametable_rgb_bytes(0);
+    assert_eq!(nes.ppu_read_mem(0x0000), 1);
+}
+
+#[test]
+fn mmc5_prg_bank_modes() {
+    // 256KB of PRG-ROM in 8KB banks
+    let mut nes = nes(&rom(5, 16, 1, 0x2000, 0x0400));
+    let banks = |nes: &mut Nes| [0x8000, 0xA000, 0xC000, 0xE000].map(|addr| nes.cpu_read_mem(addr));
+
+    // Mode 3 at power on, with the last bank at $E000
+    assert_eq!(banks(&mut nes)[3], 31);
+
+    nes.cpu_write_mem(0x5114, 0x81);
+    nes.cpu_write_mem(0x5115, 0x83);
+    nes.cpu_write_mem(0x5116, 0x85);
+    nes.cpu_write_mem(0x5117, 0x07);
+    assert_eq!(banks(&mut nes), [1, 3, 5, 7]);
+
+    // 16KB banks at $8000 and $C000, ignoring the lowest bit
+    nes.cpu_write_mem(0x5100, 1);
+    assert_eq!(banks(&mut nes), [2, 3, 6, 7]);
+
+    // 16KB at $8000 and 8KB banks at $C000 and $E000
+    nes.cpu_write_mem(0x5100, 2);
+    assert_eq!(banks(&mut nes), [2, 3, 5, 7]);
+
+    // One 32KB bank from $5117
+    nes.cpu_write_mem(0x5100, 0);
+    assert_eq!(banks(&mut nes), [4, 5, 6, 7]);
+}
+
+#[test]
+fn mmc5_prg_ram() {
+    let mut nes = nes(&rom(5, 16, 1, 0x2000, 0x0400));
+
+    // Writes are ignored until both protect registers are set
+    nes.cpu_write_mem(0x5113, 0);
+    nes.cpu_write_mem(0x6000, 0x42);
+    nes.cpu_write_mem(0x5102, 0b10);
+    nes.cpu_write_mem(0x5103, 0b01);
+    nes.cpu_write_mem(0x6000, 0x43);
+    assert_eq!(nes.cpu_read_mem(0x6000), 0x43);
+
+    // RAM mapped to $8000 in mode 3
+    nes.cpu_write_mem(0x5114, 0x00);
+    assert_eq!(nes.cpu_read_mem(0x8000), 0x43);
+    nes.cpu_write_mem(0x8001, 0x44);
+    assert_eq!(nes.cpu_read_mem(0x6001), 0x44);
+}
+
+#[test]
+fn mmc5_chr_banks_and_fill_mode() {
+    let mut nes = nes(&rom(5, 2, 4, 0x2000, 0x0400));
+
+    // 1KB banks
+    nes.cpu_write_mem(0x5101, 3);
+    nes.cpu_write_mem(0x5120, 5);
+    nes.cpu_write_mem(0x5127, 9);
+    assert_eq!(nes.ppu_read_mem(0x0000), 5);
+    assert_eq!(nes.ppu_read_mem(0x1C00), 9);
+
+    // The background banks were written last and repeat in both pattern tables
+    nes.cpu_write_mem(0x5128, 12);
+    assert_eq!(nes.ppu_read_mem(0x0000), 12);
+    assert_eq!(nes.ppu_read_mem(0x1000), 12);
+
+    // The first nametable from CIRAM and the second filled
+    nes.cpu_write_mem(0x5105, 0b1100);
+    nes.cpu_write_mem(0x5106, 0x21);
+    nes.cpu_write_mem(0x5107, 2);
+    nes.ppu_write_mem(0x2000, 0x11);
+    assert_eq!(nes.ppu_read_mem(0x2000), 0x11);
+    assert_eq!(nes.ppu_read_mem(0x2400), 0x21);
+    assert_eq!(nes.ppu_read_mem(0x27C0), 0xAA);
+}
+
+#[test]
+fn mmc5_scanline_irq() {
+    let mut nes = nes(&rom(5, 2, 1, 0x2000, 0x0400));
+
+    while nes.ppu().current_scanline() != 261 {
+        nes.ppu_mut().clock();
+    }
+
+    nes.cpu_write_mem(0x2001, 0x18);
+    nes.cpu_write_mem(0x5203, 100);
+    nes.cpu_write_mem(0x5204, 0x80);
+
+    let mut scanlines = vec![];
+    while nes.ppu().current_scanline() != 240 {
+        nes.ppu_mut().clock();
+        if nes.cartridge_mut().irq_triggered() {
+            scanlines.push(nes.ppu().current_scanline());
+            // In frame, and reading the status acknowledges the IRQ
+            assert_eq!(nes.cpu_read_mem(0x5204), 0xC0);
+        }
+    }
+
+    // Scanline 100 is detected from the nametable reads at the end of scanline 99
+    assert_eq!(scanlines, [99]);
+    assert_eq!(nes.cpu_read_mem(0x5204), 0x40);
+}
+
+#[test]
+fn mmc5_multiplier() {
+    let mut nes = nes(&rom(5, 2, 1, 0x2000, 0x0400));
+    let product =
+        |nes: &mut Nes| u16::from_le_bytes([nes.cpu_read_mem(0x5205), nes.cpu_read_mem(0x5206)]);
+
+    assert_eq!(product(&mut nes), 0xFF * 0xFF);
+
+    nes.cpu_write_mem(0x5205, 12);
+    nes.cpu_write_mem(0x5206, 34);
+    assert_eq!(product(&mut nes), 408);
+
+    nes.cpu_write_mem(0x5206, 0);
+    assert_eq!(product(&mut nes), 0);
+}
@@ -0,0 +1,163 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use nessu_lib::cartridge::Cartridge;
+use nessu_lib::input::Button;
+use nessu_lib::movie::{parse_movie, write_movie, FrameInput};
+use nessu_lib::nes::Nes;
+
+const ROM: &[u8] = include_bytes!("../../../roms/snow.nes");
+
+fn nes() -> Nes {
+    let mut nes = Nes::new();
+    nes.insert_cartridge(Cartridge::from_bytes(ROM).unwrap());
+    nes
+}
+
+fn framebuffer_hash(nes: &Nes) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    nes.framebuffer().hash(&mut hasher);
+    hasher.finish()
+}
+
+#[test]
+fn recorded_movie_replays_identically() {
+    let mut nes = nes();
+    nes.start_recording();
+
+    for frame in 0..60 {
+        nes.set_button_state_player1(Button::Right, frame % 2 == 0);
+        nes.set_button_state_player1(Button::A, frame % 10 < 5);
+        nes.set_button_state_player2(Button::Start, frame == 30);
+        nes.step_frame().unwrap();
+    }
+
+    let frames = nes.stop_recording();
+    let recorded_hash = framebuffer_hash(&nes);
+    assert_eq!(frames.len(), 60);
+
+    let frames = parse_movie(&write_movie(&frames)).unwrap();
+
+    let mut nes = self::nes();
+    nes.play_movie(frames);
+    for _ in 0..60 {
+        nes.step_frame().unwrap();
+    }
+
+    assert_eq!(framebuffer_hash(&nes), recorded_hash);
+}
+
+#[test]
+fn reset_in_movie_replays_identically() {
+    let mut nes = nes();
+    nes.start_recording();
+
+    for frame in 0..60 {
+        if frame == 25 {
+            nes.reset_at_next_frame();
+        }
+        nes.set_button_state_player1(Button::Start, frame % 20 < 2);
+        nes.step_frame().unwrap();
+    }
+
+    let frames = nes.stop_recording();
+    let recorded_hash = framebuffer_hash(&nes);
+    let recorded_ram = nes.ram_snapshot();
+
+    let resets: Vec<usize> = (0..frames.len()).filter(|&i| frames[i].reset).collect();
+    assert_eq!(resets, [25]);
+
+    let mut nes = self::nes();
+    nes.play_movie(parse_movie(&write_movie(&frames)).unwrap());
+    for _ in 0..60 {
+        nes.step_frame().unwrap();
+    }
+
+    assert_eq!(framebuffer_hash(&nes), recorded_hash);
+    assert_eq!(nes.ram_snapshot(), recorded_ram);
+
+    // Without the reset, the movie ends up somewhere else
+    let mut nes = self::nes();
+    let mut frames = frames;
+    frames[25].reset = false;
+    nes.play_movie(frames);
+    for _ in 0..60 {
+        nes.step_frame().unwrap();
+    }
+
+    assert_ne!(nes.ram_snapshot(), recorded_ram);
+}
+
+#[test]
+fn save_ram_survives_movies() {
+    let mut rom = ROM.to_vec();
+    rom[6] |= 0x02;
+
+    let save: Vec<u8> = (0..0x2000).map(|i| i as u8).collect();
+    let mut cart = Cartridge::from_bytes(&rom).unwrap();
+    assert!(cart.load_ram(&save));
+
+    let mut nes = Nes::new();
+    nes.insert_cartridge(cart);
+
+    nes.start_recording();
+    for _ in 0..10 {
+        nes.step_frame().unwrap();
+    }
+
+    // The movie runs from zeroed RAM, but the save is what gets written to disk
+    assert!(nes.cartridge().save_ram().unwrap().iter().all(|&b| b == 0));
+    assert_eq!(nes.save_ram(), Some(save.as_slice()));
+
+    let frames = nes.stop_recording();
+    assert_eq!(nes.cartridge().save_ram(), Some(save.as_slice()));
+
+    // Playback that runs to the end of the movie
+    nes.play_movie(frames.clone());
+    nes.cpu_write_mem(0x6000, 0xFF);
+    assert_eq!(nes.save_ram(), Some(save.as_slice()));
+    for _ in 0..=frames.len() {
+        nes.step_frame().unwrap();
+    }
+    assert!(!nes.is_playing_movie());
+    assert_eq!(nes.cartridge().save_ram(), Some(save.as_slice()));
+
+    // Playback that is stopped early
+    nes.play_movie(frames);
+    nes.cpu_write_mem(0x6000, 0xFF);
+    nes.stop_movie();
+    assert_eq!(nes.cartridge().save_ram(), Some(save.as_slice()));
+}
+
+#[test]
+fn movie_text_format() {
+    let frames = vec![
+        FrameInput {
+            player1: Button::Right as u8 | Button::A as u8,
+            player2: 0,
+            reset: false,
+        },
+        FrameInput {
+            player1: 0,
+            player2: Button::Start as u8,
+            reset: true,
+        },
+    ];
+
+    let text = write_movie(&frames);
+    assert_eq!(text, "|0|R......A|........||\n|1|........|....T...||\n");
+    assert_eq!(parse_movie(&text).unwrap(), frames);
+
+    // FM2 header lines and unconnected ports
+    assert_eq!(
+        parse_movie("version 3\n|0|.L......||||\n").unwrap(),
+        vec![FrameInput {
+            player1: Button::Left as u8,
+            player2: 0,
+            reset: false,
+        }]
+    );
+
+    assert!(parse_movie("|0|RL|\n").is_err());
+    assert!(parse_movie("|x|........|........||\n").is_err());
+}
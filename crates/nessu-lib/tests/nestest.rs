@@ -0,0 +1,137 @@
+//! Runs nestest in its automated mode and compares the CPU state before each instruction
+//! against the golden log.
+//!
+//! Expects `nestest.nes` and `nestest.log` in `tests/roms`, which are not bundled, so the test
+//! is ignored by default. Run it with `cargo test --test nestest -- --ignored`. Set
+//! `NESTEST_DOCUMENTED_ONLY` to stop before the undocumented opcode tests.
+
+use std::fs::{read, read_to_string};
+use std::path::PathBuf;
+
+use nessu_lib::cartridge::Cartridge;
+use nessu_lib::nes::Nes;
+
+/// Entry point of the automated mode, which runs without a PPU
+const AUTOMATED_START_ADDR: u16 = 0xC000;
+
+/// Address where the tests of the undocumented opcodes start
+const UNDOCUMENTED_OPCODES_START_ADDR: u16 = 0xC6BD;
+
+/// Break flag, which only exists in the copies of P pushed on the stack
+const B: u8 = 0b0001_0000;
+
+#[derive(Debug, PartialEq)]
+struct State {
+    pc: u16,
+    a: u8,
+    x: u8,
+    y: u8,
+    p: u8,
+    s: u8,
+    cycles: i128,
+}
+
+impl State {
+    fn parse(line: &str) -> Option<Self> {
+        let field = |name: &str| {
+            line.get(48..)?
+                .split_whitespace()
+                .find_map(|token| token.strip_prefix(name))
+        };
+        let hex = |name: &str| field(name).and_then(|val| u8::from_str_radix(val, 16).ok());
+
+        Some(Self {
+            pc: u16::from_str_radix(line.get(0..4)?, 16).ok()?,
+            a: hex("A:")?,
+            x: hex("X:")?,
+            y: hex("Y:")?,
+            p: hex("P:")? & !B,
+            s: hex("SP:")?,
+            cycles: field("CYC:")?.parse().ok()?,
+        })
+    }
+
+    fn current(nes: &Nes, cycle_offset: i128) -> Self {
+        let cpu = nes.cpu();
+
+        Self {
+            pc: cpu.pc,
+            a: cpu.a,
+            x: cpu.x,
+            y: cpu.y,
+            p: cpu.p & !B,
+            s: cpu.s,
+            cycles: cpu.cycles as i128 + cycle_offset,
+        }
+    }
+}
+
+fn rom_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("roms")
+        .join(name)
+}
+
+#[test]
+#[ignore = "needs nestest.nes and nestest.log in tests/roms"]
+fn nestest() {
+    let rom = read(rom_path("nestest.nes")).expect("nestest.nes not found in tests/roms");
+    let log = read_to_string(rom_path("nestest.log")).expect("nestest.log not found in tests/roms");
+
+    let mut nes = Nes::new();
+    nes.insert_cartridge(Cartridge::from_bytes(&rom).expect("failed to load nestest.nes"));
+    nes.cpu_mut().pc = AUTOMATED_START_ADDR;
+
+    let documented_only = std::env::var_os("NESTEST_DOCUMENTED_ONLY").is_some();
+
+    // The log starts after the reset sequence, so only the elapsed cycles are compared
+    let mut cycle_offset = None;
+
+    for (line_idx, line) in log.lines().enumerate() {
+        let expected = State::parse(line)
+            .unwrap_or_else(|| panic!("invalid line {} in nestest.log", line_idx + 1));
+
+        if documented_only && expected.pc == UNDOCUMENTED_OPCODES_START_ADDR {
+            break;
+        }
+
+        let cycle_offset =
+            *cycle_offset.get_or_insert_with(|| expected.cycles - nes.cpu().cycles as i128);
+        let actual = State::current(&nes, cycle_offset);
+
+        if actual != expected {
+            let section = if actual.pc >= UNDOCUMENTED_OPCODES_START_ADDR {
+                "undocumented"
+            } else {
+                "documented"
+            };
+
+            panic!(
+                "Mismatch on line {} ({} opcodes)\n{}\nexpected: {:X?}\nactual:   {:X?}",
+                line_idx + 1,
+                section,
+                line,
+                expected,
+                actual
+            );
+        }
+
+        nes.step_instruction()
+            .unwrap_or_else(|e| panic!("line {}: {}", line_idx + 1, e));
+    }
+
+    // Result codes of the documented and undocumented opcode tests, zero on success
+    assert_eq!(
+        nes.cpu_read_mem(0x0002),
+        0x00,
+        "documented opcode tests failed"
+    );
+    if !documented_only {
+        assert_eq!(
+            nes.cpu_read_mem(0x0003),
+            0x00,
+            "undocumented opcode tests failed"
+        );
+    }
+}
@@ -0,0 +1,621 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use nessu_lib::cartridge::Cartridge;
+use nessu_lib::nes::Nes;
+use nessu_lib::ppu::Ppu;
+use nessu_lib::region::Region;
+
+/// An NROM image that loops forever at $8000
+fn idle_rom() -> Vec<u8> {
+    let mut bytes = b"NES\x1A".to_vec();
+    bytes.extend([2, 1]);
+    bytes.resize(16, 0);
+
+    let mut prg = vec![0xEA; 0x8000];
+    // JMP $8000
+    prg[..3].copy_from_slice(&[0x4C, 0x00, 0x80]);
+    prg[0x7FFC] = 0x00;
+    prg[0x7FFD] = 0x80;
+
+    bytes.extend(prg);
+    bytes.extend(vec![0; 0x2000]);
+    bytes
+}
+
+fn run_until(nes: &mut Nes, scanline: u16, min_cycle: u16) {
+    while nes.ppu().current_scanline() != scanline || nes.ppu().current_cycle() < min_cycle {
+        nes.clock().unwrap();
+    }
+}
+
+#[test]
+fn oam_data_writes_during_rendering_are_ignored() {
+    let mut nes = Nes::new();
+    nes.insert_cartridge(Cartridge::from_bytes(&idle_rom()).unwrap());
+
+    // Enable background and sprite rendering
+    nes.cpu_write_mem(0x2001, 0x18);
+    run_until(&mut nes, 10, 100);
+
+    let oam = nes.ppu().primary_oam;
+    nes.ppu_mut().oam_addr = 0x11;
+    nes.ppu_mut().write_oam_data(0x42);
+
+    assert_eq!(nes.ppu().primary_oam, oam);
+    // Only the high 6 bits are incremented
+    assert_eq!(nes.ppu().oam_addr, 0x15);
+}
+
+#[test]
+fn oam_data_reads_during_rendering_expose_secondary_oam_clear() {
+    let mut nes = Nes::new();
+    nes.insert_cartridge(Cartridge::from_bytes(&idle_rom()).unwrap());
+
+    nes.cpu_write_mem(0x2001, 0x18);
+    run_until(&mut nes, 10, 10);
+
+    assert!(nes.ppu().current_cycle() <= 64);
+    assert_eq!(nes.cpu_read_mem(0x2004), 0xFF);
+}
+
+#[test]
+fn oam_data_reads_mask_attribute_bits() {
+    let mut nes = Nes::new();
+    nes.insert_cartridge(Cartridge::from_bytes(&idle_rom()).unwrap());
+
+    nes.cpu_write_mem(0x2003, 0x00);
+    for _ in 0..4 {
+        nes.cpu_write_mem(0x2004, 0xFF);
+    }
+
+    for (addr, expected) in [(0, 0xFF), (1, 0xFF), (2, 0xE3), (3, 0xFF)] {
+        nes.cpu_write_mem(0x2003, addr);
+        assert_eq!(nes.cpu_read_mem(0x2004), expected);
+    }
+}
+
+#[test]
+fn frame_and_scanline_callbacks() {
+    let mut nes = Nes::new();
+    nes.insert_cartridge(Cartridge::from_bytes(&idle_rom()).unwrap());
+
+    let frames = Rc::new(RefCell::new(Vec::new()));
+    let scanlines = Rc::new(RefCell::new(Vec::new()));
+
+    let frames_clone = frames.clone();
+    nes.on_frame(Box::new(move |ppu| {
+        frames_clone.borrow_mut().push(ppu.current_scanline())
+    }));
+    let scanlines_clone = scanlines.clone();
+    nes.on_scanline(Box::new(move |ppu| {
+        scanlines_clone.borrow_mut().push(ppu.current_scanline())
+    }));
+
+    nes.step_frame().unwrap();
+
+    assert_eq!(*frames.borrow(), [0]);
+    assert_eq!(*scanlines.borrow(), (1..262).chain([0]).collect::<Vec<_>>());
+
+    nes.clear_callbacks();
+    nes.step_frame().unwrap();
+    assert_eq!(frames.borrow().len(), 1);
+}
+
+/// Render nine opaque sprites side by side on the same scanlines and return the pixel colors
+/// of the first and the ninth sprite.
+fn render_nine_sprites(sprite_limit_disabled: bool) -> ([u8; 4], [u8; 4]) {
+    // CHR-RAM
+    let mut rom = idle_rom();
+    rom[5] = 0;
+    rom.truncate(16 + 0x8000);
+
+    let mut nes = Nes::new();
+    nes.insert_cartridge(Cartridge::from_bytes(&rom).unwrap());
+    nes.ppu_mut().sprite_limit_disabled = sprite_limit_disabled;
+
+    // Tile 1 is filled with color 1
+    for addr in 0x0010..0x0018 {
+        nes.ppu_write_mem(addr, 0xFF);
+    }
+    nes.ppu_write_mem(0x3F00, 0x0F);
+    nes.ppu_write_mem(0x3F11, 0x16);
+
+    let oam = &mut nes.ppu_mut().primary_oam;
+    oam.fill(0xFF);
+    for i in 0..9 {
+        oam[i * 4..][..4].copy_from_slice(&[49, 1, 0, i as u8 * 8]);
+    }
+
+    nes.cpu_write_mem(0x2001, 0x1E);
+    nes.step_frame().unwrap();
+    nes.step_frame().unwrap();
+
+    let pixel = |x: usize, y: usize| {
+        let idx = (y * 256 + x) * 4;
+        nes.framebuffer()[idx..idx + 4].try_into().unwrap()
+    };
+
+    (pixel(4, 52), pixel(68, 52))
+}
+
+#[test]
+fn sprite_limit() {
+    let (first, ninth) = render_nine_sprites(false);
+    assert_ne!(first, ninth);
+
+    let (first, ninth) = render_nine_sprites(true);
+    assert_eq!(first, ninth);
+}
+
+/// Render a frame with an opaque background and an opaque sprite 0 at `x` on scanlines 50-57,
+/// and return whether the sprite zero hit flag was set.
+fn sprite_zero_hit(x: u8, ppu_mask: u8) -> bool {
+    // CHR-RAM
+    let mut rom = idle_rom();
+    rom[5] = 0;
+    rom.truncate(16 + 0x8000);
+
+    let mut nes = Nes::new();
+    nes.insert_cartridge(Cartridge::from_bytes(&rom).unwrap());
+
+    // Tile 1 is filled with color 1 and used for both the background and sprite 0
+    for addr in 0x0010..0x0018 {
+        nes.ppu_write_mem(addr, 0xFF);
+    }
+    for addr in 0x2000..0x23C0 {
+        nes.ppu_write_mem(addr, 1);
+    }
+
+    let oam = &mut nes.ppu_mut().primary_oam;
+    oam.fill(0xFF);
+    oam[..4].copy_from_slice(&[49, 1, 0, x]);
+
+    nes.cpu_write_mem(0x2001, ppu_mask);
+    nes.step_frame().unwrap();
+    run_until(&mut nes, 240, 0);
+
+    nes.ppu().ppu_status & 0x40 != 0
+}
+
+#[test]
+fn sprite_zero_hit_respects_left_clipping() {
+    assert!(sprite_zero_hit(0, 0x1E));
+    assert!(!sprite_zero_hit(0, 0x1C));
+    assert!(!sprite_zero_hit(0, 0x1A));
+    assert!(!sprite_zero_hit(0, 0x18));
+    assert!(sprite_zero_hit(8, 0x18));
+}
+
+#[test]
+fn sprite_zero_hit_never_occurs_at_x_255() {
+    assert!(sprite_zero_hit(254, 0x1E));
+    assert!(!sprite_zero_hit(255, 0x1E));
+}
+
+/// Render a frame with eight sprites on scanlines 50-57 followed by `sprite8` and `sprite9`,
+/// and return whether the sprite overflow flag was set.
+fn sprite_overflow(sprite8: [u8; 4], sprite9: [u8; 4]) -> bool {
+    let mut nes = Nes::new();
+    nes.insert_cartridge(Cartridge::from_bytes(&idle_rom()).unwrap());
+
+    let oam = &mut nes.ppu_mut().primary_oam;
+    oam.fill(0xFF);
+    for i in 0..8 {
+        oam[i * 4..][..4].copy_from_slice(&[49, 0, 0, i as u8 * 8]);
+    }
+    oam[32..36].copy_from_slice(&sprite8);
+    oam[36..40].copy_from_slice(&sprite9);
+
+    nes.cpu_write_mem(0x2001, 0x1E);
+    nes.step_frame().unwrap();
+    run_until(&mut nes, 240, 0);
+
+    nes.ppu().ppu_status & 0x20 != 0
+}
+
+#[test]
+fn sprite_overflow_evaluation_is_buggy() {
+    assert!(sprite_overflow([49, 0, 0, 64], [0xFF; 4]));
+    assert!(!sprite_overflow([0xFF; 4], [0xFF; 4]));
+
+    // After sprite 8 is out of range, the tile index of sprite 9 is read as its Y coordinate
+    assert!(sprite_overflow([0xFF; 4], [0xFF, 49, 0xFF, 0xFF]));
+    assert!(!sprite_overflow([0xFF; 4], [49, 0xFF, 0xFF, 0xFF]));
+}
+
+#[test]
+fn sprite_viewer() {
+    // CHR-RAM
+    let mut rom = idle_rom();
+    rom[5] = 0;
+    rom.truncate(16 + 0x8000);
+
+    let mut nes = Nes::new();
+    nes.insert_cartridge(Cartridge::from_bytes(&rom).unwrap());
+
+    // Top left pixel of tile 2 has color 1 and bottom right pixel of tile 3 color 2
+    nes.ppu_write_mem(0x0020, 0x80);
+    nes.ppu_write_mem(0x003F, 0x01);
+    nes.ppu_write_mem(0x3F11, 0x16);
+    nes.ppu_write_mem(0x3F12, 0x2A);
+
+    let oam = &mut nes.ppu_mut().primary_oam;
+    oam.fill(0xFF);
+    oam[5 * 4..][..4].copy_from_slice(&[49, 2, 0, 30]);
+
+    assert_eq!(nes.oam_entries()[5], (49, 2, 0, 30));
+
+    let red = nes.ppu().palette()[0x16];
+    let green = nes.ppu().palette()[0x2A];
+    let pixel = |bytes: &[u8], x: usize, y: usize| bytes[(y * 8 + x) * 4..][..4].to_vec();
+
+    let tile = nes.sprite_rgb_bytes(5);
+    assert_eq!(tile.len(), 8 * 8 * 4);
+    assert_eq!(pixel(&tile, 0, 0), [red.0, red.1, red.2, 255]);
+
+    // 8x16 sprites
+    nes.cpu_write_mem(0x2000, 0x20);
+    let tile = nes.sprite_rgb_bytes(5);
+    assert_eq!(tile.len(), 8 * 16 * 4);
+    assert_eq!(pixel(&tile, 0, 0), [red.0, red.1, red.2, 255]);
+    assert_eq!(pixel(&tile, 7, 15), [green.0, green.1, green.2, 255]);
+    assert_eq!(pixel(&tile, 1, 0), [0, 0, 0, 0]);
+
+    // Flipped both ways
+    nes.ppu_mut().primary_oam[5 * 4 + 2] = 0xC0;
+    let tile = nes.sprite_rgb_bytes(5);
+    assert_eq!(pixel(&tile, 7, 15), [red.0, red.1, red.2, 255]);
+    assert_eq!(pixel(&tile, 0, 0), [green.0, green.1, green.2, 255]);
+
+    nes.cpu_write_mem(0x2001, 0x18);
+    run_until(&mut nes, 52, 10);
+    assert_eq!(nes.ppu().active_sprite_indices(), [5]);
+}
+
+#[test]
+fn step_frame_fast() {
+    let mut nes = Nes::new();
+    nes.insert_cartridge(Cartridge::from_bytes(&idle_rom()).unwrap());
+    let mut fast_nes = Nes::new();
+    fast_nes.insert_cartridge(Cartridge::from_bytes(&idle_rom()).unwrap());
+
+    for _ in 0..2 {
+        nes.step_frame().unwrap();
+        fast_nes.step_frame_fast().unwrap();
+    }
+
+    assert_eq!(fast_nes.frame_count(), 2);
+
+    // The CPU runs ahead by at most one instruction
+    let cpu_cycles = nes.cpu().cycles as i128;
+    let fast_cpu_cycles = fast_nes.cpu().cycles as i128;
+    assert!((fast_cpu_cycles - cpu_cycles).abs() <= 3);
+}
+
+/// Fill the screen with the given backdrop color.
+fn set_backdrop(nes: &mut Nes, color: u8) {
+    nes.cpu_write_mem(0x2001, 0x00);
+    nes.cpu_write_mem(0x2006, 0x3F);
+    nes.cpu_write_mem(0x2006, 0x00);
+    nes.cpu_write_mem(0x2007, color);
+    nes.cpu_write_mem(0x2006, 0x00);
+    nes.cpu_write_mem(0x2006, 0x00);
+    nes.cpu_write_mem(0x2001, 0x08);
+}
+
+fn zapper_light_sensed(nes: &mut Nes) -> bool {
+    nes.cpu_read_mem(0x4017) & 0x08 == 0
+}
+
+#[test]
+fn zapper() {
+    let mut nes = Nes::new();
+    nes.insert_cartridge(Cartridge::from_bytes(&idle_rom()).unwrap());
+    nes.set_zapper(128, 100, false);
+
+    set_backdrop(&mut nes, 0x30);
+    run_until(&mut nes, 97, 0);
+    assert!(!zapper_light_sensed(&mut nes));
+    run_until(&mut nes, 105, 0);
+    assert!(zapper_light_sensed(&mut nes));
+    run_until(&mut nes, 130, 0);
+    assert!(!zapper_light_sensed(&mut nes));
+
+    set_backdrop(&mut nes, 0x0F);
+    run_until(&mut nes, 105, 0);
+    assert!(!zapper_light_sensed(&mut nes));
+
+    assert_eq!(nes.cpu_read_mem(0x4017) & 0x10, 0);
+    nes.set_zapper(128, 100, true);
+    assert_eq!(nes.cpu_read_mem(0x4017) & 0x10, 0x10);
+
+    nes.disconnect_zapper();
+    assert_eq!(nes.cpu_read_mem(0x4017) & 0x18, 0);
+}
+
+/// A PPU `dots` cycles after the cycle that sets the vblank flag, so that at 0 the flag has
+/// just been set and at -1 it is set by the next cycle.
+fn ppu_at_vblank(cart: &mut Cartridge, nmi_enabled: bool, dots: i32) -> Ppu {
+    let mut ppu = Ppu::new(cart);
+    ppu.write_ppu_ctrl(if nmi_enabled { 0x80 } else { 0x00 });
+
+    while ppu.current_scanline() != 241 || ppu.current_cycle() != 1 {
+        ppu.clock();
+    }
+    for _ in 0..dots + 1 {
+        ppu.clock();
+    }
+
+    ppu
+}
+
+/// Whether an NMI is triggered before the end of the frame
+fn nmi_occurs(ppu: &mut Ppu) -> bool {
+    let mut nmi = false;
+    while ppu.current_scanline() != 261 {
+        nmi |= ppu.nmi_triggered();
+        ppu.clock();
+    }
+    nmi
+}
+
+#[test]
+fn reading_ppustatus_at_vblank_start_suppresses_nmi() {
+    let mut cart = Cartridge::from_bytes(&idle_rom()).unwrap();
+
+    // One cycle before: the flag reads as clear and is never set
+    let mut ppu = ppu_at_vblank(&mut cart, true, -1);
+    assert_eq!(ppu.read_ppu_status(false) & 0x80, 0);
+    ppu.clock();
+    assert_eq!(ppu.read_ppu_status(true) & 0x80, 0);
+    assert!(!nmi_occurs(&mut ppu));
+
+    // On the same cycle or one after: the flag reads as set, but there is no NMI
+    for dots in [0, 1] {
+        let mut ppu = ppu_at_vblank(&mut cart, true, dots);
+        assert_eq!(ppu.read_ppu_status(false) & 0x80, 0x80);
+        assert!(!nmi_occurs(&mut ppu));
+    }
+
+    let mut ppu = ppu_at_vblank(&mut cart, true, 2);
+    assert_eq!(ppu.read_ppu_status(false) & 0x80, 0x80);
+    assert!(nmi_occurs(&mut ppu));
+}
+
+#[test]
+fn disabling_nmi_at_vblank_start_suppresses_nmi() {
+    let mut cart = Cartridge::from_bytes(&idle_rom()).unwrap();
+
+    for dots in [-1, 0, 1] {
+        let mut ppu = ppu_at_vblank(&mut cart, true, dots);
+        ppu.write_ppu_ctrl(0x00);
+        assert!(!nmi_occurs(&mut ppu));
+    }
+
+    // The NMI has already occurred
+    let mut ppu = ppu_at_vblank(&mut cart, true, 2);
+    ppu.write_ppu_ctrl(0x00);
+    assert!(nmi_occurs(&mut ppu));
+}
+
+#[test]
+fn enabling_nmi_during_vblank_triggers_nmi() {
+    let mut cart = Cartridge::from_bytes(&idle_rom()).unwrap();
+
+    let mut ppu = ppu_at_vblank(&mut cart, false, -1);
+    ppu.write_ppu_ctrl(0x80);
+    assert!(nmi_occurs(&mut ppu));
+
+    let mut ppu = ppu_at_vblank(&mut cart, false, 100);
+    assert!(!ppu.nmi_triggered());
+    ppu.write_ppu_ctrl(0x80);
+    assert!(ppu.nmi_triggered());
+
+    // Toggling NMI enable off and on again triggers another NMI
+    ppu.write_ppu_ctrl(0x00);
+    ppu.write_ppu_ctrl(0x80);
+    assert!(ppu.nmi_triggered());
+
+    // Not after the flag has been cleared by reading it
+    ppu.read_ppu_status(false);
+    ppu.write_ppu_ctrl(0x00);
+    ppu.write_ppu_ctrl(0x80);
+    assert!(!nmi_occurs(&mut ppu));
+}
+
+#[test]
+fn vblank_timing_follows_region() {
+    let mut cart = Cartridge::from_bytes(&idle_rom()).unwrap();
+
+    for region in [Region::Ntsc, Region::Pal, Region::Dendy] {
+        let mut ppu = Ppu::new(&mut cart);
+        ppu.set_region(region);
+        ppu.write_ppu_ctrl(0x80);
+        // Odd NTSC frames are one cycle shorter while rendering
+        ppu.write_ppu_mask(0x18);
+
+        for _ in 0..3 {
+            while ppu.current_scanline() != region.vblank_scanline() || ppu.current_cycle() != 1 {
+                ppu.clock();
+            }
+
+            // The read suppresses the vblank flag only if it is on the cycle before the flag is set
+            assert_eq!(ppu.read_ppu_status(false) & 0x80, 0);
+            ppu.clock();
+            assert_eq!(ppu.read_ppu_status(true) & 0x80, 0);
+            assert!(!nmi_occurs(&mut ppu));
+        }
+    }
+}
+
+#[test]
+fn scanlines_per_frame_follow_region() {
+    let mut cart = Cartridge::from_bytes(&idle_rom()).unwrap();
+
+    for (region, scanlines) in [
+        (Region::Ntsc, 262),
+        (Region::Pal, 312),
+        (Region::Dendy, 312),
+    ] {
+        let mut ppu = Ppu::new(&mut cart);
+        ppu.set_region(region);
+
+        while !ppu.frame_completed() {
+            ppu.clock();
+        }
+
+        for _ in 0..2 {
+            let mut count = 0;
+            let mut scanline = ppu.current_scanline();
+
+            loop {
+                ppu.clock();
+                if ppu.current_scanline() != scanline {
+                    scanline = ppu.current_scanline();
+                    count += 1;
+                }
+                if ppu.frame_completed() {
+                    break;
+                }
+            }
+
+            assert_eq!(count, scanlines, "{:?}", region);
+        }
+    }
+}
+
+#[test]
+fn scroll_position() {
+    let mut nes = Nes::new();
+    nes.insert_cartridge(Cartridge::from_bytes(&idle_rom()).unwrap());
+
+    nes.cpu_write_mem(0x2000, 0x03);
+    nes.cpu_write_mem(0x2005, 0x7D);
+    nes.cpu_write_mem(0x2005, 0x5E);
+
+    assert_eq!(nes.ppu().base_nametable(), 3);
+    assert_eq!(nes.ppu().scroll_position(), (256 + 0x7D, 240 + 0x5E));
+
+    // The viewport wraps around to the other nametables
+    assert_eq!(
+        nes.ppu().scroll_viewport(),
+        vec![
+            (3, 0x7D, 0x5E, 256 - 0x7D, 240 - 0x5E),
+            (2, 0, 0x5E, 0x7D, 240 - 0x5E),
+            (1, 0x7D, 0, 256 - 0x7D, 0x5E),
+            (0, 0, 0, 0x7D, 0x5E),
+        ]
+    );
+
+    nes.cpu_write_mem(0x2000, 0x00);
+    nes.cpu_write_mem(0x2005, 0x00);
+    nes.cpu_write_mem(0x2005, 0x00);
+    assert_eq!(nes.ppu().scroll_viewport(), vec![(0, 0, 0, 256, 240)]);
+}
+
+#[test]
+fn nametable_rgb_bytes_follow_vram_changes() {
+    let mut rom = idle_rom();
+    // Tile 1 is solid color 3
+    let chr = rom.len() - 0x2000;
+    rom[chr + 0x10..chr + 0x20].fill(0xFF);
+
+    let mut nes = Nes::new();
+    nes.insert_cartridge(Cartridge::from_bytes(&rom).unwrap());
+    for (addr, val) in [(0x3F00, 0x0F), (0x3F03, 0x30), (0x2000, 0x00)] {
+        nes.ppu_mut().write_mem_u8(addr, val);
+    }
+
+    let pixel = |nes: &mut Nes| nes.nametable_rgb_bytes(0)[..3].to_vec();
+    let black = pixel(&mut nes);
+
+    nes.ppu_mut().write_mem_u8(0x2000, 0x01);
+    let white = pixel(&mut nes);
+    assert_ne!(black, white);
+
+    nes.ppu_mut().write_mem_u8(0x3F03, 0x0F);
+    assert_eq!(pixel(&mut nes), black);
+
+    let cached = nes.nametable_rgb_bytes(0).to_vec();
+    nes.refresh_nametables();
+    assert_eq!(nes.nametable_rgb_bytes(0), cached);
+}
+
+#[test]
+fn open_bus_bits_decay_separately() {
+    let mut cart = Cartridge::from_bytes(&idle_rom()).unwrap();
+    let mut ppu = Ppu::new(&mut cart);
+    // PPU cycles per 100 ms
+    let cycles_per_100ms = 536_931;
+
+    ppu.write_ppu_ctrl(0xFF);
+    for _ in 0..cycles_per_100ms * 3 {
+        ppu.clock();
+    }
+    assert_eq!(ppu.open_bus, 0xFF);
+
+    // Refresh the lower bits, and put the upper bits back on the bus without refreshing them
+    ppu.write_ppu_ctrl(0x0F);
+    ppu.write_open_bus(0xFF, false);
+
+    for _ in 0..cycles_per_100ms * 4 {
+        ppu.clock();
+    }
+    assert_eq!(ppu.open_bus, 0x0F);
+
+    for _ in 0..cycles_per_100ms * 3 {
+        ppu.clock();
+    }
+    assert_eq!(ppu.open_bus, 0x00);
+}
+
+#[test]
+fn four_screen_mirroring_keeps_all_nametables_apart() {
+    let mut rom = idle_rom();
+    // Four-screen VRAM
+    rom[6] |= 0b1000;
+
+    let mut nes = Nes::new();
+    nes.insert_cartridge(Cartridge::from_bytes(&rom).unwrap());
+
+    let nametables = [0x2000u16, 0x2400, 0x2800, 0x2C00];
+    for (i, addr) in nametables.into_iter().enumerate() {
+        nes.ppu_write_mem(addr, i as u8 + 1);
+        nes.ppu_write_mem(addr + 0x2FF, i as u8 + 0x11);
+    }
+
+    // $3000-$3EFF mirrors $2000-$2EFF
+    for (i, addr) in nametables.into_iter().enumerate() {
+        assert_eq!(nes.ppu_read_mem(addr), i as u8 + 1);
+        assert_eq!(nes.ppu_read_mem(addr + 0x1000), i as u8 + 1);
+        assert_eq!(nes.ppu_read_mem(addr + 0x12FF), i as u8 + 0x11);
+    }
+}
+
+#[test]
+fn reset_keeps_vram_and_power_clears_it() {
+    let mut nes = Nes::new();
+    nes.insert_cartridge(Cartridge::from_bytes(&idle_rom()).unwrap());
+
+    for (addr, val) in [(0x2000u16, 0x42), (0x3F01, 0x16)] {
+        nes.cpu_write_mem(0x2006, (addr >> 8) as u8);
+        nes.cpu_write_mem(0x2006, addr as u8);
+        nes.cpu_write_mem(0x2007, val);
+    }
+    nes.cpu_write_mem(0x2000, 0x80);
+    nes.cpu_write_mem(0x2001, 0x18);
+    nes.cpu_write_mem(0x2005, 0x10);
+    nes.cpu_write_mem(0x2005, 0x20);
+    assert_eq!(nes.ppu().scroll_position(), (0x10, 0x20));
+
+    nes.reset();
+    assert_eq!(nes.ppu().ppu_ctrl, 0);
+    assert_eq!(nes.ppu().ppu_mask, 0);
+    assert_eq!(nes.ppu().scroll_position(), (0, 0));
+    assert_eq!(nes.ppu_read_mem(0x2000), 0x42);
+    assert_eq!(nes.ppu_read_mem(0x3F01), 0x16);
+
+    nes.power();
+    assert_eq!(nes.ppu_read_mem(0x2000), 0);
+}
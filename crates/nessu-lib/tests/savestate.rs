@@ -0,0 +1,84 @@
+#![cfg(feature = "savestate")]
+
+use nessu_lib::cartridge::Cartridge;
+use nessu_lib::input::Button;
+use nessu_lib::nes::Nes;
+
+const ROM: &[u8] = include_bytes!("../../../roms/snow.nes");
+
+fn nes(rom: &[u8]) -> Nes {
+    let mut nes = Nes::new();
+    nes.insert_cartridge(Cartridge::from_bytes(rom).unwrap());
+    nes
+}
+
+#[derive(Debug, PartialEq)]
+struct Snapshot {
+    frame: u64,
+    cpu: (u16, u8, u8, u8, u8, u8, u128),
+    ppu: (u16, u16, u8, u8, u8, u16),
+    oam: Vec<u8>,
+    ram: Vec<u8>,
+    framebuffer: Vec<u8>,
+}
+
+fn snapshot(nes: &Nes) -> Snapshot {
+    let cpu = nes.cpu();
+    let ppu = nes.ppu();
+
+    Snapshot {
+        frame: nes.frame_count(),
+        cpu: (cpu.pc, cpu.a, cpu.x, cpu.y, cpu.s, cpu.p, cpu.cycles),
+        ppu: (
+            ppu.current_scanline(),
+            ppu.current_cycle(),
+            ppu.ppu_ctrl,
+            ppu.ppu_mask,
+            ppu.ppu_status,
+            ppu.vram_addr,
+        ),
+        oam: ppu.primary_oam.to_vec(),
+        ram: nes.ram_snapshot(),
+        framebuffer: nes.framebuffer().to_vec(),
+    }
+}
+
+fn run_frames(nes: &mut Nes, frames: usize) {
+    for frame in 0..frames {
+        nes.set_button_state_player1(Button::Start, frame % 10 == 0);
+        nes.set_button_state_player1(Button::Right, frame % 3 == 0);
+        nes.step_frame().unwrap();
+    }
+}
+
+#[test]
+fn state_round_trip() {
+    let mut nes = nes(ROM);
+    run_frames(&mut nes, 30);
+
+    let state = nes.save_state();
+    let saved = snapshot(&nes);
+
+    run_frames(&mut nes, 20);
+    let after = snapshot(&nes);
+    assert_ne!(after, saved);
+
+    nes.load_state(&state).unwrap();
+    assert_eq!(snapshot(&nes), saved);
+
+    // The loaded state continues the same way
+    run_frames(&mut nes, 20);
+    assert_eq!(snapshot(&nes), after);
+}
+
+#[test]
+fn states_need_the_same_rom() {
+    let state = nes(ROM).save_state();
+
+    let mut rom = ROM.to_vec();
+    rom[0x10] ^= 0xFF;
+    let mut other = nes(&rom);
+    assert!(other.load_state(&state).is_err());
+
+    assert!(nes(ROM).load_state(&state).is_ok());
+}
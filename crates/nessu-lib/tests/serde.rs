@@ -0,0 +1,13 @@
+#![cfg(feature = "serde")]
+
+use nessu_lib::cartridge::Cartridge;
+use nessu_lib::header::Header;
+
+#[test]
+fn header_round_trip() {
+    let cart = Cartridge::from_bytes(include_bytes!("../../../roms/snow.nes")).unwrap();
+    let header = *cart.header();
+
+    let json = serde_json::to_string(&header).unwrap();
+    assert_eq!(serde_json::from_str::<Header>(&json).unwrap(), header);
+}
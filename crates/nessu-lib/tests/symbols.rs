@@ -0,0 +1,78 @@
+use nessu_lib::cartridge::Cartridge;
+use nessu_lib::nes::Nes;
+use nessu_lib::symbols::Symbols;
+
+#[test]
+fn parse_mlb() {
+    let text = "\
+P:0010:Reset:Entry point
+P:7FF0-7FF5:Vectors
+R:0020:FrameCount
+W:0100:SaveData
+G:2000:PpuCtrl
+NesInternalRam:0021:Lives
+P:0020::Only a comment
+X:0000:Unknown
+garbage
+";
+
+    // 16KB PRG-ROM is mirrored at $8000 and $C000
+    let symbols = Symbols::parse_mlb(text, 0x4000);
+    assert_eq!(symbols.get(0x8010), Some("Reset"));
+    assert_eq!(symbols.get(0xC010), Some("Reset"));
+    assert_eq!(symbols.get(0x0020), Some("FrameCount"));
+    assert_eq!(symbols.get(0x0021), Some("Lives"));
+    assert_eq!(symbols.get(0x6100), Some("SaveData"));
+    assert_eq!(symbols.get(0x2000), Some("PpuCtrl"));
+    assert_eq!(symbols.get(0x8020), None);
+    // Offset $7FF0 is outside 16KB of PRG-ROM
+    assert_eq!(symbols.len(), 6);
+
+    // Only the last bank of larger ROMs has a known address
+    let symbols = Symbols::parse_mlb(text, 0x20000);
+    assert_eq!(symbols.get(0x8010), None);
+    let symbols = Symbols::parse_mlb("P:1C010:Fixed", 0x20000);
+    assert_eq!(symbols.get(0xC010), Some("Fixed"));
+}
+
+#[test]
+fn parse_nl() {
+    let text = "\
+$C000#Reset#Entry point
+$0300/10#Buffer#
+$0400##No name
+C100#NoDollar#
+";
+
+    let symbols = Symbols::parse_nl(text);
+    assert_eq!(symbols.get(0xC000), Some("Reset"));
+    assert_eq!(symbols.get(0x0300), Some("Buffer"));
+    assert_eq!(symbols.len(), 2);
+}
+
+#[test]
+fn disassembly_shows_symbols() {
+    let mut rom = b"NES\x1A".to_vec();
+    rom.extend([2, 1]);
+    rom.resize(16, 0);
+
+    let mut prg = vec![0xEA; 0x8000];
+    prg[..8].copy_from_slice(&[
+        0xA5, 0x20, // LDA $20
+        0x8D, 0x00, 0x20, // STA $2000
+        0x4C, 0x00, 0x80, // JMP $8000
+    ]);
+    rom.extend(prg);
+    rom.extend(vec![0; 0x2000]);
+
+    let mut nes = Nes::new();
+    nes.insert_cartridge(Cartridge::from_bytes(&rom).unwrap());
+
+    let symbols = Symbols::parse_mlb("R:0020:FrameCount\nG:2000:PpuCtrl\nP:0000:Main", 0x8000);
+    let asm: Vec<String> = nes
+        .disassemble_range(0x8000, 0x8005, true)
+        .iter()
+        .map(|op| op.asm_with_symbols(true, &symbols))
+        .collect();
+    assert_eq!(asm, ["LDA FrameCount", "STA PpuCtrl", "JMP Main"]);
+}